@@ -0,0 +1,726 @@
+//! symbolic execution of classic BPF programs
+//!
+//! every path from the first instruction to a RET is walked with symbolic
+//! registers, recording the branch conditions taken on the way. the result
+//! is the set of constraints on the packet bytes leading to each RET, which
+//! decompilers, equivalence checkers or test case generators can build on.
+
+use crate::bpf_base::{bpf, BPFFilter, BPFProgram};
+use std::fmt;
+use std::fmt::Write;
+use std::ops::Range;
+
+/// upper bound on the number of paths enumerated for a single program
+const MAX_PATHS: usize = 4096;
+
+/// number of scratch memory slots
+const MEMWORDS: usize = 16;
+
+/// an ALU operation applied to symbolic values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AluOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Or,
+    And,
+    Lsh,
+    Rsh,
+    Mod,
+    Xor,
+}
+
+impl AluOp {
+    fn from_code(op: u16) -> Option<Self> {
+        Some(match op {
+            0x00 => AluOp::Add,
+            0x10 => AluOp::Sub,
+            0x20 => AluOp::Mul,
+            0x30 => AluOp::Div,
+            0x40 => AluOp::Or,
+            0x50 => AluOp::And,
+            0x60 => AluOp::Lsh,
+            0x70 => AluOp::Rsh,
+            0x90 => AluOp::Mod,
+            0xa0 => AluOp::Xor,
+            _ => return None,
+        })
+    }
+
+    fn apply(self, a: u32, b: u32) -> Option<u32> {
+        Some(match self {
+            AluOp::Add => a.wrapping_add(b),
+            AluOp::Sub => a.wrapping_sub(b),
+            AluOp::Mul => a.wrapping_mul(b),
+            AluOp::Div => a.checked_div(b)?,
+            AluOp::Or => a | b,
+            AluOp::And => a & b,
+            AluOp::Lsh => a.checked_shl(b).unwrap_or(0),
+            AluOp::Rsh => a.checked_shr(b).unwrap_or(0),
+            AluOp::Mod => a.checked_rem(b)?,
+            AluOp::Xor => a ^ b,
+        })
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            AluOp::Add => "+",
+            AluOp::Sub => "-",
+            AluOp::Mul => "*",
+            AluOp::Div => "/",
+            AluOp::Or => "|",
+            AluOp::And => "&",
+            AluOp::Lsh => "<<",
+            AluOp::Rsh => ">>",
+            AluOp::Mod => "%",
+            AluOp::Xor => "^",
+        }
+    }
+}
+
+/// the symbolic content of a register or scratch slot
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Value {
+    Const(u32),
+    /// `size` big-endian bytes of the packet at `offset`, plus `index` for indirect loads
+    Packet {
+        index: Option<Box<Value>>,
+        offset: u32,
+        size: u8,
+    },
+    /// `4 * (pkt[offset] & 0xf)`, as loaded by `ldx msh`
+    HeaderLen(u32),
+    /// the length of the packet
+    Len,
+    Alu(AluOp, Box<Value>, Box<Value>),
+    Neg(Box<Value>),
+}
+
+impl Value {
+    fn alu(op: AluOp, lhs: Value, rhs: Value) -> Value {
+        match (&lhs, &rhs) {
+            (Value::Const(a), Value::Const(b)) => match op.apply(*a, *b) {
+                Some(v) => Value::Const(v),
+                None => Value::Alu(op, Box::new(lhs), Box::new(rhs)),
+            },
+            _ => Value::Alu(op, Box::new(lhs), Box::new(rhs)),
+        }
+    }
+
+    /// the packet bytes read by an absolute load
+    pub fn packet_bytes(&self) -> Option<Range<u32>> {
+        match self {
+            Value::Packet {
+                index: None,
+                offset,
+                size,
+            } => Some(*offset..offset.saturating_add(*size as u32)),
+            Value::HeaderLen(offset) => Some(*offset..offset.saturating_add(1)),
+            _ => None,
+        }
+    }
+
+    /// the value for a concrete packet, `None` when a load falls outside of it
+    /// or a division by zero aborts the program
+    pub fn eval(&self, packet: &[u8]) -> Option<u32> {
+        let byte = |at: u32| packet.get(at as usize).map(|b| *b as u32);
+        Some(match self {
+            Value::Const(k) => *k,
+            Value::Packet {
+                index,
+                offset,
+                size,
+            } => {
+                let base = match index {
+                    Some(x) => x.eval(packet)?.checked_add(*offset)?,
+                    None => *offset,
+                };
+                let mut v = 0u32;
+                for i in 0..*size as u32 {
+                    v = v << 8 | byte(base.checked_add(i)?)?;
+                }
+                v
+            }
+            Value::HeaderLen(offset) => 4 * (byte(*offset)? & 0xf),
+            Value::Len => packet.len() as u32,
+            Value::Alu(op, lhs, rhs) => op.apply(lhs.eval(packet)?, rhs.eval(packet)?)?,
+            Value::Neg(v) => v.eval(packet)?.wrapping_neg(),
+        })
+    }
+
+    fn load(index: Option<Value>, offset: u32, size: u8) -> Value {
+        match index {
+            None => Value::Packet {
+                index: None,
+                offset,
+                size,
+            },
+            Some(Value::Const(x)) => Value::Packet {
+                index: None,
+                offset: offset.wrapping_add(x),
+                size,
+            },
+            Some(x) => Value::Packet {
+                index: Some(Box::new(x)),
+                offset,
+                size,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Const(k) => write!(f, "{:#x}", k),
+            Value::Packet {
+                index: None,
+                offset,
+                size,
+            } => write!(f, "pkt[{}:{}]", offset, size),
+            Value::Packet {
+                index: Some(x),
+                offset,
+                size,
+            } => write!(f, "pkt[{} + {}:{}]", x, offset, size),
+            Value::HeaderLen(offset) => write!(f, "4*(pkt[{}:1]&0xf)", offset),
+            Value::Len => write!(f, "len"),
+            Value::Alu(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+            Value::Neg(v) => write!(f, "-{}", v),
+        }
+    }
+}
+
+/// the comparison performed by a conditional jump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Cmp {
+    Eq,
+    Gt,
+    Ge,
+    Set,
+}
+
+impl Cmp {
+    fn from_code(op: u16) -> Option<Self> {
+        Some(match op {
+            0x10 => Cmp::Eq,
+            0x20 => Cmp::Gt,
+            0x30 => Cmp::Ge,
+            0x40 => Cmp::Set,
+            _ => return None,
+        })
+    }
+
+    pub fn eval(self, a: u32, b: u32) -> bool {
+        match self {
+            Cmp::Eq => a == b,
+            Cmp::Gt => a > b,
+            Cmp::Ge => a >= b,
+            Cmp::Set => a & b != 0,
+        }
+    }
+}
+
+/// a branch condition, `lhs cmp rhs` when `holds`, its negation otherwise
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Condition {
+    pub lhs: Value,
+    pub cmp: Cmp,
+    pub rhs: Value,
+    pub holds: bool,
+}
+
+impl Condition {
+    /// whether a concrete packet satisfies the condition, `None` when it cannot be evaluated
+    pub fn eval(&self, packet: &[u8]) -> Option<bool> {
+        Some(
+            self.cmp
+                .eval(self.lhs.eval(packet)?, self.rhs.eval(packet)?)
+                == self.holds,
+        )
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.cmp, self.holds) {
+            (Cmp::Eq, true) => write!(f, "{} == {}", self.lhs, self.rhs),
+            (Cmp::Eq, false) => write!(f, "{} != {}", self.lhs, self.rhs),
+            (Cmp::Gt, true) => write!(f, "{} > {}", self.lhs, self.rhs),
+            (Cmp::Gt, false) => write!(f, "{} <= {}", self.lhs, self.rhs),
+            (Cmp::Ge, true) => write!(f, "{} >= {}", self.lhs, self.rhs),
+            (Cmp::Ge, false) => write!(f, "{} < {}", self.lhs, self.rhs),
+            (Cmp::Set, true) => write!(f, "{} & {} != 0", self.lhs, self.rhs),
+            (Cmp::Set, false) => write!(f, "{} & {} == 0", self.lhs, self.rhs),
+        }
+    }
+}
+
+/// one way through a program
+#[derive(Debug, Clone)]
+pub struct Path {
+    /// the conditions that hold for the packets taking this path, in jump order
+    pub conditions: Vec<Condition>,
+    /// the returned value
+    pub ret: Value,
+}
+
+/// a program the path analysis could not walk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnalysisError {
+    /// the opcode at this index is not a valid classic BPF instruction
+    InvalidInstruction(usize),
+    /// the instruction at this index jumps or falls past the end of the program
+    OutOfBounds(usize),
+    /// the instruction at this index uses a scratch slot beyond M[15]
+    InvalidSlot(usize),
+    /// the program has more paths than the analysis is willing to enumerate
+    TooManyPaths,
+    /// the jump at this index would need an offset beyond 255 once rewritten
+    JumpTooFar(usize),
+    /// the program has no instruction
+    Empty,
+    /// the program has this many instructions, more than `bpf::MAXINSNS`
+    TooLong(usize),
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::InvalidInstruction(i) => write!(f, "invalid instruction at {}", i),
+            AnalysisError::OutOfBounds(i) => {
+                write!(f, "instruction {} leaves the program without a RET", i)
+            }
+            AnalysisError::InvalidSlot(i) => {
+                write!(f, "instruction {} uses a scratch slot out of range", i)
+            }
+            AnalysisError::TooManyPaths => {
+                write!(f, "the program has more than {} paths", MAX_PATHS)
+            }
+            AnalysisError::JumpTooFar(i) => {
+                write!(f, "the jump at {} no longer fits in 8 bits", i)
+            }
+            AnalysisError::Empty => write!(f, "the program is empty"),
+            AnalysisError::TooLong(len) => write!(
+                f,
+                "the program has {} instructions, more than {}",
+                len,
+                bpf::MAXINSNS
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+#[derive(Clone)]
+struct State {
+    a: Value,
+    x: Value,
+    mem: [Value; MEMWORDS],
+}
+
+struct Walker<'a> {
+    filters: &'a [BPFFilter],
+    paths: Vec<Path>,
+    /// whether the loads past the end of the packet are followed
+    bounds: bool,
+}
+
+impl Walker<'_> {
+    /// fork the path of a load needing a packet of `end` bytes, `None` when
+    /// no packet is long enough
+    ///
+    /// the packets too short take a path returning 0, as the program stops
+    /// there. returns whether the load can succeed.
+    fn bound(
+        &mut self,
+        end: Option<Value>,
+        conditions: &mut Vec<Condition>,
+    ) -> Result<bool, AnalysisError> {
+        let decided = match &end {
+            Some(end) => implied(conditions, &Value::Len, Cmp::Ge, end).or_else(|| {
+                conditions.iter().find_map(|c| match (c, end) {
+                    (
+                        Condition {
+                            lhs: Value::Len,
+                            cmp: Cmp::Ge,
+                            rhs: Value::Const(known),
+                            holds,
+                        },
+                        Value::Const(end),
+                    ) if (*holds && known >= end) || (!*holds && known <= end) => Some(*holds),
+                    _ => None,
+                })
+            }),
+            None => Some(false),
+        };
+        if decided == Some(true) {
+            return Ok(true);
+        }
+        if self.paths.len() >= MAX_PATHS {
+            return Err(AnalysisError::TooManyPaths);
+        }
+        let mut short = conditions.clone();
+        if let (None, Some(end)) = (decided, &end) {
+            short.push(Condition {
+                lhs: Value::Len,
+                cmp: Cmp::Ge,
+                rhs: end.clone(),
+                holds: false,
+            });
+        }
+        self.paths.push(Path {
+            conditions: short,
+            ret: Value::Const(0),
+        });
+        match (decided, end) {
+            (None, Some(end)) => {
+                conditions.push(Condition {
+                    lhs: Value::Len,
+                    cmp: Cmp::Ge,
+                    rhs: end,
+                    holds: true,
+                });
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn walk(
+        &mut self,
+        mut pc: usize,
+        mut state: State,
+        mut conditions: Vec<Condition>,
+        mut last: usize,
+    ) -> Result<(), AnalysisError> {
+        loop {
+            let insn = self
+                .filters
+                .get(pc)
+                .ok_or(AnalysisError::OutOfBounds(last))?;
+            last = pc;
+            let code = insn.code;
+            let k = insn.k;
+            let slot = || {
+                if (k as usize) < MEMWORDS {
+                    Ok(k as usize)
+                } else {
+                    Err(AnalysisError::InvalidSlot(pc))
+                }
+            };
+            let size = match code & 0x18 {
+                0x00 => 4,
+                0x08 => 2,
+                0x10 => 1,
+                _ => 0,
+            };
+            if self.bounds {
+                let end = match (code & 0x07, code & 0xe0) {
+                    (0x00, 0x20) if size > 0 => Some(k.checked_add(size as u32).map(Value::Const)),
+                    (0x00, 0x40) if size > 0 => Some(match &state.x {
+                        Value::Const(x) => {
+                            x.wrapping_add(k).checked_add(size as u32).map(Value::Const)
+                        }
+                        x => Some(Value::alu(
+                            AluOp::Add,
+                            x.clone(),
+                            Value::Const(k.wrapping_add(size as u32)),
+                        )),
+                    }),
+                    (0x01, 0xa0) => Some(k.checked_add(1).map(Value::Const)),
+                    _ => None,
+                };
+                if let Some(end) = end {
+                    if !self.bound(end, &mut conditions)? {
+                        return Ok(());
+                    }
+                }
+            }
+            match code & 0x07 {
+                // LD
+                0x00 => {
+                    state.a = match code & 0xe0 {
+                        0x00 => Value::Const(k),
+                        0x20 if size > 0 => Value::load(None, k, size),
+                        0x40 if size > 0 => Value::load(Some(state.x.clone()), k, size),
+                        0x60 => state.mem[slot()?].clone(),
+                        0x80 => Value::Len,
+                        _ => return Err(AnalysisError::InvalidInstruction(pc)),
+                    }
+                }
+                // LDX
+                0x01 => {
+                    state.x = match code & 0xe0 {
+                        0x00 => Value::Const(k),
+                        0x60 => state.mem[slot()?].clone(),
+                        0x80 => Value::Len,
+                        0xa0 => Value::HeaderLen(k),
+                        _ => return Err(AnalysisError::InvalidInstruction(pc)),
+                    }
+                }
+                // ST
+                0x02 => state.mem[slot()?] = state.a.clone(),
+                // STX
+                0x03 => state.mem[slot()?] = state.x.clone(),
+                // ALU
+                0x04 => {
+                    let rhs = if code & 0x08 == 0 {
+                        Value::Const(k)
+                    } else {
+                        state.x.clone()
+                    };
+                    state.a = match code & 0xf0 {
+                        0x80 => match state.a {
+                            Value::Const(a) => Value::Const(a.wrapping_neg()),
+                            a => Value::Neg(Box::new(a)),
+                        },
+                        op => match AluOp::from_code(op) {
+                            Some(op) => Value::alu(op, state.a.clone(), rhs),
+                            None => return Err(AnalysisError::InvalidInstruction(pc)),
+                        },
+                    }
+                }
+                // JMP
+                0x05 => {
+                    if code & 0xf0 == 0x00 {
+                        pc = pc + 1 + k as usize;
+                        continue;
+                    }
+                    let cmp =
+                        Cmp::from_code(code & 0xf0).ok_or(AnalysisError::InvalidInstruction(pc))?;
+                    let rhs = if code & 0x08 == 0 {
+                        Value::Const(k)
+                    } else {
+                        state.x.clone()
+                    };
+                    let taken = pc + 1 + insn.jt as usize;
+                    let not_taken = pc + 1 + insn.jf as usize;
+                    match implied(&conditions, &state.a, cmp, &rhs) {
+                        Some(true) => pc = taken,
+                        Some(false) => pc = not_taken,
+                        None => {
+                            if self.paths.len() >= MAX_PATHS {
+                                return Err(AnalysisError::TooManyPaths);
+                            }
+                            let mut forked = conditions.clone();
+                            forked.push(Condition {
+                                lhs: state.a.clone(),
+                                cmp,
+                                rhs: rhs.clone(),
+                                holds: true,
+                            });
+                            self.walk(taken, state.clone(), forked, pc)?;
+                            conditions.push(Condition {
+                                lhs: state.a.clone(),
+                                cmp,
+                                rhs,
+                                holds: false,
+                            });
+                            pc = not_taken;
+                        }
+                    }
+                    continue;
+                }
+                // RET
+                0x06 => {
+                    let ret = match code & 0x18 {
+                        0x00 => Value::Const(k),
+                        0x08 => state.x.clone(),
+                        0x10 => state.a.clone(),
+                        _ => return Err(AnalysisError::InvalidInstruction(pc)),
+                    };
+                    if self.paths.len() >= MAX_PATHS {
+                        return Err(AnalysisError::TooManyPaths);
+                    }
+                    self.paths.push(Path { conditions, ret });
+                    return Ok(());
+                }
+                // MISC
+                _ => match code & 0xf8 {
+                    0x00 => state.x = state.a.clone(),
+                    0x80 => state.a = state.x.clone(),
+                    _ => return Err(AnalysisError::InvalidInstruction(pc)),
+                },
+            }
+            pc += 1;
+        }
+    }
+}
+
+/// decide `lhs cmp rhs` from constants or from the conditions already on the path
+fn implied(conditions: &[Condition], lhs: &Value, cmp: Cmp, rhs: &Value) -> Option<bool> {
+    if let (Value::Const(a), Value::Const(b)) = (lhs, rhs) {
+        return Some(cmp.eval(*a, *b));
+    }
+    for c in conditions.iter().filter(|c| &c.lhs == lhs) {
+        if c.cmp == cmp && &c.rhs == rhs {
+            return Some(c.holds);
+        }
+        // a value known to equal a constant decides every comparison against constants
+        if let (Cmp::Eq, true, Value::Const(known), Value::Const(b)) = (c.cmp, c.holds, &c.rhs, rhs)
+        {
+            return Some(cmp.eval(*known, *b));
+        }
+    }
+    None
+}
+
+/// enumerate every path through `filters`
+///
+/// branches already decided by the conditions on the path are not forked,
+/// so the paths returned are the feasible ones as far as the analysis can tell.
+/// the order is the one of a depth-first walk, taken branches first.
+pub fn symbolic_paths(filters: &[BPFFilter]) -> Result<Vec<Path>, AnalysisError> {
+    walk(filters, false)
+}
+
+/// enumerate every path through `filters`, the loads past the end of the packet included
+///
+/// as [`symbolic_paths`], with each load from the packet adding the condition
+/// on `len` it needs to succeed and forking a path returning 0 for the packets
+/// too short for it. `len` stands for the captured length there.
+pub(crate) fn bounded_paths(filters: &[BPFFilter]) -> Result<Vec<Path>, AnalysisError> {
+    walk(filters, true)
+}
+
+fn walk(filters: &[BPFFilter], bounds: bool) -> Result<Vec<Path>, AnalysisError> {
+    let mut walker = Walker {
+        filters,
+        paths: Vec::new(),
+        bounds,
+    };
+    let state = State {
+        a: Value::Const(0),
+        x: Value::Const(0),
+        mem: Default::default(),
+    };
+    walker.walk(0, state, Vec::new(), 0)?;
+    Ok(walker.paths)
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Const(0)
+    }
+}
+
+/// the paths of a program leading to one return value
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub ret: Value,
+    /// the alternative sets of conditions, any of which leads to `ret`
+    pub paths: Vec<Vec<Condition>>,
+}
+
+/// group the paths of a program by the value they return
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+///
+/// let outcomes = outcomes(&filters).unwrap();
+/// assert_eq!(outcomes[0].ret, Value::Const(u32::MAX));
+/// let condition = &outcomes[0].paths[0][0];
+/// assert_eq!(condition.lhs.packet_bytes(), Some(6..7));
+/// assert_eq!(condition.eval(&[0, 0, 0, 0, 0, 0, 58]), Some(true));
+/// ```
+pub fn outcomes(filters: &[BPFFilter]) -> Result<Vec<Outcome>, AnalysisError> {
+    let mut outcomes: Vec<Outcome> = Vec::new();
+    for path in symbolic_paths(filters)? {
+        match outcomes.iter_mut().find(|o| o.ret == path.ret) {
+            Some(outcome) => outcome.paths.push(path.conditions),
+            None => outcomes.push(Outcome {
+                ret: path.ret,
+                paths: vec![path.conditions],
+            }),
+        }
+    }
+    Ok(outcomes)
+}
+
+fn describe_ret(ret: &Value) -> String {
+    match ret {
+        Value::Const(0) => "`0`: the packet is dropped".to_string(),
+        Value::Const(u32::MAX) => format!("`{:#x}`: the whole packet is accepted", u32::MAX),
+        Value::Const(k) => format!("`{:#x}`: at most {} bytes of the packet are accepted", k, k),
+        v => format!("`{}`: computed from the packet", v),
+    }
+}
+
+impl BPFProgram {
+    /// describe what a program does as a Markdown report
+    ///
+    /// the report lists, for each value the program can return, the conditions
+    /// on the packet that lead to it. conditions are rendered over `pkt[offset:size]`,
+    /// the big-endian field of `size` bytes at `offset`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let filters = [
+    ///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
+    ///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1),
+    ///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+    ///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    /// ];
+    ///
+    /// let report = BPFProgram::from(&filters[..]).explain().unwrap();
+    /// assert!(report.contains("pkt[6:1] == 0x3a"));
+    /// ```
+    pub fn explain(&self) -> Result<String, AnalysisError> {
+        let outcomes = outcomes(self)?;
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "# program of {} instructions with {} paths",
+            self.len(),
+            outcomes.iter().map(|o| o.paths.len()).sum::<usize>()
+        );
+        for outcome in &outcomes {
+            let _ = writeln!(out, "\n## returns {}", describe_ret(&outcome.ret));
+            for conditions in &outcome.paths {
+                if conditions.is_empty() {
+                    let _ = writeln!(out, "\n- always");
+                    continue;
+                }
+                let _ = writeln!(out, "\n- when all of:");
+                for condition in conditions {
+                    let _ = writeln!(out, "  - `{}`", condition);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[test]
+fn test_paths_prune_implied_branches() {
+    use crate::bpf_base::bpf;
+    // the second comparison of pkt[12:2] is decided by the first one
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 2),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    let paths = symbolic_paths(&filters).unwrap();
+    assert_eq!(paths.len(), 2);
+    assert_eq!(paths[0].ret, Value::load(None, 12, 2));
+    assert_eq!(paths[1].ret, Value::Const(0));
+    assert_eq!(paths[1].conditions.len(), 1);
+}
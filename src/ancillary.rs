@@ -0,0 +1,49 @@
+//! Linux ancillary data offsets
+//!
+//! an absolute load at `SKF_AD_OFF + SKF_AD_*` reads the packet metadata kept
+//! by the kernel instead of the packet bytes. loads at `SKF_NET_OFF + n` and
+//! `SKF_LL_OFF + n` read the packet `n` bytes after the start of its network
+//! and link-layer headers, wherever the socket sees the packet start. other
+//! systems reject them.
+
+/// base offset of the loads relative to the network header, as an unsigned `k`
+pub const SKF_NET_OFF: u32 = -0x10_0000i32 as u32;
+/// base offset of the loads relative to the link-layer header, as an unsigned `k`
+pub const SKF_LL_OFF: u32 = -0x20_0000i32 as u32;
+
+/// base offset of the ancillary fields, as an unsigned `k`
+pub const SKF_AD_OFF: u32 = -0x1000i32 as u32;
+/// the EtherType of the packet, in network byte order as in `skb->protocol`
+pub const SKF_AD_PROTOCOL: u32 = 0;
+/// the packet type, one of the `PACKET_*` values such as `PACKET_HOST`
+pub const SKF_AD_PKTTYPE: u32 = 4;
+/// the index of the interface the packet came from
+pub const SKF_AD_IFINDEX: u32 = 8;
+/// the offset of the netlink attribute of type X, searched from offset A
+pub const SKF_AD_NLATTR: u32 = 12;
+/// the offset of the netlink attribute of type X, nested at offset A
+pub const SKF_AD_NLATTR_NEST: u32 = 16;
+/// the mark of the packet, `SO_MARK` or set by netfilter
+pub const SKF_AD_MARK: u32 = 20;
+/// the receive queue of the NIC the packet came from
+pub const SKF_AD_QUEUE: u32 = 24;
+/// the ARPHRD_* type of the interface the packet came from
+pub const SKF_AD_HATYPE: u32 = 28;
+/// the hash the kernel computed over the flow of the packet
+pub const SKF_AD_RXHASH: u32 = 32;
+/// the CPU receiving the packet
+pub const SKF_AD_CPU: u32 = 36;
+/// A ^= X rather than a load
+pub const SKF_AD_ALU_XOR_X: u32 = 40;
+/// the TCI of the stripped VLAN tag
+pub const SKF_AD_VLAN_TAG: u32 = 44;
+/// 1 when the NIC stripped a VLAN tag from the packet, 0 otherwise
+pub const SKF_AD_VLAN_TAG_PRESENT: u32 = 48;
+/// the offset of the transport payload, after the network and transport headers
+pub const SKF_AD_PAY_OFFSET: u32 = 52;
+/// a random 32-bit number
+pub const SKF_AD_RANDOM: u32 = 56;
+/// the TPID of the stripped VLAN tag, 0x8100 for 802.1Q or 0x88a8 for 802.1ad
+pub const SKF_AD_VLAN_TPID: u32 = 60;
+/// the end of the ancillary fields
+pub const SKF_AD_MAX: u32 = 64;
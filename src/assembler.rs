@@ -0,0 +1,407 @@
+//! assembly of the textual syntax of the kernel `bpf_asm` tool
+
+use crate::ancillary::*;
+use crate::bpf_base::BPFFilter;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+/// a source [`assemble`] refuses, at the offending line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+fn error<T, S: Into<String>>(line: usize, message: S) -> Result<T, AssembleError> {
+    Err(AssembleError {
+        line,
+        message: message.into(),
+    })
+}
+
+/// the ancillary fields `bpf_asm` loads by name, with their `SKF_AD_*` offset
+const EXTENSIONS: &[(&str, u32)] = &[
+    ("proto", SKF_AD_PROTOCOL),
+    ("type", SKF_AD_PKTTYPE),
+    ("ifidx", SKF_AD_IFINDEX),
+    ("nla", SKF_AD_NLATTR),
+    ("nlan", SKF_AD_NLATTR_NEST),
+    ("mark", SKF_AD_MARK),
+    ("queue", SKF_AD_QUEUE),
+    ("hatype", SKF_AD_HATYPE),
+    ("rxhash", SKF_AD_RXHASH),
+    ("cpu", SKF_AD_CPU),
+    ("vlan_tci", SKF_AD_VLAN_TAG),
+    ("vlan_avail", SKF_AD_VLAN_TAG_PRESENT),
+    ("vlan_pr", SKF_AD_VLAN_TAG_PRESENT),
+    ("poff", SKF_AD_PAY_OFFSET),
+    ("rand", SKF_AD_RANDOM),
+    ("vlan_tpid", SKF_AD_VLAN_TPID),
+];
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operand {
+    Imm(u32),
+    Len,
+    Ext(u32),
+    Mem(u32),
+    Abs(u32),
+    Ind(u32),
+    Msh(u32),
+    X,
+    A,
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(text) => (true, text),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = text.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = text.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2).ok()?
+    } else {
+        text.parse().ok()?
+    };
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+/// an operand, with the whitespace already removed
+fn parse_operand(text: &str) -> Option<Operand> {
+    let bracketed = |text: &str, prefix: &str| {
+        text.strip_prefix(prefix)
+            .and_then(|t| t.strip_suffix(']'))
+            .map(str::to_string)
+    };
+    match text {
+        "x" | "%x" => return Some(Operand::X),
+        "a" | "%a" => return Some(Operand::A),
+        "#len" | "len" | "#pktlen" => return Some(Operand::Len),
+        _ => {}
+    }
+    let name = text.strip_prefix('#').unwrap_or(text);
+    if let Some((_, offset)) = EXTENSIONS.iter().find(|(n, _)| *n == name) {
+        return Some(Operand::Ext(*offset));
+    }
+    if let Some(k) = text.strip_prefix('#') {
+        return parse_number(k).map(Operand::Imm);
+    }
+    if let Some(k) = bracketed(text, "M[") {
+        return parse_number(&k).map(Operand::Mem);
+    }
+    if let Some(k) = text
+        .strip_prefix("4*([")
+        .and_then(|t| t.strip_suffix("]&0xf)"))
+    {
+        return parse_number(k).map(Operand::Msh);
+    }
+    let inner = bracketed(text, "[")?;
+    match inner
+        .strip_prefix("x+")
+        .or_else(|| inner.strip_prefix("%x+"))
+    {
+        Some(k) => parse_number(k).map(Operand::Ind),
+        None if inner == "x" || inner == "%x" => Some(Operand::Ind(0)),
+        None => parse_number(&inner).map(Operand::Abs),
+    }
+}
+
+/// an instruction whose jump targets are still label names
+struct Pending {
+    line: usize,
+    code: u16,
+    k: u32,
+    /// the names of the jt and jf targets, `None` being the next instruction
+    jt: Option<String>,
+    jf: Option<String>,
+}
+
+fn is_label(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// the mnemonics that are neither ALU operations nor conditional jumps
+const MNEMONICS: &[&str] = &[
+    "ld", "ldi", "ldh", "ldb", "ldx", "ldxi", "ldxb", "st", "stx", "neg", "tax", "txa", "ret",
+    "ja", "jmp",
+];
+
+fn alu_op(mnemonic: &str) -> Option<u16> {
+    Some(match mnemonic {
+        "add" => 0x00,
+        "sub" => 0x10,
+        "mul" => 0x20,
+        "div" => 0x30,
+        "or" => 0x40,
+        "and" => 0x50,
+        "lsh" => 0x60,
+        "rsh" => 0x70,
+        "mod" => 0x90,
+        "xor" => 0xa0,
+        _ => return None,
+    })
+}
+
+/// the jump opcode of a mnemonic, and whether its targets are swapped
+fn jmp_op(mnemonic: &str) -> Option<(u16, bool)> {
+    Some(match mnemonic {
+        "jeq" => (0x10, false),
+        "jne" | "jneq" => (0x10, true),
+        "jgt" => (0x20, false),
+        "jle" => (0x20, true),
+        "jge" => (0x30, false),
+        "jlt" => (0x30, true),
+        "jset" => (0x40, false),
+        _ => return None,
+    })
+}
+
+fn parse_instruction(line: usize, text: &str) -> Result<Pending, AssembleError> {
+    let (mnemonic, rest) = match text.split_once(char::is_whitespace) {
+        Some((mnemonic, rest)) => (mnemonic, rest),
+        None => (text, ""),
+    };
+    let rest: String = rest.chars().filter(|c| !c.is_whitespace()).collect();
+    let operands: Vec<&str> = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').collect()
+    };
+    let pending = |code, k| Pending {
+        line,
+        code,
+        k,
+        jt: None,
+        jf: None,
+    };
+    let invalid = || error(line, format!("invalid operands for {}: {}", mnemonic, rest));
+
+    if let Some((op, swapped)) = jmp_op(mnemonic) {
+        let (code, k) = match operands.first().and_then(|o| parse_operand(o)) {
+            Some(Operand::Imm(k)) => (0x05 | op, k),
+            Some(Operand::X) => (0x0d | op, 0),
+            _ => return invalid(),
+        };
+        let labels = &operands[1..];
+        if labels.is_empty() || labels.len() > 2 || !labels.iter().all(|l| is_label(l)) {
+            return invalid();
+        }
+        let (mut jt, mut jf) = (Some(labels[0].to_string()), None);
+        if let Some(label) = labels.get(1) {
+            jf = Some(label.to_string());
+        }
+        if swapped {
+            std::mem::swap(&mut jt, &mut jf);
+        }
+        return Ok(Pending {
+            jt,
+            jf,
+            ..pending(code, k)
+        });
+    }
+
+    let operand = match operands.as_slice() {
+        [] => None,
+        [operand] => match parse_operand(operand) {
+            Some(operand) => Some(operand),
+            None if matches!(mnemonic, "ja" | "jmp") && is_label(operand) => {
+                return Ok(Pending {
+                    jt: Some(operand.to_string()),
+                    ..pending(0x05, 0)
+                })
+            }
+            None => return invalid(),
+        },
+        _ => return invalid(),
+    };
+    let (code, k) = match (mnemonic, operand) {
+        ("ld" | "ldi", Some(Operand::Imm(k))) => (0x00, k),
+        ("ld", Some(Operand::Len)) => (0x80, 0),
+        ("ld", Some(Operand::Ext(offset))) => (0x20, SKF_AD_OFF.wrapping_add(offset)),
+        ("ld", Some(Operand::Mem(k))) => (0x60, k),
+        ("ld", Some(Operand::Abs(k))) => (0x20, k),
+        ("ld", Some(Operand::Ind(k))) => (0x40, k),
+        ("ldh", Some(Operand::Abs(k))) => (0x28, k),
+        ("ldh", Some(Operand::Ind(k))) => (0x48, k),
+        ("ldb", Some(Operand::Abs(k))) => (0x30, k),
+        ("ldb", Some(Operand::Ind(k))) => (0x50, k),
+        ("ldx" | "ldxi", Some(Operand::Imm(k))) => (0x01, k),
+        ("ldx", Some(Operand::Len)) => (0x81, 0),
+        ("ldx", Some(Operand::Mem(k))) => (0x61, k),
+        ("ldx" | "ldxb", Some(Operand::Msh(k))) => (0xb1, k),
+        ("st", Some(Operand::Mem(k))) => (0x02, k),
+        ("stx", Some(Operand::Mem(k))) => (0x03, k),
+        ("neg", None) => (0x84, 0),
+        ("tax", None) => (0x07, 0),
+        ("txa", None) => (0x87, 0),
+        ("ret", Some(Operand::Imm(k))) => (0x06, k),
+        ("ret", Some(Operand::A)) => (0x16, 0),
+        (mnemonic, operand) => match (alu_op(mnemonic), operand) {
+            (Some(op), Some(Operand::Imm(k))) => (0x04 | op, k),
+            (Some(op), Some(Operand::X)) => (0x0c | op, 0),
+            (None, _) if !MNEMONICS.contains(&mnemonic) => {
+                return error(line, format!("unknown instruction {}", mnemonic))
+            }
+            _ => return invalid(),
+        },
+    };
+    Ok(pending(code, k))
+}
+
+/// assemble a program written in the syntax of the kernel `bpf_asm` tool
+///
+/// each line holds an optional `label:` and an instruction, `;` starting a
+/// comment. jumps name their targets with labels, a conditional jump with a
+/// single label falling through to the next instruction otherwise. `jne`,
+/// `jlt` and `jle` are assembled as the opposite jump with swapped targets,
+/// and the ancillary fields are loaded by name, as in `ld #rxhash`.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let source = "
+///     ; IPv4 TCP segments
+///     ldh [12]
+///     jne #0x800, drop
+///     ldb [23]
+///     jeq #6, pass, drop
+/// pass: ret #-1
+/// drop: ret #0
+/// ";
+///
+/// let filters = assemble(source).unwrap();
+/// assert_eq!(
+///     filters,
+///     [
+///         BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///         BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x800, 0, 3),
+///         BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+///         BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 6, 0, 1),
+///         BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///         BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+///     ]
+/// );
+/// ```
+pub fn assemble(source: &str) -> Result<Vec<BPFFilter>, AssembleError> {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut pending: Vec<Pending> = Vec::new();
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        if text.trim_start().starts_with('#') {
+            // preprocessor leftovers
+            continue;
+        }
+        let mut text = text.split(';').next().unwrap_or_default().trim();
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if is_label(label) {
+                if labels.insert(label.to_string(), pending.len()).is_some() {
+                    return error(line, format!("label {} is defined twice", label));
+                }
+                text = rest.trim();
+            }
+        }
+        if !text.is_empty() {
+            pending.push(parse_instruction(line, text)?);
+        }
+    }
+
+    let mut filters = Vec::with_capacity(pending.len());
+    for (index, insn) in pending.iter().enumerate() {
+        let offset = |target: &Option<String>| -> Result<u32, AssembleError> {
+            let name = match target {
+                Some(name) => name,
+                None => return Ok(0),
+            };
+            match labels.get(name) {
+                Some(&target) if target > index => Ok((target - index - 1) as u32),
+                Some(_) => error(insn.line, format!("label {} is not after the jump", name)),
+                None => error(insn.line, format!("unknown label {}", name)),
+            }
+        };
+        let (jt, jf) = (offset(&insn.jt)?, offset(&insn.jf)?);
+        filters.push(if insn.code == 0x05 {
+            BPFFilter::from((insn.code, 0, 0, jt))
+        } else {
+            let short = |offset: u32| {
+                u8::try_from(offset).or(error(
+                    insn.line,
+                    format!(
+                        "the jump target is {} instructions away, more than 255",
+                        offset
+                    ),
+                ))
+            };
+            BPFFilter::from((insn.code, short(jt)?, short(jf)?, insn.k))
+        });
+    }
+    Ok(filters)
+}
+
+#[test]
+fn test_assemble() {
+    use crate::bpf_base::bpf;
+
+    let source = "
+        ldxb 4*([14]&0xf)
+        ld [x + 16]
+        ld #rxhash
+        st M[3]
+        ldx M[3]
+        ldx #len
+        lsh #2
+        xor %x
+        jset x, next
+    next:
+        jmp end
+        neg ; unreachable
+    end: ret a
+    ";
+    let filters = assemble(source).unwrap();
+    assert_eq!(
+        filters,
+        [
+            BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::IND, 16),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF + 32),
+            BPFFilter::bpf_stmt(bpf::ST, 3),
+            BPFFilter::bpf_stmt(bpf::LDX | bpf::MEM, 3),
+            BPFFilter::bpf_stmt(bpf::LDX | bpf::W | bpf::LEN, 0),
+            BPFFilter::bpf_stmt(bpf::ALU | bpf::LSH | bpf::K, 2),
+            BPFFilter::from((0xac, 0, 0, 0)),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JSET | bpf::X, 0, 0, 0),
+            BPFFilter::bpf_stmt(bpf::JMP | bpf::JA, 1),
+            BPFFilter::bpf_stmt(bpf::ALU | bpf::NEG, 0),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+        ]
+    );
+
+    let failure = |source: &str| assemble(source).unwrap_err().to_string();
+    assert_eq!(
+        failure("ret #0\nfoo [1]"),
+        "line 2: unknown instruction foo"
+    );
+    assert_eq!(failure("ldh #1"), "line 1: invalid operands for ldh: #1");
+    assert_eq!(failure("tax x"), "line 1: invalid operands for tax: x");
+    assert_eq!(
+        failure("l: ret #0\njeq #1, l"),
+        "line 2: label l is not after the jump"
+    );
+    assert_eq!(failure("ja nowhere"), "line 1: unknown label nowhere");
+}
@@ -4,6 +4,10 @@
  * seems it is compatible with MIT
  */
 
+use crate::ancillary::{SKF_AD_OFF, SKF_LL_OFF, SKF_NET_OFF};
+use crate::scoped::AttachedFilter;
+use std::fmt;
+use std::io;
 use std::os::unix::io::AsRawFd;
 
 /// element of a classic BPF program
@@ -24,13 +28,13 @@ use std::os::unix::io::AsRawFd;
 /// // or execute the command after the next (when does not match)
 /// let filter2 = BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1);
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct BPFFilter {
-    code: u16,
-    jt: u8,
-    jf: u8,
-    k: u32,
+    pub(crate) code: u16,
+    pub(crate) jt: u8,
+    pub(crate) jf: u8,
+    pub(crate) k: u32,
 }
 
 impl BPFFilter {
@@ -58,6 +62,218 @@ impl BPFFilter {
             k,
         }
     }
+
+    /// the opcode of the instruction
+    pub fn code(&self) -> u16 {
+        self.code
+    }
+
+    /// the relative offset of the next instruction when the jump is taken
+    pub fn jt(&self) -> u8 {
+        self.jt
+    }
+
+    /// the relative offset of the next instruction when the jump is not taken
+    pub fn jf(&self) -> u8 {
+        self.jf
+    }
+
+    /// the generic field of the instruction
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+}
+
+impl From<(u16, u8, u8, u32)> for BPFFilter {
+    /// an instruction from its raw `(code, jt, jf, k)` fields
+    fn from((code, jt, jf, k): (u16, u8, u8, u32)) -> Self {
+        Self { code, jt, jf, k }
+    }
+}
+
+impl From<BPFFilter> for (u16, u8, u8, u32) {
+    fn from(filter: BPFFilter) -> Self {
+        (filter.code, filter.jt, filter.jf, filter.k)
+    }
+}
+
+/// whether `code` is the opcode of a classic BPF instruction
+pub(crate) fn is_known_opcode(code: u16) -> bool {
+    match code & 0x07 {
+        // LD: W, H or B for ABS and IND, W only for IMM, MEM and LEN
+        0x00 => matches!(
+            code,
+            0x00 | 0x20 | 0x28 | 0x30 | 0x40 | 0x48 | 0x50 | 0x60 | 0x80
+        ),
+        // LDX: IMM, MEM, LEN and B MSH
+        0x01 => matches!(code, 0x01 | 0x61 | 0x81 | 0xb1),
+        0x02 | 0x03 => code <= 0x03,
+        0x04 => match code & 0xf0 {
+            0x80 => code == 0x84,
+            op => op <= 0xa0 && code <= 0xff,
+        },
+        0x05 => match code & 0xf0 {
+            0x00 => code == 0x05,
+            op => op <= 0x40 && code <= 0xff,
+        },
+        0x06 => matches!(code, 0x06 | 0x16),
+        _ => matches!(code, 0x07 | 0x87),
+    }
+}
+
+impl BPFFilter {
+    /// the mnemonic and the operand of the instruction, as libpcap names them
+    ///
+    /// the operand of a JA is its offset, relative to the next instruction.
+    fn image(&self) -> (&'static str, String) {
+        let k = self.k;
+        let src = || {
+            if self.code & 0x08 != 0 {
+                "x".to_string()
+            } else {
+                format!("#{:#x}", k)
+            }
+        };
+        // the Linux loads relative to the network and link-layer headers
+        let abs = || match k {
+            k if (SKF_NET_OFF..SKF_AD_OFF).contains(&k) => format!("[net + {}]", k - SKF_NET_OFF),
+            k if (SKF_LL_OFF..SKF_NET_OFF).contains(&k) => format!("[ll + {}]", k - SKF_LL_OFF),
+            k => format!("[{}]", k),
+        };
+        let unimp = ("unimp", format!("{:#x}", self.code));
+        match self.code {
+            0x00 => ("ld", format!("#{:#x}", k)),
+            0x20 => ("ld", abs()),
+            0x28 => ("ldh", abs()),
+            0x30 => ("ldb", abs()),
+            0x40 => ("ld", format!("[x + {}]", k)),
+            0x48 => ("ldh", format!("[x + {}]", k)),
+            0x50 => ("ldb", format!("[x + {}]", k)),
+            0x60 => ("ld", format!("M[{}]", k)),
+            0x80 => ("ld", "#pktlen".to_string()),
+            0x01 => ("ldx", format!("#{:#x}", k)),
+            0x61 => ("ldx", format!("M[{}]", k)),
+            0x81 => ("ldx", "#pktlen".to_string()),
+            0xb1 => ("ldxb", format!("4*([{}]&0xf)", k)),
+            0x02 => ("st", format!("M[{}]", k)),
+            0x03 => ("stx", format!("M[{}]", k)),
+            0x84 => ("neg", String::new()),
+            code if code & 0x07 == 0x04 && code <= 0xff => {
+                let op = match code & 0xf0 {
+                    0x00 => "add",
+                    0x10 => "sub",
+                    0x20 => "mul",
+                    0x30 => "div",
+                    0x40 => "or",
+                    0x50 => "and",
+                    0x60 => "lsh",
+                    0x70 => "rsh",
+                    0x90 => "mod",
+                    0xa0 => "xor",
+                    _ => return unimp,
+                };
+                (op, src())
+            }
+            0x05 => ("ja", k.to_string()),
+            code if code & 0x07 == 0x05 && code <= 0xff => {
+                let op = match code & 0xf0 {
+                    0x10 => "jeq",
+                    0x20 => "jgt",
+                    0x30 => "jge",
+                    0x40 => "jset",
+                    _ => return unimp,
+                };
+                (op, src())
+            }
+            0x06 => ("ret", format!("#{}", k)),
+            0x16 => ("ret", "a".to_string()),
+            0x07 => ("tax", String::new()),
+            0x87 => ("txa", String::new()),
+            _ => unimp,
+        }
+    }
+
+    fn is_conditional_jump(&self) -> bool {
+        self.code & 0x07 == 0x05 && self.code != 0x05
+    }
+}
+
+/// the mnemonic form of the instruction, as printed by `tcpdump -d`
+///
+/// the jump offsets are relative to the next instruction, the program is not
+/// known here. see [`disassemble`] for the listing of a whole program.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filter = BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x3a, 0, 1);
+/// assert_eq!(filter.to_string(), "jeq #0x3a jt 0 jf 1");
+/// ```
+impl fmt::Display for BPFFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (op, operand) = self.image();
+        if self.code == 0x05 {
+            write!(f, "ja +{}", operand)
+        } else if self.is_conditional_jump() {
+            write!(f, "{} {} jt {} jf {}", op, operand, self.jt, self.jf)
+        } else if operand.is_empty() {
+            write!(f, "{}", op)
+        } else {
+            write!(f, "{} {}", op, operand)
+        }
+    }
+}
+
+/// the listing of a program, line for line as printed by `tcpdump -d`
+///
+/// each line holds the index of the instruction and, for jumps, the absolute
+/// index of their targets, so listings can be diffed against tcpdump.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 262144),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+///
+/// assert_eq!(
+///     disassemble(&filters),
+///     "(000) ldh      [12]\n\
+///      (001) jeq      #0x86dd          jt 2\tjf 3\n\
+///      (002) ret      #262144\n\
+///      (003) ret      #0\n"
+/// );
+/// ```
+pub fn disassemble(filters: &[BPFFilter]) -> String {
+    let mut listing = String::new();
+    for (n, filter) in filters.iter().enumerate() {
+        let (op, mut operand) = filter.image();
+        if filter.code == 0x05 {
+            operand = (n as u64 + 1 + filter.k as u64).to_string();
+        }
+        let line = if filter.is_conditional_jump() {
+            format!(
+                "({:03}) {:<8} {:<16} jt {}\tjf {}",
+                n,
+                op,
+                operand,
+                n + 1 + filter.jt as usize,
+                n + 1 + filter.jf as usize
+            )
+        } else {
+            format!("({:03}) {:<8} {}", n, op, operand)
+        };
+        listing.push_str(&line);
+        listing.push('\n');
+    }
+    listing
 }
 
 /// represents a classic BPF program
@@ -67,7 +283,7 @@ impl BPFFilter {
 /// ```
 /// use classic_bpf::*;
 ///
-/// // filter the ICMPv6 packets
+/// // filter the ICMPv6 packets, filters::icmpv6_types matches their types too
 /// let filters = [
 ///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
 ///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1),
@@ -95,14 +311,154 @@ impl<'a> BPFFProg<'a> {
             filters: unsafe { &*(filters.as_ptr()) },
         }
     }
+
+    /// the instructions of the program
+    pub fn filters(&self) -> &'a [BPFFilter] {
+        unsafe { std::slice::from_raw_parts(self.filters, self.len as usize) }
+    }
+}
+
+impl<'a> From<&'a [BPFFilter]> for BPFFProg<'a> {
+    fn from(filters: &'a [BPFFilter]) -> Self {
+        Self::new(filters)
+    }
+}
+
+impl<'a> From<&'a BPFProgram> for BPFFProg<'a> {
+    fn from(program: &'a BPFProgram) -> Self {
+        Self::new(&program.filters)
+    }
+}
+
+/// an owned classic BPF program
+///
+/// unlike [`BPFFProg`], it can be returned from the function building it and
+/// stored in structs. it dereferences to its instructions.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// fn icmpv6_only() -> BPFProgram {
+///     BPFProgram::new(vec![
+///         BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
+///         BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1),
+///         BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///         BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+///     ])
+/// }
+///
+/// let program = icmpv6_only();
+/// assert_eq!(program.len(), 4);
+///
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// (&program).attach_filter(&socket).unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BPFProgram {
+    filters: Vec<BPFFilter>,
+}
+
+impl BPFProgram {
+    pub fn new(filters: Vec<BPFFilter>) -> Self {
+        Self { filters }
+    }
+
+    /// the borrowed form of the program, as given to the kernel
+    pub fn as_prog(&self) -> BPFFProg<'_> {
+        BPFFProg::new(&self.filters)
+    }
+
+    /// the instructions of the program
+    pub fn filters(&self) -> &[BPFFilter] {
+        &self.filters
+    }
+
+    pub fn into_filters(self) -> Vec<BPFFilter> {
+        self.filters
+    }
+}
+
+impl std::ops::Deref for BPFProgram {
+    type Target = [BPFFilter];
+
+    fn deref(&self) -> &[BPFFilter] {
+        &self.filters
+    }
+}
+
+impl From<Vec<BPFFilter>> for BPFProgram {
+    fn from(filters: Vec<BPFFilter>) -> Self {
+        Self::new(filters)
+    }
+}
+
+impl From<&[BPFFilter]> for BPFProgram {
+    fn from(filters: &[BPFFilter]) -> Self {
+        Self::new(filters.to_vec())
+    }
+}
+
+impl From<BPFFProg<'_>> for BPFProgram {
+    fn from(prog: BPFFProg<'_>) -> Self {
+        prog.filters().into()
+    }
+}
+
+impl From<BPFProgram> for Vec<BPFFilter> {
+    fn from(program: BPFProgram) -> Self {
+        program.filters
+    }
+}
+
+impl std::iter::FromIterator<BPFFilter> for BPFProgram {
+    fn from_iter<I: IntoIterator<Item = BPFFilter>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl BPFOperations for BPFProgram {
+    fn attach_filter<T>(self, socket: &T) -> io::Result<()>
+    where
+        T: AsRawFd,
+    {
+        (&self).attach_filter(socket)
+    }
+}
+
+impl BPFOperations for &BPFProgram {
+    fn attach_filter<T>(self, socket: &T) -> io::Result<()>
+    where
+        T: AsRawFd,
+    {
+        self.as_prog().attach_filter(socket)
+    }
 }
 
 /// safe wrapper for some operations related to BPFProg
 pub trait BPFOperations {
     /// attach the classic BPF program to a socket
-    fn attach_filter<T>(self, socket: &T) -> Result<(), i32>
+    fn attach_filter<T>(self, socket: &T) -> io::Result<()>
     where
         T: AsRawFd;
+
+    /// attach the classic BPF program until the returned guard is dropped
+    fn attach_scoped<T>(self, socket: &T) -> io::Result<AttachedFilter<'_, T>>
+    where
+        Self: Sized,
+        T: AsRawFd,
+    {
+        self.attach_filter(socket)?;
+        Ok(AttachedFilter::new(socket))
+    }
+}
+
+/// errno of the last failed libc call
+pub(crate) fn errno() -> i32 {
+    std::io::Error::last_os_error()
+        .raw_os_error()
+        .unwrap_or(libc::EIO)
 }
 
 pub trait BPFCode {
@@ -135,41 +491,41 @@ pub mod bpf {
         };
     }
 
-    pub struct BPFLd(u16);
+    pub struct BPFLd(pub(crate) u16);
     add_inst!(BPFLd);
     add_op!(BPFLd, BPFSize);
     add_op!(BPFLd, BPFMode);
 
-    pub struct BPFSt(u16);
+    pub struct BPFSt(pub(crate) u16);
     add_inst!(BPFSt);
 
-    pub struct BPFAlu(u16);
+    pub struct BPFAlu(pub(crate) u16);
     add_inst!(BPFAlu);
     add_op!(BPFAlu, BPFOp);
     add_op!(BPFAlu, BPFSrc);
 
-    pub struct BPFJmp(u16);
+    pub struct BPFJmp(pub(crate) u16);
     add_inst!(BPFJmp);
     add_op!(BPFJmp, BPFJmpOp);
     add_op!(BPFJmp, BPFSrc);
 
-    pub struct BPFRet(u16);
+    pub struct BPFRet(pub(crate) u16);
     add_inst!(BPFRet);
     add_op!(BPFRet, BPFSrc);
     add_op!(BPFRet, BPFRetSrc);
 
-    pub struct BPFMisc(u16);
+    pub struct BPFMisc(pub(crate) u16);
     add_inst!(BPFMisc);
     add_op!(BPFMisc, BPFMiscOp);
 
-    pub struct BPFSize(u16);
-    pub struct BPFMode(u16);
+    pub struct BPFSize(pub(crate) u16);
+    pub struct BPFMode(pub(crate) u16);
 
-    pub struct BPFOp(u16);
-    pub struct BPFJmpOp(u16);
-    pub struct BPFSrc(u16);
-    pub struct BPFRetSrc(u16);
-    pub struct BPFMiscOp(u16);
+    pub struct BPFOp(pub(crate) u16);
+    pub struct BPFJmpOp(pub(crate) u16);
+    pub struct BPFSrc(pub(crate) u16);
+    pub struct BPFRetSrc(pub(crate) u16);
+    pub struct BPFMiscOp(pub(crate) u16);
 
     pub const LD: BPFLd = BPFLd(0x00);
     pub const LDX: BPFLd = BPFLd(0x01);
@@ -199,6 +555,10 @@ pub mod bpf {
     pub const LSH: BPFOp = BPFOp(0x60);
     pub const RSH: BPFOp = BPFOp(0x70);
     pub const NEG: BPFOp = BPFOp(0x80);
+    /// Linux extension, A = A % k
+    pub const MOD: BPFOp = BPFOp(0x90);
+    /// Linux extension, A = A ^ k
+    pub const XOR: BPFOp = BPFOp(0xa0);
 
     pub const JA: BPFJmpOp = BPFJmpOp(0x00);
     pub const JEQ: BPFJmpOp = BPFJmpOp(0x10);
@@ -208,10 +568,13 @@ pub mod bpf {
 
     pub const K: BPFSrc = BPFSrc(0x00);
     pub const X: BPFSrc = BPFSrc(0x08);
-    pub const A: BPFRetSrc = BPFRetSrc(0x08);
+    pub const A: BPFRetSrc = BPFRetSrc(0x10);
 
     pub const TAX: BPFMiscOp = BPFMiscOp(0x00);
     pub const TXA: BPFMiscOp = BPFMiscOp(0x80);
+
+    /// the longest program the kernels accept
+    pub const MAXINSNS: usize = 4096;
 }
 
 #[test]
@@ -248,3 +611,84 @@ fn test_bpf_jump() {
     assert_eq!(item.jf, reference.jf);
     assert_eq!(item.k, reference.k);
 }
+
+#[test]
+fn test_display() {
+    let lines: Vec<String> = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::IND, 16),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::AND | bpf::K, 0x1fff),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::SUB | bpf::X, 0),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JGT | bpf::X, 0, 2, 0),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::X, 0, 1, 0),
+        BPFFilter::bpf_stmt(bpf::MISC | bpf::TAX, 0),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 262144),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ]
+    .iter()
+    .map(|filter| filter.to_string())
+    .collect();
+    assert_eq!(
+        lines,
+        [
+            "ldh [12]",
+            "ldxb 4*([14]&0xf)",
+            "ldh [x + 16]",
+            "and #0x1fff",
+            "sub x",
+            "jgt x jt 2 jf 0",
+            "jeq x jt 1 jf 0",
+            "tax",
+            "ret #262144",
+            "ret a",
+        ]
+    );
+}
+
+#[test]
+fn test_program_conversions() {
+    let filters = vec![
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    let program = BPFProgram::from(filters.clone());
+    let prog = BPFFProg::from(&program);
+    assert_eq!(prog.filters(), &filters[..]);
+    assert_eq!(BPFProgram::from(prog), program);
+    assert_eq!(filters.iter().copied().collect::<BPFProgram>(), program);
+    assert_eq!(Vec::from(program), filters);
+}
+
+#[test]
+fn test_disassemble() {
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::LEN, 0),
+        BPFFilter::bpf_stmt(bpf::ST, 1),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::NEG, 0),
+        BPFFilter::bpf_stmt(bpf::JMP | bpf::JA, 1),
+        BPFFilter::bpf_stmt(bpf::MISC | bpf::TXA, 0),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JSET | bpf::X, 0, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+        BPFFilter::from((0xff, 0, 0, 0)),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, SKF_NET_OFF + 9),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, SKF_LL_OFF + 12),
+    ];
+    let listing = disassemble(&filters);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "(000) ld       #pktlen",
+            "(001) st       M[1]",
+            "(002) neg      ",
+            "(003) ja       5",
+            "(004) txa      ",
+            "(005) jset     x                jt 6\tjf 7",
+            "(006) ret      a",
+            "(007) unimp    0xff",
+            "(008) ldb      [net + 9]",
+            "(009) ldh      [ll + 12]",
+        ]
+    );
+}
@@ -0,0 +1,682 @@
+use crate::bpf_base::*;
+use crate::bpf_records::BpfPackets;
+#[cfg(target_os = "freebsd")]
+use crate::bpf_records::RecordFormat;
+use crate::buffer::AlignedBuf;
+use crate::dlt::Dlt;
+use crate::monitor::{DropEvent, DropMonitor};
+use crate::replay::Injector;
+use std::convert::TryFrom;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(target_os = "freebsd")]
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// the capture counters of a BPF device, see [`BpfDevice::stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BpfStats {
+    /// the packets the filter saw
+    pub received: u64,
+    /// the packets accepted by the filter but dropped for lack of buffer space
+    pub dropped: u64,
+}
+
+/// `struct bpf_stat`
+#[repr(C)]
+#[derive(Default)]
+struct BpfStat {
+    bs_recv: libc::c_uint,
+    bs_drop: libc::c_uint,
+}
+
+/// `struct bpf_dltlist`
+#[repr(C)]
+struct DltList {
+    len: libc::c_uint,
+    list: *mut libc::c_uint,
+}
+
+#[cfg(target_os = "freebsd")]
+const BIOCGDLTLIST: libc::c_ulong = libc::BIOCGDLTLIST;
+#[cfg(all(target_os = "macos", target_pointer_width = "64"))]
+const BIOCGDLTLIST: libc::c_ulong = 0xc010_4279; // _IOWR('B', 121, struct bpf_dltlist)
+#[cfg(all(target_os = "macos", target_pointer_width = "32"))]
+const BIOCGDLTLIST: libc::c_ulong = 0xc008_4279;
+
+/// the packets a BPF device captures, by the way they cross the interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// the packets received
+    In,
+    /// the packets sent, FreeBSD only
+    Out,
+    /// both, what a fresh device captures
+    InOut,
+}
+
+// _IOW('B', 119, u_int) and _IOR('B', 118, u_int), BIOC[SG]SEESENT on macOS
+const BIOCSDIRECTION: libc::c_ulong = 0x8004_4277;
+const BIOCGDIRECTION: libc::c_ulong = 0x4004_4276;
+
+#[cfg(target_os = "freebsd")]
+const BPF_D_IN: libc::c_uint = 0;
+#[cfg(target_os = "freebsd")]
+const BPF_D_INOUT: libc::c_uint = 1;
+#[cfg(target_os = "freebsd")]
+const BPF_D_OUT: libc::c_uint = 2;
+
+#[cfg(all(target_os = "freebsd", target_pointer_width = "64"))]
+const BIOCSETWF: libc::c_ulong = 0x8010_427b; // _IOW('B', 123, struct bpf_program)
+#[cfg(all(target_os = "freebsd", target_pointer_width = "32"))]
+const BIOCSETWF: libc::c_ulong = 0x8008_427b;
+
+#[cfg(target_os = "freebsd")]
+const BIOCLOCK: libc::c_ulong = 0x2000_427a; // _IO('B', 122)
+
+#[cfg(target_os = "freebsd")]
+const BIOCSETBUFMODE: libc::c_ulong = 0x8004_427e; // _IOW('B', 126, u_int)
+#[cfg(target_os = "freebsd")]
+const BPF_BUFMODE_ZBUF: libc::c_uint = 2;
+// _IOR('B', 128, struct bpf_zbuf) and _IOW('B', 129, struct bpf_zbuf)
+#[cfg(all(target_os = "freebsd", target_pointer_width = "64"))]
+const BIOCROTZBUF: libc::c_ulong = 0x4018_4280;
+#[cfg(all(target_os = "freebsd", target_pointer_width = "64"))]
+const BIOCSETZBUF: libc::c_ulong = 0x8018_4281;
+#[cfg(all(target_os = "freebsd", target_pointer_width = "32"))]
+const BIOCROTZBUF: libc::c_ulong = 0x400c_4280;
+#[cfg(all(target_os = "freebsd", target_pointer_width = "32"))]
+const BIOCSETZBUF: libc::c_ulong = 0x800c_4281;
+
+/// `struct bpf_zbuf`
+#[cfg(target_os = "freebsd")]
+#[repr(C)]
+struct ZBuf {
+    bufa: *mut libc::c_void,
+    bufb: *mut libc::c_void,
+    buflen: libc::size_t,
+}
+
+/// the length of the `struct bpf_zbuf_header` leading each shared buffer
+#[cfg(target_os = "freebsd")]
+const ZBUF_HEADER_LEN: usize = 32;
+
+/// an open BPF device, owning its descriptor
+///
+/// the device captures nothing until it is bound to an interface. programs
+/// are attached to it with `BPFOperations::attach_filter`.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// let device = BpfDevice::open().unwrap();
+/// device.bind_interface("em0").unwrap();
+/// BPFFProg::new(presets::arp_only()).attach_filter(&device).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct BpfDevice {
+    fd: OwnedFd,
+}
+
+impl BpfDevice {
+    /// open a free BPF device for reading and writing
+    ///
+    /// the cloning `/dev/bpf` of FreeBSD is tried first, then `/dev/bpf0`,
+    /// `/dev/bpf1`... skipping the devices already in use.
+    pub fn open() -> io::Result<Self> {
+        let mut busy = None;
+        let numbered = (0..256).map(|n| format!("/dev/bpf{}", n));
+        for path in std::iter::once("/dev/bpf".to_string()).chain(numbered) {
+            match OpenOptions::new().read(true).write(true).open(&path) {
+                Ok(file) => return Ok(Self::from(OwnedFd::from(file))),
+                Err(e) if e.raw_os_error() == Some(libc::EBUSY) => busy = Some(e),
+                // the numbered devices stop at the first missing one
+                Err(e) if e.kind() == io::ErrorKind::NotFound && path != "/dev/bpf" => break,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(busy.unwrap_or_else(|| io::Error::from_raw_os_error(libc::ENOENT)))
+    }
+
+    /// the length of the buffers of the device, what each read must ask (BIOCGBLEN)
+    pub fn buffer_len(&self) -> io::Result<usize> {
+        let mut len: libc::c_uint = 0;
+        self.ioctl(libc::BIOCGBLEN, &mut len)?;
+        Ok(len as usize)
+    }
+
+    /// ask for buffers of `len` bytes, returning the length the kernel chose (BIOCSBLEN)
+    ///
+    /// only allowed before the device is bound to an interface. the kernel
+    /// caps the length to its `net.bpf.maxbufsize`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use classic_bpf::*;
+    ///
+    /// let device = BpfDevice::open().unwrap();
+    /// device.set_buffer_len(1 << 20).unwrap();
+    /// device.bind_interface("em0").unwrap();
+    /// let mut buf = device.read_buffer().unwrap();
+    /// let packets = device.read_packets(&mut buf).unwrap();
+    /// ```
+    pub fn set_buffer_len(&self, len: usize) -> io::Result<usize> {
+        let mut len = len.min(libc::c_uint::MAX as usize) as libc::c_uint;
+        self.ioctl(libc::BIOCSBLEN, &mut len)?;
+        Ok(len as usize)
+    }
+
+    /// allocate a buffer for `read_packets`, as long as the buffers of the device
+    pub fn read_buffer(&self) -> io::Result<AlignedBuf> {
+        Ok(AlignedBuf::new(self.buffer_len()?))
+    }
+
+    /// bind the device to the interface `name`, e.g. "em0" (BIOCSETIF)
+    ///
+    /// the device then starts capturing, set its buffer length with
+    /// `set_buffer_len` first.
+    pub fn bind_interface(&self, name: &str) -> io::Result<()> {
+        let mut ifreq: libc::ifreq = unsafe { std::mem::zeroed() };
+        // the name must leave room for its NUL terminator
+        if name.len() >= ifreq.ifr_name.len() || name.contains('\0') {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+        for (dst, src) in ifreq.ifr_name.iter_mut().zip(name.bytes()) {
+            *dst = src as libc::c_char;
+        }
+        self.ioctl(libc::BIOCSETIF, &mut ifreq)
+    }
+
+    /// read the packets the device holds, blocking until there are some
+    ///
+    /// the device only accepts reads of exactly its buffer length, `buf`
+    /// must be that long, see `read_buffer`. the records are decoded in place.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use classic_bpf::*;
+    ///
+    /// let device = BpfDevice::open().unwrap();
+    /// device.bind_interface("em0").unwrap();
+    /// let mut buf = device.read_buffer().unwrap();
+    /// for packet in device.read_packets(&mut buf).unwrap() {
+    ///     println!("{:?}: {} of {} bytes", packet.timestamp, packet.caplen, packet.datalen);
+    /// }
+    /// ```
+    pub fn read_packets<'a>(&self, buf: &'a mut AlignedBuf) -> io::Result<BpfPackets<'a>> {
+        match unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        } {
+            -1 => Err(io::Error::last_os_error()),
+            n => Ok(self.packets(&buf[..n as usize])),
+        }
+    }
+
+    /// the packets received and dropped since the device was bound (BIOCGSTATS)
+    ///
+    /// the counters only grow, see `report_drops`. drops mean the buffer is
+    /// too small for the traffic.
+    pub fn stats(&self) -> io::Result<BpfStats> {
+        let mut stat = BpfStat::default();
+        self.ioctl(libc::BIOCGSTATS, &mut stat)?;
+        Ok(BpfStats {
+            received: stat.bs_recv as u64,
+            dropped: stat.bs_drop as u64,
+        })
+    }
+
+    /// feed `monitor` the packets the device dropped since the previous call
+    ///
+    /// the monitor keeps the last value of the counter of `stats`, give each
+    /// device its own monitor.
+    pub fn report_drops<F>(&self, monitor: &mut DropMonitor<F>) -> io::Result<()>
+    where
+        F: FnMut(DropEvent),
+    {
+        monitor.update(self.stats()?.dropped);
+        Ok(())
+    }
+
+    /// put the bound interface in promiscuous mode (BIOCPROMISC)
+    ///
+    /// the device then sees the traffic not addressed to the host. there is
+    /// no request to leave the mode, the interface leaves it once the device
+    /// is closed.
+    pub fn set_promiscuous(&self) -> io::Result<()> {
+        match unsafe { libc::ioctl(self.fd.as_raw_fd(), libc::BIOCPROMISC as libc::c_ulong) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// return reads as soon as a packet arrives rather than once the buffer
+    /// fills or the read timeout expires (BIOCIMMEDIATE)
+    pub fn set_immediate(&self, immediate: bool) -> io::Result<()> {
+        let mut value = immediate as libc::c_uint;
+        self.ioctl(libc::BIOCIMMEDIATE, &mut value)
+    }
+
+    /// bound how long a read waits for the buffer to fill (BIOCSRTIMEOUT)
+    ///
+    /// `None` waits forever, as a fresh device does. a read returning on the
+    /// timeout yields the packets captured so far, maybe none.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.unwrap_or_default();
+        let mut tv = libc::timeval {
+            tv_sec: timeout.as_secs() as libc::time_t,
+            tv_usec: timeout.subsec_micros() as libc::suseconds_t,
+        };
+        self.ioctl(libc::BIOCSRTIMEOUT, &mut tv)
+    }
+
+    /// the read timeout, `None` when reads wait forever (BIOCGRTIMEOUT)
+    pub fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        let mut tv: libc::timeval = unsafe { std::mem::zeroed() };
+        self.ioctl(libc::BIOCGRTIMEOUT, &mut tv)?;
+        let timeout = Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+        Ok(Some(timeout).filter(|t| !t.is_zero()))
+    }
+
+    /// the link type of the frames read, `Err` with the number of a
+    /// `DLT_*` that `Dlt` does not name (BIOCGDLT)
+    ///
+    /// the device must be bound to an interface.
+    pub fn link_type(&self) -> io::Result<Result<Dlt, u32>> {
+        let mut dlt: libc::c_uint = 0;
+        self.ioctl(libc::BIOCGDLT, &mut dlt)?;
+        Ok(Dlt::try_from(dlt as u32))
+    }
+
+    /// switch the link type of the frames read to one of `link_types` (BIOCSDLT)
+    ///
+    /// the offsets of the programs depend on it, attach them afterwards.
+    pub fn set_link_type(&self, dlt: Dlt) -> io::Result<()> {
+        crate::bsd::set_link_type(self, dlt.value())
+    }
+
+    /// the link types the bound interface can provide (BIOCGDLTLIST)
+    pub fn link_types(&self) -> io::Result<Vec<Result<Dlt, u32>>> {
+        // a first request without a list only counts the link types
+        let mut dlts = DltList {
+            len: 0,
+            list: std::ptr::null_mut(),
+        };
+        self.ioctl(BIOCGDLTLIST, &mut dlts)?;
+        let mut list: Vec<libc::c_uint> = vec![0; dlts.len as usize];
+        dlts.list = list.as_mut_ptr();
+        self.ioctl(BIOCGDLTLIST, &mut dlts)?;
+        list.truncate(dlts.len as usize);
+        Ok(list
+            .into_iter()
+            .map(|dlt| Dlt::try_from(dlt as u32))
+            .collect())
+    }
+
+    /// capture the packets going in `direction` only (BIOCSDIRECTION)
+    ///
+    /// macOS only tells whether the packets sent are seen (BIOCSSEESENT),
+    /// `Direction::Out` fails there with EINVAL.
+    pub fn set_direction(&self, direction: Direction) -> io::Result<()> {
+        #[cfg(target_os = "freebsd")]
+        let mut value = match direction {
+            Direction::In => BPF_D_IN,
+            Direction::Out => BPF_D_OUT,
+            Direction::InOut => BPF_D_INOUT,
+        };
+        #[cfg(target_os = "macos")]
+        let mut value: libc::c_uint = match direction {
+            Direction::In => 0,
+            Direction::Out => return Err(io::Error::from_raw_os_error(libc::EINVAL)),
+            Direction::InOut => 1,
+        };
+        self.ioctl(BIOCSDIRECTION, &mut value)
+    }
+
+    /// the direction of the packets captured (BIOCGDIRECTION)
+    pub fn direction(&self) -> io::Result<Direction> {
+        let mut value: libc::c_uint = 0;
+        self.ioctl(BIOCGDIRECTION, &mut value)?;
+        #[cfg(target_os = "freebsd")]
+        let direction = match value {
+            BPF_D_IN => Direction::In,
+            BPF_D_OUT => Direction::Out,
+            _ => Direction::InOut,
+        };
+        #[cfg(target_os = "macos")]
+        let direction = match value {
+            0 => Direction::In,
+            _ => Direction::InOut,
+        };
+        Ok(direction)
+    }
+
+    /// send `frame` out of the bound interface, link-layer header included
+    ///
+    /// the kernel fills in the source address of the header, unless
+    /// `set_header_complete` was enabled. a frame the device only partly
+    /// writes fails with EMSGSIZE. the device is also an [`Injector`] for
+    /// `replay`.
+    pub fn inject(&self, frame: &[u8]) -> io::Result<()> {
+        Injector::inject(self, frame)
+    }
+
+    /// keep the link-layer source address of the injected frames as is (BIOCSHDRCMPLT)
+    pub fn set_header_complete(&self, complete: bool) -> io::Result<()> {
+        let mut value = complete as libc::c_uint;
+        self.ioctl(libc::BIOCSHDRCMPLT, &mut value)
+    }
+
+    /// replace the program without flushing the packets already buffered (BIOCSETFNR)
+    ///
+    /// `BPFOperations::attach_filter` (BIOCSETF) discards them, losing what
+    /// a long-running capture queued before the update.
+    pub fn replace_filter_no_flush(&self, mut program: BPFFProg<'_>) -> io::Result<()> {
+        self.ioctl(libc::BIOCSETFNR, &mut program)
+    }
+
+    /// run `program` over the frames injected, refusing the writes it drops (BIOCSETWF)
+    ///
+    /// keeps a sandboxed injector from sending anything else.
+    #[cfg(target_os = "freebsd")]
+    pub fn attach_write_filter(&self, mut program: BPFFProg<'_>) -> io::Result<()> {
+        self.ioctl(BIOCSETWF, &mut program)
+    }
+
+    /// freeze the configuration of the device (BIOCLOCK)
+    ///
+    /// the interface, filters and flags can no longer be changed, even by
+    /// root. a daemon locks the device before dropping its privileges, so
+    /// a compromise cannot widen what it captures or sends.
+    #[cfg(target_os = "freebsd")]
+    pub fn lock(&self) -> io::Result<()> {
+        match unsafe { libc::ioctl(self.fd.as_raw_fd(), BIOCLOCK) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+
+    /// the records of `buf`, in the format the device writes them
+    ///
+    /// FreeBSD switches to `bpf_xhdr` on the stamps set by `set_timestamping`.
+    fn packets<'a>(&self, buf: &'a [u8]) -> BpfPackets<'a> {
+        #[cfg(target_os = "freebsd")]
+        if let Ok(format) = crate::bsd::timestamp_format(self) {
+            return BpfPackets::with_format(buf, RecordFormat::from_timestamps(format));
+        }
+        BpfPackets::new(buf)
+    }
+
+    /// make reads fail with `io::ErrorKind::WouldBlock` rather than wait (FIONBIO)
+    ///
+    /// for event loops, which wait for the device through `kevent`.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        let mut value = nonblocking as libc::c_int;
+        self.ioctl(libc::FIONBIO, &mut value)
+    }
+
+    /// the change registering the device in a kqueue, readable once it holds packets
+    ///
+    /// the event fires by the same rules as a read returns: once the buffer
+    /// fills, the read timeout expires or, in immediate mode, a packet arrives.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use classic_bpf::*;
+    ///
+    /// let device = BpfDevice::open().unwrap();
+    /// device.set_nonblocking(true).unwrap();
+    /// let change = device.kevent(std::ptr::null_mut());
+    /// // kevent(kq, &change, 1, ...) along the other descriptors of the loop
+    /// ```
+    pub fn kevent(&self, udata: *mut libc::c_void) -> libc::kevent {
+        let mut change: libc::kevent = unsafe { std::mem::zeroed() };
+        change.ident = self.as_raw_fd() as libc::uintptr_t;
+        change.filter = libc::EVFILT_READ;
+        change.flags = libc::EV_ADD;
+        change.udata = udata as _;
+        change
+    }
+
+    /// wait for the device to hold packets, `Ok(false)` once `timeout` expired
+    pub fn wait_readable(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let kq = unsafe { libc::kqueue() };
+        if kq < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let kq = unsafe { OwnedFd::from_raw_fd(kq) };
+        let change = self.kevent(std::ptr::null_mut());
+        let ts = timeout.map(|t| libc::timespec {
+            tv_sec: t.as_secs() as libc::time_t,
+            tv_nsec: t.subsec_nanos() as _,
+        });
+        let ts_ptr = ts
+            .as_ref()
+            .map_or(std::ptr::null(), |ts| ts as *const libc::timespec);
+        let mut event: libc::kevent = unsafe { std::mem::zeroed() };
+        match unsafe { libc::kevent(kq.as_raw_fd(), &change, 1, &mut event, 1, ts_ptr) } {
+            -1 => Err(io::Error::last_os_error()),
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// issue a BIOC* request whose argument is `arg`
+    fn ioctl<A>(&self, request: libc::c_ulong, arg: &mut A) -> io::Result<()> {
+        match unsafe { libc::ioctl(self.fd.as_raw_fd(), request, arg as *mut A) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl AsRawFd for BpfDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl AsFd for BpfDevice {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl From<OwnedFd> for BpfDevice {
+    fn from(fd: OwnedFd) -> Self {
+        Self { fd }
+    }
+}
+
+impl From<BpfDevice> for OwnedFd {
+    fn from(device: BpfDevice) -> Self {
+        device.fd
+    }
+}
+
+/// a BPF device sharing its two buffers with the kernel, without copies
+///
+/// see [`BpfDevice::into_zero_copy`]. the kernel fills one buffer while the
+/// other is handed out by `next_buffer`, until it is released. the device
+/// is still configured through `Deref`.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// let mut device = BpfDevice::open().unwrap().into_zero_copy(1 << 20).unwrap();
+/// device.bind_interface("ix0").unwrap();
+/// loop {
+///     // wait for the descriptor to be readable, or rotate() on a timeout
+///     while let Some(buffer) = device.next_buffer() {
+///         for packet in buffer.packets() {
+///             println!("{} bytes", packet.caplen);
+///         }
+///     }
+/// }
+/// ```
+#[cfg(target_os = "freebsd")]
+#[derive(Debug)]
+pub struct ZeroCopyDevice {
+    // closed once the buffers are unmapped, the kernel holding the pages
+    // it writes to until then
+    device: BpfDevice,
+    bufs: [*mut u8; 2],
+    len: usize,
+}
+
+#[cfg(target_os = "freebsd")]
+impl BpfDevice {
+    /// switch the device to zero-copy buffers of about `len` bytes each
+    /// (BIOCSETBUFMODE, BIOCSETZBUF)
+    ///
+    /// the length is rounded up to whole pages, and must not exceed
+    /// `net.bpf.zbuf.zbufmax`. the device must not be bound to an interface
+    /// yet.
+    pub fn into_zero_copy(self, len: usize) -> io::Result<ZeroCopyDevice> {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let len = len.max(1).div_ceil(page) * page;
+        let mut mode = BPF_BUFMODE_ZBUF;
+        self.ioctl(BIOCSETBUFMODE, &mut mode)?;
+
+        let mut device = ZeroCopyDevice {
+            device: self,
+            bufs: [std::ptr::null_mut(); 2],
+            len,
+        };
+        for buf in &mut device.bufs {
+            let ptr = unsafe {
+                libc::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_ANON | libc::MAP_PRIVATE,
+                    -1,
+                    0,
+                )
+            };
+            if ptr == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            *buf = ptr as *mut u8;
+        }
+        let mut zbuf = ZBuf {
+            bufa: device.bufs[0] as *mut libc::c_void,
+            bufb: device.bufs[1] as *mut libc::c_void,
+            buflen: len,
+        };
+        device.device.ioctl(BIOCSETZBUF, &mut zbuf)?;
+        Ok(device)
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl ZeroCopyDevice {
+    /// the `index`th word of the header of `buf`, shared with the kernel
+    fn header(&self, buf: *mut u8, index: usize) -> &AtomicU32 {
+        unsafe { &*(buf as *const AtomicU32).add(index) }
+    }
+
+    /// the oldest buffer the kernel handed over, `None` while both are its own
+    ///
+    /// the buffer goes back to the kernel once the returned guard is dropped.
+    pub fn next_buffer(&mut self) -> Option<ZeroCopyBuffer<'_>> {
+        let this = &*self;
+        this.bufs
+            .iter()
+            .filter_map(|&buf| {
+                // bzh_kernel_gen, bzh_kernel_len then bzh_user_gen
+                let generation = this.header(buf, 0).load(Ordering::Acquire);
+                let user_gen = this.header(buf, 2);
+                if generation == user_gen.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let len = this.header(buf, 1).load(Ordering::Relaxed) as usize;
+                let data = unsafe {
+                    std::slice::from_raw_parts(
+                        buf.add(ZBUF_HEADER_LEN),
+                        len.min(this.len - ZBUF_HEADER_LEN),
+                    )
+                };
+                Some(ZeroCopyBuffer {
+                    packets: this.device.packets(data),
+                    user_gen,
+                    generation,
+                })
+            })
+            .min_by_key(|buffer| buffer.generation)
+    }
+
+    /// hand over the buffer the kernel is filling, if it holds packets (BIOCROTZBUF)
+    ///
+    /// a capture waiting on a timeout gets the packets of a slow link this
+    /// way, instead of waiting for the buffer to fill.
+    pub fn rotate(&self) -> io::Result<()> {
+        let mut zbuf = ZBuf {
+            bufa: std::ptr::null_mut(),
+            bufb: std::ptr::null_mut(),
+            buflen: 0,
+        };
+        self.device.ioctl(BIOCROTZBUF, &mut zbuf)
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl std::ops::Deref for ZeroCopyDevice {
+    type Target = BpfDevice;
+
+    fn deref(&self) -> &BpfDevice {
+        &self.device
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl AsRawFd for ZeroCopyDevice {
+    fn as_raw_fd(&self) -> RawFd {
+        self.device.as_raw_fd()
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl Drop for ZeroCopyDevice {
+    fn drop(&mut self) {
+        for &buf in self.bufs.iter().filter(|buf| !buf.is_null()) {
+            unsafe { libc::munmap(buf as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+/// a buffer of a [`ZeroCopyDevice`], given back to the kernel on drop
+#[cfg(target_os = "freebsd")]
+#[derive(Debug)]
+pub struct ZeroCopyBuffer<'a> {
+    packets: BpfPackets<'a>,
+    user_gen: &'a AtomicU32,
+    generation: u32,
+}
+
+#[cfg(target_os = "freebsd")]
+impl ZeroCopyBuffer<'_> {
+    /// the packets of the buffer, decoded in place
+    pub fn packets(&self) -> BpfPackets<'_> {
+        self.packets.clone()
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+impl Drop for ZeroCopyBuffer<'_> {
+    fn drop(&mut self) {
+        self.user_gen.store(self.generation, Ordering::Release);
+    }
+}
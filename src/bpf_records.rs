@@ -0,0 +1,242 @@
+use std::convert::TryInto;
+use std::time::Duration;
+
+/// the fraction of a second stamping a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fraction {
+    Micros,
+    #[cfg(any(target_os = "freebsd", test))]
+    Nanos,
+    /// in units of 2^-64 seconds, BPF_T_BINTIME
+    #[cfg(any(target_os = "freebsd", test))]
+    Binary,
+}
+
+/// the layout of the records in the buffer of a BPF device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct RecordFormat {
+    /// the widths of the seconds and of the fraction leading each record
+    sec: usize,
+    frac: usize,
+    fraction: Fraction,
+    /// `BPF_ALIGNMENT`
+    align: usize,
+}
+
+impl RecordFormat {
+    /// `struct bpf_hdr`, stamped with a `struct timeval`
+    #[cfg(target_os = "freebsd")]
+    pub(crate) const HDR: Self = Self {
+        sec: std::mem::size_of::<libc::time_t>(),
+        frac: std::mem::size_of::<libc::suseconds_t>(),
+        fraction: Fraction::Micros,
+        align: std::mem::size_of::<libc::c_long>(),
+    };
+
+    /// `struct bpf_hdr`, stamped with a `struct timeval32`
+    #[cfg(target_os = "macos")]
+    pub(crate) const HDR: Self = Self {
+        sec: 4,
+        frac: 4,
+        fraction: Fraction::Micros,
+        align: 4,
+    };
+
+    /// the records written under the `BPF_T_*` flags `format` (BIOCGTSTAMP)
+    ///
+    /// stamps other than microseconds lead with `struct bpf_xhdr`.
+    #[cfg(target_os = "freebsd")]
+    pub(crate) fn from_timestamps(format: libc::c_uint) -> Self {
+        let fraction = match format & crate::bsd::BPF_T_FORMAT_MASK {
+            crate::bsd::BPF_T_NANOTIME => Fraction::Nanos,
+            crate::bsd::BPF_T_BINTIME => Fraction::Binary,
+            _ => return Self::HDR,
+        };
+        Self {
+            sec: 8,
+            frac: 8,
+            fraction,
+            align: Self::HDR.align,
+        }
+    }
+
+    /// `BPF_WORDALIGN`
+    fn word_align(&self, len: usize) -> usize {
+        (len + self.align - 1) & !(self.align - 1)
+    }
+
+    /// the timestamp, caplen, datalen and header length of the record of `rest`
+    fn header(&self, rest: &[u8]) -> Option<(Duration, u32, u32, usize)> {
+        let lengths = (self.sec + self.frac).next_multiple_of(self.sec);
+        let sec = field(rest, 0, self.sec)?;
+        let frac = field(rest, self.sec, self.frac)?;
+        let caplen = field(rest, lengths, 4)? as u32;
+        let datalen = field(rest, lengths + 4, 4)? as u32;
+        let hdrlen = field(rest, lengths + 8, 2)? as usize;
+        let nanos = match self.fraction {
+            Fraction::Micros => frac.checked_mul(1000)?,
+            #[cfg(any(target_os = "freebsd", test))]
+            Fraction::Nanos => frac,
+            #[cfg(any(target_os = "freebsd", test))]
+            Fraction::Binary => ((frac >> 32) * 1_000_000_000) >> 32,
+        };
+        let timestamp = Duration::from_secs(sec).checked_add(Duration::from_nanos(nanos))?;
+        Some((timestamp, caplen, datalen, hdrlen))
+    }
+}
+
+/// the native endian integer of `width` bytes at `at` in `rest`
+fn field(rest: &[u8], at: usize, width: usize) -> Option<u64> {
+    let bytes = rest.get(at..at + width)?;
+    match width {
+        2 => Some(u16::from_ne_bytes(bytes.try_into().ok()?) as u64),
+        4 => Some(u32::from_ne_bytes(bytes.try_into().ok()?) as u64),
+        8 => Some(u64::from_ne_bytes(bytes.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// a packet read from a BPF device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfPacket<'a> {
+    /// when the packet was stamped, as a duration since the UNIX epoch
+    pub timestamp: Duration,
+    /// the number of bytes captured
+    pub caplen: u32,
+    /// the length of the packet on the wire
+    pub datalen: u32,
+    /// the captured bytes
+    pub data: &'a [u8],
+}
+
+/// the packets of a buffer read from a BPF device
+///
+/// see [`BpfDevice::read_packets`](crate::BpfDevice::read_packets)
+#[derive(Debug, Clone)]
+pub struct BpfPackets<'a> {
+    buf: &'a [u8],
+    offset: usize,
+    format: RecordFormat,
+}
+
+impl<'a> BpfPackets<'a> {
+    /// the `bpf_hdr` records of a buffer filled by `read(2)`
+    #[cfg(any(target_os = "freebsd", target_os = "macos"))]
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self::with_format(buf, RecordFormat::HDR)
+    }
+
+    /// the `bpf_xhdr` records of a buffer, read with nanosecond stamps
+    #[cfg(target_os = "freebsd")]
+    pub fn new_extended(buf: &'a [u8]) -> Self {
+        Self::with_format(
+            buf,
+            RecordFormat::from_timestamps(crate::bsd::BPF_T_NANOTIME),
+        )
+    }
+
+    /// the records of `buf`, laid out as `format`
+    pub(crate) fn with_format(buf: &'a [u8], format: RecordFormat) -> Self {
+        Self {
+            buf,
+            offset: 0,
+            format,
+        }
+    }
+}
+
+impl<'a> Iterator for BpfPackets<'a> {
+    type Item = BpfPacket<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = self.buf.get(self.offset..)?;
+        let (timestamp, caplen, datalen, start) = self.format.header(rest)?;
+        // a record cut by a short read is dropped with the ones after it
+        let data = rest.get(start..start + caplen as usize)?;
+        self.offset += self.format.word_align(start + data.len());
+        Some(BpfPacket {
+            timestamp,
+            caplen,
+            datalen,
+            data,
+        })
+    }
+}
+
+#[test]
+fn test_bpf_packets() {
+    // a record of `format` stamped 3.5s, its header padded to `hdrlen`
+    fn record(format: &RecordFormat, frac: u64, hdrlen: usize, data: &[u8]) -> Vec<u8> {
+        let mut rec = vec![0; hdrlen];
+        rec[..format.sec].copy_from_slice(&3u64.to_ne_bytes()[..format.sec]);
+        let frac = match format.frac {
+            8 => frac.to_ne_bytes().to_vec(),
+            _ => (frac as u32).to_ne_bytes().to_vec(),
+        };
+        rec[format.sec..format.sec + format.frac].copy_from_slice(&frac[..format.frac]);
+        let lengths = (format.sec + format.frac).next_multiple_of(format.sec);
+        rec[lengths..lengths + 4].copy_from_slice(&(data.len() as u32).to_ne_bytes());
+        rec[lengths + 4..lengths + 8].copy_from_slice(&1500u32.to_ne_bytes());
+        rec[lengths + 8..lengths + 10].copy_from_slice(&(hdrlen as u16).to_ne_bytes());
+        rec.extend_from_slice(data);
+        rec.resize(format.word_align(rec.len()), 0);
+        rec
+    }
+
+    let formats = [
+        // bpf_hdr of 64 bit FreeBSD, of macOS, then bpf_xhdr
+        (
+            RecordFormat {
+                sec: 8,
+                frac: 8,
+                fraction: Fraction::Micros,
+                align: 8,
+            },
+            500_000,
+            32,
+        ),
+        (
+            RecordFormat {
+                sec: 4,
+                frac: 4,
+                fraction: Fraction::Micros,
+                align: 4,
+            },
+            500_000,
+            20,
+        ),
+        (
+            RecordFormat {
+                sec: 8,
+                frac: 8,
+                fraction: Fraction::Nanos,
+                align: 8,
+            },
+            500_000_000,
+            32,
+        ),
+        (
+            RecordFormat {
+                sec: 8,
+                frac: 8,
+                fraction: Fraction::Binary,
+                align: 8,
+            },
+            1 << 63,
+            32,
+        ),
+    ];
+    for (format, frac, hdrlen) in formats.iter() {
+        let mut buf = record(format, *frac, *hdrlen, &[1, 2, 3]);
+        buf.extend(record(format, *frac, *hdrlen, &[4; 9]));
+        // a record cut by a short read
+        buf.extend(&record(format, *frac, *hdrlen, &[5; 4])[..*hdrlen + 2]);
+
+        let packets: Vec<_> = BpfPackets::with_format(&buf, *format).collect();
+        assert_eq!(packets.len(), 2, "{:?}", format);
+        assert_eq!(packets[0].timestamp, Duration::from_millis(3500));
+        assert_eq!((packets[0].caplen, packets[0].datalen), (3, 1500));
+        assert_eq!(packets[0].data, &[1, 2, 3]);
+        assert_eq!(packets[1].data, &[4; 9]);
+    }
+}
@@ -1,8 +1,18 @@
 use crate::bpf_base::*;
-use std::os::unix::io::AsRawFd;
+use crate::dlt::*;
+use crate::interface::LinkDetails;
+use crate::privileges::PrivilegeError;
+#[cfg(target_os = "freebsd")]
+use crate::timestamp::*;
+use std::fs::File;
+use std::io::{self, IoSliceMut};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 impl BPFOperations for BPFFProg<'_> {
-    fn attach_filter<T>(self, socket: &T) -> Result<(), i32>
+    fn attach_filter<T>(self, socket: &T) -> io::Result<()>
     where
         T: AsRawFd,
     {
@@ -14,9 +24,318 @@ impl BPFOperations for BPFFProg<'_> {
             )
         } {
             0 => Ok(()),
-            errno => Err(errno),
+            _ => Err(io::Error::last_os_error()),
         }
     }
 }
 
+/// read from a BPF device into several buffers with a single `readv(2)`
+///
+/// a BPF device only accepts reads of exactly its buffer length (BIOCGBLEN),
+/// so the lengths of `bufs` must add up to it. the store buffer is copied out
+/// contiguously, hence record boundaries do not line up with `bufs`.
+///
+/// returns the number of bytes read
+pub fn read_vectored<T>(device: &T, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize>
+where
+    T: AsRawFd,
+{
+    // IoSliceMut is guaranteed to be ABI compatible with iovec on unix
+    match unsafe {
+        libc::readv(
+            device.as_raw_fd(),
+            bufs.as_mut_ptr() as *mut libc::iovec,
+            bufs.len() as libc::c_int,
+        )
+    } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// the changes to a file, seen through kqueue on the file and its directory
+///
+/// editors often replace the file rather than writing it in place, which
+/// only shows as a write to the directory. the kqueue keeps the events
+/// between two waits.
+#[derive(Debug)]
+pub(crate) struct Watch {
+    kq: OwnedFd,
+    dir: File,
+    path: PathBuf,
+    /// the file currently at `path`, replaced when the directory changes
+    file: Mutex<Option<File>>,
+}
+
+const FILE_EVENTS: u32 =
+    libc::NOTE_WRITE | libc::NOTE_EXTEND | libc::NOTE_DELETE | libc::NOTE_RENAME;
+
+impl Watch {
+    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = File::open(dir)?;
+        let kq = unsafe { libc::kqueue() };
+        if kq < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let watch = Self {
+            kq: unsafe { OwnedFd::from_raw_fd(kq) },
+            dir,
+            path: path.to_path_buf(),
+            file: Mutex::new(None),
+        };
+        watch.register(watch.dir.as_raw_fd(), libc::NOTE_WRITE)?;
+        let mut file = None;
+        watch.reopen(&mut file)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            ..watch
+        })
+    }
+
+    fn register(&self, fd: RawFd, fflags: u32) -> io::Result<()> {
+        let mut change: libc::kevent = unsafe { std::mem::zeroed() };
+        change.ident = fd as libc::uintptr_t;
+        change.filter = libc::EVFILT_VNODE;
+        change.flags = libc::EV_ADD | libc::EV_CLEAR;
+        change.fflags = fflags;
+        match unsafe {
+            libc::kevent(
+                self.kq.as_raw_fd(),
+                &change,
+                1,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null(),
+            )
+        } {
+            -1 => Err(io::Error::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    /// watch the file now at `path` if it is not the one watched already
+    ///
+    /// the old file is closed, which drops its registration.
+    fn reopen(&self, file: &mut Option<File>) -> io::Result<()> {
+        let current = match File::open(&self.path) {
+            Ok(current) => current,
+            Err(_) => {
+                *file = None;
+                return Ok(());
+            }
+        };
+        let id = |f: &File| f.metadata().ok().map(|m| (m.dev(), m.ino()));
+        let watched = file.as_ref().and_then(id);
+        if watched.is_none() || watched != id(&current) {
+            self.register(current.as_raw_fd(), FILE_EVENTS)?;
+            *file = Some(current);
+        }
+        Ok(())
+    }
+
+    /// wait for the file to change, `Ok(false)` once `timeout` expired
+    pub(crate) fn wait(&self, timeout: Option<std::time::Duration>) -> io::Result<bool> {
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let ts = timeout.map(|t| libc::timespec {
+            tv_sec: t.as_secs() as libc::time_t,
+            tv_nsec: t.subsec_nanos() as _,
+        });
+        let ts_ptr = ts
+            .as_ref()
+            .map_or(std::ptr::null(), |ts| ts as *const libc::timespec);
+        let mut events: [libc::kevent; 4] = unsafe { std::mem::zeroed() };
+        let n = match unsafe {
+            libc::kevent(
+                self.kq.as_raw_fd(),
+                std::ptr::null(),
+                0,
+                events.as_mut_ptr(),
+                events.len() as libc::c_int,
+                ts_ptr,
+            )
+        } {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Ok(false),
+            n => n as usize,
+        };
+        // the file may have been replaced, watch the new one before the
+        // caller reads it
+        if events[..n].iter().any(|event| {
+            event.ident == self.dir.as_raw_fd() as libc::uintptr_t
+                || event.fflags & (libc::NOTE_DELETE | libc::NOTE_RENAME) != 0
+        }) {
+            self.reopen(&mut file)?;
+        }
+        Ok(true)
+    }
+}
+
+const IFT_ETHER: u8 = 0x06;
+const IFT_LOOP: u8 = 0x18;
+const IFT_IEEE80211: u8 = 0x47;
+
+/// the details of the AF_LINK address entry of an interface
+pub(crate) fn link_details(ifa: &libc::ifaddrs) -> Option<LinkDetails> {
+    let addr = unsafe { (ifa.ifa_addr as *const libc::sockaddr_dl).as_ref()? };
+    if addr.sdl_family != libc::AF_LINK as u8 {
+        return None;
+    }
+    let data = unsafe { (ifa.ifa_data as *const libc::if_data).as_ref() };
+    let link_type = match addr.sdl_type {
+        // 802.11 interfaces present Ethernet frames by default
+        IFT_ETHER | IFT_IEEE80211 => Some(DLT_EN10MB),
+        IFT_LOOP => Some(DLT_NULL),
+        _ => None,
+    };
+    // sdl_data holds the name of the interface, then its address
+    let data_ptr = addr.sdl_data.as_ptr() as *const u8;
+    let hwaddr = unsafe {
+        std::slice::from_raw_parts(data_ptr.add(addr.sdl_nlen as usize), addr.sdl_alen as usize)
+    };
+    Some(LinkDetails {
+        mtu: data.map(|data| data.ifi_mtu as u32),
+        link_type,
+        hwaddr: hwaddr.to_vec(),
+    })
+}
+
+const BIOCSDLT: libc::c_ulong = 0x8004_4278; // _IOW('B', 120, u_int)
+
+/// select the data link type of the frames read from a BPF device (BIOCSDLT)
+///
+/// the device must be bound to an interface first. e.g. `dlt::DLT_IEEE802_11_RADIO`
+/// gets the 802.11 frames of a wireless interface in monitor mode with their
+/// radiotap header.
+pub fn set_link_type<T>(device: &T, dlt: u32) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let dlt = dlt as libc::c_uint;
+    match unsafe { libc::ioctl(device.as_raw_fd(), BIOCSDLT, &dlt as *const libc::c_uint) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// the BPF device checked by `check_capture_privileges`
+#[cfg(target_os = "freebsd")]
+const BPF_DEVICE: &str = "/dev/bpf";
+#[cfg(target_os = "macos")]
+const BPF_DEVICE: &str = "/dev/bpf0";
+
+/// check that the process may open the BPF devices for reading and writing
+///
+/// on macOS the devices are usually made accessible to the `access_bpf`
+/// group by the ChmodBPF launch daemon.
+pub fn check_capture_privileges() -> Result<(), PrivilegeError> {
+    let path = std::ffi::CString::new(BPF_DEVICE).unwrap();
+    match unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } {
+        0 => Ok(()),
+        _ => Err(PrivilegeError::DeviceAccess {
+            path: BPF_DEVICE.to_string(),
+            errno: errno(),
+        }),
+    }
+}
+
+#[cfg(target_os = "freebsd")]
+const BIOCGTSTAMP: libc::c_ulong = 0x4004_4283; // _IOR('B', 131, u_int)
+#[cfg(target_os = "freebsd")]
+const BIOCSTSTAMP: libc::c_ulong = 0x8004_4284; // _IOW('B', 132, u_int)
+#[cfg(target_os = "freebsd")]
+const BPF_T_MICROTIME: libc::c_uint = 0x0000;
+#[cfg(target_os = "freebsd")]
+pub(crate) const BPF_T_NANOTIME: libc::c_uint = 0x0001;
+#[cfg(target_os = "freebsd")]
+pub(crate) const BPF_T_BINTIME: libc::c_uint = 0x0002;
+#[cfg(target_os = "freebsd")]
+pub(crate) const BPF_T_FORMAT_MASK: libc::c_uint = 0x0003;
+#[cfg(target_os = "freebsd")]
+const BPF_T_MONOTONIC: libc::c_uint = 0x0200;
+
+/// the clock stamping the records of a BPF device
+#[cfg(target_os = "freebsd")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BpfClock {
+    /// the wall clock, stamps since the UNIX epoch
+    Realtime,
+    /// stamps since boot, unaffected by the changes of the wall clock
+    Monotonic,
+}
+
+/// the `BPF_T_*` flags stamping the records of a BPF device (BIOCGTSTAMP)
+#[cfg(target_os = "freebsd")]
+pub(crate) fn timestamp_format<T>(device: &T) -> io::Result<libc::c_uint>
+where
+    T: AsRawFd,
+{
+    let mut format: libc::c_uint = 0;
+    match unsafe {
+        libc::ioctl(
+            device.as_raw_fd(),
+            BIOCGTSTAMP,
+            &mut format as *mut libc::c_uint,
+        )
+    } {
+        0 => Ok(format),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// replace the `BPF_T_*` flags stamping the records of a BPF device (BIOCSTSTAMP)
+#[cfg(target_os = "freebsd")]
+fn set_timestamp_format<T>(device: &T, format: libc::c_uint) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    match unsafe {
+        libc::ioctl(
+            device.as_raw_fd(),
+            BIOCSTSTAMP,
+            &format as *const libc::c_uint,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// select how the records read from a BPF device are stamped (BIOCSTSTAMP)
+///
+/// the BPF device has no hardware stamps, `TsSource::Hardware` fails with EOPNOTSUPP.
+/// nanosecond stamps switch the records from `bpf_hdr` to `bpf_xhdr`, which
+/// `BpfDevice::read_packets` follows. the clock is left as is.
+#[cfg(target_os = "freebsd")]
+pub fn set_timestamping<T>(device: &T, source: TsSource, precision: TsPrecision) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let precision = match (source, precision) {
+        (TsSource::Hardware, _) => return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+        (TsSource::Kernel, TsPrecision::Micro) => BPF_T_MICROTIME,
+        (TsSource::Kernel, TsPrecision::Nano) => BPF_T_NANOTIME,
+    };
+    let format = timestamp_format(device)?;
+    set_timestamp_format(device, (format & !BPF_T_FORMAT_MASK) | precision)
+}
+
+/// select the clock stamping the records read from a BPF device (BIOCSTSTAMP)
+///
+/// the resolution is left as is, see `set_timestamping`.
+#[cfg(target_os = "freebsd")]
+pub fn set_timestamp_clock<T>(device: &T, clock: BpfClock) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let format = timestamp_format(device)? & !BPF_T_MONOTONIC;
+    match clock {
+        BpfClock::Realtime => set_timestamp_format(device, format),
+        BpfClock::Monotonic => set_timestamp_format(device, format | BPF_T_MONOTONIC),
+    }
+}
+
 // test
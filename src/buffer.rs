@@ -0,0 +1,230 @@
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// alignment guaranteed by [`AlignedBuf`]
+///
+/// it covers `BPF_WORDALIGN` on the BSDs and `TPACKET_ALIGNMENT` on Linux
+pub const BUF_ALIGN: usize = 16;
+
+#[derive(Clone, Copy)]
+#[repr(C, align(16))]
+struct Chunk([u8; BUF_ALIGN]);
+
+/// a reusable, heap allocated read buffer whose start is aligned to [`BUF_ALIGN`]
+///
+/// the capture read paths take `&mut AlignedBuf` instead of `&mut [u8]`,
+/// so the records they hand out can be decoded in place without copying
+/// and without allocating on every read: `read_into` on Linux, whose
+/// [`FrameIter`] walks the frames, and `BpfDevice::read_packets` on the BSDs.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let mut buf = AlignedBuf::new(4096);
+/// assert_eq!(buf.len(), 4096);
+/// assert_eq!(buf.as_ptr() as usize % BUF_ALIGN, 0);
+/// buf[0] = 0xff;
+/// ```
+pub struct AlignedBuf {
+    chunks: Vec<Chunk>,
+    len: usize,
+}
+
+impl AlignedBuf {
+    /// allocate a zeroed buffer of `len` bytes
+    pub fn new(len: usize) -> Self {
+        let count = len.div_ceil(BUF_ALIGN);
+        Self {
+            chunks: vec![Chunk([0; BUF_ALIGN]); count],
+            len,
+        }
+    }
+
+    /// grow or shrink the buffer to `len` bytes, keeping the alignment
+    pub fn resize(&mut self, len: usize) {
+        let count = len.div_ceil(BUF_ALIGN);
+        self.chunks.resize(count, Chunk([0; BUF_ALIGN]));
+        self.len = len;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Deref for AlignedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.chunks.as_ptr() as *const u8, self.len) }
+    }
+}
+
+impl DerefMut for AlignedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.chunks.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
+impl std::fmt::Debug for AlignedBuf {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AlignedBuf")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// the most frames a single `read_into` receives on Linux
+pub const READ_BATCH: usize = 64;
+
+/// a frame received into an [`AlignedBuf`] by `read_into` on Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    /// the captured bytes, starting on a [`BUF_ALIGN`] boundary
+    pub data: &'a [u8],
+    /// the length of the packet, which exceeds the captured bytes when truncated
+    pub len: usize,
+}
+
+impl Frame<'_> {
+    /// whether the packet was cut at the capture length
+    pub fn is_truncated(&self) -> bool {
+        self.len > self.data.len()
+    }
+}
+
+/// the frames a single `read_into` received into an [`AlignedBuf`]
+#[derive(Debug, Clone)]
+pub struct FrameIter<'a> {
+    buf: &'a [u8],
+    stride: usize,
+    snaplen: usize,
+    lens: [usize; READ_BATCH],
+    count: usize,
+    next: usize,
+}
+
+impl<'a> FrameIter<'a> {
+    /// the frames of `lens` cut at `snaplen`, in the first `count` slots of
+    /// `stride` bytes of `buf`
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(
+        buf: &'a [u8],
+        stride: usize,
+        snaplen: usize,
+        lens: [usize; READ_BATCH],
+        count: usize,
+    ) -> Self {
+        Self {
+            buf,
+            stride,
+            snaplen,
+            lens,
+            count,
+            next: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for FrameIter<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.count {
+            return None;
+        }
+        let len = self.lens[self.next];
+        let start = self.next * self.stride;
+        self.next += 1;
+        Some(Frame {
+            data: self.buf.get(start..start + len.min(self.snaplen))?,
+            len,
+        })
+    }
+}
+
+/// a reusable receive slot holding one frame and its metadata
+///
+/// see `recv_batch` on Linux
+#[derive(Debug)]
+pub struct FrameBuf {
+    buf: AlignedBuf,
+    len: usize,
+    truncated: bool,
+    timestamp: Option<Duration>,
+}
+
+impl FrameBuf {
+    /// allocate a slot able to hold frames of up to `capacity` bytes
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            buf: AlignedBuf::new(capacity),
+            len: 0,
+            truncated: false,
+            timestamp: None,
+        }
+    }
+
+    /// the bytes of the last frame received into this slot
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len.min(self.buf.len())]
+    }
+
+    /// the length of the last frame, which exceeds `capacity()` when truncated
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// whether the last frame did not fit into the slot
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// when the last frame was stamped, as a duration since the UNIX epoch
+    ///
+    /// only available once timestamping is enabled with `set_timestamping`
+    pub fn timestamp(&self) -> Option<Duration> {
+        self.timestamp
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn buf_mut(&mut self) -> &mut AlignedBuf {
+        &mut self.buf
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn set_received(
+        &mut self,
+        len: usize,
+        truncated: bool,
+        timestamp: Option<Duration>,
+    ) {
+        self.len = len;
+        self.truncated = truncated;
+        self.timestamp = timestamp;
+    }
+}
+
+#[test]
+fn test_aligned_buf_resize() {
+    let mut buf = AlignedBuf::new(3);
+    buf.copy_from_slice(&[1, 2, 3]);
+    buf.resize(40);
+    assert_eq!(buf.len(), 40);
+    assert_eq!(&buf[..4], &[1, 2, 3, 0]);
+    assert_eq!(buf.as_ptr() as usize % BUF_ALIGN, 0);
+}
@@ -0,0 +1,1198 @@
+use crate::ancillary::*;
+use crate::bpf_base::bpf::*;
+use crate::bpf_base::BPFFilter;
+use crate::dlt::{network_offset, Dlt};
+use crate::expr::Expr;
+use crate::fragments;
+use crate::offsets::{Field, Layer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// number of scratch memory slots, M[0] to M[15]
+const MEMWORDS: usize = 16;
+
+/// a scratch memory slot handed out by [`ProgramBuilder::scratch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    index: u8,
+    generation: u32,
+}
+
+impl Slot {
+    /// the index of the slot in M[]
+    pub fn index(&self) -> u32 {
+        self.index as u32
+    }
+}
+
+/// a jump target of a [`ProgramBuilder`], placed with [`ProgramBuilder::bind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "L{}", self.0)
+    }
+}
+
+/// a program the builder refuses to produce
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// more than 16 scratch slots were live at once
+    ScratchExhausted,
+    /// the instruction at `index` may read M[slot] before anything was stored there
+    ReadBeforeWrite { slot: u32, index: usize },
+    /// the instruction at `index` uses a slot handle after it was released
+    StaleSlot { slot: u32, index: usize },
+    /// the raw instruction at `index` touches M[slot], which is allocated to a handle
+    SlotConflict { slot: u32, index: usize },
+    /// the instruction at `index` uses a scratch slot beyond M[15]
+    InvalidSlot { index: usize },
+    /// the instruction at `index` jumps or falls past the end of the program
+    JumpOutOfRange { index: usize },
+    /// the instruction at `index` jumps to a label that was never bound
+    UnboundLabel { index: usize },
+    /// the conditional jump at `index` is `distance` instructions away from
+    /// `label`, more than the 255 jt and jf can hold
+    JumpTooFar {
+        label: Label,
+        index: usize,
+        distance: usize,
+    },
+    /// a label was bound a second time, at instruction `index`
+    DuplicateLabel { index: usize },
+    /// the load at `index` reads a field behind the link header of `dlt`,
+    /// whose length is not fixed
+    NoNetworkOffset { dlt: Dlt, index: usize },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ScratchExhausted => write!(f, "all {} scratch slots are in use", MEMWORDS),
+            BuildError::ReadBeforeWrite { slot, index } => write!(
+                f,
+                "instruction {} may read M[{}] before it is written",
+                index, slot
+            ),
+            BuildError::StaleSlot { slot, index } => write!(
+                f,
+                "instruction {} uses M[{}] after its handle was released",
+                index, slot
+            ),
+            BuildError::SlotConflict { slot, index } => write!(
+                f,
+                "instruction {} touches M[{}], which is allocated to a handle",
+                index, slot
+            ),
+            BuildError::InvalidSlot { index } => {
+                write!(f, "instruction {} uses a scratch slot out of range", index)
+            }
+            BuildError::JumpOutOfRange { index } => {
+                write!(f, "instruction {} leaves the program", index)
+            }
+            BuildError::JumpTooFar {
+                label,
+                index,
+                distance,
+            } => write!(
+                f,
+                "instruction {} jumps {} instructions ahead to {}, conditional jumps reach 255 at most",
+                index, distance, label
+            ),
+            BuildError::UnboundLabel { index } => {
+                write!(f, "instruction {} jumps to an unbound label", index)
+            }
+            BuildError::DuplicateLabel { index } => {
+                write!(f, "a label is bound again at instruction {}", index)
+            }
+            BuildError::NoNetworkOffset { dlt, index } => write!(
+                f,
+                "instruction {} reads behind the link header of {:?}, whose length is not fixed",
+                index, dlt
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// the byte order of the fields compared by the `jeq_field_*` helpers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    /// big-endian, as in the packet headers
+    #[default]
+    Network,
+    /// the order of the host, as in `seccomp_data` or other kernel structures
+    Host,
+}
+
+/// assembles a classic BPF program instruction by instruction
+///
+/// scratch memory is handed out as [`Slot`] handles instead of raw M[] indices,
+/// and [`build`](ProgramBuilder::build) checks that no slot is read before
+/// being written on some path, or used after it was released and possibly
+/// reallocated to another fragment of the program.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let mut builder = ProgramBuilder::new();
+/// let saved = builder.scratch();
+/// builder
+///     .ld_len()
+///     .st(saved)
+///     .ld_abs(bpf::B, 6)
+///     .ld_mem(saved)
+///     .ret_a();
+/// let filters = builder.build().unwrap();
+/// assert_eq!(filters.len(), 5);
+/// ```
+#[derive(Debug, Default)]
+pub struct ProgramBuilder {
+    insns: Vec<BPFFilter>,
+    /// bitmap of the slots allocated to live handles
+    allocated: u16,
+    generations: [u32; MEMWORDS],
+    /// instructions emitted through a handle, exempt from the conflict check
+    handled: Vec<usize>,
+    /// the first misuse found while emitting
+    error: Option<BuildError>,
+    order: ByteOrder,
+    /// the instruction each label is bound to
+    labels: Vec<Option<usize>>,
+    /// the jumps to labels, with `None` for the next instruction
+    fixups: Vec<(usize, Option<Label>, Option<Label>)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the number of instructions emitted so far
+    pub fn len(&self) -> usize {
+        self.insns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.insns.is_empty()
+    }
+
+    /// append a raw instruction
+    pub fn push(&mut self, insn: BPFFilter) -> &mut Self {
+        self.insns.push(insn);
+        self
+    }
+
+    /// the network offset of `dlt`, failing the build for the link types
+    /// whose header length is not fixed
+    pub(crate) fn network_offset(&mut self, dlt: Dlt) -> Option<u32> {
+        let offset = network_offset(dlt.value());
+        if offset.is_none() {
+            let index = self.insns.len();
+            self.fail(BuildError::NoNetworkOffset { dlt, index });
+        }
+        offset
+    }
+
+    fn fail(&mut self, error: BuildError) {
+        if self.error.is_none() {
+            self.error = Some(error);
+        }
+    }
+
+    /// allocate a free scratch slot
+    ///
+    /// when all 16 slots are live, the returned handle is unusable and
+    /// `build()` fails with `ScratchExhausted`.
+    pub fn scratch(&mut self) -> Slot {
+        match (0..MEMWORDS).find(|i| self.allocated & (1 << i) == 0) {
+            Some(i) => {
+                self.allocated |= 1 << i;
+                Slot {
+                    index: i as u8,
+                    generation: self.generations[i],
+                }
+            }
+            None => {
+                self.fail(BuildError::ScratchExhausted);
+                Slot {
+                    index: 0,
+                    generation: u32::MAX,
+                }
+            }
+        }
+    }
+
+    /// give a slot back, any later use of `slot` is an error
+    pub fn release(&mut self, slot: Slot) -> &mut Self {
+        let i = slot.index as usize;
+        if self.generations[i] == slot.generation {
+            self.allocated &= !(1 << i);
+            self.generations[i] = self.generations[i].wrapping_add(1);
+        }
+        self
+    }
+
+    fn emit_slot(&mut self, code: u16, slot: Slot) -> &mut Self {
+        let i = slot.index as usize;
+        if self.generations[i] != slot.generation || self.allocated & (1 << i) == 0 {
+            self.fail(BuildError::StaleSlot {
+                slot: slot.index(),
+                index: self.insns.len(),
+            });
+        }
+        self.handled.push(self.insns.len());
+        self.push(BPFFilter {
+            code,
+            jt: 0,
+            jf: 0,
+            k: slot.index(),
+        })
+    }
+
+    /// M[slot] = A
+    pub fn st(&mut self, slot: Slot) -> &mut Self {
+        self.emit_slot(ST.0, slot)
+    }
+
+    /// M[slot] = X
+    pub fn stx(&mut self, slot: Slot) -> &mut Self {
+        self.emit_slot(STX.0, slot)
+    }
+
+    /// A = M[slot]
+    pub fn ld_mem(&mut self, slot: Slot) -> &mut Self {
+        self.emit_slot(LD.0 | W.0 | MEM.0, slot)
+    }
+
+    /// X = M[slot]
+    pub fn ldx_mem(&mut self, slot: Slot) -> &mut Self {
+        self.emit_slot(LDX.0 | W.0 | MEM.0, slot)
+    }
+
+    fn stmt(&mut self, code: u16, k: u32) -> &mut Self {
+        self.push(BPFFilter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        })
+    }
+
+    /// A = k
+    pub fn ld_imm(&mut self, k: u32) -> &mut Self {
+        self.stmt(LD.0 | W.0 | IMM.0, k)
+    }
+
+    /// A = the `size` bytes of the packet at `offset`
+    pub fn ld_abs(&mut self, size: BPFSize, offset: u32) -> &mut Self {
+        self.stmt(LD.0 | size.0 | ABS.0, offset)
+    }
+
+    /// A = the `size` bytes of the packet at X + `offset`
+    pub fn ld_ind(&mut self, size: BPFSize, offset: u32) -> &mut Self {
+        self.stmt(LD.0 | size.0 | IND.0, offset)
+    }
+
+    /// A = the byte of the packet at `offset`
+    pub fn ld_abs_b(&mut self, offset: u32) -> &mut Self {
+        self.ld_abs(B, offset)
+    }
+
+    /// A = the big-endian half-word of the packet at `offset`
+    pub fn ld_abs_h(&mut self, offset: u32) -> &mut Self {
+        self.ld_abs(H, offset)
+    }
+
+    /// A = the big-endian word of the packet at `offset`
+    pub fn ld_abs_w(&mut self, offset: u32) -> &mut Self {
+        self.ld_abs(W, offset)
+    }
+
+    /// A = the protocol field `field` of a frame of link type `dlt`
+    ///
+    /// transport fields are loaded relative to X, which must hold the
+    /// length of the network header, e.g. from
+    /// `fragments::load_ipv4_transport_base` for IPv4 or 40 for IPv6 without
+    /// extension headers.
+    ///
+    /// [`build`](Self::build) fails with `BuildError::NoNetworkOffset` if
+    /// `field` is not a link field and the header length of `dlt` is not
+    /// fixed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    /// use classic_bpf::offsets::Ipv4;
+    ///
+    /// let mut builder = ProgramBuilder::new();
+    /// builder.ld_field(Dlt::En10mb, Ipv4::Protocol).ret_a();
+    /// assert_eq!(builder.build().unwrap()[0].k(), 23);
+    /// ```
+    pub fn ld_field<F>(&mut self, dlt: Dlt, field: F) -> &mut Self
+    where
+        F: Field,
+    {
+        let base = match field.layer() {
+            Layer::Link => 0,
+            _ => match self.network_offset(dlt) {
+                Some(offset) => offset,
+                None => return self,
+            },
+        };
+        match field.layer() {
+            Layer::Transport => self.ld_ind(field.size(), base + field.offset()),
+            _ => self.ld_abs(field.size(), base + field.offset()),
+        }
+    }
+
+    /// A = the ancillary field `field`, one of `ancillary::SKF_AD_*`
+    pub fn ld_ancillary(&mut self, field: u32) -> &mut Self {
+        self.ld_abs_w(SKF_AD_OFF.wrapping_add(field))
+    }
+
+    /// A = the word, half-word or byte `offset` bytes into the network header
+    ///
+    /// on Linux, wherever the socket sees the packet start.
+    pub fn ld_net(&mut self, size: BPFSize, offset: u32) -> &mut Self {
+        self.ld_abs(size, SKF_NET_OFF.wrapping_add(offset))
+    }
+
+    /// A = the word, half-word or byte `offset` bytes into the link-layer header
+    ///
+    /// on Linux, wherever the socket sees the packet start.
+    pub fn ld_ll(&mut self, size: BPFSize, offset: u32) -> &mut Self {
+        self.ld_abs(size, SKF_LL_OFF.wrapping_add(offset))
+    }
+
+    /// A = the EtherType of the packet
+    pub fn ld_protocol(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_PROTOCOL)
+    }
+
+    /// A = the `PACKET_*` type of the packet
+    pub fn ld_pkttype(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_PKTTYPE)
+    }
+
+    /// A = the index of the interface the packet came from
+    pub fn ld_ifindex(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_IFINDEX)
+    }
+
+    /// A = the mark of the packet
+    pub fn ld_mark(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_MARK)
+    }
+
+    /// A = the NIC receive queue of the packet
+    pub fn ld_queue(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_QUEUE)
+    }
+
+    /// A = the ARPHRD_* type of the interface the packet came from
+    pub fn ld_hatype(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_HATYPE)
+    }
+
+    /// A = the flow hash of the packet
+    pub fn ld_rxhash(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_RXHASH)
+    }
+
+    /// A = the CPU receiving the packet
+    pub fn ld_cpu(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_CPU)
+    }
+
+    /// A = the TCI of the stripped VLAN tag
+    pub fn ld_vlan_tci(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_VLAN_TAG)
+    }
+
+    /// A = the offset of the transport payload
+    pub fn ld_pay_offset(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_PAY_OFFSET)
+    }
+
+    /// A = a random number
+    pub fn ld_random(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_RANDOM)
+    }
+
+    /// A = 1 when a VLAN tag was stripped from the packet
+    pub fn ld_vlan_tag_present(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_VLAN_TAG_PRESENT)
+    }
+
+    /// A = the TPID of the stripped VLAN tag
+    pub fn ld_vlan_tpid(&mut self) -> &mut Self {
+        self.ld_ancillary(SKF_AD_VLAN_TPID)
+    }
+
+    /// A = the byte of the packet at X + `offset`
+    pub fn ld_ind_b(&mut self, offset: u32) -> &mut Self {
+        self.ld_ind(B, offset)
+    }
+
+    /// A = the big-endian half-word of the packet at X + `offset`
+    pub fn ld_ind_h(&mut self, offset: u32) -> &mut Self {
+        self.ld_ind(H, offset)
+    }
+
+    /// A = the big-endian word of the packet at X + `offset`
+    pub fn ld_ind_w(&mut self, offset: u32) -> &mut Self {
+        self.ld_ind(W, offset)
+    }
+
+    /// A = the length of the packet
+    pub fn ld_len(&mut self) -> &mut Self {
+        self.stmt(LD.0 | W.0 | LEN.0, 0)
+    }
+
+    /// X = k
+    pub fn ldx_imm(&mut self, k: u32) -> &mut Self {
+        self.stmt(LDX.0 | W.0 | IMM.0, k)
+    }
+
+    /// X = the length of the packet
+    pub fn ldx_len(&mut self) -> &mut Self {
+        self.stmt(LDX.0 | W.0 | LEN.0, 0)
+    }
+
+    /// X = 4 * (the byte of the packet at `offset` & 0xf), the length of an IPv4 header
+    pub fn ldx_msh(&mut self, offset: u32) -> &mut Self {
+        self.stmt(LDX.0 | B.0 | MSH.0, offset)
+    }
+
+    /// load X with the length of the IPv4 header at `ip_offset` and emit `body`
+    ///
+    /// this is the `ldx msh` idiom: in `body`, `ld_ind_*(ip_offset + n)` reads
+    /// byte `n` of the transport header whatever the IPv4 options are,
+    /// as long as `body` leaves X alone.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// // accept TCP segments to port 443 behind an Ethernet header
+    /// let mut builder = ProgramBuilder::new();
+    /// builder
+    ///     .ld_abs_b(14 + 9)
+    ///     .jmp(bpf::JEQ, bpf::K, 6, 0, 4)
+    ///     .with_x_as_ip_header_len(14, |b| {
+    ///         b.ld_ind_h(14 + 2);
+    ///     })
+    ///     .jmp(bpf::JEQ, bpf::K, 443, 0, 1)
+    ///     .ret_k(u32::MAX)
+    ///     .ret_k(0);
+    /// assert!(builder.build().is_ok());
+    /// ```
+    pub fn with_x_as_ip_header_len<F>(&mut self, ip_offset: u32, body: F) -> &mut Self
+    where
+        F: FnOnce(&mut Self),
+    {
+        fragments::load_ipv4_transport_base_at(self, ip_offset);
+        body(self);
+        self
+    }
+
+    /// X = the length of the radiotap header of a `DLT_IEEE802_11_RADIO` frame
+    ///
+    /// it clobbers A. the 802.11 frame is then loaded relative to X.
+    pub fn ldx_radiotap_len(&mut self) -> &mut Self {
+        // the length is a little-endian half-word at offset 2
+        self.ld_abs_b(3)
+            .alu(LSH, K, 8)
+            .tax()
+            .ld_abs_b(2)
+            .alu(OR, X, 0)
+            .tax()
+    }
+
+    /// A = A `op` k, or A = A `op` X with `bpf::X` as `src`
+    pub fn alu(&mut self, op: BPFOp, src: BPFSrc, k: u32) -> &mut Self {
+        self.stmt(ALU.0 | op.0 | src.0, k)
+    }
+
+    /// A = -A
+    pub fn neg(&mut self) -> &mut Self {
+        self.stmt(ALU.0 | NEG.0, 0)
+    }
+
+    /// `TAX` (X = A) or `TXA` (A = X)
+    pub fn misc(&mut self, op: BPFMiscOp) -> &mut Self {
+        self.stmt(MISC.0 | op.0, 0)
+    }
+
+    /// X = A
+    pub fn tax(&mut self) -> &mut Self {
+        self.misc(TAX)
+    }
+
+    /// A = X
+    pub fn txa(&mut self) -> &mut Self {
+        self.misc(TXA)
+    }
+
+    /// compare A with k, or with X with `bpf::X` as `src`, and skip `jt` or `jf` instructions
+    pub fn jmp(&mut self, op: BPFJmpOp, src: BPFSrc, k: u32, jt: u8, jf: u8) -> &mut Self {
+        self.push(BPFFilter {
+            code: JMP.0 | op.0 | src.0,
+            jt,
+            jf,
+            k,
+        })
+    }
+
+    /// skip `jt` instructions if any bit of `mask` is set in A, `jf` otherwise
+    pub fn jset(&mut self, mask: u32, jt: u8, jf: u8) -> &mut Self {
+        self.jmp(JSET, K, mask, jt, jf)
+    }
+
+    /// set the byte order of the fields compared by the `jeq_field_*` helpers
+    ///
+    /// H and W loads always read big-endian values, so comparing a host-order
+    /// field needs the value swapped on little-endian hosts. the default is
+    /// `ByteOrder::Network`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let mut builder = ProgramBuilder::new();
+    /// // the ethertype of an Ethernet frame, in host order as everywhere in Rust
+    /// builder.jeq_field_u16(12, 0x0800, 0, 3);
+    /// // a host-order field of a kernel structure
+    /// builder.byte_order(ByteOrder::Host).jeq_field_u32(0, 59, 0, 1);
+    /// builder.ret_k(u32::MAX).ret_k(0);
+    ///
+    /// let filters = builder.build().unwrap();
+    /// assert_eq!(filters[1].k(), 0x0800);
+    /// assert_eq!(filters[3].k(), u32::from_be_bytes(59u32.to_ne_bytes()));
+    /// ```
+    pub fn byte_order(&mut self, order: ByteOrder) -> &mut Self {
+        self.order = order;
+        self
+    }
+
+    /// compare the half-word at `offset` with `value`, skipping `jt` or `jf` instructions
+    ///
+    /// `value` is a plain Rust integer, never to be passed through `to_be()`.
+    pub fn jeq_field_u16(&mut self, offset: u32, value: u16, jt: u8, jf: u8) -> &mut Self {
+        let k = match self.order {
+            ByteOrder::Network => value,
+            ByteOrder::Host => u16::from_be_bytes(value.to_ne_bytes()),
+        };
+        self.ld_abs_h(offset).jmp(JEQ, K, k as u32, jt, jf)
+    }
+
+    /// compare the word at `offset` with `value`, skipping `jt` or `jf` instructions
+    ///
+    /// `value` is a plain Rust integer, never to be passed through `to_be()`.
+    pub fn jeq_field_u32(&mut self, offset: u32, value: u32, jt: u8, jf: u8) -> &mut Self {
+        let k = match self.order {
+            ByteOrder::Network => value,
+            ByteOrder::Host => u32::from_be_bytes(value.to_ne_bytes()),
+        };
+        self.ld_abs_w(offset).jmp(JEQ, K, k, jt, jf)
+    }
+
+    /// compare the IPv4 address at `offset` with `addr`, skipping `jt` or `jf` instructions
+    ///
+    /// addresses are always in network order, whatever the `byte_order`.
+    pub fn jeq_field_ipv4(&mut self, offset: u32, addr: Ipv4Addr, jt: u8, jf: u8) -> &mut Self {
+        self.ld_abs_w(offset).jmp(JEQ, K, u32::from(addr), jt, jf)
+    }
+
+    /// create a label, to be bound to an instruction with [`bind`](Self::bind)
+    pub fn label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// bind `label` to the next instruction emitted
+    pub fn bind(&mut self, label: Label) -> &mut Self {
+        let here = self.insns.len();
+        match self.labels[label.0] {
+            Some(_) => self.fail(BuildError::DuplicateLabel { index: here }),
+            None => self.labels[label.0] = Some(here),
+        }
+        self
+    }
+
+    /// compare A with k or X and jump to `jt` or `jf`, `None` meaning the next
+    /// instruction
+    ///
+    /// the offsets are computed by [`build`](Self::build), which fails for
+    /// labels never bound, bound before the jump or more than 255
+    /// instructions away.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let mut builder = ProgramBuilder::new();
+    /// let drop = builder.label();
+    /// builder
+    ///     .ld_abs_h(12)
+    ///     .jmp_label(bpf::JEQ, bpf::K, 0x0800, None, Some(drop))
+    ///     .ld_abs_b(23)
+    ///     .jmp_label(bpf::JEQ, bpf::K, 17, None, Some(drop))
+    ///     .ret_k(u32::MAX);
+    /// builder.bind(drop).ret_k(0);
+    ///
+    /// let filters = builder.build().unwrap();
+    /// assert_eq!(filters[1], BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 3));
+    /// assert_eq!(filters[3], BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 17, 0, 1));
+    /// ```
+    pub fn jmp_label(
+        &mut self,
+        op: BPFJmpOp,
+        src: BPFSrc,
+        k: u32,
+        jt: Option<Label>,
+        jf: Option<Label>,
+    ) -> &mut Self {
+        self.fixups.push((self.insns.len(), jt, jf));
+        self.jmp(op, src, k, 0, 0)
+    }
+
+    fn jmp_to(&mut self, op: BPFJmpOp, k: u32, jt: Option<Label>, jf: Option<Label>) -> &mut Self {
+        self.jmp_label(op, K, k, jt, jf)
+    }
+
+    /// jump to `accept` if A is one of `values`, to `fallthrough` otherwise
+    ///
+    /// runs of 3 or more consecutive values are tested as a range with two
+    /// jumps, the other values with one JEQ each.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let mut builder = ProgramBuilder::new();
+    /// let (accept, drop) = (builder.label(), builder.label());
+    /// // TCP or UDP
+    /// builder.ld_abs_b(23).jeq_any(&[6, 17], accept, drop);
+    /// builder.bind(accept).ret_k(u32::MAX);
+    /// builder.bind(drop).ret_k(0);
+    /// assert_eq!(builder.build().unwrap().len(), 5);
+    /// ```
+    pub fn jeq_any(&mut self, values: &[u32], accept: Label, fallthrough: Label) -> &mut Self {
+        let mut values = values.to_vec();
+        values.sort_unstable();
+        values.dedup();
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for v in values {
+            match runs.last_mut() {
+                Some((_, hi)) if hi.checked_add(1) == Some(v) => *hi = v,
+                _ => runs.push((v, v)),
+            }
+        }
+        if runs.is_empty() {
+            return self.ja_label(fallthrough);
+        }
+        let last = runs.len() - 1;
+        for (i, (lo, hi)) in runs.into_iter().enumerate() {
+            let next = if i == last { fallthrough } else { self.label() };
+            if hi - lo >= 2 {
+                self.jmp_to(JGE, lo, None, Some(next))
+                    .jmp_to(JGT, hi, Some(next), Some(accept));
+            } else {
+                for v in lo..=hi {
+                    let miss = if v == hi { Some(next) } else { None };
+                    self.jmp_to(JEQ, v, Some(accept), miss);
+                }
+            }
+            if i != last {
+                self.bind(next);
+            }
+        }
+        self
+    }
+
+    /// emit the instructions of `then` for the packets `cond` holds for
+    ///
+    /// the jumps around the blocks are computed by the builder, the blocks
+    /// can nest.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::expr::*;
+    /// use classic_bpf::*;
+    ///
+    /// let mut builder = ProgramBuilder::new();
+    /// builder
+    ///     .if_(ether_type().eq(0x0800))
+    ///     .then(|b| {
+    ///         b.if_(ip_proto().eq(17)).then(|b| {
+    ///             b.ret_k(u32::MAX);
+    ///         });
+    ///         b.ret_k(64);
+    ///     })
+    ///     .else_(|b| {
+    ///         b.ret_k(0);
+    ///     });
+    /// let filters = builder.build().unwrap();
+    ///
+    /// let mut frame = [0u8; 34];
+    /// frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    /// assert_eq!(run(&filters, &frame, 34), 64);
+    /// frame[23] = 17;
+    /// assert_eq!(run(&filters, &frame, 34), u32::MAX);
+    /// ```
+    pub fn if_(&mut self, cond: Expr) -> IfBlock<'_> {
+        IfBlock {
+            builder: self,
+            cond,
+        }
+    }
+
+    /// whether nothing reaches the next instruction, the last one being a
+    /// RET and no label bound to it
+    fn is_unreachable(&self) -> bool {
+        let here = self.insns.len();
+        self.insns
+            .last()
+            .is_some_and(|insn| insn.code & 0x07 == 0x06)
+            && !self.labels.contains(&Some(here))
+    }
+
+    /// jump to `label` unconditionally, however far it is
+    pub fn ja_label(&mut self, label: Label) -> &mut Self {
+        self.fixups.push((self.insns.len(), Some(label), None));
+        self.ja(0)
+    }
+
+    /// skip `k` instructions unconditionally
+    pub fn ja(&mut self, k: u32) -> &mut Self {
+        self.stmt(JMP.0 | JA.0, k)
+    }
+
+    /// return k
+    ///
+    /// the value returned is the capture length: the number of bytes of the
+    /// packet kept, 0 dropping it and anything past its length keeping it whole.
+    pub fn ret_k(&mut self, k: u32) -> &mut Self {
+        self.stmt(RET.0 | K.0, k)
+    }
+
+    /// accept the packet, truncated to its first `snaplen` bytes
+    ///
+    /// `u32::MAX` keeps every packet whole, 0 drops it.
+    pub fn accept_truncated(&mut self, snaplen: u32) -> &mut Self {
+        self.ret_k(snaplen)
+    }
+
+    /// return A
+    pub fn ret_a(&mut self) -> &mut Self {
+        self.stmt(RET.0 | A.0, 0)
+    }
+
+    /// check the program and return its instructions
+    pub fn build(&self) -> Result<Vec<BPFFilter>, BuildError> {
+        if let Some(error) = &self.error {
+            return Err(error.clone());
+        }
+        let insns = self.resolve_labels()?;
+        self.check_scratch(&insns)?;
+        Ok(insns)
+    }
+
+    /// the instructions with the jumps to labels patched
+    fn resolve_labels(&self) -> Result<Vec<BPFFilter>, BuildError> {
+        let mut insns = self.insns.clone();
+        for (index, jt, jf) in &self.fixups {
+            let index = *index;
+            let offset = |label: Option<Label>| -> Result<u32, BuildError> {
+                let target = match label {
+                    Some(label) => {
+                        self.labels[label.0].ok_or(BuildError::UnboundLabel { index })?
+                    }
+                    None => index + 1,
+                };
+                target
+                    .checked_sub(index + 1)
+                    .map(|offset| offset as u32)
+                    .ok_or(BuildError::JumpOutOfRange { index })
+            };
+            let insn = &mut insns[index];
+            if insn.code & 0xf0 == JA.0 {
+                insn.k = offset(*jt)?;
+            } else {
+                let short = |label: Option<Label>| -> Result<u8, BuildError> {
+                    let distance = offset(label)?;
+                    u8::try_from(distance).map_err(|_| BuildError::JumpTooFar {
+                        // only jumps to labels can be that long
+                        label: label.unwrap(),
+                        index,
+                        distance: distance as usize,
+                    })
+                };
+                insn.jt = short(*jt)?;
+                insn.jf = short(*jf)?;
+            }
+        }
+        Ok(insns)
+    }
+
+    /// follow the slots definitely written on every path to each instruction
+    fn check_scratch(&self, insns: &[BPFFilter]) -> Result<(), BuildError> {
+        let len = insns.len();
+        let mut written: Vec<Option<u16>> = vec![None; len];
+        if len > 0 {
+            written[0] = Some(0);
+        }
+        for (i, insn) in insns.iter().enumerate() {
+            let mut state = match written[i] {
+                Some(state) => state,
+                None => continue,
+            };
+            let class = insn.code & 0x07;
+            let mode = insn.code & 0xe0;
+            let mem = match class {
+                0x00 | 0x01 if mode == MEM.0 => Some(false),
+                0x02 | 0x03 => Some(true),
+                _ => None,
+            };
+            if let Some(store) = mem {
+                if insn.k as usize >= MEMWORDS {
+                    return Err(BuildError::InvalidSlot { index: i });
+                }
+                let slot = insn.k;
+                if !self.handled.contains(&i) && self.allocated & (1 << slot) != 0 {
+                    return Err(BuildError::SlotConflict { slot, index: i });
+                }
+                if store {
+                    state |= 1 << slot;
+                } else if state & (1 << slot) == 0 {
+                    return Err(BuildError::ReadBeforeWrite { slot, index: i });
+                }
+            }
+
+            let successors: Vec<usize> = match class {
+                0x05 if insn.code & 0xf0 == JA.0 => vec![i + 1 + insn.k as usize],
+                0x05 => vec![i + 1 + insn.jt as usize, i + 1 + insn.jf as usize],
+                0x06 => vec![],
+                _ => vec![i + 1],
+            };
+            for next in successors {
+                if next >= len {
+                    return Err(BuildError::JumpOutOfRange { index: i });
+                }
+                written[next] = Some(written[next].map_or(state, |s| s & state));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the condition of a block, from [`ProgramBuilder::if_`]
+#[derive(Debug)]
+pub struct IfBlock<'b> {
+    builder: &'b mut ProgramBuilder,
+    cond: Expr,
+}
+
+impl<'b> IfBlock<'b> {
+    /// emit the block run when the condition holds
+    pub fn then<F>(self, body: F) -> ThenBlock<'b>
+    where
+        F: FnOnce(&mut ProgramBuilder),
+    {
+        let builder = self.builder;
+        let (then, otherwise) = (builder.label(), builder.label());
+        self.cond.emit(builder, then, otherwise);
+        builder.bind(then);
+        body(builder);
+        ThenBlock {
+            builder: Some(builder),
+            otherwise,
+        }
+    }
+}
+
+/// the block run when a condition holds, the code emitted next runs
+/// after it or when the condition does not hold
+///
+/// dropping it ends the block without an else.
+#[derive(Debug)]
+pub struct ThenBlock<'b> {
+    builder: Option<&'b mut ProgramBuilder>,
+    otherwise: Label,
+}
+
+impl<'b> ThenBlock<'b> {
+    /// emit the block run when the condition does not hold
+    pub fn else_<F>(mut self, body: F) -> &'b mut ProgramBuilder
+    where
+        F: FnOnce(&mut ProgramBuilder),
+    {
+        let builder = self.builder.take().unwrap();
+        let end = builder.label();
+        if !builder.is_unreachable() {
+            builder.ja_label(end);
+        }
+        builder.bind(self.otherwise);
+        body(builder);
+        builder.bind(end)
+    }
+
+    /// end the block without an else
+    pub fn end(mut self) -> &'b mut ProgramBuilder {
+        self.builder.take().unwrap().bind(self.otherwise)
+    }
+}
+
+impl Drop for ThenBlock<'_> {
+    fn drop(&mut self) {
+        if let Some(builder) = self.builder.take() {
+            builder.bind(self.otherwise);
+        }
+    }
+}
+
+#[test]
+fn test_addressing_modes() {
+    let mut builder = ProgramBuilder::new();
+    let m = builder.scratch();
+    builder
+        .ld_imm(1)
+        .ld_abs(W, 0)
+        .ld_abs(H, 0)
+        .ld_abs(B, 0)
+        .ld_ind(W, 0)
+        .ld_ind(H, 0)
+        .ld_ind(B, 0)
+        .ld_len()
+        .st(m)
+        .ld_mem(m)
+        .ldx_imm(1)
+        .ldx_len()
+        .ldx_msh(14)
+        .stx(m)
+        .ldx_mem(m)
+        .alu(ADD, K, 1)
+        .alu(SUB, X, 0)
+        .neg()
+        .misc(TAX)
+        .misc(TXA)
+        .jmp(JGT, X, 0, 0, 0)
+        .ja(0)
+        .ret_a()
+        .ret_k(0);
+    let codes: Vec<u16> = builder.build().unwrap().iter().map(|f| f.code).collect();
+    assert_eq!(
+        codes,
+        [
+            0x00, 0x20, 0x28, 0x30, 0x40, 0x48, 0x50, 0x80, 0x02, 0x60, 0x01, 0x81, 0xb1, 0x03,
+            0x61, 0x04, 0x1c, 0x84, 0x07, 0x87, 0x2d, 0x05, 0x16, 0x06
+        ]
+    );
+}
+
+#[test]
+fn test_ancillary_loads() {
+    let mut builder = ProgramBuilder::new();
+    builder
+        .ld_protocol()
+        .ld_pkttype()
+        .ld_ifindex()
+        .ld_mark()
+        .ld_hatype()
+        .ld_rxhash()
+        .ld_cpu()
+        .ld_vlan_tci()
+        .ld_pay_offset()
+        .ld_random()
+        .ret_a();
+    let insns = builder.build().unwrap();
+    assert!(insns[..10].iter().all(|f| f.code == 0x20));
+    let fields: Vec<u32> = insns[..10]
+        .iter()
+        .map(|f| f.k.wrapping_sub(SKF_AD_OFF))
+        .collect();
+    assert_eq!(fields, [0, 4, 8, 20, 28, 32, 36, 44, 52, 56]);
+}
+
+#[test]
+fn test_scratch_misuse() {
+    // M[a] is only written when the jump is taken
+    let mut builder = ProgramBuilder::new();
+    let a = builder.scratch();
+    builder
+        .push(BPFFilter::bpf_stmt(LD | B | ABS, 6))
+        .push(BPFFilter::bpf_jump(JMP | JEQ | K, 58, 0, 1))
+        .st(a)
+        .ld_mem(a)
+        .push(BPFFilter::bpf_stmt(RET | A, 0));
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::ReadBeforeWrite { slot: 0, index: 3 })
+    );
+
+    // the released slot is reallocated to b, a is stale
+    let mut builder = ProgramBuilder::new();
+    let a = builder.scratch();
+    builder
+        .push(BPFFilter::bpf_stmt(LD | LEN, 0))
+        .st(a)
+        .release(a);
+    let b = builder.scratch();
+    assert_eq!(a.index(), b.index());
+    builder.st(b).ld_mem(a);
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::StaleSlot { slot: 0, index: 3 })
+    );
+}
+
+#[test]
+fn test_jeq_any() {
+    let mut builder = ProgramBuilder::new();
+    let (accept, drop) = (builder.label(), builder.label());
+    builder.jeq_any(&[17, 81, 6, 80, 82, 17], accept, drop);
+    builder.bind(accept).ret_k(u32::MAX);
+    builder.bind(drop).ret_k(0);
+    assert_eq!(
+        builder.build().unwrap(),
+        [
+            BPFFilter::bpf_jump(JMP | JEQ | K, 6, 3, 0),
+            BPFFilter::bpf_jump(JMP | JEQ | K, 17, 2, 0),
+            BPFFilter::bpf_jump(JMP | JGE | K, 80, 0, 2),
+            BPFFilter::bpf_jump(JMP | JGT | K, 82, 1, 0),
+            BPFFilter::bpf_stmt(RET | K, u32::MAX),
+            BPFFilter::bpf_stmt(RET | K, 0),
+        ]
+    );
+
+    let mut builder = ProgramBuilder::new();
+    let nowhere = builder.label();
+    builder.jeq_any(&[1], nowhere, nowhere).ret_k(0);
+    assert_eq!(builder.build(), Err(BuildError::UnboundLabel { index: 0 }));
+}
+
+#[test]
+fn test_jump_too_far() {
+    let mut builder = ProgramBuilder::new();
+    let (accept, drop) = (builder.label(), builder.label());
+    builder.ld_abs_b(23).jeq_any(&[6], accept, drop);
+    for _ in 0..300 {
+        builder.ld_len();
+    }
+    builder.bind(accept).ret_k(u32::MAX);
+    builder.bind(drop).ret_k(0);
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::JumpTooFar {
+            label: accept,
+            index: 1,
+            distance: 300,
+        })
+    );
+}
+
+#[test]
+fn test_jump_labels() {
+    // an unconditional jump is not limited to 255 instructions
+    let mut builder = ProgramBuilder::new();
+    let end = builder.label();
+    builder.ja_label(end);
+    for _ in 0..300 {
+        builder.ld_len();
+    }
+    builder.bind(end).ret_k(0);
+    assert_eq!(builder.build().unwrap()[0].k(), 300);
+
+    // backward jumps are refused
+    let mut builder = ProgramBuilder::new();
+    let start = builder.label();
+    builder.bind(start).ld_len();
+    builder.jmp_label(JGT, X, 0, Some(start), None).ret_k(0);
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::JumpOutOfRange { index: 1 })
+    );
+
+    let mut builder = ProgramBuilder::new();
+    let done = builder.label();
+    builder.bind(done).ret_k(0);
+    builder.bind(done);
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::DuplicateLabel { index: 1 })
+    );
+}
+
+#[test]
+fn test_if_else() {
+    use crate::expr::{a, len};
+    use crate::interpreter::run;
+    // 1 for the short packets, then 2, 3 or 4 by the first byte
+    let mut builder = ProgramBuilder::new();
+    builder
+        .if_(len().lt(2))
+        .then(|b| {
+            b.ret_k(1);
+        })
+        .else_(|b| {
+            b.ld_abs_b(0)
+                .if_(a().eq(0) | a().eq(1))
+                .then(|b| {
+                    b.ld_imm(2);
+                })
+                .else_(|b| {
+                    b.if_(a().gt(0x7f)).then(|b| {
+                        b.ld_imm(3).ret_a();
+                    });
+                    b.ld_imm(4);
+                })
+                .ret_a();
+        });
+    let filters = builder.build().unwrap();
+    assert_eq!(run(&filters, &[0], 1), 1);
+    assert_eq!(run(&filters, &[0, 0], 2), 2);
+    assert_eq!(run(&filters, &[1, 0], 2), 2);
+    assert_eq!(run(&filters, &[0x80, 0], 2), 3);
+    assert_eq!(run(&filters, &[0x10, 0], 2), 4);
+
+    // the false path of the inner if ends the then block, not in the else
+    let mut builder = ProgramBuilder::new();
+    builder
+        .if_(len().gt(1))
+        .then(|b| {
+            b.ld_abs_b(0).if_(a().eq(1)).then(|b| {
+                b.ret_k(1);
+            });
+        })
+        .else_(|b| {
+            b.ld_imm(7);
+        })
+        .ret_a();
+    let filters = builder.build().unwrap();
+    assert_eq!(run(&filters, &[1, 0], 2), 1);
+    assert_eq!(run(&filters, &[0, 0], 2), 0);
+    assert_eq!(run(&filters, &[0], 1), 7);
+
+    // the end of the last block is past the program
+    let mut builder = ProgramBuilder::new();
+    builder.if_(len().eq(0)).then(|b| {
+        b.ret_k(0);
+    });
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::JumpOutOfRange { index: 1 })
+    );
+}
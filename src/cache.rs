@@ -0,0 +1,127 @@
+use crate::bpf_base::BPFProgram;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// where a program is meant to be attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AttachPoint {
+    /// a socket, with `SO_ATTACH_FILTER`
+    Socket,
+    /// a BPF device
+    Device,
+    /// a `SO_REUSEPORT` group, with `SO_ATTACH_REUSEPORT_CBPF`
+    Reuseport,
+    /// a seccomp filter
+    Seccomp,
+}
+
+/// what a cached program was made from
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FilterKey {
+    /// the filter expression, or any fingerprint of a built program
+    pub source: String,
+    /// the `dlt::DLT_*` link type the program expects
+    pub dlt: u32,
+    pub attach: AttachPoint,
+}
+
+impl FilterKey {
+    pub fn new<S: Into<String>>(source: S, dlt: u32, attach: AttachPoint) -> Self {
+        Self {
+            source: source.into(),
+            dlt,
+            attach,
+        }
+    }
+}
+
+/// shares the programs compiled for identical keys
+///
+/// it can be used from several threads, the programs are handed out as
+/// `Arc`s so attaching them to thousands of sockets costs one compilation.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let cache = FilterCache::new();
+/// let key = FilterKey::new("arp", dlt::DLT_EN10MB, AttachPoint::Socket);
+/// let compile = || Ok::<_, ()>(presets::arp_only().to_vec());
+/// let a = cache.get_or_compile(key.clone(), compile).unwrap();
+/// let b = cache.get_or_compile(key, compile).unwrap();
+/// assert!(std::sync::Arc::ptr_eq(&a, &b));
+/// ```
+#[derive(Debug, Default)]
+pub struct FilterCache {
+    programs: Mutex<HashMap<FilterKey, Arc<BPFProgram>>>,
+}
+
+impl FilterCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// the program cached for `key`, if any
+    pub fn get(&self, key: &FilterKey) -> Option<Arc<BPFProgram>> {
+        self.programs.lock().unwrap().get(key).cloned()
+    }
+
+    /// the program cached for `key`, compiled and cached first if needed
+    ///
+    /// `compile` runs without the cache locked. when two threads compile the
+    /// same key at once, the first program inserted is kept and returned to both.
+    pub fn get_or_compile<F, P, E>(&self, key: FilterKey, compile: F) -> Result<Arc<BPFProgram>, E>
+    where
+        F: FnOnce() -> Result<P, E>,
+        P: Into<BPFProgram>,
+    {
+        if let Some(program) = self.get(&key) {
+            return Ok(program);
+        }
+        let program = Arc::new(compile()?.into());
+        Ok(self
+            .programs
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert(program)
+            .clone())
+    }
+
+    /// the number of cached programs
+    pub fn len(&self) -> usize {
+        self.programs.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// forget every program, the ones handed out stay valid
+    pub fn clear(&self) {
+        self.programs.lock().unwrap().clear();
+    }
+}
+
+#[test]
+fn test_filter_cache() {
+    use crate::presets;
+
+    let cache = FilterCache::new();
+    let socket = FilterKey::new("arp", 1, AttachPoint::Socket);
+    let device = FilterKey::new("arp", 1, AttachPoint::Device);
+    assert_eq!(
+        cache.get_or_compile(socket.clone(), || Err::<BPFProgram, _>("nope")),
+        Err("nope")
+    );
+    assert!(cache.is_empty());
+    cache
+        .get_or_compile(socket.clone(), || Ok::<_, ()>(presets::arp_only().to_vec()))
+        .unwrap();
+    cache
+        .get_or_compile(device, || Ok::<_, ()>(presets::drop_all().to_vec()))
+        .unwrap();
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cache.get(&socket).unwrap().filters(), presets::arp_only());
+}
@@ -0,0 +1,921 @@
+//! compilation of pcap-filter expressions, without libpcap
+//!
+//! the common primitives of `pcap-filter(7)` are supported:
+//!
+//! - protocols: `ether proto N`, `ip`, `ip6`, `arp`, `rarp`, `ip proto N`,
+//!   `ip6 proto N`, `tcp`, `udp`, `sctp`, `icmp`, `icmp6`, `igmp`
+//! - addresses: `[src|dst] host ADDR`, `[src|dst] net ADDR/LEN`,
+//!   `net ADDR mask MASK`, `ether [src|dst] host MAC`, for IPv4 and IPv6
+//! - ports: `[tcp|udp|sctp] [src|dst] port N`, `portrange N-M`
+//! - lengths: `len OP N`, `less N`, `greater N`
+//! - `and`/`&&`, `or`/`||`, `not`/`!` and parentheses
+//!
+//! as in libpcap, `and` and `or` have the same precedence and associate left
+//! to right: `tcp or udp and port 53` is `(tcp or udp) and port 53`.
+//!
+//! `src or dst` and `src and dst` qualify both directions, and a bare value
+//! reuses the qualifiers of the previous primitive, as in `port 53 or 853`.
+//! host names and service names are not resolved.
+
+use crate::bpf_base::{bpf::*, BPFProgram};
+use crate::builder::{BuildError, Label, ProgramBuilder};
+use crate::dlt::Dlt;
+use crate::fragments;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+/// the capture length of the accepted packets, the snaplen of tcpdump
+const SNAPLEN: u32 = 262144;
+
+/// an expression [`compile`] refuses
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileError {
+    /// the expression is malformed at byte `position`
+    Syntax { position: usize, message: String },
+    /// the primitive at byte `position` cannot be matched in frames of `dlt`
+    UnsupportedLinkType { position: usize, dlt: Dlt },
+    /// the program cannot be built, too long for its jumps
+    Build(BuildError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Syntax { position, message } => {
+                write!(f, "syntax error at {}: {}", position, message)
+            }
+            CompileError::UnsupportedLinkType { position, dlt } => write!(
+                f,
+                "the primitive at {} is not supported for {:?} frames",
+                position, dlt
+            ),
+            CompileError::Build(error) => write!(f, "cannot build the program: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rel {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    Rel(Rel),
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | ':' | '/' | '-')
+}
+
+fn lex(text: &str) -> Result<Vec<(usize, Token)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((position, c)) = chars.next() {
+        let mut next_is = |expected: char| chars.next_if(|(_, c)| *c == expected).is_some();
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '!' if next_is('=') => Token::Rel(Rel::Ne),
+            '!' => Token::Not,
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '<' if next_is('=') => Token::Rel(Rel::Le),
+            '<' => Token::Rel(Rel::Lt),
+            '>' if next_is('=') => Token::Rel(Rel::Ge),
+            '>' => Token::Rel(Rel::Gt),
+            '=' => {
+                next_is('=');
+                Token::Rel(Rel::Eq)
+            }
+            c if is_word_char(c) => {
+                let mut word = c.to_string();
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_word_char(*c)) {
+                    word.push(c);
+                }
+                match word.as_str() {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    _ => Token::Word(word),
+                }
+            }
+            c => {
+                return Err(CompileError::Syntax {
+                    position,
+                    message: format!("unexpected character {:?}", c),
+                })
+            }
+        };
+        tokens.push((position, token));
+    }
+    Ok(tokens)
+}
+
+/// the packet data a test compares
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Load {
+    /// the `size` bytes at an offset
+    Abs(u8, u32),
+    /// the `size` bytes at an offset of the transport header, behind the
+    /// IPv4 header at the first offset
+    Ind(u8, u32, u32),
+    Len,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Jump {
+    Eq,
+    Gt,
+    Ge,
+    Set,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Test {
+    load: Load,
+    mask: Option<u32>,
+    jump: Jump,
+    k: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    True,
+    False,
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Test(Test),
+}
+
+fn test(load: Load, jump: Jump, k: u32) -> Node {
+    Node::Test(Test {
+        load,
+        mask: None,
+        jump,
+        k,
+    })
+}
+
+fn masked(load: Load, mask: u32, k: u32) -> Node {
+    Node::Test(Test {
+        load,
+        mask: Some(mask),
+        jump: Jump::Eq,
+        k,
+    })
+}
+
+fn and(a: Node, b: Node) -> Node {
+    match (a, b) {
+        (Node::True, n) | (n, Node::True) => n,
+        (Node::False, _) => Node::False,
+        (a, b) => Node::And(Box::new(a), Box::new(b)),
+    }
+}
+
+fn or(a: Node, b: Node) -> Node {
+    match (a, b) {
+        (Node::False, n) | (n, Node::False) => n,
+        (Node::True, _) => Node::True,
+        (a, b) => Node::Or(Box::new(a), Box::new(b)),
+    }
+}
+
+fn not(node: Node) -> Node {
+    match node {
+        Node::True => Node::False,
+        Node::False => Node::True,
+        Node::Not(node) => *node,
+        node => Node::Not(Box::new(node)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Proto {
+    Ether,
+    Ip,
+    Ip6,
+    Arp,
+    Rarp,
+    Tcp,
+    Udp,
+    Sctp,
+    Icmp,
+    Icmp6,
+    Igmp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dir {
+    Src,
+    Dst,
+    Either,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Host,
+    Net,
+    Port,
+    PortRange,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Quals {
+    proto: Option<Proto>,
+    dir: Dir,
+    kind: Kind,
+}
+
+fn proto_keyword(word: &str) -> Option<Proto> {
+    Some(match word {
+        "ether" => Proto::Ether,
+        "ip" => Proto::Ip,
+        "ip6" => Proto::Ip6,
+        "arp" => Proto::Arp,
+        "rarp" => Proto::Rarp,
+        "tcp" => Proto::Tcp,
+        "udp" => Proto::Udp,
+        "sctp" => Proto::Sctp,
+        "icmp" => Proto::Icmp,
+        "icmp6" => Proto::Icmp6,
+        "igmp" => Proto::Igmp,
+        _ => return None,
+    })
+}
+
+fn dir_keyword(word: &str) -> Option<Dir> {
+    match word {
+        "src" => Some(Dir::Src),
+        "dst" => Some(Dir::Dst),
+        _ => None,
+    }
+}
+
+fn kind_keyword(word: &str) -> Option<Kind> {
+    match word {
+        "host" => Some(Kind::Host),
+        "net" => Some(Kind::Net),
+        "port" => Some(Kind::Port),
+        "portrange" => Some(Kind::PortRange),
+        _ => None,
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    proto_keyword(word).is_some()
+        || dir_keyword(word).is_some()
+        || kind_keyword(word).is_some()
+        || matches!(word, "proto" | "mask" | "len" | "less" | "greater")
+}
+
+fn parse_number(word: &str) -> Option<u32> {
+    match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => word.parse().ok(),
+    }
+}
+
+fn parse_mac(word: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut parts = word.split([':', '-']);
+    for byte in mac.iter_mut() {
+        *byte = u8::from_str_radix(parts.next()?, 16).ok()?;
+    }
+    match parts.next() {
+        None => Some(mac),
+        Some(_) => None,
+    }
+}
+
+/// an IPv4 network, `10.1` standing for `10.1.0.0/16` as in libpcap
+fn parse_ipv4_net(word: &str) -> Option<(u32, u32)> {
+    let (addr, len) = match word.split_once('/') {
+        Some((addr, len)) => (addr, Some(len.parse::<u32>().ok().filter(|l| *l <= 32)?)),
+        None => (word, None),
+    };
+    let octets = addr
+        .split('.')
+        .map(|o| o.parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    if octets.is_empty() || octets.len() > 4 {
+        return None;
+    }
+    let mut bytes = [0; 4];
+    bytes[..octets.len()].copy_from_slice(&octets);
+    let len = len.unwrap_or(8 * octets.len() as u32);
+    Some((u32::from_be_bytes(bytes), prefix_mask(len)))
+}
+
+fn prefix_mask(len: u32) -> u32 {
+    u32::MAX.checked_shl(32 - len).unwrap_or(0)
+}
+
+fn parse_ipv6_net(word: &str) -> Option<(Ipv6Addr, u32)> {
+    match word.split_once('/') {
+        Some((addr, len)) => Some((addr.parse().ok()?, len.parse().ok().filter(|l| *l <= 128)?)),
+        None => Some((word.parse().ok()?, 128)),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [(usize, Token)],
+    pos: usize,
+    end: usize,
+    dlt: Dlt,
+    last: Option<Quals>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(_, t)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn peek_word(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Word(word)) => Some(word),
+            _ => None,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, CompileError> {
+        Err(CompileError::Syntax {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    fn unsupported<T>(&self, position: usize) -> Result<T, CompileError> {
+        Err(CompileError::UnsupportedLinkType {
+            position,
+            dlt: self.dlt,
+        })
+    }
+
+    fn word(&mut self, what: &str) -> Result<(usize, String), CompileError> {
+        match self.tokens.get(self.pos) {
+            Some((position, Token::Word(word))) if !is_keyword(word) => {
+                self.pos += 1;
+                Ok((*position, word.clone()))
+            }
+            _ => self.error(format!("expected {}", what)),
+        }
+    }
+
+    fn number(&mut self, what: &str) -> Result<u32, CompileError> {
+        let (position, word) = self.word(what)?;
+        parse_number(&word).ok_or(CompileError::Syntax {
+            position,
+            message: format!("{} is not a number", word),
+        })
+    }
+
+    /// `and` and `or` have the same precedence and associate left to right
+    fn expression(&mut self) -> Result<Node, CompileError> {
+        let mut node = self.unary()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.pos += 1;
+                    node = and(node, self.unary()?);
+                }
+                Some(Token::Or) => {
+                    self.pos += 1;
+                    node = or(node, self.unary()?);
+                }
+                _ => return Ok(node),
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Node, CompileError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(not(self.unary()?))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let node = self.expression()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return self.error("expected )");
+                }
+                self.pos += 1;
+                Ok(node)
+            }
+            Some(Token::Word(_)) => self.primitive(),
+            _ => self.error("expected a primitive"),
+        }
+    }
+
+    fn primitive(&mut self) -> Result<Node, CompileError> {
+        let start = self.position();
+        let first = self.peek_word().unwrap_or_default().to_string();
+        match first.as_str() {
+            "len" => {
+                self.pos += 1;
+                let rel = match self.peek() {
+                    Some(Token::Rel(rel)) => *rel,
+                    _ => return self.error("expected a comparison"),
+                };
+                self.pos += 1;
+                let n = self.number("a length")?;
+                return Ok(match rel {
+                    Rel::Lt => not(test(Load::Len, Jump::Ge, n)),
+                    Rel::Le => not(test(Load::Len, Jump::Gt, n)),
+                    Rel::Gt => test(Load::Len, Jump::Gt, n),
+                    Rel::Ge => test(Load::Len, Jump::Ge, n),
+                    Rel::Eq => test(Load::Len, Jump::Eq, n),
+                    Rel::Ne => not(test(Load::Len, Jump::Eq, n)),
+                });
+            }
+            "less" => {
+                self.pos += 1;
+                return Ok(not(test(Load::Len, Jump::Gt, self.number("a length")?)));
+            }
+            "greater" => {
+                self.pos += 1;
+                return Ok(test(Load::Len, Jump::Ge, self.number("a length")?));
+            }
+            _ => {}
+        }
+
+        let proto = proto_keyword(&first);
+        if proto.is_some() {
+            self.pos += 1;
+        }
+        if let (Some(proto), Some("proto")) = (proto, self.peek_word()) {
+            self.pos += 1;
+            let n = self.number("a protocol number")?;
+            return match proto {
+                Proto::Ether => self.ethertype(start, n),
+                Proto::Ip => self.ip_proto(start, false, n),
+                Proto::Ip6 => self.ip_proto(start, true, n),
+                _ => self.error("proto only qualifies ether, ip and ip6"),
+            };
+        }
+
+        let mut dir = None;
+        if let Some(first) = self.peek_word().and_then(dir_keyword) {
+            self.pos += 1;
+            dir = Some(first);
+            let combined = match self.peek() {
+                Some(Token::Or) => Dir::Either,
+                Some(Token::And) => Dir::Both,
+                _ => first,
+            };
+            let other = match self.tokens.get(self.pos + 1) {
+                Some((_, Token::Word(word))) => dir_keyword(word),
+                _ => None,
+            };
+            if combined != first && other.is_some_and(|other| other != first) {
+                self.pos += 2;
+                dir = Some(combined);
+            }
+        }
+        let kind = self.peek_word().and_then(kind_keyword);
+        if kind.is_some() {
+            self.pos += 1;
+        }
+
+        let quals = match (proto, dir, kind) {
+            (Some(proto), None, None) => return self.protocol(start, proto),
+            (None, None, None) => self.last.unwrap_or(Quals {
+                proto: None,
+                dir: Dir::Either,
+                kind: Kind::Host,
+            }),
+            (proto, dir, kind) => Quals {
+                proto,
+                dir: dir.unwrap_or(Dir::Either),
+                kind: kind.unwrap_or(Kind::Host),
+            },
+        };
+        self.last = Some(quals);
+        let (position, value) = self.word("an address or a port")?;
+        let mut mask = None;
+        if quals.kind == Kind::Net && self.peek_word() == Some("mask") {
+            self.pos += 1;
+            let (position, word) = self.word("a mask")?;
+            match word.parse::<Ipv4Addr>() {
+                Ok(m) => mask = Some(u32::from(m)),
+                Err(_) => {
+                    return Err(CompileError::Syntax {
+                        position,
+                        message: format!("{} is not an IPv4 mask", word),
+                    })
+                }
+            }
+        }
+        self.value(start, quals, position, &value, mask)
+    }
+
+    /// the test of the ethertype of the frame
+    fn ethertype(&self, position: usize, ethertype: u32) -> Result<Node, CompileError> {
+        match self.dlt {
+            Dlt::En10mb => Ok(test(Load::Abs(2, 12), Jump::Eq, ethertype)),
+            Dlt::LinuxSll => Ok(test(Load::Abs(2, 14), Jump::Eq, ethertype)),
+            // the IP version is all there is
+            Dlt::Raw => Ok(match ethertype {
+                0x0800 => masked(Load::Abs(1, 0), 0xf0, 0x40),
+                0x86dd => masked(Load::Abs(1, 0), 0xf0, 0x60),
+                _ => Node::False,
+            }),
+            _ => self.unsupported(position),
+        }
+    }
+
+    fn network_offset(&self) -> u32 {
+        crate::dlt::network_offset(self.dlt.value()).unwrap_or(0)
+    }
+
+    fn ip_proto(&self, position: usize, ip6: bool, proto: u32) -> Result<Node, CompileError> {
+        let net = self.network_offset();
+        Ok(if ip6 {
+            // as libpcap, look past a fragment header too
+            let fragment = and(
+                test(Load::Abs(1, net + 6), Jump::Eq, 44),
+                test(Load::Abs(1, net + 40), Jump::Eq, proto),
+            );
+            and(
+                self.ethertype(position, 0x86dd)?,
+                or(test(Load::Abs(1, net + 6), Jump::Eq, proto), fragment),
+            )
+        } else {
+            and(
+                self.ethertype(position, 0x0800)?,
+                test(Load::Abs(1, net + 9), Jump::Eq, proto),
+            )
+        })
+    }
+
+    fn protocol(&self, position: usize, proto: Proto) -> Result<Node, CompileError> {
+        let both = |proto| -> Result<Node, CompileError> {
+            Ok(or(
+                self.ip_proto(position, true, proto)?,
+                self.ip_proto(position, false, proto)?,
+            ))
+        };
+        match proto {
+            Proto::Ether => self.error("expected proto or host after ether"),
+            Proto::Ip => self.ethertype(position, 0x0800),
+            Proto::Ip6 => self.ethertype(position, 0x86dd),
+            Proto::Arp => self.ethertype(position, 0x0806),
+            Proto::Rarp => self.ethertype(position, 0x8035),
+            Proto::Tcp => both(6),
+            Proto::Udp => both(17),
+            Proto::Sctp => both(132),
+            Proto::Icmp => self.ip_proto(position, false, 1),
+            Proto::Icmp6 => self.ip_proto(position, true, 58),
+            Proto::Igmp => self.ip_proto(position, false, 2),
+        }
+    }
+
+    fn value(
+        &self,
+        start: usize,
+        quals: Quals,
+        position: usize,
+        value: &str,
+        mask: Option<u32>,
+    ) -> Result<Node, CompileError> {
+        let bad_value = |what: &str| {
+            Err(CompileError::Syntax {
+                position,
+                message: format!("{} is not {}", value, what),
+            })
+        };
+        let dir = |src: Node, dst: Node| match quals.dir {
+            Dir::Src => src,
+            Dir::Dst => dst,
+            Dir::Either => or(src, dst),
+            Dir::Both => and(src, dst),
+        };
+        let net = self.network_offset();
+        match (quals.kind, quals.proto) {
+            (Kind::Host, Some(Proto::Ether)) => {
+                let mac = match parse_mac(value) {
+                    Some(mac) => mac,
+                    None => return bad_value("a MAC address"),
+                };
+                if self.dlt != Dlt::En10mb {
+                    return self.unsupported(start);
+                }
+                let hi = u16::from_be_bytes([mac[0], mac[1]]) as u32;
+                let lo = u32::from_be_bytes([mac[2], mac[3], mac[4], mac[5]]);
+                let at = |offset| {
+                    and(
+                        test(Load::Abs(4, offset + 2), Jump::Eq, lo),
+                        test(Load::Abs(2, offset), Jump::Eq, hi),
+                    )
+                };
+                Ok(dir(at(6), at(0)))
+            }
+            (Kind::Host, proto) | (Kind::Net, proto) => {
+                let explicit = mask;
+                if let Some((addr, mask)) = parse_ipv4_net(value)
+                    .filter(|_| quals.kind == Kind::Net || value.parse::<Ipv4Addr>().is_ok())
+                {
+                    let mask = explicit.unwrap_or(mask);
+                    let word = |offset| {
+                        if mask == 0 {
+                            Node::True
+                        } else if mask == u32::MAX {
+                            test(Load::Abs(4, offset), Jump::Eq, addr)
+                        } else {
+                            masked(Load::Abs(4, offset), mask, addr & mask)
+                        }
+                    };
+                    let families: &[(u32, u32)] = match proto {
+                        None => &[(0x0800, 12), (0x0806, 14), (0x8035, 14)],
+                        Some(Proto::Ip) => &[(0x0800, 12)],
+                        Some(Proto::Arp) => &[(0x0806, 14)],
+                        Some(Proto::Rarp) => &[(0x8035, 14)],
+                        _ => return self.error("IPv4 addresses only qualify ip, arp and rarp"),
+                    };
+                    let mut node = Node::False;
+                    for (ethertype, src) in families {
+                        // the target address of ARP is 10 bytes after the sender
+                        let dst = if *ethertype == 0x0800 { 16 } else { 24 };
+                        node = or(
+                            node,
+                            and(
+                                self.ethertype(start, *ethertype)?,
+                                dir(word(net + src), word(net + dst)),
+                            ),
+                        );
+                    }
+                    return Ok(node);
+                }
+                let (addr, len) = match parse_ipv6_net(value) {
+                    Some((addr, len)) if quals.kind == Kind::Net || len == 128 => (addr, len),
+                    _ if quals.kind == Kind::Net => return bad_value("a network"),
+                    _ => return bad_value("an address (host names are not resolved)"),
+                };
+                if explicit.is_some() {
+                    return bad_value("an IPv4 network");
+                }
+                if !matches!(proto, None | Some(Proto::Ip6)) {
+                    return self.error("IPv6 addresses only qualify ip6");
+                }
+                let words = |offset: u32| {
+                    let mut node = Node::True;
+                    for (i, chunk) in addr.octets().chunks(4).enumerate() {
+                        let i = i as u32;
+                        let bits = len.saturating_sub(32 * i).min(32);
+                        let word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                        let mask = prefix_mask(bits);
+                        let load = Load::Abs(4, offset + 4 * i);
+                        node = and(
+                            node,
+                            match mask {
+                                0 => Node::True,
+                                u32::MAX => test(load, Jump::Eq, word),
+                                mask => masked(load, mask, word & mask),
+                            },
+                        );
+                    }
+                    node
+                };
+                Ok(and(
+                    self.ethertype(start, 0x86dd)?,
+                    dir(words(net + 8), words(net + 24)),
+                ))
+            }
+            (Kind::Port, proto) | (Kind::PortRange, proto) => {
+                let (lo, hi) = if quals.kind == Kind::Port {
+                    match parse_number(value).filter(|p| *p <= 0xffff) {
+                        Some(port) => (port, port),
+                        None => return bad_value("a port number"),
+                    }
+                } else {
+                    match value
+                        .split_once('-')
+                        .and_then(|(lo, hi)| Some((parse_number(lo)?, parse_number(hi)?)))
+                    {
+                        Some((lo, hi)) if lo <= hi && hi <= 0xffff => (lo, hi),
+                        _ => return bad_value("a port range"),
+                    }
+                };
+                let port = |load: Load| {
+                    if lo == hi {
+                        test(load, Jump::Eq, lo)
+                    } else {
+                        and(
+                            test(load.clone(), Jump::Ge, lo),
+                            not(test(load, Jump::Gt, hi)),
+                        )
+                    }
+                };
+                let (ip6, ip4, transports): (bool, bool, &[u32]) = match proto {
+                    None => (true, true, &[6, 17, 132]),
+                    Some(Proto::Tcp) => (true, true, &[6]),
+                    Some(Proto::Udp) => (true, true, &[17]),
+                    Some(Proto::Sctp) => (true, true, &[132]),
+                    Some(Proto::Ip) => (false, true, &[6, 17, 132]),
+                    Some(Proto::Ip6) => (true, false, &[6, 17, 132]),
+                    _ => return self.error("ports only qualify ip, ip6, tcp, udp and sctp"),
+                };
+                let mut node = Node::False;
+                if ip6 {
+                    let mut protos = Node::False;
+                    for t in transports {
+                        protos = or(protos, test(Load::Abs(1, net + 6), Jump::Eq, *t));
+                    }
+                    node = and(
+                        and(self.ethertype(start, 0x86dd)?, protos),
+                        dir(port(Load::Abs(2, net + 40)), port(Load::Abs(2, net + 42))),
+                    );
+                }
+                if ip4 {
+                    let mut protos = Node::False;
+                    for t in transports {
+                        protos = or(protos, test(Load::Abs(1, net + 9), Jump::Eq, *t));
+                    }
+                    let fragment = test(Load::Abs(2, net + 6), Jump::Set, 0x1fff);
+                    node = or(
+                        node,
+                        and(
+                            and(self.ethertype(start, 0x0800)?, protos),
+                            and(
+                                not(fragment),
+                                dir(port(Load::Ind(2, net, 0)), port(Load::Ind(2, net, 2))),
+                            ),
+                        ),
+                    );
+                }
+                Ok(node)
+            }
+        }
+    }
+}
+
+fn emit(builder: &mut ProgramBuilder, node: &Node, t: Label, f: Label) {
+    match node {
+        Node::True => {
+            builder.ja_label(t);
+        }
+        Node::False => {
+            builder.ja_label(f);
+        }
+        Node::Not(node) => emit(builder, node, f, t),
+        Node::And(a, b) => {
+            let next = builder.label();
+            emit(builder, a, next, f);
+            builder.bind(next);
+            emit(builder, b, t, f);
+        }
+        Node::Or(a, b) => {
+            let next = builder.label();
+            emit(builder, a, t, next);
+            builder.bind(next);
+            emit(builder, b, t, f);
+        }
+        Node::Test(test) => {
+            let size = |bytes| match bytes {
+                1 => B,
+                2 => H,
+                _ => W,
+            };
+            match test.load {
+                Load::Abs(bytes, offset) => builder.ld_abs(size(bytes), offset),
+                Load::Ind(bytes, ip, offset) => fragments::load_ipv4_transport_base_at(builder, ip)
+                    .ld_ind(size(bytes), ip + offset),
+                Load::Len => builder.ld_len(),
+            };
+            if let Some(mask) = test.mask {
+                builder.alu(AND, K, mask);
+            }
+            let op = match test.jump {
+                Jump::Eq => JEQ,
+                Jump::Gt => JGT,
+                Jump::Ge => JGE,
+                Jump::Set => JSET,
+            };
+            builder.jmp_label(op, K, test.k, Some(t), Some(f));
+        }
+    }
+}
+
+/// compile a pcap-filter expression for frames of link type `dlt`
+///
+/// see the [module documentation](self) for the supported primitives. the
+/// accepted packets are kept whole, up to 262144 bytes as with tcpdump, and
+/// the empty expression accepts every packet.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let program = compile("tcp and dst port 443", Dlt::En10mb).unwrap();
+///
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// program.attach_filter(&socket).unwrap();
+///
+/// assert!(compile("tcp port http", Dlt::En10mb).is_err());
+/// ```
+pub fn compile(expression: &str, dlt: Dlt) -> Result<BPFProgram, CompileError> {
+    compile_with_snaplen(expression, dlt, SNAPLEN)
+}
+
+/// compile a pcap-filter expression, keeping the first `snaplen` bytes of the
+/// accepted packets
+pub fn compile_with_snaplen(
+    expression: &str,
+    dlt: Dlt,
+    snaplen: u32,
+) -> Result<BPFProgram, CompileError> {
+    let tokens = lex(expression)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        end: expression.len(),
+        dlt,
+        last: None,
+    };
+    let node = if tokens.is_empty() {
+        Node::True
+    } else {
+        parser.expression()?
+    };
+    if parser.pos != tokens.len() {
+        return parser.error("expected and, or or the end of the expression");
+    }
+
+    let mut builder = ProgramBuilder::new();
+    match node {
+        Node::True => {
+            builder.ret_k(snaplen);
+        }
+        Node::False => {
+            builder.ret_k(0);
+        }
+        node => {
+            let (accept, reject) = (builder.label(), builder.label());
+            emit(&mut builder, &node, accept, reject);
+            builder.bind(accept).ret_k(snaplen);
+            builder.bind(reject).ret_k(0);
+        }
+    }
+    builder
+        .build()
+        .map(BPFProgram::from)
+        .map_err(CompileError::Build)
+}
+
+#[test]
+fn test_compile() {
+    use crate::decompile::decompile;
+
+    let cases = [
+        ("tcp dst port 443", "tcp and dst port 443"),
+        ("ip and udp", "ip and udp"),
+        ("ip6 and not tcp", "ip6 and not tcp"),
+        ("ip6 or ip and udp", "udp"),
+        ("ip src net 10.0.0.0/8", "ip and src net 10.0.0.0/8"),
+        // the second address inherits the qualifiers
+        (
+            "ip dst host 192.0.2.1 or 192.0.2.2",
+            "(ip and dst host 192.0.2.1) or (ip and dst host 192.0.2.2)",
+        ),
+    ];
+    for (expression, expected) in cases.iter() {
+        let program = compile(expression, Dlt::En10mb).unwrap();
+        let decompiled = decompile(&program).unwrap();
+        assert_eq!(&decompiled.expression, expected, "{}", expression);
+    }
+    assert_eq!(compile("", Dlt::Raw).unwrap().len(), 1);
+    assert_eq!(
+        compile("ether host 00:11:22:33:44:55", Dlt::Raw),
+        Err(CompileError::UnsupportedLinkType {
+            position: 0,
+            dlt: Dlt::Raw
+        })
+    );
+    assert!(matches!(
+        compile("tcp and (port 22", Dlt::En10mb),
+        Err(CompileError::Syntax { position: 16, .. })
+    ));
+}
@@ -0,0 +1,412 @@
+//! named filters described in a configuration file
+//!
+//! the format is a subset of TOML: one `[filters.<name>]` table per filter,
+//! holding string, integer and string-array values.
+//!
+//! ```text
+//! # ARP on the uplinks
+//! [filters.arp]
+//! preset = "arp_only"
+//! interfaces = ["eth0", "eth1"]
+//!
+//! [filters.dns]
+//! expression = "udp port 53"
+//! dlt = 1
+//!
+//! [filters.raw]
+//! # the output of bpf_asm, or of tcpdump -ddd on one line
+//! bytecode = "4,40 0 0 12,21 0 1 2054,6 0 0 262144,6 0 0 0"
+//! ```
+//!
+//! each filter has exactly one of `expression`, `preset` and `bytecode`.
+//! `dlt` defaults to `dlt::DLT_EN10MB`.
+//!
+//! the crate depends on libc alone, so the subset is parsed here rather than
+//! with a TOML or YAML crate: other tables, inline tables and multi-line
+//! values are errors. the errors, of the file or of the programs it
+//! describes, point at the line and column of the offending value.
+
+use crate::bpf_base::BPFFilter;
+use crate::dlt::DLT_EN10MB;
+use crate::presets;
+use crate::transform::import_program;
+use std::fmt;
+
+/// a configuration error, at the offending value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// 1-based line number, 0 for the errors that are not in the text
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => f.write_str(&self.message),
+            line => write!(f, "line {}, column {}: {}", line, self.column, self.message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn error<T, S: Into<String>>(line: usize, column: usize, message: S) -> Result<T, ConfigError> {
+    Err(ConfigError {
+        line,
+        column,
+        message: message.into(),
+    })
+}
+
+/// a line of the configuration, locating the errors in its parts
+#[derive(Clone, Copy)]
+struct Line<'a> {
+    text: &'a str,
+    number: usize,
+}
+
+impl Line<'_> {
+    /// the column of `part`, a slice of the line
+    fn column(&self, part: &str) -> usize {
+        let offset = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        self.text[..offset].chars().count() + 1
+    }
+
+    fn error<T, S: Into<String>>(&self, part: &str, message: S) -> Result<T, ConfigError> {
+        error(self.number, self.column(part), message)
+    }
+}
+
+/// how the program of a filter is obtained
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterSource {
+    /// a pcap-filter expression, compiled by the caller
+    Expression(String),
+    /// the name of a function of the `presets` module
+    Preset(String),
+    /// the instructions themselves
+    Bytecode(Vec<BPFFilter>),
+}
+
+/// a filter as described in the configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterConfig {
+    pub name: String,
+    pub source: FilterSource,
+    /// the `dlt::DLT_*` link type of the interfaces
+    pub dlt: u32,
+    /// the interfaces to attach the filter to
+    pub interfaces: Vec<String>,
+    /// the line of the `[filters.<name>]` header
+    pub line: usize,
+    /// the line and column of the expression, preset or bytecode
+    pub source_line: usize,
+    pub source_column: usize,
+}
+
+impl FilterConfig {
+    /// the program of the filter, checked with `import_program`
+    ///
+    /// expressions are passed to `compile` along with the link type.
+    pub fn program<F, E>(&self, compile: F) -> Result<Vec<BPFFilter>, ConfigError>
+    where
+        F: FnOnce(&str, u32) -> Result<Vec<BPFFilter>, E>,
+        E: fmt::Display,
+    {
+        let error = |message: String| error(self.source_line, self.source_column, message);
+        let program = match &self.source {
+            FilterSource::Expression(expression) => match compile(expression, self.dlt) {
+                Ok(program) => program,
+                Err(e) => return error(format!("{}: {}", self.name, e)),
+            },
+            FilterSource::Preset(name) => match presets::by_name(name) {
+                Some(program) => program.to_vec(),
+                None => return error(format!("{}: unknown preset {}", self.name, name)),
+            },
+            FilterSource::Bytecode(program) => program.clone(),
+        };
+        match import_program(&program) {
+            Ok(program) => Ok(program.into_filters()),
+            Err(e) => error(format!("{}: {}", self.name, e)),
+        }
+    }
+}
+
+enum Item {
+    Str(String),
+    Int(u64),
+    List(Vec<String>),
+}
+
+fn parse_string<'a>(text: &'a str, line: Line) -> Result<(String, &'a str), ConfigError> {
+    let mut chars = text.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        return line.error(text, "expected a string");
+    }
+    let mut value = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((value, &text[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => value.push('"'),
+                Some('\\') => value.push('\\'),
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                _ => return line.error(&text[i..], "unsupported escape sequence"),
+            },
+            c => value.push(c),
+        }
+    }
+    line.error(text, "unterminated string")
+}
+
+/// the value after the `=`, with nothing but a comment behind it
+fn parse_item(text: &str, line: Line) -> Result<Item, ConfigError> {
+    let end = |rest: &str| -> Result<(), ConfigError> {
+        let rest = rest.trim();
+        if rest.is_empty() || rest.starts_with('#') {
+            Ok(())
+        } else {
+            line.error(rest, format!("unexpected {}", rest))
+        }
+    };
+    if text.starts_with('"') {
+        let (value, rest) = parse_string(text, line)?;
+        end(rest)?;
+        return Ok(Item::Str(value));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut values = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                end(after)?;
+                return Ok(Item::List(values));
+            }
+            let (value, after) = parse_string(rest, line)?;
+            values.push(value);
+            rest = after.trim_start();
+            rest = match rest.strip_prefix(',') {
+                Some(after) => after,
+                None if rest.starts_with(']') => rest,
+                None => return line.error(rest, "expected , or ] in the array"),
+            };
+        }
+    }
+    let number = text.split('#').next().unwrap_or(text).trim();
+    match number.parse() {
+        Ok(n) => Ok(Item::Int(n)),
+        Err(_) => line.error(number, format!("invalid value {}", number)),
+    }
+}
+
+/// parse the `count,code jt jf k,...` output of bpf_asm, the string `value`
+/// of `line`
+fn parse_bytecode(text: &str, line: Line, value: &str) -> Result<Vec<BPFFilter>, ConfigError> {
+    let mut parts = text.split(',').map(str::trim).filter(|p| !p.is_empty());
+    let count: usize = match parts.next().map(str::parse) {
+        Some(Ok(count)) => count,
+        _ => return line.error(value, "the bytecode must start with the instruction count"),
+    };
+    let program = parts
+        .map(|insn| {
+            let fields: Vec<&str> = insn.split_whitespace().collect();
+            match fields[..] {
+                [code, jt, jf, k] => Some(BPFFilter::from((
+                    code.parse().ok()?,
+                    jt.parse().ok()?,
+                    jf.parse().ok()?,
+                    k.parse().ok()?,
+                ))),
+                _ => None,
+            }
+        })
+        .collect::<Option<Vec<_>>>();
+    match program {
+        Some(program) if program.len() == count => Ok(program),
+        Some(program) => line.error(
+            value,
+            format!("{} instructions announced, {} found", count, program.len()),
+        ),
+        None => line.error(value, "invalid instruction in the bytecode"),
+    }
+}
+
+struct Pending {
+    config: FilterConfig,
+    source: Option<FilterSource>,
+    /// the column of the `[filters.<name>]` header
+    column: usize,
+}
+
+fn finish(pending: Pending) -> Result<FilterConfig, ConfigError> {
+    let mut config = pending.config;
+    match pending.source {
+        Some(source) => config.source = source,
+        None => {
+            return error(
+                config.line,
+                pending.column,
+                format!("{} has no expression, preset or bytecode", config.name),
+            )
+        }
+    }
+    Ok(config)
+}
+
+/// parse the filters of a configuration
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::config;
+///
+/// let text = "[filters.arp]\npreset = \"arp_only\"\ninterfaces = [\"eth0\"]\n";
+/// let filters = config::parse(text).unwrap();
+/// assert_eq!(filters[0].name, "arp");
+/// let program = filters[0].program(|_, _| Err("no compiler")).unwrap();
+/// assert_eq!(program.len(), 4);
+/// ```
+pub fn parse(text: &str) -> Result<Vec<FilterConfig>, ConfigError> {
+    let mut filters: Vec<FilterConfig> = Vec::new();
+    let mut pending: Option<Pending> = None;
+    for (i, raw) in text.lines().enumerate() {
+        let line = Line {
+            text: raw,
+            number: i + 1,
+        };
+        let content = raw.trim();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if let Some(header) = content.strip_prefix('[') {
+            let header = match header
+                .split('#')
+                .next()
+                .unwrap_or(header)
+                .trim()
+                .strip_suffix(']')
+            {
+                Some(header) => header.trim(),
+                None => return line.error(content, "unterminated table header"),
+            };
+            let name = match header.strip_prefix("filters.") {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => return line.error(header, format!("unknown table {}", header)),
+            };
+            if filters.iter().any(|f| f.name == name)
+                || pending.as_ref().is_some_and(|p| p.config.name == name)
+            {
+                return line.error(header, format!("filter {} is defined twice", name));
+            }
+            if let Some(done) = pending.take() {
+                filters.push(finish(done)?);
+            }
+            pending = Some(Pending {
+                config: FilterConfig {
+                    name,
+                    source: FilterSource::Bytecode(Vec::new()),
+                    dlt: DLT_EN10MB,
+                    interfaces: Vec::new(),
+                    line: line.number,
+                    source_line: 0,
+                    source_column: 0,
+                },
+                source: None,
+                column: line.column(content),
+            });
+            continue;
+        }
+        let (key, value) = match content.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => return line.error(content, "expected key = value"),
+        };
+        let current = match pending.as_mut() {
+            Some(current) => current,
+            None => return line.error(key, format!("{} outside of a [filters.<name>] table", key)),
+        };
+        let source = match (key, parse_item(value, line)?) {
+            ("expression", Item::Str(s)) => Some(FilterSource::Expression(s)),
+            ("preset", Item::Str(s)) => Some(FilterSource::Preset(s)),
+            ("bytecode", Item::Str(s)) => {
+                Some(FilterSource::Bytecode(parse_bytecode(&s, line, value)?))
+            }
+            ("dlt", Item::Int(dlt)) if dlt <= u32::MAX as u64 => {
+                current.config.dlt = dlt as u32;
+                None
+            }
+            ("interfaces", Item::List(interfaces)) => {
+                current.config.interfaces = interfaces;
+                None
+            }
+            ("expression" | "preset" | "bytecode" | "dlt" | "interfaces", _) => {
+                return line.error(value, format!("invalid value for {}", key))
+            }
+            _ => return line.error(key, format!("unknown key {}", key)),
+        };
+        if let Some(source) = source {
+            if current.source.is_some() {
+                return line.error(
+                    key,
+                    "only one of expression, preset and bytecode may be given",
+                );
+            }
+            current.source = Some(source);
+            current.config.source_line = line.number;
+            current.config.source_column = line.column(value);
+        }
+    }
+    if let Some(done) = pending.take() {
+        filters.push(finish(done)?);
+    }
+    Ok(filters)
+}
+
+#[test]
+fn test_parse() {
+    let text = r#"
+# comment
+[filters.dns]
+expression = "udp port 53" # trailing comment
+dlt = 113
+interfaces = ["eth0", "eth1"]
+
+[filters.raw]
+bytecode = "4,40 0 0 12,21 0 1 2054,6 0 0 262144,6 0 0 0"
+"#;
+    let filters = parse(text).unwrap();
+    assert_eq!(
+        filters[0].source,
+        FilterSource::Expression("udp port 53".to_string())
+    );
+    assert_eq!(filters[0].dlt, 113);
+    assert_eq!(filters[0].interfaces, ["eth0", "eth1"]);
+    assert_eq!(filters[1].line, 8);
+    let compiled = filters[0].program(|expression, dlt| {
+        assert_eq!((expression, dlt), ("udp port 53", 113));
+        Ok::<_, String>(presets::accept_all().to_vec())
+    });
+    assert_eq!(compiled.unwrap(), presets::accept_all());
+    assert_eq!(filters[1].program(|_, _| Err("unused")).unwrap().len(), 4);
+
+    let error = |text: &str| {
+        let e = parse(text).unwrap_err();
+        (e.line, e.column)
+    };
+    assert_eq!(
+        error("[filters.a]\npreset = \"x\"\nbytecode = \"1,6 0 0 0\""),
+        (3, 1)
+    );
+    assert_eq!(error("[filters.a]\n\n[filters.b]\npreset = \"x\""), (1, 1));
+    assert_eq!(error("[filters.a]\ndlt = \"one\""), (2, 7));
+    assert_eq!(error("[filters.a]\n  dlt = 1 x"), (2, 9));
+    assert_eq!(error("[filters.a]\nexpression = \"a\\q\""), (2, 16));
+    assert_eq!(error("[filters.a]\nbytecode = \"2,6 0 0 0\""), (2, 12));
+    let unknown = parse("[filters.a]\npreset = \"nope\"").unwrap();
+    let e = unknown[0].program(|_, _| Err("unused")).unwrap_err();
+    assert_eq!((e.line, e.column), (2, 10));
+    assert_eq!(e.to_string(), "line 2, column 10: a: unknown preset nope");
+}
@@ -0,0 +1,162 @@
+use crate::bpf_base::bpf::*;
+use crate::bpf_base::BPFFilter;
+
+const NOP: BPFFilter = BPFFilter {
+    code: 0,
+    jt: 0,
+    jf: 0,
+    k: 0,
+};
+
+/// builds a fixed-size classic BPF program at compile time
+///
+/// every method is a `const fn`, so a program assigned to a `const` or `static`
+/// is assembled and checked by the compiler: pushing more than `N` instructions,
+/// pushing fewer, jumping past the end or not ending with a RET fails the build.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // filter the ICMPv6 packets
+/// static FILTERS: [BPFFilter; 4] = ConstBuilder::new()
+///     .ld(bpf::B, bpf::ABS, 6)
+///     .jmp(bpf::JEQ, bpf::K, libc::IPPROTO_ICMPV6 as u32, 0, 1)
+///     .ret_k(u32::MAX)
+///     .ret_k(0)
+///     .build();
+///
+/// let program = BPFFProg::new(&FILTERS);
+/// ```
+///
+/// a program of the wrong length does not compile:
+///
+/// ```compile_fail
+/// use classic_bpf::*;
+///
+/// const FILTERS: [BPFFilter; 2] = ConstBuilder::new().ret_k(0).build();
+/// ```
+#[derive(Debug)]
+pub struct ConstBuilder<const N: usize> {
+    insns: [BPFFilter; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for ConstBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ConstBuilder<N> {
+    pub const fn new() -> Self {
+        Self {
+            insns: [NOP; N],
+            len: 0,
+        }
+    }
+
+    /// append an instruction given by its raw opcode
+    pub const fn jump(mut self, code: u16, k: u32, jt: u8, jf: u8) -> Self {
+        if self.len == N {
+            panic!("ConstBuilder: more instructions than the program length");
+        }
+        self.insns[self.len] = BPFFilter { code, jt, jf, k };
+        self.len += 1;
+        self
+    }
+
+    /// append a non-jump instruction given by its raw opcode
+    pub const fn stmt(self, code: u16, k: u32) -> Self {
+        self.jump(code, k, 0, 0)
+    }
+
+    /// `LD | size | mode`
+    pub const fn ld(self, size: BPFSize, mode: BPFMode, k: u32) -> Self {
+        self.stmt(LD.0 | size.0 | mode.0, k)
+    }
+
+    /// `LDX | size | mode`
+    pub const fn ldx(self, size: BPFSize, mode: BPFMode, k: u32) -> Self {
+        self.stmt(LDX.0 | size.0 | mode.0, k)
+    }
+
+    /// `ST`, M[k] = A
+    pub const fn st(self, k: u32) -> Self {
+        self.stmt(ST.0, k)
+    }
+
+    /// `STX`, M[k] = X
+    pub const fn stx(self, k: u32) -> Self {
+        self.stmt(STX.0, k)
+    }
+
+    /// `ALU | op | src`
+    pub const fn alu(self, op: BPFOp, src: BPFSrc, k: u32) -> Self {
+        self.stmt(ALU.0 | op.0 | src.0, k)
+    }
+
+    /// `JMP | op | src`
+    pub const fn jmp(self, op: BPFJmpOp, src: BPFSrc, k: u32, jt: u8, jf: u8) -> Self {
+        self.jump(JMP.0 | op.0 | src.0, k, jt, jf)
+    }
+
+    /// `RET | K`
+    pub const fn ret_k(self, k: u32) -> Self {
+        self.stmt(RET.0 | K.0, k)
+    }
+
+    /// `RET | A`
+    pub const fn ret_a(self) -> Self {
+        self.stmt(RET.0 | A.0, 0)
+    }
+
+    /// `MISC | op`
+    pub const fn misc(self, op: BPFMiscOp) -> Self {
+        self.stmt(MISC.0 | op.0, 0)
+    }
+
+    /// check the program and return its instructions
+    pub const fn build(self) -> [BPFFilter; N] {
+        if self.len != N {
+            panic!("ConstBuilder: fewer instructions than the program length");
+        }
+        let mut i = 0;
+        while i < N {
+            let insn = &self.insns[i];
+            // conditional jumps use jt/jf, JA uses k
+            if insn.code & 0x07 == JMP.0 {
+                let far = if insn.code & 0xf0 == JA.0 {
+                    insn.k as usize
+                } else if insn.jt > insn.jf {
+                    insn.jt as usize
+                } else {
+                    insn.jf as usize
+                };
+                if far >= N - i - 1 {
+                    panic!("ConstBuilder: jump past the end of the program");
+                }
+            }
+            i += 1;
+        }
+        if N == 0 || self.insns[N - 1].code & 0x07 != RET.0 {
+            panic!("ConstBuilder: the program does not end with a RET");
+        }
+        self.insns
+    }
+}
+
+#[test]
+fn test_const_builder() {
+    const FILTERS: [BPFFilter; 4] = ConstBuilder::new()
+        .ld(H, ABS, 12)
+        .jmp(JEQ, K, 0x86dd, 0, 1)
+        .ret_a()
+        .ret_k(0)
+        .build();
+    assert_eq!(FILTERS[0].code, 0x28);
+    assert_eq!(FILTERS[1].code, 0x15);
+    assert_eq!(FILTERS[1].jf, 1);
+    assert_eq!(FILTERS[2].code, 0x16);
+}
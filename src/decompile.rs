@@ -0,0 +1,371 @@
+//! best-effort reconstruction of pcap-filter expressions
+//!
+//! the accept paths found by the path analysis are matched against the code
+//! shapes libpcap emits for Ethernet (DLT_EN10MB) captures.
+
+use crate::analysis::{symbolic_paths, AluOp, AnalysisError, Cmp, Condition, Value};
+use crate::bpf_base::BPFFilter;
+use std::net::Ipv4Addr;
+
+/// the outcome of [`decompile`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decompiled {
+    /// the reconstructed expression, with unrecognized conditions as `<?...?>`
+    pub expression: String,
+    /// whether every condition of the program was recognized
+    pub exact: bool,
+}
+
+/// the offset of the IPv4 and IPv6 headers behind an Ethernet header
+const NET_OFF: u32 = 14;
+
+fn ether_proto(k: u32) -> String {
+    match k {
+        0x0800 => "ip".to_string(),
+        0x86dd => "ip6".to_string(),
+        0x0806 => "arp".to_string(),
+        0x8035 => "rarp".to_string(),
+        k => format!("ether proto {:#x}", k),
+    }
+}
+
+fn ip_proto(family: &str, k: u32) -> String {
+    match (family, k) {
+        (_, 6) => "tcp".to_string(),
+        (_, 17) => "udp".to_string(),
+        (_, 132) => "sctp".to_string(),
+        ("ip", 1) => "icmp".to_string(),
+        ("ip", 2) => "igmp".to_string(),
+        ("ip6", 58) => "icmp6".to_string(),
+        (family, k) => format!("{} proto {}", family, k),
+    }
+}
+
+fn prefix_len(mask: u32) -> Option<u32> {
+    if mask.leading_ones() + mask.trailing_zeros() == 32 {
+        Some(mask.leading_ones())
+    } else {
+        None
+    }
+}
+
+/// the port fields, `Some(true)` for the source port
+fn port_field(value: &Value) -> Option<bool> {
+    match value {
+        Value::Packet {
+            index: Some(x),
+            offset,
+            size: 2,
+        } if **x == Value::HeaderLen(NET_OFF) => match *offset {
+            14 => Some(true),
+            16 => Some(false),
+            _ => None,
+        },
+        // IPv6 without extension headers
+        Value::Packet {
+            index: None,
+            offset: 54,
+            size: 2,
+        } => Some(true),
+        Value::Packet {
+            index: None,
+            offset: 56,
+            size: 2,
+        } => Some(false),
+        _ => None,
+    }
+}
+
+/// the field of `size` bytes at `offset` of an absolute load
+fn field(offset: u32, size: u8) -> Value {
+    Value::Packet {
+        index: None,
+        offset,
+        size,
+    }
+}
+
+fn direction(src: bool) -> &'static str {
+    if src {
+        "src"
+    } else {
+        "dst"
+    }
+}
+
+/// render a condition holding for an equality or a mask-and-compare
+fn positive_term(lhs: &Value, k: u32) -> Option<String> {
+    if let Some(src) = port_field(lhs) {
+        return Some(format!("{} port {}", direction(src), k));
+    }
+    match lhs {
+        Value::Packet {
+            index: None,
+            offset,
+            size,
+        } => match (*offset, *size) {
+            (12, 2) => Some(ether_proto(k)),
+            (23, 1) => Some(ip_proto("ip", k)),
+            (20, 1) => Some(ip_proto("ip6", k)),
+            (26, 4) => Some(format!("src host {}", Ipv4Addr::from(k))),
+            (30, 4) => Some(format!("dst host {}", Ipv4Addr::from(k))),
+            _ => None,
+        },
+        Value::Alu(AluOp::And, field, mask) => match (&**field, &**mask) {
+            (
+                Value::Packet {
+                    index: None,
+                    offset,
+                    size: 4,
+                },
+                Value::Const(mask),
+            ) if *offset == 26 || *offset == 30 => {
+                let len = prefix_len(*mask)?;
+                let src = *offset == 26;
+                Some(format!(
+                    "{} net {}/{}",
+                    direction(src),
+                    Ipv4Addr::from(k),
+                    len
+                ))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// the terms of one accept path, `Err` holding an unrecognized condition
+fn path_terms(conditions: &[Condition]) -> Vec<Result<String, String>> {
+    let mut terms = Vec::new();
+    // (lower, upper) bounds of the port fields, for portrange
+    let mut ranges: [(Option<u32>, Option<u32>); 2] = [(None, None); 2];
+    // libpcap also looks for the IPv6 protocol behind a fragment header
+    let next_header = field(NET_OFF + 6, 1);
+    let behind_fragment = field(NET_OFF + 40, 1);
+    let fragment = conditions
+        .iter()
+        .any(|c| c.lhs == next_header && c.cmp == Cmp::Eq && c.holds && c.rhs == Value::Const(44))
+        && conditions.iter().any(|c| c.lhs == behind_fragment);
+    for (i, c) in conditions.iter().enumerate() {
+        let rhs = match c.rhs {
+            Value::Const(k) => k,
+            _ => {
+                terms.push(Err(c.to_string()));
+                continue;
+            }
+        };
+        let lhs = if fragment && c.lhs == behind_fragment {
+            &next_header
+        } else {
+            &c.lhs
+        };
+        // a field that is later (or earlier) known to be equal to something
+        // makes its inequalities redundant, as in the ip6/ip dispatch
+        let decided = || {
+            conditions.iter().enumerate().any(|(j, other)| {
+                j != i && other.lhs == c.lhs && other.cmp == Cmp::Eq && other.holds
+            })
+        };
+        // the test for a fragment header after the protocol test failed
+        let fragment_check = *lhs == next_header
+            && rhs == 44
+            && (c.holds
+                || conditions.iter().any(|other| {
+                    other.lhs == next_header && other.cmp == Cmp::Eq && other.rhs != c.rhs
+                }));
+        if fragment_check && (fragment || !c.holds) {
+            continue;
+        }
+        match (c.cmp, c.holds) {
+            (Cmp::Eq, true) => match positive_term(lhs, rhs) {
+                Some(term) => terms.push(Ok(term)),
+                None => terms.push(Err(c.to_string())),
+            },
+            (Cmp::Eq, false) if decided() => {}
+            (Cmp::Eq, false) => match positive_term(lhs, rhs) {
+                Some(term) => terms.push(Ok(format!("not {}", term))),
+                None => terms.push(Err(c.to_string())),
+            },
+            // the "not a fragment" check libpcap emits before port tests
+            (Cmp::Set, false) if rhs == 0x1fff && c.lhs == field(NET_OFF + 6, 2) => {}
+            (Cmp::Ge, true) | (Cmp::Gt, false) if port_field(&c.lhs).is_some() => {
+                let range = &mut ranges[(port_field(&c.lhs) == Some(true)) as usize];
+                if c.cmp == Cmp::Ge {
+                    range.0 = Some(rhs);
+                } else {
+                    range.1 = Some(rhs);
+                }
+            }
+            _ => terms.push(Err(c.to_string())),
+        }
+    }
+    for (src, range) in ranges.iter().enumerate() {
+        let dir = direction(src == 1);
+        match *range {
+            (Some(lo), Some(hi)) => terms.push(Ok(format!("{} portrange {}-{}", dir, lo, hi))),
+            (Some(lo), None) => terms.push(Err(format!("{} port >= {}", dir, lo))),
+            (None, Some(hi)) => terms.push(Err(format!("{} port <= {}", dir, hi))),
+            (None, None) => {}
+        }
+    }
+    terms
+}
+
+/// reconstruct an approximate pcap-filter expression from a program
+///
+/// only Ethernet captures of the shapes libpcap emits are recognized: link,
+/// network and transport protocols, IPv4 hosts and nets, ports and port ranges.
+/// anything else is kept in the expression as `<?condition?>` and clears
+/// [`Decompiled::exact`].
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // tcpdump -dd 'ip and udp'
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 3),
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 17, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 262144),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+///
+/// let decompiled = decompile(&filters).unwrap();
+/// assert_eq!(decompiled.expression, "ip and udp");
+/// assert!(decompiled.exact);
+/// ```
+pub fn decompile(filters: &[BPFFilter]) -> Result<Decompiled, AnalysisError> {
+    let paths = symbolic_paths(filters)?;
+    let mut exact = true;
+    let mut alternatives: Vec<Vec<String>> = Vec::new();
+    let mut all_accept = true;
+    for path in &paths {
+        match path.ret {
+            Value::Const(0) => {
+                all_accept = false;
+                continue;
+            }
+            Value::Const(_) => {}
+            _ => exact = false,
+        }
+        let terms = path_terms(&path.conditions)
+            .into_iter()
+            .map(|term| {
+                term.unwrap_or_else(|condition| {
+                    exact = false;
+                    format!("<?{}?>", condition)
+                })
+            })
+            .collect::<Vec<_>>();
+        if !alternatives.contains(&terms) {
+            alternatives.push(terms);
+        }
+    }
+
+    if all_accept && exact {
+        // the empty expression accepts everything
+        return Ok(Decompiled {
+            expression: String::new(),
+            exact,
+        });
+    }
+    if alternatives.is_empty() {
+        return Ok(Decompiled {
+            expression: "len < 0".to_string(),
+            exact,
+        });
+    }
+
+    // libpcap tests transport protocols for IPv6 and IPv4 in turn,
+    // fold those pairs of alternatives back into one
+    let mut folded: Vec<Vec<String>> = Vec::new();
+    for terms in alternatives {
+        let family = terms.first().map(String::as_str);
+        if family == Some("ip") || family == Some("ip6") {
+            let other = if family == Some("ip") { "ip6" } else { "ip" };
+            let rest = &terms[1..];
+            if let Some(pos) = folded
+                .iter()
+                .position(|t| t.first().map(String::as_str) == Some(other) && &t[1..] == rest)
+            {
+                if !rest.is_empty() {
+                    folded[pos] = rest.to_vec();
+                    continue;
+                }
+            }
+        }
+        folded.push(terms);
+    }
+
+    let expression = if folded.len() == 1 {
+        folded[0].join(" and ")
+    } else {
+        folded
+            .iter()
+            .map(|terms| {
+                if terms.len() > 1 {
+                    format!("({})", terms.join(" and "))
+                } else {
+                    terms.join(" and ")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" or ")
+    };
+    Ok(Decompiled { expression, exact })
+}
+
+#[test]
+fn test_decompile_tcp_dst_port() {
+    use crate::bpf_base::bpf;
+    // tcpdump -dd 'tcp dst port 443'
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 4),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 20),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x6, 0, 11),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 56),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x1bb, 8, 9),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x800, 0, 8),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x6, 0, 6),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 20),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JSET | bpf::K, 0x1fff, 4, 0),
+        BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::IND, 16),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x1bb, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0x40000),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    ];
+    let decompiled = decompile(&filters).unwrap();
+    assert_eq!(decompiled.expression, "tcp and dst port 443");
+    assert!(decompiled.exact);
+}
+
+#[test]
+fn test_decompile_ip6_fragment() {
+    use crate::bpf_base::bpf;
+    // tcpdump -dd 'tcp', which looks behind an IPv6 fragment header too
+    let jeq = |k, jt, jf| BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, k, jt, jf);
+    let tcp = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        jeq(0x86dd, 0, 5),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 20),
+        jeq(0x6, 6, 0),
+        jeq(0x2c, 0, 6),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 54),
+        jeq(0x6, 3, 4),
+        jeq(0x800, 0, 3),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+        jeq(0x6, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0x40000),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    ];
+    let decompiled = decompile(&tcp).unwrap();
+    assert_eq!(decompiled.expression, "tcp");
+    assert!(decompiled.exact);
+}
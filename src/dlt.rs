@@ -0,0 +1,109 @@
+//! data link types of the captured frames
+//!
+//! the values are the `DLT_*` numbers of libpcap and of the BPF devices.
+
+/// BSD loopback, a 4-byte host-order address family
+pub const DLT_NULL: u32 = 0;
+/// Ethernet
+pub const DLT_EN10MB: u32 = 1;
+/// raw IP packets, without any link-layer header
+pub const DLT_RAW: u32 = 12;
+/// OpenBSD loopback, a 4-byte network-order address family
+pub const DLT_LOOP: u32 = 108;
+/// Linux cooked captures, behind the 16-byte `sll` header
+pub const DLT_LINUX_SLL: u32 = 113;
+/// 802.11 frames without any capture header
+pub const DLT_IEEE802_11: u32 = 105;
+/// 802.11 frames behind a radiotap header, as captured in monitor mode
+pub const DLT_IEEE802_11_RADIO: u32 = 127;
+
+/// a link type, the typed form of the `DLT_*` numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Dlt {
+    Null,
+    En10mb,
+    Raw,
+    Loop,
+    LinuxSll,
+    Ieee802_11,
+    Ieee802_11Radio,
+}
+
+impl Dlt {
+    /// the `DLT_*` number of the link type
+    pub fn value(self) -> u32 {
+        match self {
+            Dlt::Null => DLT_NULL,
+            Dlt::En10mb => DLT_EN10MB,
+            Dlt::Raw => DLT_RAW,
+            Dlt::Loop => DLT_LOOP,
+            Dlt::LinuxSll => DLT_LINUX_SLL,
+            Dlt::Ieee802_11 => DLT_IEEE802_11,
+            Dlt::Ieee802_11Radio => DLT_IEEE802_11_RADIO,
+        }
+    }
+}
+
+impl From<Dlt> for u32 {
+    fn from(dlt: Dlt) -> Self {
+        dlt.value()
+    }
+}
+
+impl std::convert::TryFrom<u32> for Dlt {
+    /// the unknown `DLT_*` number
+    type Error = u32;
+
+    fn try_from(dlt: u32) -> Result<Self, u32> {
+        match dlt {
+            DLT_NULL => Ok(Dlt::Null),
+            DLT_EN10MB => Ok(Dlt::En10mb),
+            DLT_RAW => Ok(Dlt::Raw),
+            DLT_LOOP => Ok(Dlt::Loop),
+            DLT_LINUX_SLL => Ok(Dlt::LinuxSll),
+            DLT_IEEE802_11 => Ok(Dlt::Ieee802_11),
+            DLT_IEEE802_11_RADIO => Ok(Dlt::Ieee802_11Radio),
+            dlt => Err(dlt),
+        }
+    }
+}
+
+/// the offset of the network header in the frames of link type `dlt`
+///
+/// returns `None` for the link types whose header length is not fixed, such
+/// as `DLT_IEEE802_11_RADIO`. VLAN tags are not accounted for.
+pub fn network_offset(dlt: u32) -> Option<u32> {
+    match dlt {
+        DLT_NULL | DLT_LOOP => Some(4),
+        DLT_EN10MB => Some(14),
+        DLT_RAW => Some(0),
+        DLT_LINUX_SLL => Some(16),
+        _ => None,
+    }
+}
+
+/// the length of the radiotap header leading a `DLT_IEEE802_11_RADIO` frame
+///
+/// the 802.11 frame starts right after it. returns `None` when `frame` is
+/// too short or not a version 0 radiotap header.
+pub fn radiotap_len(frame: &[u8]) -> Option<usize> {
+    match frame {
+        [0, _, lo, hi, ..] => {
+            let len = u16::from_le_bytes([*lo, *hi]) as usize;
+            if len >= 8 && len <= frame.len() {
+                Some(len)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_radiotap_len() {
+    let frame = [0, 0, 0x0c, 0, 0x04, 0x80, 0, 0, 0x02, 0, 0x18, 0, 0x80, 0];
+    assert_eq!(radiotap_len(&frame), Some(12));
+    assert_eq!(radiotap_len(&frame[..10]), None);
+    assert_eq!(radiotap_len(&[1, 0, 8, 0, 0, 0, 0, 0]), None);
+}
@@ -0,0 +1,392 @@
+use crate::ancillary::*;
+use crate::bpf_base::*;
+use crate::validate::*;
+use std::fmt;
+
+/// an eBPF instruction, laid out as the Linux `struct bpf_insn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(C)]
+pub struct EbpfInsn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+impl EbpfInsn {
+    /// an instruction from its opcode, registers, offset and immediate
+    pub fn new(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> Self {
+        Self {
+            code,
+            regs: (src << 4) | (dst & 0x0f),
+            off,
+            imm,
+        }
+    }
+
+    /// the opcode of the instruction
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    /// the destination register
+    pub fn dst(&self) -> u8 {
+        self.regs & 0x0f
+    }
+
+    /// the source register
+    pub fn src(&self) -> u8 {
+        self.regs >> 4
+    }
+
+    /// the offset of the memory access or of the jump
+    pub fn off(&self) -> i16 {
+        self.off
+    }
+
+    /// the immediate operand
+    pub fn imm(&self) -> i32 {
+        self.imm
+    }
+}
+
+/// the reason a classic program has no eBPF translation, from [`to_ebpf`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TranslationError {
+    /// the program does not pass [`validate`]
+    Invalid(ValidationError),
+    /// the instruction at `index` loads the ancillary field `SKF_AD_OFF + field`,
+    /// which eBPF socket filters cannot read
+    UnsupportedExtension { index: usize, field: u32 },
+}
+
+impl fmt::Display for TranslationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranslationError::Invalid(error) => error.fmt(f),
+            TranslationError::UnsupportedExtension { index, field } => write!(
+                f,
+                "instruction {}: the ancillary field {} has no eBPF equivalent",
+                index, field
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TranslationError {}
+
+impl From<ValidationError> for TranslationError {
+    fn from(error: ValidationError) -> Self {
+        TranslationError::Invalid(error)
+    }
+}
+
+// the registers, as bpf_convert_filter() assigns them, the temporary one is
+// callee-saved so that the packet loads keep it
+const REG_A: u8 = 0;
+const REG_ARG1: u8 = 1;
+const REG_CTX: u8 = 6;
+const REG_X: u8 = 7;
+const REG_TMP: u8 = 8;
+const REG_FP: u8 = 10;
+
+const MOV32_K: u8 = 0xb4;
+const MOV32_X: u8 = 0xbc;
+const MOV64_X: u8 = 0xbf;
+const AND32_K: u8 = 0x54;
+const LSH32_K: u8 = 0x64;
+const XOR32_X: u8 = 0xac;
+const BE: u8 = 0xdc;
+const LDX_W: u8 = 0x61;
+const STX_W: u8 = 0x63;
+const JNE_K: u8 = 0x55;
+const CALL: u8 = 0x85;
+const EXIT: u8 = 0x95;
+
+// BPF_FUNC_get_prandom_u32 and BPF_FUNC_get_smp_processor_id
+const GET_PRANDOM_U32: i32 = 7;
+const GET_SMP_PROCESSOR_ID: i32 = 8;
+
+// the fields of struct __sk_buff
+const SKB_LEN: i16 = 0;
+const SKB_PKT_TYPE: i16 = 4;
+const SKB_MARK: i16 = 8;
+const SKB_QUEUE_MAPPING: i16 = 12;
+const SKB_PROTOCOL: i16 = 16;
+const SKB_VLAN_PRESENT: i16 = 20;
+const SKB_VLAN_TCI: i16 = 24;
+const SKB_VLAN_PROTO: i16 = 28;
+const SKB_IFINDEX: i16 = 40;
+const SKB_HASH: i16 = 68;
+
+/// the stack offset of M[slot]
+fn slot(k: u32) -> i16 {
+    -4 * (k as i16 + 1)
+}
+
+#[derive(Default)]
+struct Translation {
+    insns: Vec<EbpfInsn>,
+    /// the jumps to resolve, with the classic instruction they go to
+    jumps: Vec<(usize, usize)>,
+}
+
+impl Translation {
+    fn emit(&mut self, code: u8, dst: u8, src: u8, off: i16, imm: i32) {
+        self.insns.push(EbpfInsn::new(code, dst, src, off, imm));
+    }
+
+    fn jump(&mut self, code: u8, src: u8, imm: i32, target: usize) {
+        self.jumps.push((self.insns.len(), target));
+        self.emit(code, REG_A, src, 0, imm);
+    }
+
+    fn extension(&mut self, index: usize, field: u32) -> Result<(), TranslationError> {
+        let load = match field {
+            SKF_AD_PROTOCOL => SKB_PROTOCOL,
+            SKF_AD_PKTTYPE => SKB_PKT_TYPE,
+            SKF_AD_IFINDEX => SKB_IFINDEX,
+            SKF_AD_MARK => SKB_MARK,
+            SKF_AD_QUEUE => SKB_QUEUE_MAPPING,
+            SKF_AD_RXHASH => SKB_HASH,
+            SKF_AD_VLAN_TAG => SKB_VLAN_TCI,
+            SKF_AD_VLAN_TAG_PRESENT => SKB_VLAN_PRESENT,
+            SKF_AD_VLAN_TPID => SKB_VLAN_PROTO,
+            SKF_AD_CPU => {
+                self.emit(CALL, 0, 0, 0, GET_SMP_PROCESSOR_ID);
+                return Ok(());
+            }
+            SKF_AD_RANDOM => {
+                self.emit(CALL, 0, 0, 0, GET_PRANDOM_U32);
+                return Ok(());
+            }
+            SKF_AD_ALU_XOR_X => {
+                self.emit(XOR32_X, REG_A, REG_X, 0, 0);
+                return Ok(());
+            }
+            field => return Err(TranslationError::UnsupportedExtension { index, field }),
+        };
+        self.emit(LDX_W, REG_A, REG_CTX, load, 0);
+        // __sk_buff keeps both EtherTypes in network byte order
+        if matches!(field, SKF_AD_PROTOCOL | SKF_AD_VLAN_TPID) {
+            self.emit(BE, REG_A, 0, 0, 16);
+        }
+        Ok(())
+    }
+}
+
+/// translate a classic program to eBPF, as the Linux `bpf_convert_filter()` does
+///
+/// the result is a `BPF_PROG_TYPE_SOCKET_FILTER` program: A lives in R0, X in
+/// R7, the context in R6 and M[] on the stack. packet loads keep the eBPF
+/// `LD_ABS` and `LD_IND` instructions, the ancillary loads read `__sk_buff`
+/// or call the matching helper. the netlink attribute, payload offset and
+/// hardware type fields have no such equivalent.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+///
+/// let insns = to_ebpf(&filters).unwrap();
+/// // the 3 instructions of the prologue, then the JEQ becomes a JNE
+/// assert_eq!(insns.len(), 9);
+/// assert_eq!(insns[4].code(), 0x55);
+/// assert_eq!(insns[4].off(), 2);
+/// ```
+pub fn to_ebpf(filters: &[BPFFilter]) -> Result<Vec<EbpfInsn>, TranslationError> {
+    validate(filters)?;
+
+    let mut t = Translation::default();
+    // A and X start at 0, set rather than XORed as the verifier refuses to
+    // read registers that were never written
+    t.emit(MOV32_K, REG_A, 0, 0, 0);
+    t.emit(MOV32_K, REG_X, 0, 0, 0);
+    t.emit(MOV64_X, REG_CTX, REG_ARG1, 0, 0);
+
+    let mut starts = Vec::with_capacity(filters.len());
+    for (index, insn) in filters.iter().enumerate() {
+        starts.push(t.insns.len());
+        let (code, k) = (insn.code(), insn.k());
+        let next = index + 1;
+        match code {
+            // LD and LDX
+            0x00 => t.emit(MOV32_K, REG_A, 0, 0, k as i32),
+            0x01 => t.emit(MOV32_K, REG_X, 0, 0, k as i32),
+            0x20 | 0x28 | 0x30 if k >= SKF_AD_OFF => t.extension(index, k - SKF_AD_OFF)?,
+            0x20 | 0x28 | 0x30 => t.emit(code as u8, REG_A, 0, 0, k as i32),
+            0x40 | 0x48 | 0x50 => t.emit(code as u8, REG_A, REG_X, 0, k as i32),
+            0x60 => t.emit(LDX_W, REG_A, REG_FP, slot(k), 0),
+            0x61 => t.emit(LDX_W, REG_X, REG_FP, slot(k), 0),
+            0x80 => t.emit(LDX_W, REG_A, REG_CTX, SKB_LEN, 0),
+            0x81 => t.emit(LDX_W, REG_X, REG_CTX, SKB_LEN, 0),
+            0xb1 => {
+                t.emit(MOV64_X, REG_TMP, REG_A, 0, 0);
+                t.emit(0x30, REG_A, 0, 0, k as i32);
+                t.emit(AND32_K, REG_A, 0, 0, 0xf);
+                t.emit(LSH32_K, REG_A, 0, 0, 2);
+                t.emit(MOV64_X, REG_X, REG_A, 0, 0);
+                t.emit(MOV64_X, REG_A, REG_TMP, 0, 0);
+            }
+            // ST and STX
+            0x02 => t.emit(STX_W, REG_FP, REG_A, slot(k), 0),
+            0x03 => t.emit(STX_W, REG_FP, REG_X, slot(k), 0),
+            // ALU
+            0x84 => t.emit(0x84, REG_A, 0, 0, 0),
+            code if code & 0x07 == 0x04 => {
+                if code & 0x08 == 0 {
+                    t.emit(code as u8, REG_A, 0, 0, k as i32);
+                } else {
+                    if matches!(code, 0x3c | 0x9c) {
+                        // a division by X = 0 returns 0
+                        t.emit(MOV32_X, REG_X, REG_X, 0, 0);
+                        t.emit(JNE_K, REG_X, 0, 2, 0);
+                        t.emit(XOR32_X, REG_A, REG_A, 0, 0);
+                        t.emit(EXIT, 0, 0, 0, 0);
+                    }
+                    t.emit(code as u8, REG_A, REG_X, 0, 0);
+                }
+            }
+            // JMP
+            0x05 => t.jump(0x05, 0, 0, next + k as usize),
+            code if code & 0x07 == 0x05 => {
+                // eBPF immediates are signed, the larger constants go
+                // through the temporary register to compare as unsigned
+                let (src, reg, imm) = if code & 0x08 != 0 {
+                    (0x08, REG_X, 0)
+                } else if (k as i32) < 0 {
+                    t.emit(MOV32_K, REG_TMP, 0, 0, k as i32);
+                    (0x08, REG_TMP, 0)
+                } else {
+                    (0x00, 0, k as i32)
+                };
+                let op = (code & 0xf0) as u8;
+                let (jt, jf) = (next + insn.jt() as usize, next + insn.jf() as usize);
+                // the inverse of JEQ, JGT and JGE: JNE, JLE and JLT
+                let inverse = match op {
+                    0x10 => Some(0x50),
+                    0x20 => Some(0xb0),
+                    0x30 => Some(0xa0),
+                    _ => None,
+                };
+                match inverse {
+                    _ if insn.jf() == 0 => t.jump(0x05 | op | src, reg, imm, jt),
+                    Some(inverse) if insn.jt() == 0 => t.jump(0x05 | inverse | src, reg, imm, jf),
+                    _ => {
+                        t.jump(0x05 | op | src, reg, imm, jt);
+                        t.jump(0x05, 0, 0, jf);
+                    }
+                }
+            }
+            // RET
+            0x06 => {
+                t.emit(MOV32_K, REG_A, 0, 0, k as i32);
+                t.emit(EXIT, 0, 0, 0, 0);
+            }
+            0x16 => t.emit(EXIT, 0, 0, 0, 0),
+            // MISC
+            0x07 => t.emit(MOV64_X, REG_X, REG_A, 0, 0),
+            0x87 => t.emit(MOV64_X, REG_A, REG_X, 0, 0),
+            code => unreachable!("validate() accepted the opcode {:#x}", code),
+        }
+    }
+
+    // at most 6 eBPF instructions per classic one, the 4096 instructions of
+    // a valid program never need an offset beyond i16::MAX
+    let Translation { mut insns, jumps } = t;
+    for (at, target) in jumps {
+        insns[at].off = (starts[target] - at - 1) as i16;
+    }
+    Ok(insns)
+}
+
+#[test]
+fn test_to_ebpf() {
+    // M[1] = X = IP header length, return A / M[1] when the TOS is 0xff
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+        BPFFilter::bpf_stmt(bpf::STX, 1),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 15),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0xff, 0, 4),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::LEN, 0),
+        BPFFilter::bpf_stmt(bpf::LDX | bpf::MEM, 1),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::DIV | bpf::X, 0),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    ];
+    let insn = EbpfInsn::new;
+    assert_eq!(
+        to_ebpf(&filters).unwrap(),
+        vec![
+            insn(0xb4, 0, 0, 0, 0),
+            insn(0xb4, 7, 0, 0, 0),
+            insn(0xbf, 6, 1, 0, 0),
+            // ldx 4 * ([14] & 0xf)
+            insn(0xbf, 8, 0, 0, 0),
+            insn(0x30, 0, 0, 0, 14),
+            insn(0x54, 0, 0, 0, 0xf),
+            insn(0x64, 0, 0, 0, 2),
+            insn(0xbf, 7, 0, 0, 0),
+            insn(0xbf, 0, 8, 0, 0),
+            // stx M[1]
+            insn(0x63, 10, 7, -8, 0),
+            insn(0x30, 0, 0, 0, 15),
+            // jeq #0xff, 0, 4 jumps to the RET #0
+            insn(0x55, 0, 0, 8, 0xff),
+            // ld len, ldx M[1]
+            insn(0x61, 0, 6, 0, 0),
+            insn(0x61, 7, 10, -8, 0),
+            insn(0xbc, 7, 7, 0, 0),
+            insn(0x55, 7, 0, 2, 0),
+            insn(0xac, 0, 0, 0, 0),
+            insn(0x95, 0, 0, 0, 0),
+            insn(0x3c, 0, 7, 0, 0),
+            insn(0x95, 0, 0, 0, 0),
+            insn(0xb4, 0, 0, 0, 0),
+            insn(0x95, 0, 0, 0, 0),
+        ]
+    );
+
+    // a JSET with both branches, on a constant above i32::MAX
+    let filters = [
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JSET | bpf::K, 0x8000_0000, 1, 2),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF + SKF_AD_PROTOCOL),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 1),
+    ];
+    let insns = to_ebpf(&filters).unwrap();
+    assert_eq!(
+        insns[3..8],
+        [
+            insn(0xb4, 8, 0, 0, i32::MIN),
+            insn(0x4d, 0, 8, 3, 0),
+            insn(0x05, 0, 0, 3, 0),
+            insn(0x61, 0, 6, 16, 0),
+            insn(0xdc, 0, 0, 0, 16),
+        ]
+    );
+    assert_eq!(insns[9], insn(0xb4, 0, 0, 0, 1));
+
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF + SKF_AD_HATYPE),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(
+        to_ebpf(&filters),
+        Err(TranslationError::UnsupportedExtension {
+            index: 0,
+            field: SKF_AD_HATYPE
+        })
+    );
+    assert_eq!(
+        to_ebpf(&[]),
+        Err(TranslationError::Invalid(ValidationError::Empty))
+    );
+}
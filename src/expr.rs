@@ -0,0 +1,314 @@
+//! filter expressions built with Rust operators
+//!
+//! a [`Value`] read from an Ethernet frame is compared into an [`Expr`],
+//! then the expressions are combined with `&`, `|` and `!`:
+//!
+//! ```
+//! use classic_bpf::expr::*;
+//! use classic_bpf::run;
+//!
+//! let icmpv6 = ether_type().eq(0x86dd) & ip6_next_header().eq(58);
+//! let filters = icmpv6.build().unwrap();
+//!
+//! let mut frame = [0u8; 54];
+//! frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+//! frame[20] = 58;
+//! assert_ne!(run(&filters, &frame, 54), 0);
+//! ```
+//!
+//! the program tests the operands from the left and stops as soon as the
+//! result is known.
+
+use crate::analysis::Cmp;
+use crate::bpf_base::bpf::{self, BPFSize};
+use crate::bpf_base::BPFFilter;
+use crate::builder::{BuildError, Label, ProgramBuilder};
+use crate::dlt::Dlt;
+use crate::fragments;
+use crate::offsets::{Eth, Field, Ipv4, Ipv6, Layer, Tcp};
+use std::ops::{BitAnd, BitOr, Not};
+
+/// where a value is read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Load {
+    /// the load of `size` at `offset` from the start of the frame
+    Abs(u16, u32),
+    /// the load of `size` at `offset` from the start of the IPv4 payload
+    Ipv4Payload(u16, u32),
+    /// the length of the packet
+    Len,
+    /// A, as left by the instructions before
+    A,
+}
+
+/// a value of the packet, to compare against a constant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value {
+    load: Load,
+    mask: Option<u32>,
+}
+
+impl Value {
+    fn new(load: Load) -> Self {
+        Self { load, mask: None }
+    }
+
+    /// the bits of the value in `mask`, the others cleared
+    pub fn masked(self, mask: u32) -> Self {
+        Self {
+            mask: Some(self.mask.unwrap_or(u32::MAX) & mask),
+            ..self
+        }
+    }
+
+    fn cmp(self, op: Cmp, k: u32, negated: bool) -> Expr {
+        Expr::Cmp {
+            value: self,
+            op,
+            k,
+            negated,
+        }
+    }
+
+    /// the value is `k`
+    pub fn eq(self, k: u32) -> Expr {
+        self.cmp(Cmp::Eq, k, false)
+    }
+
+    /// the value is not `k`
+    pub fn ne(self, k: u32) -> Expr {
+        self.cmp(Cmp::Eq, k, true)
+    }
+
+    /// the value is greater than `k`
+    pub fn gt(self, k: u32) -> Expr {
+        self.cmp(Cmp::Gt, k, false)
+    }
+
+    /// the value is greater than or equal to `k`
+    pub fn ge(self, k: u32) -> Expr {
+        self.cmp(Cmp::Ge, k, false)
+    }
+
+    /// the value is less than `k`
+    pub fn lt(self, k: u32) -> Expr {
+        self.cmp(Cmp::Ge, k, true)
+    }
+
+    /// the value is less than or equal to `k`
+    pub fn le(self, k: u32) -> Expr {
+        self.cmp(Cmp::Gt, k, true)
+    }
+
+    /// any bit of `mask` is set in the value
+    pub fn any_bits(self, mask: u32) -> Expr {
+        self.cmp(Cmp::Set, mask, false)
+    }
+}
+
+/// a boolean expression on the packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// the comparison of a value with `k`, inverted when `negated`
+    Cmp {
+        value: Value,
+        op: Cmp,
+        k: u32,
+        negated: bool,
+    },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+}
+
+impl Expr {
+    /// emit the tests of the expression, jumping to `accept` when it holds
+    /// and to `reject` otherwise
+    pub fn emit(&self, builder: &mut ProgramBuilder, accept: Label, reject: Label) {
+        match self {
+            Expr::Cmp {
+                value,
+                op,
+                k,
+                negated,
+            } => {
+                match value.load {
+                    Load::Abs(size, offset) => builder.ld_abs(BPFSize(size), offset),
+                    Load::Ipv4Payload(size, offset) => {
+                        fragments::load_ipv4_transport_base(builder, Dlt::En10mb)
+                            .ld_ind(BPFSize(size), Eth::NETWORK + offset)
+                    }
+                    Load::Len => builder.ld_len(),
+                    Load::A => builder,
+                };
+                if let Some(mask) = value.mask {
+                    builder.alu(bpf::AND, bpf::K, mask);
+                }
+                let (jt, jf) = if *negated {
+                    (reject, accept)
+                } else {
+                    (accept, reject)
+                };
+                let op = match op {
+                    Cmp::Eq => bpf::JEQ,
+                    Cmp::Gt => bpf::JGT,
+                    Cmp::Ge => bpf::JGE,
+                    Cmp::Set => bpf::JSET,
+                };
+                builder.jmp_label(op, bpf::K, *k, Some(jt), Some(jf));
+            }
+            Expr::And(left, right) => {
+                let next = builder.label();
+                left.emit(builder, next, reject);
+                builder.bind(next);
+                right.emit(builder, accept, reject);
+            }
+            Expr::Or(left, right) => {
+                let next = builder.label();
+                left.emit(builder, accept, next);
+                builder.bind(next);
+                right.emit(builder, accept, reject);
+            }
+            Expr::Not(inner) => inner.emit(builder, reject, accept),
+        }
+    }
+
+    /// a program accepting the whole packets the expression holds for
+    pub fn build(&self) -> Result<Vec<BPFFilter>, BuildError> {
+        let mut builder = ProgramBuilder::new();
+        let (accept, reject) = (builder.label(), builder.label());
+        self.emit(&mut builder, accept, reject);
+        builder.bind(accept).ret_k(u32::MAX);
+        builder.bind(reject).ret_k(0);
+        builder.build()
+    }
+}
+
+impl BitAnd for Expr {
+    type Output = Expr;
+
+    fn bitand(self, other: Expr) -> Expr {
+        Expr::And(Box::new(self), Box::new(other))
+    }
+}
+
+impl BitOr for Expr {
+    type Output = Expr;
+
+    fn bitor(self, other: Expr) -> Expr {
+        Expr::Or(Box::new(self), Box::new(other))
+    }
+}
+
+impl Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::Not(Box::new(self))
+    }
+}
+
+/// the `size` bytes of the frame at `offset`
+pub fn field(size: BPFSize, offset: u32) -> Value {
+    Value::new(Load::Abs(size.0, offset))
+}
+
+/// the header field `f` of an Ethernet frame, the transport headers behind
+/// IPv4
+fn header_field<F: Field>(f: F) -> Value {
+    let size = f.size().0;
+    Value::new(match f.layer() {
+        Layer::Link => Load::Abs(size, f.offset()),
+        Layer::Network => Load::Abs(size, Eth::NETWORK + f.offset()),
+        Layer::Transport => Load::Ipv4Payload(size, f.offset()),
+    })
+}
+
+/// the length of the packet
+pub fn len() -> Value {
+    Value::new(Load::Len)
+}
+
+/// the accumulator, as left by the instructions before the test or with
+/// the value of the previous test, masked
+pub fn a() -> Value {
+    Value::new(Load::A)
+}
+
+/// the ethertype of an untagged Ethernet frame
+pub fn ether_type() -> Value {
+    header_field(Eth::EtherType)
+}
+
+/// the protocol of an IPv4 packet
+pub fn ip_proto() -> Value {
+    header_field(Ipv4::Protocol)
+}
+
+/// the source address of an IPv4 packet
+pub fn ip_src() -> Value {
+    header_field(Ipv4::Src)
+}
+
+/// the destination address of an IPv4 packet
+pub fn ip_dst() -> Value {
+    header_field(Ipv4::Dst)
+}
+
+/// the fragment offset of an IPv4 packet, 0 for unfragmented packets and first fragments
+pub fn ip_fragment_offset() -> Value {
+    header_field(Ipv4::FlagsFragment).masked(0x1fff)
+}
+
+/// the next header of an IPv6 packet
+pub fn ip6_next_header() -> Value {
+    header_field(Ipv6::NextHeader)
+}
+
+/// the source port of a TCP or UDP segment carried by IPv4
+///
+/// only the first fragment has the ports, see [`ip_fragment_offset`].
+pub fn src_port() -> Value {
+    header_field(Tcp::SrcPort)
+}
+
+/// the destination port of a TCP or UDP segment carried by IPv4
+pub fn dst_port() -> Value {
+    header_field(Tcp::DstPort)
+}
+
+#[test]
+fn test_expr() {
+    use crate::interpreter::run;
+
+    let frame = |ethertype: u16, protocol: u8, port: u16| {
+        let mut frame = vec![0u8; 42];
+        frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        frame[14] = 0x45;
+        frame[23] = protocol;
+        frame[36..38].copy_from_slice(&port.to_be_bytes());
+        frame
+    };
+    let dns = ether_type().eq(0x0800)
+        & ip_fragment_offset().eq(0)
+        & (ip_proto().eq(17) | ip_proto().eq(6))
+        & dst_port().eq(53);
+    let filters = dns.build().unwrap();
+    let not_dns = (!dns).build().unwrap();
+    for (frame, expected) in [
+        (frame(0x0800, 17, 53), true),
+        (frame(0x0800, 6, 53), true),
+        (frame(0x0800, 1, 53), false),
+        (frame(0x0800, 17, 54), false),
+        (frame(0x86dd, 17, 53), false),
+    ] {
+        assert_eq!(run(&filters, &frame, 42) != 0, expected);
+        assert_eq!(run(&not_dns, &frame, 42) != 0, !expected);
+    }
+
+    let small = len().lt(64) & !ether_type().masked(0xff00).ne(0x0800 & 0xff00);
+    let filters = small.build().unwrap();
+    assert_ne!(run(&filters, &frame(0x0806, 0, 0), 42), 0);
+    assert_eq!(run(&filters, &frame(0x86dd, 0, 0), 42), 0);
+    assert_eq!(run(&filters, &[0u8; 64], 64), 0);
+}
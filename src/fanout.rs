@@ -0,0 +1,173 @@
+use crate::bpf_base::*;
+use crate::interface::Interface;
+use crate::linux::{bind_packet_socket, packet_socket};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+
+/// how a fanout group spreads the packets over its sockets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FanoutMode {
+    /// by the hash of the flow, the packets of a flow stay on one socket
+    Hash,
+    /// in turn
+    LoadBalance,
+    /// by the CPU receiving the packet
+    Cpu,
+    /// to the first socket, the next ones when its queue is full
+    Rollover,
+    /// at random
+    Random,
+    /// by the receive queue of the NIC
+    QueueMapping,
+}
+
+impl FanoutMode {
+    fn value(self) -> u32 {
+        match self {
+            FanoutMode::Hash => libc::PACKET_FANOUT_HASH,
+            FanoutMode::LoadBalance => libc::PACKET_FANOUT_LB,
+            FanoutMode::Cpu => libc::PACKET_FANOUT_CPU,
+            FanoutMode::Rollover => libc::PACKET_FANOUT_ROLLOVER,
+            FanoutMode::Random => libc::PACKET_FANOUT_RND,
+            FanoutMode::QueueMapping => libc::PACKET_FANOUT_QM,
+        }
+    }
+}
+
+/// AF_PACKET sockets of one interface sharing its packets (PACKET_FANOUT)
+///
+/// each packet goes to a single socket of the group, one per capture thread.
+/// every member runs the same filter, attached before the socket is bound so
+/// that it never queues an unfiltered packet.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut group = FanoutGroup::new("eth0", 42, FanoutMode::Hash).with_defrag();
+/// for _ in 0..4 {
+///     group.join(presets::arp_only())?;
+/// }
+/// for socket in group.into_sockets() {
+///     std::thread::spawn(move || {
+///         // recv_batch(&socket, ...)
+///         drop(socket);
+///     });
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct FanoutGroup {
+    interface: Interface,
+    id: u16,
+    mode: FanoutMode,
+    flags: u32,
+    sockets: Vec<OwnedFd>,
+}
+
+impl FanoutGroup {
+    /// an empty group `id` on `interface`
+    ///
+    /// the id is shared by all the sockets of the network namespace, the
+    /// sockets joining an existing group must use its mode.
+    pub fn new<I>(interface: I, id: u16, mode: FanoutMode) -> Self
+    where
+        I: Into<Interface>,
+    {
+        Self {
+            interface: interface.into(),
+            id,
+            mode,
+            flags: 0,
+            sockets: Vec::new(),
+        }
+    }
+
+    /// reassemble the IP fragments first, so that they all go to one socket
+    pub fn with_defrag(mut self) -> Self {
+        self.flags |= libc::PACKET_FANOUT_FLAG_DEFRAG;
+        self
+    }
+
+    /// hand the packets to the next socket when the one picked is full
+    pub fn with_rollover(mut self) -> Self {
+        self.flags |= libc::PACKET_FANOUT_FLAG_ROLLOVER;
+        self
+    }
+
+    /// the id of the group
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// add a socket to the group, filtering with `filters`
+    pub fn join(&mut self, filters: &[BPFFilter]) -> io::Result<&OwnedFd> {
+        let index = self.interface.index()?;
+        let socket = packet_socket()?;
+        BPFFProg::new(filters).attach_filter(&socket)?;
+        bind_packet_socket(&socket, index, libc::ETH_P_ALL as u16)?;
+        let arg: u32 = self.id as u32 | (self.mode.value() | self.flags) << 16;
+        if unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_PACKET,
+                libc::PACKET_FANOUT,
+                &arg as *const _ as *const libc::c_void,
+                size_of::<u32>() as libc::socklen_t,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        self.sockets.push(socket);
+        Ok(self.sockets.last().unwrap())
+    }
+
+    /// replace the filter of every socket of the group
+    pub fn set_filter(&self, filters: &[BPFFilter]) -> io::Result<()> {
+        for socket in &self.sockets {
+            BPFFProg::new(filters).attach_filter(socket)?;
+        }
+        Ok(())
+    }
+
+    /// the sockets of the group, in the order they joined
+    pub fn sockets(&self) -> &[OwnedFd] {
+        &self.sockets
+    }
+
+    /// the sockets, to hand over to the capture threads
+    ///
+    /// the group lasts as long as one of them stays open.
+    pub fn into_sockets(self) -> Vec<OwnedFd> {
+        self.sockets
+    }
+}
+
+#[test]
+fn test_fanout_group() {
+    use crate::linux::get_filter;
+
+    let mut group = FanoutGroup::new("lo", 0x4242, FanoutMode::Hash).with_rollover();
+    match group.join(crate::filters::drop_all()) {
+        Ok(_) => {}
+        Err(e) => return assert_eq!(e.raw_os_error(), Some(libc::EPERM)),
+    }
+    group.join(crate::filters::drop_all()).unwrap();
+    assert_eq!(group.sockets().len(), 2);
+
+    let arp = crate::presets::arp_only();
+    group.set_filter(arp).unwrap();
+    for socket in group.sockets() {
+        assert_eq!(get_filter(socket).unwrap(), arp);
+    }
+
+    // the group exists with another mode
+    let mut other = FanoutGroup::new("lo", 0x4242, FanoutMode::Cpu);
+    let error = other.join(arp).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EINVAL));
+}
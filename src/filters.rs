@@ -0,0 +1,1165 @@
+//! ready-made programs for common tasks
+
+use crate::ancillary::*;
+use crate::bpf_base::bpf::{self, BPFSize};
+use crate::bpf_base::BPFFilter;
+use crate::builder::{Label, ProgramBuilder};
+use crate::dlt::Dlt;
+use crate::fragments;
+use crate::offsets::{Arp, Eth, Field, Icmp, Ipv4, Ipv6, Sll, Tcp};
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+
+pub(crate) fn build(builder: &ProgramBuilder) -> Vec<BPFFilter> {
+    builder
+        .build()
+        .expect("ready-made programs are well formed")
+}
+
+pub use crate::presets::{accept_all, drop_all};
+
+/// spread the flows over `n` sockets by their receive hash
+///
+/// the program returns `rxhash % n`, the index of the socket in a
+/// `SO_ATTACH_REUSEPORT_CBPF` group or of the member of a
+/// `PACKET_FANOUT_CBPF` group. packets of the same flow always get the same
+/// index. it relies on Linux extensions.
+///
+/// # Panics
+///
+/// panics if `n` is 0
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = filters::steer_by_rxhash(4);
+/// assert_eq!(filters.len(), 3);
+/// ```
+pub fn steer_by_rxhash(n: u32) -> Vec<BPFFilter> {
+    assert!(n > 0, "cannot steer to an empty group");
+    build(
+        ProgramBuilder::new()
+            .ld_ancillary(SKF_AD_RXHASH)
+            .alu(bpf::MOD, bpf::K, n)
+            .ret_a(),
+    )
+}
+
+/// steer every packet to the socket of the CPU receiving it
+///
+/// the program returns the CPU number, so the sockets of the
+/// `SO_ATTACH_REUSEPORT_CBPF` or `PACKET_FANOUT_CBPF` group must be added in
+/// CPU order, one per CPU. it relies on Linux extensions.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = filters::steer_by_cpu();
+/// assert_eq!(filters.len(), 2);
+/// ```
+pub fn steer_by_cpu() -> Vec<BPFFilter> {
+    build(ProgramBuilder::new().ld_ancillary(SKF_AD_CPU).ret_a())
+}
+
+/// accept the whole of the packets received on the NIC queue `queue`
+///
+/// it relies on Linux extensions.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = filters::match_queue(3);
+/// assert_eq!(filters.len(), 4);
+/// ```
+pub fn match_queue(queue: u32) -> Vec<BPFFilter> {
+    build(
+        ProgramBuilder::new()
+            .ld_queue()
+            .jmp(bpf::JEQ, bpf::K, queue, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+/// accept the whole of the packets whose stripped VLAN tag has the TPID `tpid`
+///
+/// use 0x8100 for 802.1Q tags and 0x88a8 for 802.1ad ones. untagged packets
+/// are dropped. it relies on Linux extensions, the packets must come from a
+/// NIC stripping the VLAN tags.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // QinQ outer tags only
+/// let filters = filters::match_vlan_tpid(0x88a8);
+/// assert_eq!(filters.len(), 6);
+/// ```
+pub fn match_vlan_tpid(tpid: u16) -> Vec<BPFFilter> {
+    build(
+        ProgramBuilder::new()
+            .ld_vlan_tag_present()
+            .jmp(bpf::JEQ, bpf::K, 0, 3, 0)
+            .ld_vlan_tpid()
+            .jmp(bpf::JEQ, bpf::K, tpid as u32, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+/// accept the whole of the 802.11 frames of type `frame_type` and subtype `subtype`
+///
+/// for `dlt::DLT_IEEE802_11_RADIO` captures: the radiotap header is skipped
+/// at run time since its length changes from frame to frame. the type is 0
+/// for management, 1 for control and 2 for data frames, e.g. beacons are
+/// type 0 subtype 8.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // beacons
+/// let filters = filters::wlan_type_subtype(0, 8);
+/// assert_eq!(filters.len(), 11);
+/// ```
+pub fn wlan_type_subtype(frame_type: u8, subtype: u8) -> Vec<BPFFilter> {
+    let fc = ((frame_type as u32 & 0x3) << 2) | ((subtype as u32 & 0xf) << 4);
+    build(
+        ProgramBuilder::new()
+            .ldx_radiotap_len()
+            // the first byte of the frame control field
+            .ld_ind_b(0)
+            .alu(bpf::AND, bpf::K, 0xfc)
+            .jmp(bpf::JEQ, bpf::K, fc, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+/// accept the whole of the packets with any bit of `mask` set in a field
+///
+/// the field is the `size` big-endian bytes at `offset`, e.g. the TCP SYN or
+/// RST flags of an IPv4 packet without options with
+/// `field_has_bits(47, bpf::B, 0x06)`.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // the "more fragments" flag of IPv4 behind Ethernet
+/// let filters = filters::field_has_bits(20, bpf::H, 0x2000);
+/// assert_eq!(filters.len(), 4);
+/// ```
+pub fn field_has_bits(offset: u32, size: BPFSize, mask: u32) -> Vec<BPFFilter> {
+    build(
+        ProgramBuilder::new()
+            .ld_abs(size, offset)
+            .jset(mask, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+/// accept the whole of the packets holding `bytes` at `offset`
+///
+/// the bytes are compared 4 at a time with W loads, a remainder of 2 or 3
+/// bytes with an H load then a B load. an empty `bytes` accepts every
+/// packet.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // an HTTP/1.x response in an IPv4 TCP segment without options
+/// let filters = filters::bytes_at(54, b"HTTP/1.").unwrap();
+/// assert_eq!(filters.len(), 8);
+/// ```
+pub fn bytes_at(offset: u32, bytes: &[u8]) -> Result<Vec<BPFFilter>, BytesAtError> {
+    let mut loads: Vec<(BPFSize, u32, u32)> = Vec::new();
+    let mut at = 0;
+    while at < bytes.len() {
+        let rest = &bytes[at..];
+        let (size, len) = match rest.len() {
+            1 => (bpf::B, 1),
+            2 | 3 => (bpf::H, 2),
+            _ => (bpf::W, 4),
+        };
+        let value = rest[..len]
+            .iter()
+            .fold(0u32, |value, byte| value << 8 | *byte as u32);
+        if loads.len() == 128 {
+            return Err(BytesAtError::TooLong);
+        }
+        let offset = offset
+            .checked_add(at as u32)
+            .ok_or(BytesAtError::OffsetOverflow)?;
+        loads.push((size, offset, value));
+        at += len;
+    }
+    let mut builder = ProgramBuilder::new();
+    let count = loads.len();
+    for (i, (size, offset, value)) in loads.into_iter().enumerate() {
+        // skip the loads and tests left and the final accept
+        let reject = (2 * (count - 1 - i) + 1) as u8;
+        builder
+            .ld_abs(size, offset)
+            .jmp(bpf::JEQ, bpf::K, value, 0, reject);
+    }
+    builder.ret_k(u32::MAX);
+    if count > 0 {
+        builder.ret_k(0);
+    }
+    Ok(build(&builder))
+}
+
+/// why [`bytes_at`] cannot compare a byte string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BytesAtError {
+    /// the comparison takes more than 128 loads, over 500 bytes
+    TooLong,
+    /// the bytes reach past the last offset a load can address
+    OffsetOverflow,
+}
+
+impl fmt::Display for BytesAtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytesAtError::TooLong => write!(f, "too many bytes to compare"),
+            BytesAtError::OffsetOverflow => write!(f, "the bytes end past the last offset"),
+        }
+    }
+}
+
+impl std::error::Error for BytesAtError {}
+
+/// ICMP echo reply
+pub const ICMP_ECHO_REPLY: u8 = 0;
+/// ICMP destination unreachable
+pub const ICMP_DEST_UNREACH: u8 = 3;
+/// ICMP redirect
+pub const ICMP_REDIRECT: u8 = 5;
+/// ICMP echo request
+pub const ICMP_ECHO: u8 = 8;
+/// ICMP time exceeded
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+
+/// ICMPv6 destination unreachable
+pub const ICMP6_DST_UNREACH: u8 = 1;
+/// ICMPv6 packet too big
+pub const ICMP6_PACKET_TOO_BIG: u8 = 2;
+/// ICMPv6 time exceeded
+pub const ICMP6_TIME_EXCEEDED: u8 = 3;
+/// ICMPv6 echo request
+pub const ICMP6_ECHO_REQUEST: u8 = 128;
+/// ICMPv6 echo reply
+pub const ICMP6_ECHO_REPLY: u8 = 129;
+/// neighbor discovery router solicitation
+pub const ND_ROUTER_SOLICIT: u8 = 133;
+/// neighbor discovery router advertisement
+pub const ND_ROUTER_ADVERT: u8 = 134;
+/// neighbor discovery neighbor solicitation
+pub const ND_NEIGHBOR_SOLICIT: u8 = 135;
+/// neighbor discovery neighbor advertisement
+pub const ND_NEIGHBOR_ADVERT: u8 = 136;
+/// neighbor discovery redirect
+pub const ND_REDIRECT: u8 = 137;
+
+/// jump to `reject` unless the frame of link type `dlt` carries IPv6, or
+/// IPv4 without `v6`, returning the offset of the network header
+fn check_ip(builder: &mut ProgramBuilder, dlt: Dlt, v6: bool, reject: Label) -> u32 {
+    let ethertype = if v6 { 0x86dd } else { 0x0800 };
+    match dlt {
+        Dlt::En10mb => {
+            builder.ld_abs_h(Eth::EtherType.offset()).jmp_label(
+                bpf::JEQ,
+                bpf::K,
+                ethertype,
+                None,
+                Some(reject),
+            );
+            Eth::NETWORK
+        }
+        Dlt::LinuxSll => {
+            builder.ld_abs_h(Sll::Protocol.offset()).jmp_label(
+                bpf::JEQ,
+                bpf::K,
+                ethertype,
+                None,
+                Some(reject),
+            );
+            Sll::NETWORK
+        }
+        // the IP version is all there is, in the upper 4 bits of the packet
+        Dlt::Raw => {
+            let version = if v6 { 0x60 } else { 0x40 };
+            builder.ld_abs_b(0).alu(bpf::AND, bpf::K, 0xf0).jmp_label(
+                bpf::JEQ,
+                bpf::K,
+                version,
+                None,
+                Some(reject),
+            );
+            0
+        }
+        dlt => panic!("no IP test for the link type {:?}", dlt),
+    }
+}
+
+/// X = the offset of the payload of the IPv4 packet at `net`, jumping to
+/// `reject` for the fragments after the first, which have no transport header
+fn ipv4_payload(builder: &mut ProgramBuilder, net: u32, reject: Label) {
+    builder
+        .ld_abs_h(net + Ipv4::FlagsFragment.offset())
+        .jmp_label(bpf::JSET, bpf::K, 0x1fff, Some(reject), None);
+    fragments::load_ipv4_transport_base_at(builder, net);
+}
+
+fn accept_or_reject(builder: &mut ProgramBuilder, accept: Label, reject: Label) -> Vec<BPFFilter> {
+    builder.bind(accept).ret_k(u32::MAX);
+    builder.bind(reject).ret_k(0);
+    build(builder)
+}
+
+fn type_values(types: &[u8]) -> Vec<u32> {
+    types.iter().map(|t| *t as u32).collect()
+}
+
+/// accept the whole of the ICMP messages of one of the `types`
+///
+/// `dlt` is `Dlt::En10mb`, `Dlt::LinuxSll` or `Dlt::Raw`. the programs for
+/// `Dlt::Raw` also suit raw IPv4 sockets, which see the IP header.
+///
+/// # Panics
+///
+/// panics for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = filters::icmp_types(Dlt::En10mb, &[filters::ICMP_ECHO]);
+/// assert_eq!(filters.len(), 11);
+/// ```
+pub fn icmp_types(dlt: Dlt, types: &[u8]) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let net = check_ip(&mut builder, dlt, false, reject);
+    builder.ld_abs_b(net + Ipv4::Protocol.offset()).jmp_label(
+        bpf::JEQ,
+        bpf::K,
+        1,
+        None,
+        Some(reject),
+    );
+    ipv4_payload(&mut builder, net, reject);
+    builder
+        .ld_ind_b(net + Icmp::Type.offset())
+        .jeq_any(&type_values(types), accept, reject);
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// accept the whole of the ICMPv6 messages of one of the `types`, behind
+/// an IPv6 header without extension headers
+///
+/// `dlt` is `Dlt::En10mb`, `Dlt::LinuxSll` or `Dlt::Raw`. raw ICMPv6
+/// sockets see the messages without their IPv6 header, they need
+/// [`icmpv6_socket_types`].
+///
+/// # Panics
+///
+/// panics for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::*;
+///
+/// let filters = icmpv6_types(Dlt::En10mb, &[ND_NEIGHBOR_SOLICIT, ND_ROUTER_ADVERT]);
+/// assert_eq!(filters.len(), 9);
+/// ```
+pub fn icmpv6_types(dlt: Dlt, types: &[u8]) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let net = check_ip(&mut builder, dlt, true, reject);
+    builder
+        .ld_abs_b(net + Ipv6::NextHeader.offset())
+        .jmp_label(bpf::JEQ, bpf::K, 58, None, Some(reject))
+        .ld_abs_b(net + Ipv6::LEN + Icmp::Type.offset())
+        .jeq_any(&type_values(types), accept, reject);
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// accept the ICMPv6 messages of one of the `types` on a raw ICMPv6 socket
+///
+/// the program sees each message from its ICMPv6 header.
+pub fn icmpv6_socket_types(types: &[u8]) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    builder
+        .ld_abs_b(Icmp::Type.offset())
+        .jeq_any(&type_values(types), accept, reject);
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// the addresses or ports a filter looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// the source only
+    Src,
+    /// the destination only
+    Dst,
+    /// either the source or the destination
+    Either,
+}
+
+impl Direction {
+    /// the offsets of the fields of the direction, from those of the source
+    /// and destination
+    fn fields(self, src: u32, dst: u32) -> Vec<u32> {
+        match self {
+            Direction::Src => vec![src],
+            Direction::Dst => vec![dst],
+            Direction::Either => vec![src, dst],
+        }
+    }
+}
+
+/// jump to `accept` if one of the port fields of `direction`, read by
+/// `load`, is in `range`, to `reject` otherwise
+fn test_ports<F>(
+    builder: &mut ProgramBuilder,
+    load: F,
+    direction: Direction,
+    range: &RangeInclusive<u16>,
+    accept: Label,
+    reject: Label,
+) where
+    F: Fn(&mut ProgramBuilder, u32),
+{
+    let (lo, hi) = (*range.start() as u32, *range.end() as u32);
+    let fields = direction.fields(Tcp::SrcPort.offset(), Tcp::DstPort.offset());
+    for (i, field) in fields.iter().enumerate() {
+        let miss = if i + 1 == fields.len() {
+            reject
+        } else {
+            builder.label()
+        };
+        load(builder, *field);
+        if lo > hi {
+            // an empty range
+            builder.ja_label(miss);
+        } else if lo == hi {
+            builder.jmp_label(bpf::JEQ, bpf::K, lo, Some(accept), Some(miss));
+        } else {
+            builder
+                .jmp_label(bpf::JGE, bpf::K, lo, None, Some(miss))
+                .jmp_label(bpf::JGT, bpf::K, hi, Some(miss), Some(accept));
+        }
+        if miss != reject {
+            builder.bind(miss);
+        }
+    }
+}
+
+/// accept the whole of the IPv4 and IPv6 packets of the transport protocol
+/// `proto` whose ports of `direction` are in `range`
+///
+/// the IPv4 transport header is found behind the options, with `ldxb
+/// 4*([n]&0xf)`, and only the first fragment is kept. the IPv6 one is
+/// expected right behind the 40 bytes of the fixed header, extension headers
+/// are not followed. `dlt` is `Dlt::En10mb`, `Dlt::LinuxSll` or `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// // SCTP to or from port 3868, Diameter
+/// let filters = filters::port_range(Dlt::Raw, 132, Direction::Either, 3868..=3868);
+/// ```
+pub fn port_range(
+    dlt: Dlt,
+    proto: u8,
+    direction: Direction,
+    range: RangeInclusive<u16>,
+) -> Vec<BPFFilter> {
+    transport_ports(dlt, Family::Both, &[proto], direction, range)
+}
+
+/// the IP versions a filter accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Family {
+    V4,
+    V6,
+    Both,
+}
+
+/// the packets of `family` and one of the transport `protos` whose ports
+/// of `direction` are in `range`
+fn transport_ports(
+    dlt: Dlt,
+    family: Family,
+    protos: &[u8],
+    direction: Direction,
+    range: RangeInclusive<u16>,
+) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let protos: Vec<u32> = protos.iter().map(|p| *p as u32).collect();
+    if family != Family::V6 {
+        let v6 = match family {
+            Family::Both => builder.label(),
+            _ => reject,
+        };
+        let net = check_ip(&mut builder, dlt, false, v6);
+        let ports = builder.label();
+        builder
+            .ld_abs_b(net + Ipv4::Protocol.offset())
+            .jeq_any(&protos, ports, reject);
+        builder.bind(ports);
+        ipv4_payload(&mut builder, net, reject);
+        let ipv4_port = |builder: &mut ProgramBuilder, field| {
+            builder.ld_ind_h(net + field);
+        };
+        test_ports(&mut builder, ipv4_port, direction, &range, accept, reject);
+        if v6 != reject {
+            builder.bind(v6);
+        }
+    }
+    if family != Family::V4 {
+        let net = check_ip(&mut builder, dlt, true, reject);
+        let ports = builder.label();
+        builder
+            .ld_abs_b(net + Ipv6::NextHeader.offset())
+            .jeq_any(&protos, ports, reject);
+        builder.bind(ports);
+        let ipv6_port = |builder: &mut ProgramBuilder, field| {
+            builder.ld_abs_h(net + Ipv6::LEN + field);
+        };
+        test_ports(&mut builder, ipv6_port, direction, &range, accept, reject);
+    }
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// accept the whole of the DHCPv4 messages, UDP on the server port 67
+///
+/// `Direction::Dst` keeps the messages to the servers and relays, from the
+/// clients, `Direction::Src` the messages from them to the clients on port
+/// 68, and `Direction::Either` both. `dlt` is `Dlt::En10mb`,
+/// `Dlt::LinuxSll` or `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// // what a DHCP client waits for
+/// let offers = filters::dhcp(Dlt::En10mb, Direction::Src);
+/// assert_eq!(offers.len(), 11);
+/// ```
+pub fn dhcp(dlt: Dlt, direction: Direction) -> Vec<BPFFilter> {
+    transport_ports(dlt, Family::V4, &[17], direction, 67..=67)
+}
+
+/// accept the whole of the DHCPv6 messages, UDP on the server and relay
+/// port 547
+///
+/// `Direction::Dst` keeps the messages to the servers and relays, from the
+/// clients on port 546, `Direction::Src` the messages from them, and
+/// `Direction::Either` both. `dlt` is `Dlt::En10mb`, `Dlt::LinuxSll` or
+/// `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics for the other link types
+pub fn dhcpv6(dlt: Dlt, direction: Direction) -> Vec<BPFFilter> {
+    transport_ports(dlt, Family::V6, &[17], direction, 547..=547)
+}
+
+/// accept the whole of the DNS messages over IPv4 and IPv6, UDP or TCP on port 53
+///
+/// `Direction::Dst` keeps the queries, to the servers, `Direction::Src` the
+/// responses, and `Direction::Either` both. `dlt` is `Dlt::En10mb`,
+/// `Dlt::LinuxSll` or `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// let queries = filters::dns(Dlt::LinuxSll, Direction::Dst);
+/// ```
+pub fn dns(dlt: Dlt, direction: Direction) -> Vec<BPFFilter> {
+    transport_ports(dlt, Family::Both, &[6, 17], direction, 53..=53)
+}
+
+/// accept the whole of the TCP segments to `port` in Ethernet frames
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let https = filters::tcp_dst_port(443);
+/// assert_eq!(https.len(), 17);
+/// ```
+pub fn tcp_dst_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 6, Direction::Dst, port..=port)
+}
+
+/// accept the whole of the TCP segments from `port` in Ethernet frames
+pub fn tcp_src_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 6, Direction::Src, port..=port)
+}
+
+/// accept the whole of the TCP segments from or to `port` in Ethernet frames
+pub fn tcp_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 6, Direction::Either, port..=port)
+}
+
+/// accept the whole of the TCP segments from or to a port of `range` in Ethernet frames
+pub fn tcp_port_range(range: RangeInclusive<u16>) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 6, Direction::Either, range)
+}
+
+/// accept the whole of the UDP datagrams to `port` in Ethernet frames
+pub fn udp_dst_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 17, Direction::Dst, port..=port)
+}
+
+/// accept the whole of the UDP datagrams from `port` in Ethernet frames
+pub fn udp_src_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 17, Direction::Src, port..=port)
+}
+
+/// accept the whole of the UDP datagrams from or to `port` in Ethernet frames
+pub fn udp_port(port: u16) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 17, Direction::Either, port..=port)
+}
+
+/// accept the whole of the UDP datagrams from or to a port of `range` in Ethernet frames
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let rtp = filters::udp_port_range(5000..=5100);
+/// ```
+pub fn udp_port_range(range: RangeInclusive<u16>) -> Vec<BPFFilter> {
+    port_range(Dlt::En10mb, 17, Direction::Either, range)
+}
+
+/// jump to `accept` if one of the addresses at `fields` is in the network
+/// of the first `prefix` bits of `words`, to `reject` otherwise
+fn test_prefix(
+    builder: &mut ProgramBuilder,
+    fields: &[u32],
+    words: &[u32],
+    prefix: u8,
+    accept: Label,
+    reject: Label,
+) {
+    // the words with bits of the prefix, their offset and mask
+    let tests: Vec<(u32, u32, u32)> = words
+        .iter()
+        .enumerate()
+        .filter_map(|(i, word)| {
+            let bits = (prefix as u32).saturating_sub(32 * i as u32).min(32);
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            (bits > 0).then(|| (4 * i as u32, mask, word & mask))
+        })
+        .collect();
+    if tests.is_empty() {
+        builder.ja_label(accept);
+        return;
+    }
+    for (i, field) in fields.iter().enumerate() {
+        let miss = if i + 1 == fields.len() {
+            reject
+        } else {
+            builder.label()
+        };
+        for (n, (offset, mask, value)) in tests.iter().enumerate() {
+            builder.ld_abs_w(field + offset);
+            if *mask != u32::MAX {
+                builder.alu(bpf::AND, bpf::K, *mask);
+            }
+            let hit = if n + 1 == tests.len() {
+                Some(accept)
+            } else {
+                None
+            };
+            builder.jmp_label(bpf::JEQ, bpf::K, *value, hit, Some(miss));
+        }
+        if miss != reject {
+            builder.bind(miss);
+        }
+    }
+}
+
+/// accept the whole of the IPv4 packets whose addresses of `direction` are
+/// in `net`/`prefix`
+///
+/// the host bits of `net` are ignored. `dlt` is `Dlt::En10mb`,
+/// `Dlt::LinuxSll` or `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics if `prefix` is over 32, or for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// let private = filters::ipv4_net(Dlt::En10mb, Direction::Src, [10, 0, 0, 0].into(), 8);
+/// assert_eq!(private.len(), 7);
+/// ```
+pub fn ipv4_net(dlt: Dlt, direction: Direction, net: Ipv4Addr, prefix: u8) -> Vec<BPFFilter> {
+    assert!(prefix <= 32, "an IPv4 prefix has 32 bits at most");
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let base = check_ip(&mut builder, dlt, false, reject);
+    let fields = direction.fields(base + Ipv4::Src.offset(), base + Ipv4::Dst.offset());
+    test_prefix(
+        &mut builder,
+        &fields,
+        &[u32::from(net)],
+        prefix,
+        accept,
+        reject,
+    );
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// accept the whole of the IPv6 packets whose addresses of `direction` are
+/// in `net`/`prefix`
+///
+/// the addresses are compared a word at a time, the word the prefix ends
+/// in masked. `dlt` is `Dlt::En10mb`, `Dlt::LinuxSll` or `Dlt::Raw`.
+///
+/// # Panics
+///
+/// panics if `prefix` is over 128, or for the other link types
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// let net = "2001:db8::".parse().unwrap();
+/// let doc = filters::ipv6_net(Dlt::En10mb, Direction::Either, net, 32);
+/// assert_eq!(doc.len(), 8);
+/// ```
+pub fn ipv6_net(dlt: Dlt, direction: Direction, net: Ipv6Addr, prefix: u8) -> Vec<BPFFilter> {
+    assert!(prefix <= 128, "an IPv6 prefix has 128 bits at most");
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let base = check_ip(&mut builder, dlt, true, reject);
+    let fields = direction.fields(base + Ipv6::Src0.offset(), base + Ipv6::Dst0.offset());
+    let octets = net.octets();
+    let words: Vec<u32> = octets
+        .chunks(4)
+        .map(|word| u32::from_be_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    test_prefix(&mut builder, &fields, &words, prefix, accept, reject);
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// the Ethernet broadcast address
+pub const ETHER_BROADCAST: [u8; 6] = [0xff; 6];
+
+/// accept the whole of the Ethernet frames whose addresses of `direction` are `mac`
+///
+/// the 48-bit address is compared as a word then a half-word.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::Direction;
+///
+/// let router = filters::ether_addr(Direction::Src, [0x02, 0, 0x5e, 0x10, 0, 1]);
+/// assert_eq!(router.len(), 6);
+/// ```
+pub fn ether_addr(direction: Direction, mac: [u8; 6]) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (accept, reject) = (builder.label(), builder.label());
+    let high = u32::from_be_bytes([mac[0], mac[1], mac[2], mac[3]]);
+    let low = u16::from_be_bytes([mac[4], mac[5]]) as u32;
+    let fields = direction.fields(Eth::SrcHigh.offset(), Eth::DstHigh.offset());
+    for (i, field) in fields.iter().enumerate() {
+        let miss = if i + 1 == fields.len() {
+            reject
+        } else {
+            builder.label()
+        };
+        builder
+            .ld_abs_w(*field)
+            .jmp_label(bpf::JEQ, bpf::K, high, None, Some(miss))
+            .ld_abs_h(field + 4)
+            .jmp_label(bpf::JEQ, bpf::K, low, Some(accept), Some(miss));
+        if miss != reject {
+            builder.bind(miss);
+        }
+    }
+    accept_or_reject(&mut builder, accept, reject)
+}
+
+/// accept the whole of the Ethernet frames sent to the broadcast address
+pub fn ether_broadcast() -> Vec<BPFFilter> {
+    ether_addr(Direction::Dst, ETHER_BROADCAST)
+}
+
+/// accept the whole of the Ethernet frames sent to a multicast address,
+/// the broadcast address included
+///
+/// the multicast addresses have the lowest bit of their first byte set.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = filters::ether_multicast();
+/// assert_eq!(filters.len(), 4);
+/// ```
+pub fn ether_multicast() -> Vec<BPFFilter> {
+    build(
+        ProgramBuilder::new()
+            .ld_abs_b(Eth::DstHigh.offset())
+            .jset(0x01, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+/// the operation of an ARP message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpOp {
+    /// who has the protocol address, tell the sender
+    Request,
+    /// the protocol address is at the hardware address of the sender
+    Reply,
+}
+
+impl ArpOp {
+    /// the value of the `oper` field
+    pub fn value(self) -> u16 {
+        match self {
+            ArpOp::Request => 1,
+            ArpOp::Reply => 2,
+        }
+    }
+}
+
+/// accept the whole of the ARP messages in Ethernet frames
+///
+/// the program of `presets::arp_only`.
+pub fn arp() -> Vec<BPFFilter> {
+    crate::presets::arp_only().to_vec()
+}
+
+/// accept the whole of the ARP messages of the operation `op` in Ethernet frames
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use classic_bpf::filters::ArpOp;
+///
+/// // the replies, gratuitous or not, that a spoofing detector checks
+/// let replies = filters::arp_opcode(ArpOp::Reply);
+/// assert_eq!(replies.len(), 6);
+/// ```
+pub fn arp_opcode(op: ArpOp) -> Vec<BPFFilter> {
+    build(
+        ProgramBuilder::new()
+            .ld_abs_h(Eth::EtherType.offset())
+            .jmp(bpf::JEQ, bpf::K, 0x0806, 0, 3)
+            .ld_abs_h(Eth::NETWORK + Arp::Oper.offset())
+            .jmp(bpf::JEQ, bpf::K, op.value() as u32, 0, 1)
+            .ret_k(u32::MAX)
+            .ret_k(0),
+    )
+}
+
+#[test]
+fn test_steer_by_rxhash() {
+    let filters = steer_by_rxhash(8);
+    assert_eq!(
+        filters,
+        [
+            BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF + SKF_AD_RXHASH),
+            BPFFilter::bpf_stmt(bpf::ALU | bpf::MOD | bpf::K, 8),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_bytes_at() {
+    let jeq = |k, jf| BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, k, 0, jf);
+    assert_eq!(
+        bytes_at(2, b"GET / H").unwrap(),
+        [
+            BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, 2),
+            jeq(0x47455420, 5),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 6),
+            jeq(0x2f20, 3),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 8),
+            jeq(0x48, 1),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+        ]
+    );
+    assert_eq!(bytes_at(0, b"HTTP/1.1").unwrap().len(), 6);
+    assert_eq!(bytes_at(0, b"HTTP/2").unwrap().len(), 6);
+    assert_eq!(bytes_at(0, b"").unwrap().len(), 1);
+    assert_eq!(
+        bytes_at(u32::MAX - 2, b"HTTP/1"),
+        Err(BytesAtError::OffsetOverflow)
+    );
+    assert_eq!(bytes_at(0, &[0; 600]), Err(BytesAtError::TooLong));
+}
+
+#[test]
+fn test_icmp_types() {
+    use crate::interpreter::run;
+    let mut frame = [0u8; 62];
+    frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+    frame[20] = 58;
+    frame[54] = ND_NEIGHBOR_SOLICIT;
+    let nd = icmpv6_types(Dlt::En10mb, &[ND_NEIGHBOR_SOLICIT, ND_ROUTER_ADVERT]);
+    assert_eq!(run(&nd, &frame, 62), u32::MAX);
+    frame[54] = ICMP6_ECHO_REQUEST;
+    assert_eq!(run(&nd, &frame, 62), 0);
+    let raw = icmpv6_types(Dlt::Raw, &[ICMP6_ECHO_REQUEST]);
+    frame[14] = 0x60;
+    assert_eq!(run(&raw, &frame[14..], 48), u32::MAX);
+    let socket = icmpv6_socket_types(&[ICMP6_ECHO_REQUEST]);
+    assert_eq!(run(&socket, &frame[54..], 8), u32::MAX);
+
+    // an IPv4 header with options on a cooked capture
+    let mut frame = [0u8; 48];
+    frame[14..16].copy_from_slice(&[0x08, 0x00]);
+    frame[16] = 0x46;
+    frame[16 + 9] = 1;
+    frame[40] = ICMP_ECHO;
+    let ping = icmp_types(Dlt::LinuxSll, &[ICMP_ECHO, ICMP_ECHO_REPLY]);
+    assert_eq!(run(&ping, &frame, 48), u32::MAX);
+    // a later fragment
+    frame[22] = 0x01;
+    assert_eq!(run(&ping, &frame, 48), 0);
+}
+
+#[test]
+fn test_port_range() {
+    let (ipv4, ipv6) = (
+        |proto, src, dst| ipv4_segment(Dlt::En10mb, proto, src, dst),
+        |proto, src, dst| ipv6_segment(Dlt::En10mb, proto, src, dst),
+    );
+
+    let https = tcp_dst_port(443);
+    assert!(accepted(&https, ipv4(6, 1234, 443)));
+    assert!(accepted(&https, ipv6(6, 1234, 443)));
+    assert!(!accepted(&https, ipv4(6, 443, 1234)));
+    assert!(!accepted(&https, ipv4(17, 1234, 443)));
+    assert!(!accepted(&https, ipv6(17, 1234, 443)));
+    assert!(accepted(&tcp_src_port(443), ipv4(6, 443, 1234)));
+    assert!(accepted(&tcp_port(443), ipv6(6, 443, 1234)));
+
+    let rtp = udp_port_range(5000..=5100);
+    assert!(accepted(&rtp, ipv4(17, 5000, 1)));
+    assert!(accepted(&rtp, ipv6(17, 1, 5100)));
+    assert!(!accepted(&rtp, ipv4(17, 4999, 5101)));
+    assert!(!accepted(&rtp, ipv6(6, 5050, 5050)));
+    let empty = udp_port_range(RangeInclusive::new(5100, 5000));
+    assert!(!accepted(&empty, ipv4(17, 5000, 5100)));
+}
+
+#[test]
+fn test_ip_nets() {
+    use crate::interpreter::run;
+    let ipv4 = |src: [u8; 4], dst: [u8; 4]| {
+        let mut frame = vec![0u8; 20];
+        frame[0] = 0x45;
+        frame[12..16].copy_from_slice(&src);
+        frame[16..20].copy_from_slice(&dst);
+        frame
+    };
+    let filters = ipv4_net(Dlt::Raw, Direction::Dst, [192, 168, 77, 1].into(), 20);
+    assert_ne!(run(&filters, &ipv4([1; 4], [192, 168, 64, 9]), 20), 0);
+    assert_ne!(run(&filters, &ipv4([1; 4], [192, 168, 79, 255]), 20), 0);
+    assert_eq!(run(&filters, &ipv4([1; 4], [192, 168, 80, 0]), 20), 0);
+    assert_eq!(run(&filters, &ipv4([192, 168, 64, 9], [1; 4]), 20), 0);
+    let any = ipv4_net(Dlt::Raw, Direction::Src, Ipv4Addr::UNSPECIFIED, 0);
+    assert_ne!(run(&any, &ipv4([1; 4], [1; 4]), 20), 0);
+    let host = ipv4_net(Dlt::Raw, Direction::Either, [10, 1, 2, 3].into(), 32);
+    assert_ne!(run(&host, &ipv4([1; 4], [10, 1, 2, 3]), 20), 0);
+    assert_eq!(run(&host, &ipv4([1; 4], [10, 1, 2, 4]), 20), 0);
+
+    let ipv6 = |src: Ipv6Addr, dst: Ipv6Addr| {
+        let mut frame = vec![0u8; 40];
+        frame[0] = 0x60;
+        frame[8..24].copy_from_slice(&src.octets());
+        frame[24..40].copy_from_slice(&dst.octets());
+        frame
+    };
+    let addr = |s: &str| s.parse::<Ipv6Addr>().unwrap();
+    let filters = ipv6_net(Dlt::Raw, Direction::Either, addr("fd00:1234:5678::"), 44);
+    let inside = addr("fd00:1234:5670::1");
+    let outside = addr("fd00:1234:5680::1");
+    assert_ne!(run(&filters, &ipv6(outside, inside), 40), 0);
+    assert_ne!(run(&filters, &ipv6(inside, outside), 40), 0);
+    assert_eq!(run(&filters, &ipv6(outside, outside), 40), 0);
+    assert_eq!(run(&filters, &ipv4([1; 4], [1; 4]), 20), 0);
+}
+
+#[test]
+fn test_ether_addrs() {
+    use crate::interpreter::run;
+    let frame = |dst: [u8; 6], src: [u8; 6]| {
+        let mut frame = [0u8; 14];
+        frame[..6].copy_from_slice(&dst);
+        frame[6..12].copy_from_slice(&src);
+        frame
+    };
+    let mac = [0x02, 0x42, 0xac, 0x11, 0x00, 0x02];
+    let other = [0x02, 0x42, 0xac, 0x11, 0x00, 0x03];
+    let multicast = [0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb];
+    let filters = ether_addr(Direction::Either, mac);
+    assert_ne!(run(&filters, &frame(mac, other), 14), 0);
+    assert_ne!(run(&filters, &frame(other, mac), 14), 0);
+    assert_eq!(run(&filters, &frame(other, other), 14), 0);
+    let filters = ether_addr(Direction::Dst, mac);
+    assert_eq!(run(&filters, &frame(other, mac), 14), 0);
+
+    let broadcast = ether_broadcast();
+    assert_ne!(run(&broadcast, &frame(ETHER_BROADCAST, mac), 14), 0);
+    assert_eq!(run(&broadcast, &frame(multicast, mac), 14), 0);
+    let filters = ether_multicast();
+    assert_ne!(run(&filters, &frame(ETHER_BROADCAST, mac), 14), 0);
+    assert_ne!(run(&filters, &frame(multicast, mac), 14), 0);
+    assert_eq!(run(&filters, &frame(other, multicast), 14), 0);
+}
+
+#[test]
+fn test_arp() {
+    use crate::interpreter::run;
+    let mut frame = [0u8; 42];
+    frame[12..14].copy_from_slice(&[0x08, 0x06]);
+    frame[20..22].copy_from_slice(&[0, 1]);
+    assert_ne!(run(&arp(), &frame, 42), 0);
+    assert_ne!(run(&arp_opcode(ArpOp::Request), &frame, 42), 0);
+    assert_eq!(run(&arp_opcode(ArpOp::Reply), &frame, 42), 0);
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    assert_eq!(run(&arp_opcode(ArpOp::Request), &frame, 42), 0);
+}
+
+#[test]
+fn test_dhcp_dns() {
+    let (ipv4, ipv6) = (
+        |proto, src, dst| ipv4_segment(Dlt::Raw, proto, src, dst),
+        |proto, src, dst| ipv6_segment(Dlt::Raw, proto, src, dst),
+    );
+
+    let client = dhcp(Dlt::Raw, Direction::Src);
+    assert!(accepted(&client, ipv4(17, 67, 68)));
+    assert!(!accepted(&client, ipv4(17, 68, 67)));
+    assert!(!accepted(&client, ipv6(17, 67, 68)));
+    assert!(accepted(
+        &dhcp(Dlt::Raw, Direction::Either),
+        ipv4(17, 68, 67)
+    ));
+
+    let server = dhcpv6(Dlt::Raw, Direction::Dst);
+    assert!(accepted(&server, ipv6(17, 546, 547)));
+    assert!(!accepted(&server, ipv6(17, 547, 546)));
+    assert!(!accepted(&server, ipv4(17, 546, 547)));
+
+    let queries = dns(Dlt::Raw, Direction::Dst);
+    assert!(accepted(&queries, ipv4(17, 40000, 53)));
+    assert!(accepted(&queries, ipv4(6, 40000, 53)));
+    assert!(accepted(&queries, ipv6(6, 40000, 53)));
+    assert!(!accepted(&queries, ipv6(17, 53, 40000)));
+    assert!(!accepted(&queries, ipv4(132, 40000, 53)));
+    assert!(accepted(
+        &dns(Dlt::Raw, Direction::Src),
+        ipv6(17, 53, 40000)
+    ));
+}
+
+// an IPv4 header with 4 bytes of options, then the ports, behind `dlt`'s link header
+#[cfg(test)]
+fn ipv4_segment(dlt: Dlt, proto: u8, src: u16, dst: u16) -> Vec<u8> {
+    let mut packet = link_header(dlt, 0x0800);
+    let net = packet.len();
+    packet.resize(net + 28, 0);
+    packet[net] = 0x46;
+    packet[net + 9] = proto;
+    packet[net + 24..net + 26].copy_from_slice(&src.to_be_bytes());
+    packet[net + 26..net + 28].copy_from_slice(&dst.to_be_bytes());
+    packet
+}
+
+// an IPv6 header, then the ports, behind `dlt`'s link header
+#[cfg(test)]
+fn ipv6_segment(dlt: Dlt, proto: u8, src: u16, dst: u16) -> Vec<u8> {
+    let mut packet = link_header(dlt, 0x86dd);
+    let net = packet.len();
+    packet.resize(net + 44, 0);
+    packet[net] = 0x60;
+    packet[net + 6] = proto;
+    packet[net + 40..net + 42].copy_from_slice(&src.to_be_bytes());
+    packet[net + 42..net + 44].copy_from_slice(&dst.to_be_bytes());
+    packet
+}
+
+#[cfg(test)]
+fn link_header(dlt: Dlt, ether_type: u16) -> Vec<u8> {
+    match dlt {
+        Dlt::En10mb => {
+            let mut header = vec![0u8; 14];
+            header[12..14].copy_from_slice(&ether_type.to_be_bytes());
+            header
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+fn accepted(filters: &[BPFFilter], packet: Vec<u8>) -> bool {
+    crate::interpreter::run(filters, &packet, packet.len() as u32) != 0
+}
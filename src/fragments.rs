@@ -0,0 +1,50 @@
+//! reusable pieces of programs, emitted into a [`ProgramBuilder`]
+
+use crate::bpf_base::bpf::{B, MSH};
+use crate::builder::ProgramBuilder;
+use crate::const_builder::ConstBuilder;
+use crate::dlt::Dlt;
+
+/// X = the offset of the transport header of an IPv4 packet of link type `dlt`
+///
+/// emits `ldxb 4*([off]&0xf)` on the IHL of the IPv4 header, which is the
+/// prerequisite of every IND load of a transport field: the transport field at
+/// `n` bytes into its header is then at `ld_ind(size, off + n)` where `off` is
+/// the network offset. the packet must already be known to be IPv4.
+///
+/// [`build`](ProgramBuilder::build) fails with `BuildError::NoNetworkOffset`
+/// if the header length of `dlt` is not fixed, see
+/// [`dlt::network_offset`](crate::dlt::network_offset).
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let mut builder = ProgramBuilder::new();
+/// fragments::load_ipv4_transport_base(&mut builder, Dlt::En10mb)
+///     // the TCP destination port
+///     .ld_ind_h(14 + 2)
+///     .ret_a();
+/// assert_eq!(builder.build().unwrap().len(), 3);
+/// ```
+pub fn load_ipv4_transport_base(builder: &mut ProgramBuilder, dlt: Dlt) -> &mut ProgramBuilder {
+    match builder.network_offset(dlt) {
+        Some(net) => load_ipv4_transport_base_at(builder, net),
+        None => builder,
+    }
+}
+
+/// X = the offset of the transport header of the IPv4 packet at `net` in the
+/// frame, [`load_ipv4_transport_base`] past a header of another length
+pub fn load_ipv4_transport_base_at(builder: &mut ProgramBuilder, net: u32) -> &mut ProgramBuilder {
+    builder.ldx_msh(net)
+}
+
+/// [`load_ipv4_transport_base_at`] for the programs of a [`ConstBuilder`]
+pub const fn const_ipv4_transport_base<const N: usize>(
+    builder: ConstBuilder<N>,
+    net: u32,
+) -> ConstBuilder<N> {
+    builder.ldx(B, MSH, net)
+}
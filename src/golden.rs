@@ -0,0 +1,258 @@
+//! comparison of generated programs against `tcpdump -dd` fixtures
+//!
+//! a corpus is a text file of cases, each made of an expression, a DLT and
+//! the output of `tcpdump -y <dlt> -dd <expression>`:
+//!
+//! ```text
+//! # expression: ip and udp
+//! # dlt: 1
+//! { 0x28, 0, 0, 0x0000000c },
+//! { 0x15, 0, 3, 0x00000800 },
+//! { 0x30, 0, 0, 0x00000017 },
+//! { 0x15, 0, 1, 0x00000011 },
+//! { 0x6, 0, 0, 0x00040000 },
+//! { 0x6, 0, 0, 0x00000000 },
+//! ```
+
+use crate::analysis::AnalysisError;
+use crate::bpf_base::BPFFilter;
+use crate::prover::{equivalent, Verdict};
+use std::convert::TryFrom;
+use std::fmt;
+
+/// one expression of a corpus with the program libpcap compiled it to
+#[derive(Debug)]
+pub struct GoldenCase {
+    pub expression: String,
+    /// the DLT_* link type the expression was compiled for
+    pub dlt: u32,
+    pub expected: Vec<BPFFilter>,
+}
+
+/// a malformed line in `tcpdump -dd` output or in a corpus
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenParseError {
+    /// 1-based line number
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for GoldenParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for GoldenParseError {}
+
+fn parse_number(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// parse one `{ code, jt, jf, k },` line
+fn parse_dd_line(line: &str) -> Option<BPFFilter> {
+    let inner = line
+        .trim()
+        .trim_end_matches(',')
+        .strip_prefix('{')?
+        .strip_suffix('}')?;
+    let fields: Vec<u64> = inner.split(',').map(parse_number).collect::<Option<_>>()?;
+    match fields[..] {
+        [code, jt, jf, k] if code <= 0xffff && jt <= 0xff && jf <= 0xff && k <= 0xffff_ffff => {
+            Some(BPFFilter {
+                code: code as u16,
+                jt: jt as u8,
+                jf: jf as u8,
+                k: k as u32,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// parse the output of `tcpdump -dd`
+pub fn parse_dd(text: &str) -> Result<Vec<BPFFilter>, GoldenParseError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            parse_dd_line(line).ok_or_else(|| GoldenParseError {
+                line: i + 1,
+                message: format!("not a `{{ code, jt, jf, k }}` instruction: {}", line.trim()),
+            })
+        })
+        .collect()
+}
+
+/// parse a corpus of cases, see the module documentation for the format
+pub fn parse_corpus(text: &str) -> Result<Vec<GoldenCase>, GoldenParseError> {
+    let mut cases: Vec<GoldenCase> = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let error = |message: String| GoldenParseError {
+            line: i + 1,
+            message,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(expression) = line.strip_prefix("# expression:") {
+            cases.push(GoldenCase {
+                expression: expression.trim().to_string(),
+                dlt: 1,
+                expected: Vec::new(),
+            });
+        } else if let Some(dlt) = line.strip_prefix("# dlt:") {
+            let case = cases
+                .last_mut()
+                .ok_or_else(|| error("`# dlt:` before any `# expression:`".to_string()))?;
+            case.dlt = parse_number(dlt)
+                .and_then(|dlt| u32::try_from(dlt).ok())
+                .ok_or_else(|| error(format!("invalid DLT: {}", dlt.trim())))?;
+        } else if line.starts_with('#') {
+            continue;
+        } else {
+            let insn = parse_dd_line(line).ok_or_else(|| {
+                error(format!(
+                    "not a `{{ code, jt, jf, k }}` instruction: {}",
+                    line
+                ))
+            })?;
+            cases
+                .last_mut()
+                .ok_or_else(|| error("instruction before any `# expression:`".to_string()))?
+                .expected
+                .push(insn);
+        }
+    }
+    Ok(cases)
+}
+
+/// a case whose generated program does not match libpcap's
+#[derive(Debug)]
+pub struct GoldenFailure {
+    pub expression: String,
+    pub dlt: u32,
+    pub reason: String,
+}
+
+impl fmt::Display for GoldenFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` (DLT {}): {}",
+            self.expression, self.dlt, self.reason
+        )
+    }
+}
+
+/// compile every case with `compile` and compare the result with the fixture
+///
+/// programs are compared with
+/// [`BPFProgram::equivalent`](crate::BPFProgram::equivalent), so they only
+/// need to accept the same packets, not to be identical. cases the prover
+/// cannot decide are reported as failures as well.
+pub fn check_corpus<F, E>(cases: &[GoldenCase], mut compile: F) -> Vec<GoldenFailure>
+where
+    F: FnMut(&str, u32) -> Result<Vec<BPFFilter>, E>,
+    E: fmt::Display,
+{
+    let mut failures = Vec::new();
+    for case in cases {
+        let reason = match compile(&case.expression, case.dlt) {
+            Err(e) => format!("compilation failed: {}", e),
+            Ok(actual) => match equivalent(&case.expected, &actual) {
+                Ok(Verdict::Proven) => continue,
+                Ok(Verdict::Refuted(packet)) => {
+                    format!("programs disagree on packet {:02x?}", packet)
+                }
+                Ok(Verdict::Unknown) => "equivalence could not be decided".to_string(),
+                Err(AnalysisError::TooManyPaths) => "programs too large to compare".to_string(),
+                Err(e) => format!("invalid program: {}", e),
+            },
+        };
+        failures.push(GoldenFailure {
+            expression: case.expression.clone(),
+            dlt: case.dlt,
+            reason,
+        });
+    }
+    failures
+}
+
+/// like [`check_corpus`], panicking with a report of every failed case
+pub fn assert_corpus<F, E>(cases: &[GoldenCase], compile: F)
+where
+    F: FnMut(&str, u32) -> Result<Vec<BPFFilter>, E>,
+    E: fmt::Display,
+{
+    let failures = check_corpus(cases, compile);
+    if !failures.is_empty() {
+        let report: Vec<String> = failures.iter().map(|f| f.to_string()).collect();
+        panic!(
+            "{} of {} golden cases failed:\n{}",
+            failures.len(),
+            cases.len(),
+            report.join("\n")
+        );
+    }
+}
+
+#[test]
+fn test_check_corpus() {
+    use crate::bpf_base::bpf;
+    let cases = parse_corpus(
+        "# expression: ip and udp\n\
+         # dlt: 1\n\
+         { 0x28, 0, 0, 0x0000000c },\n\
+         { 0x15, 0, 3, 0x00000800 },\n\
+         { 0x30, 0, 0, 0x00000017 },\n\
+         { 0x15, 0, 1, 0x00000011 },\n\
+         { 0x6, 0, 0, 0x00040000 },\n\
+         { 0x6, 0, 0, 0x00000000 },\n",
+    )
+    .unwrap();
+    assert_eq!(cases.len(), 1);
+    assert_eq!(cases[0].expected.len(), 6);
+
+    // same filter with the tests swapped and another snapshot length
+    let swapped = |_: &str, _: u32| -> Result<Vec<BPFFilter>, String> {
+        Ok(vec![
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 17, 0, 3),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 1),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+        ])
+    };
+    assert!(check_corpus(&cases, swapped).is_empty());
+
+    let tcp = |_: &str, _: u32| -> Result<Vec<BPFFilter>, String> {
+        Ok(vec![
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 6, 0, 1),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+        ])
+    };
+    assert_eq!(check_corpus(&cases, tcp).len(), 1);
+}
+
+#[test]
+fn test_tcpdump_fixtures() {
+    use crate::compiler::compile;
+    use crate::dlt::Dlt;
+    let cases = parse_corpus(include_str!("../tests/golden/tcpdump.txt")).unwrap();
+    assert!(!cases.is_empty());
+    assert_corpus(&cases, |expression, dlt| {
+        let dlt = Dlt::try_from(dlt).map_err(|_| format!("unknown DLT {}", dlt))?;
+        compile(expression, dlt)
+            .map(|program| program.filters().to_vec())
+            .map_err(|e| e.to_string())
+    });
+}
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::io;
+
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+use crate::bsd::link_details;
+#[cfg(target_os = "linux")]
+use crate::linux::link_details;
+
+/// a network interface, by name or by index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Interface {
+    Name(String),
+    Index(u32),
+}
+
+impl Interface {
+    /// the index of the interface, with `if_nametoindex(3)` for names
+    pub fn index(&self) -> io::Result<u32> {
+        match self {
+            Interface::Index(index) => Ok(*index),
+            Interface::Name(name) => {
+                let name = CString::new(name.as_str())
+                    .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+                match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+                    0 => Err(io::Error::last_os_error()),
+                    index => Ok(index),
+                }
+            }
+        }
+    }
+
+    /// the description of the interface, from [`list_interfaces`]
+    pub fn info(&self) -> io::Result<InterfaceInfo> {
+        let index = self.index()?;
+        list_interfaces()?
+            .into_iter()
+            .find(|info| info.index == index)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENODEV))
+    }
+
+    /// the name of the interface, with `if_indextoname(3)` for indexes
+    pub fn name(&self) -> io::Result<String> {
+        match self {
+            Interface::Name(name) => Ok(name.clone()),
+            Interface::Index(index) => {
+                let mut buf = [0 as libc::c_char; libc::IF_NAMESIZE];
+                if unsafe { libc::if_indextoname(*index, buf.as_mut_ptr()) }.is_null() {
+                    return Err(io::Error::last_os_error());
+                }
+                let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+                Ok(name.to_string_lossy().into_owned())
+            }
+        }
+    }
+}
+
+impl From<&str> for Interface {
+    fn from(name: &str) -> Self {
+        Interface::Name(name.to_string())
+    }
+}
+
+impl From<String> for Interface {
+    fn from(name: String) -> Self {
+        Interface::Name(name)
+    }
+}
+
+impl From<u32> for Interface {
+    fn from(index: u32) -> Self {
+        Interface::Index(index)
+    }
+}
+
+/// a network interface reported by [`list_interfaces`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceInfo {
+    pub name: String,
+    pub index: u32,
+    /// the interface is administratively up
+    pub up: bool,
+    pub loopback: bool,
+    /// the interface is on a broadcast medium, where the promiscuous mode makes sense
+    pub promisc_capable: bool,
+    /// the interface is in promiscuous mode
+    pub promiscuous: bool,
+    pub mtu: Option<u32>,
+    /// the `dlt::DLT_*` link type of the frames captured on the interface
+    pub link_type: Option<u32>,
+    /// the hardware address, empty when the interface has none
+    pub hwaddr: Vec<u8>,
+}
+
+/// the link-layer details of an interface, found in its link-layer address entry
+pub(crate) struct LinkDetails {
+    pub(crate) mtu: Option<u32>,
+    pub(crate) link_type: Option<u32>,
+    pub(crate) hwaddr: Vec<u8>,
+}
+
+/// list the network interfaces of the system, with `getifaddrs(3)`
+///
+/// the interfaces are sorted by index.
+pub fn list_interfaces() -> io::Result<Vec<InterfaceInfo>> {
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // getifaddrs reports every address of an interface
+    let mut interfaces = BTreeMap::new();
+    let mut cursor = addrs;
+    while let Some(ifa) = unsafe { cursor.as_ref() } {
+        cursor = ifa.ifa_next;
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        let index = match Interface::from(name.as_str()).index() {
+            Ok(index) => index,
+            Err(_) => continue,
+        };
+        let flags = ifa.ifa_flags as libc::c_int;
+        let info = interfaces.entry(index).or_insert_with(|| InterfaceInfo {
+            name,
+            index,
+            up: flags & libc::IFF_UP != 0,
+            loopback: flags & libc::IFF_LOOPBACK != 0,
+            promisc_capable: flags & libc::IFF_BROADCAST != 0
+                && flags & (libc::IFF_LOOPBACK | libc::IFF_POINTOPOINT) == 0,
+            promiscuous: flags & libc::IFF_PROMISC != 0,
+            mtu: None,
+            link_type: None,
+            hwaddr: Vec::new(),
+        });
+        if let Some(details) = link_details(ifa) {
+            info.mtu = details.mtu;
+            info.link_type = details.link_type;
+            info.hwaddr = details.hwaddr;
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(interfaces.into_values().collect())
+}
+
+#[test]
+fn test_list_interfaces() {
+    let interfaces = list_interfaces().unwrap();
+    let lo = interfaces.iter().find(|i| i.loopback).unwrap();
+    assert!(!lo.promisc_capable);
+    assert!(lo.mtu.is_some());
+    assert_eq!(&Interface::from(lo.index).info().unwrap(), lo);
+    assert_eq!(Interface::from(lo.name.as_str()).index().unwrap(), lo.index);
+    assert_eq!(Interface::from(lo.index).name().unwrap(), lo.name);
+}
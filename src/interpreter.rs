@@ -0,0 +1,251 @@
+use crate::ancillary::{SKF_AD_OFF, SKF_LL_OFF, SKF_NET_OFF};
+use crate::bpf_base::*;
+use std::convert::TryInto;
+
+/// where in `packet` a load at `offset` reads, for the Linux negative offsets
+///
+/// `packet` starts with the link-layer header, the network header starts at
+/// `network` when it is known.
+fn resolve(offset: u32, network: Option<u32>) -> Option<u32> {
+    match offset {
+        offset if offset < SKF_LL_OFF => Some(offset),
+        offset if offset < SKF_NET_OFF => Some(offset - SKF_LL_OFF),
+        offset => network?.checked_add(offset - SKF_NET_OFF),
+    }
+}
+
+/// the `size` bytes of `packet` at `offset`, big-endian as BPF loads them
+fn load(packet: &[u8], offset: u32, size: usize) -> Option<u32> {
+    let start = offset as usize;
+    let bytes = packet.get(start..start.checked_add(size)?)?;
+    Some(match size {
+        4 => u32::from_be_bytes(bytes.try_into().unwrap()),
+        2 => u16::from_be_bytes(bytes.try_into().unwrap()) as u32,
+        _ => bytes[0] as u32,
+    })
+}
+
+/// run a classic BPF program over a packet, as `bpf_filter()` does
+///
+/// `packet` holds the captured bytes and `wirelen` the length of the packet
+/// on the wire, what `len` loads. returns the number of bytes to keep, 0
+/// dropping the packet.
+///
+/// as in the kernel, loads past the end of the packet, divisions by zero,
+/// jumps or falls past the end of the program and invalid instructions drop
+/// the packet. the Linux ancillary loads are not emulated and drop it too.
+/// an empty program accepts every packet, as in libpcap.
+///
+/// `packet` is taken to start with the link-layer header, which the Linux
+/// `SKF_LL_OFF` loads read. the `SKF_NET_OFF` loads drop the packet, see
+/// [`run_with_network_offset`].
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // accept the IPv6 packets of an Ethernet capture
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+///
+/// let mut frame = [0u8; 54];
+/// assert_eq!(run(&filters, &frame, 54), 0);
+/// frame[12..14].copy_from_slice(&[0x86, 0xdd]);
+/// assert_eq!(run(&filters, &frame, 54), u32::MAX);
+/// ```
+pub fn run(filters: &[BPFFilter], packet: &[u8], wirelen: u32) -> u32 {
+    execute(filters, packet, wirelen, None)
+}
+
+/// run a classic BPF program over a packet whose network header starts at `network`
+///
+/// as [`run`], with the Linux `SKF_NET_OFF` loads reading from `network`.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let mut program = ProgramBuilder::new();
+/// program.ld_net(bpf::B, 9).ret_a();
+/// let filters = program.build().unwrap();
+///
+/// // the protocol of an IPv4 packet behind an Ethernet header
+/// let mut frame = [0u8; 34];
+/// frame[23] = 17;
+/// assert_eq!(run_with_network_offset(&filters, &frame, 34, 14), 17);
+/// assert_eq!(run(&filters, &frame, 34), 0);
+/// ```
+pub fn run_with_network_offset(
+    filters: &[BPFFilter],
+    packet: &[u8],
+    wirelen: u32,
+    network: u32,
+) -> u32 {
+    execute(filters, packet, wirelen, Some(network))
+}
+
+fn execute(filters: &[BPFFilter], packet: &[u8], wirelen: u32, network: Option<u32>) -> u32 {
+    if filters.is_empty() {
+        return u32::MAX;
+    }
+    let mut a: u32 = 0;
+    let mut x: u32 = 0;
+    let mut mem = [0u32; 16];
+    let mut pc = 0;
+    while let Some(insn) = filters.get(pc) {
+        pc += 1;
+        let k = insn.k;
+        let size = match insn.code & 0x18 {
+            0x00 => 4,
+            0x08 => 2,
+            _ => 1,
+        };
+        match insn.code {
+            // LD and LDX
+            0x00 => a = k,
+            0x01 => x = k,
+            // the ancillary fields are only known to the kernel
+            0x20 | 0x28 | 0x30 if k >= SKF_AD_OFF => return 0,
+            0x20 | 0x28 | 0x30 => match resolve(k, network).and_then(|k| load(packet, k, size)) {
+                Some(value) => a = value,
+                None => return 0,
+            },
+            0x40 | 0x48 | 0x50 => {
+                match resolve(x.wrapping_add(k), network).and_then(|k| load(packet, k, size)) {
+                    Some(value) => a = value,
+                    None => return 0,
+                }
+            }
+            0x60 | 0x61 => match mem.get(k as usize) {
+                Some(value) if insn.code == 0x60 => a = *value,
+                Some(value) => x = *value,
+                None => return 0,
+            },
+            0x80 => a = wirelen,
+            0x81 => x = wirelen,
+            0xb1 => match load(packet, k, 1) {
+                Some(value) => x = (value & 0xf) << 2,
+                None => return 0,
+            },
+            // ST and STX
+            0x02 | 0x03 => match mem.get_mut(k as usize) {
+                Some(slot) => *slot = if insn.code == 0x02 { a } else { x },
+                None => return 0,
+            },
+            // ALU
+            code if code & 0x07 == 0x04 => {
+                let operand = if code & 0x08 != 0 { x } else { k };
+                a = match code & 0xf0 {
+                    0x00 => a.wrapping_add(operand),
+                    0x10 => a.wrapping_sub(operand),
+                    0x20 => a.wrapping_mul(operand),
+                    0x30 => match a.checked_div(operand) {
+                        Some(value) => value,
+                        None => return 0,
+                    },
+                    0x40 => a | operand,
+                    0x50 => a & operand,
+                    0x60 => a.wrapping_shl(operand),
+                    0x70 => a.wrapping_shr(operand),
+                    0x80 => a.wrapping_neg(),
+                    0x90 => match a.checked_rem(operand) {
+                        Some(value) => value,
+                        None => return 0,
+                    },
+                    0xa0 => a ^ operand,
+                    _ => return 0,
+                }
+            }
+            // JMP
+            0x05 => {
+                pc = match pc.checked_add(k as usize) {
+                    Some(pc) => pc,
+                    None => return 0,
+                }
+            }
+            code if code & 0x07 == 0x05 => {
+                let operand = if code & 0x08 != 0 { x } else { k };
+                let taken = match code & 0xf0 {
+                    0x10 => a == operand,
+                    0x20 => a > operand,
+                    0x30 => a >= operand,
+                    0x40 => a & operand != 0,
+                    _ => return 0,
+                };
+                pc += if taken { insn.jt } else { insn.jf } as usize;
+            }
+            // RET
+            0x06 => return k,
+            0x16 => return a,
+            // MISC
+            0x07 => x = a,
+            0x87 => a = x,
+            _ => return 0,
+        }
+    }
+    0
+}
+
+#[test]
+fn test_run() {
+    use crate::compiler::compile;
+    use crate::dlt::Dlt;
+
+    // an Ethernet frame of an IPv4 TCP segment to port 443
+    let mut frame = vec![0u8; 54];
+    frame[12..14].copy_from_slice(&[0x08, 0x00]);
+    frame[14] = 0x45;
+    frame[23] = 6;
+    frame[36..38].copy_from_slice(&443u16.to_be_bytes());
+    let program = compile("tcp dst port 443", Dlt::En10mb).unwrap();
+    assert_eq!(run(&program, &frame, 60), 262144);
+    frame[21] = 1;
+    assert_eq!(
+        run(&program, &frame, 60),
+        0,
+        "a later fragment has no ports"
+    );
+
+    // X = IP header length, M[3] = wirelen, A = (M[3] % X) ^ 0xff
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::LEN, 0),
+        BPFFilter::bpf_stmt(bpf::ST, 3),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::MEM, 3),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::MOD | bpf::X, 0),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::XOR | bpf::K, 0xff),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(run(&filters, &frame, 62), (62 % 20) ^ 0xff);
+    frame[14] = 0x40;
+    assert_eq!(run(&filters, &frame, 62), 0, "division by zero");
+
+    // the Linux loads relative to the headers, ancillary loads drop
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, SKF_LL_OFF + 12),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(run(&filters, &frame, 60), 0x0800);
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, SKF_NET_OFF),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(run(&filters, &frame, 60), 0);
+    assert_eq!(run_with_network_offset(&filters, &frame, 60, 14), 0x40);
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(run_with_network_offset(&filters, &frame, 60, 14), 0);
+
+    // loads past the end of the packet and falling off the program drop
+    let filters = [BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, 52)];
+    assert_eq!(run(&filters, &frame, 60), 0);
+    assert_eq!(run(&filters[..0], &frame, 60), u32::MAX);
+}
@@ -4,12 +4,129 @@
 mod bpf_base;
 pub use bpf_base::*;
 
+mod builder;
+pub use builder::*;
+
+mod const_builder;
+pub use const_builder::*;
+
+mod analysis;
+pub use analysis::*;
+
+mod decompile;
+pub use decompile::*;
+
+mod compiler;
+pub use compiler::*;
+
+mod interpreter;
+pub use interpreter::*;
+
+mod validate;
+pub use validate::*;
+
+mod ebpf;
+pub use ebpf::*;
+
+mod assembler;
+pub use assembler::*;
+
+mod prover;
+pub use prover::*;
+
+pub mod golden;
+
+mod transform;
+pub use transform::*;
+
+mod template;
+pub use template::*;
+
+pub mod ancillary;
+
+pub mod filters;
+
+pub mod dlt;
+pub use dlt::Dlt;
+
+pub mod presets;
+
+pub mod seccomp;
+
+pub mod netlink;
+
+pub mod fragments;
+
+pub mod offsets;
+
+pub mod expr;
+
+pub mod config;
+
+mod buffer;
+pub use buffer::*;
+
+mod timestamp;
+pub use timestamp::*;
+
+mod privileges;
+pub use privileges::*;
+
+mod interface;
+pub use interface::*;
+
+mod replay;
+pub use replay::*;
+
+mod pcap;
+pub use pcap::*;
+
+mod monitor;
+pub use monitor::*;
+
+mod cache;
+pub use cache::*;
+
+mod two_stage;
+pub use two_stage::*;
+
+mod registry;
+pub use registry::*;
+
+mod scoped;
+pub use scoped::*;
+
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+mod reload;
+#[cfg(any(target_os = "linux", target_os = "freebsd", target_os = "macos"))]
+pub use reload::*;
+
 #[cfg(target_os = "linux")]
 mod linux;
 #[cfg(target_os = "linux")]
 pub use linux::*;
 
+#[cfg(target_os = "linux")]
+mod fanout;
+#[cfg(target_os = "linux")]
+pub use fanout::*;
+
+#[cfg(target_os = "linux")]
+mod ring;
+#[cfg(target_os = "linux")]
+pub use ring::*;
+
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 mod bsd;
 #[cfg(any(target_os = "freebsd", target_os = "macos"))]
 pub use bsd::*;
+
+#[cfg(any(target_os = "freebsd", target_os = "macos", test))]
+mod bpf_records;
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+pub use bpf_records::{BpfPacket, BpfPackets};
+
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+mod bpf_device;
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+pub use bpf_device::*;
@@ -1,9 +1,21 @@
 use crate::bpf_base::*;
+use crate::buffer::{AlignedBuf, FrameBuf, FrameIter, BUF_ALIGN, READ_BATCH};
+use crate::dlt::*;
+#[cfg(feature = "ebpf")]
+use crate::ebpf::*;
+use crate::interface::{Interface, LinkDetails};
+use crate::privileges::PrivilegeError;
+use crate::timestamp::*;
+use std::fs::File;
+use std::io;
 use std::mem::size_of;
-use std::os::unix::io::AsRawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use std::time::Duration;
 
 impl BPFOperations for BPFFProg<'_> {
-    fn attach_filter<T>(self, socket: &T) -> Result<(), i32>
+    fn attach_filter<T>(self, socket: &T) -> io::Result<()>
     where
         T: AsRawFd,
     {
@@ -17,27 +29,1319 @@ impl BPFOperations for BPFFProg<'_> {
             )
         } {
             0 => Ok(()),
-            errno => Err(errno),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+impl From<libc::sock_filter> for BPFFilter {
+    fn from(filter: libc::sock_filter) -> Self {
+        Self {
+            code: filter.code,
+            jt: filter.jt,
+            jf: filter.jf,
+            k: filter.k,
+        }
+    }
+}
+
+impl From<BPFFilter> for libc::sock_filter {
+    fn from(filter: BPFFilter) -> Self {
+        Self {
+            code: filter.code,
+            jt: filter.jt,
+            jf: filter.jf,
+            k: filter.k,
         }
     }
 }
 
 /// remove the classic BPF program attached to a socket
-pub fn detach_filter<T>(socket: &T) -> Result<(), i32>
+pub fn detach_filter<T>(socket: &T) -> io::Result<()>
 where
     T: AsRawFd,
 {
+    // the kernel wants an int sized option, even if it is ignored
+    let unused: libc::c_int = 0;
     match unsafe {
         libc::setsockopt(
             socket.as_raw_fd(),
             libc::SOL_SOCKET,
             libc::SO_DETACH_FILTER,
-            std::ptr::null::<libc::c_void>(),
-            0,
+            &unused as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// the classic BPF program attached to `socket`, empty without one (SO_GET_FILTER)
+///
+/// the kernel keeps the instructions as they were attached, they can be
+/// decompiled or attached to another socket.
+pub fn get_filter<T>(socket: &T) -> io::Result<Vec<BPFFilter>>
+where
+    T: AsRawFd,
+{
+    loop {
+        // the lengths are counted in instructions, a 0 length asks for it
+        let mut len: libc::socklen_t = 0;
+        if unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_GET_FILTER,
+                std::ptr::null_mut(),
+                &mut len,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        // BPFFilter has the layout of sock_filter
+        let mut filters = vec![BPFFilter::from((0, 0, 0, 0)); len as usize];
+        match unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_GET_FILTER,
+                filters.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        } {
+            0 => {
+                filters.truncate(len as usize);
+                return Ok(filters);
+            }
+            _ => match io::Error::last_os_error() {
+                // a longer program was attached in between
+                e if e.raw_os_error() == Some(libc::EINVAL) => continue,
+                e => return Err(e),
+            },
+        }
+    }
+}
+
+/// pick the socket of the SO_REUSEPORT group of `socket` receiving each
+/// packet with `filters` (SO_ATTACH_REUSEPORT_CBPF)
+///
+/// the program returns the index of the socket in the group, in the order
+/// the sockets were bound. an index past the group falls back to the
+/// kernel hash. `socket` must have SO_REUSEPORT set, see
+/// `filters::steer_by_rxhash`.
+pub fn attach_reuseport_filter<T>(socket: &T, filters: &[BPFFilter]) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let program = BPFFProg::new(filters);
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_REUSEPORT_CBPF,
+            &program as *const _ as *const libc::c_void,
+            size_of::<BPFFProg>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// forbid any later change to the program attached to `socket` (SO_LOCK_FILTER)
+///
+/// the program can no longer be detached or replaced, not even by root, and
+/// the socket cannot be unlocked. attach the filter then lock it before
+/// dropping privileges.
+pub fn lock_filter<T>(socket: &T) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let locked: libc::c_int = 1;
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LOCK_FILTER,
+            &locked as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// how `attach_best_filter` attached a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachMechanism {
+    /// the classic program itself, with SO_ATTACH_FILTER
+    Classic,
+    /// its eBPF translation, loaded with bpf(2) and attached with SO_ATTACH_BPF
+    Ebpf,
+}
+
+// the leading fields of union bpf_attr for BPF_PROG_LOAD, the kernel takes
+// the fields it was not given as 0
+#[cfg(feature = "ebpf")]
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+}
+
+#[cfg(feature = "ebpf")]
+const BPF_PROG_LOAD: libc::c_int = 5;
+#[cfg(feature = "ebpf")]
+const BPF_PROG_TYPE_SOCKET_FILTER: u32 = 1;
+
+/// load an eBPF socket filter with bpf(2), see `to_ebpf`
+///
+/// the kernel verifies the program, most systems only let CAP_BPF or
+/// CAP_SYS_ADMIN load one.
+#[cfg(feature = "ebpf")]
+pub fn load_ebpf_filter(insns: &[EbpfInsn]) -> io::Result<OwnedFd> {
+    let license = b"MIT\0";
+    let attr = ProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_SOCKET_FILTER,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: 0,
+        log_size: 0,
+        log_buf: 0,
+        kern_version: 0,
+        prog_flags: 0,
+    };
+    match unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_LOAD,
+            &attr as *const ProgLoadAttr,
+            size_of::<ProgLoadAttr>() as libc::c_uint,
+        )
+    } {
+        -1 => Err(io::Error::last_os_error()),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) }),
+    }
+}
+
+/// attach the eBPF socket filter `program` to `socket` (SO_ATTACH_BPF)
+///
+/// the socket keeps its own reference, `program` can be closed afterwards.
+/// `detach_filter` removes it as a classic one.
+#[cfg(feature = "ebpf")]
+pub fn attach_ebpf_filter<T, P>(socket: &T, program: &P) -> io::Result<()>
+where
+    T: AsRawFd,
+    P: AsRawFd,
+{
+    let fd: libc::c_int = program.as_raw_fd();
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ATTACH_BPF,
+            &fd as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// attach `filters` to `socket` the best way the system allows
+///
+/// with the `ebpf` feature, the program is translated and attached as eBPF,
+/// falling back to the classic program when it has no translation or the
+/// kernel refuses to load it, as it does for unprivileged users. without the
+/// feature, this is `attach_filter`.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0")?;
+/// let mechanism = attach_best_filter(&socket, presets::arp_only())?;
+/// if !cfg!(feature = "ebpf") {
+///     assert_eq!(mechanism, AttachMechanism::Classic);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub fn attach_best_filter<T>(socket: &T, filters: &[BPFFilter]) -> io::Result<AttachMechanism>
+where
+    T: AsRawFd,
+{
+    #[cfg(feature = "ebpf")]
+    {
+        let attached = to_ebpf(filters)
+            .ok()
+            .and_then(|insns| load_ebpf_filter(&insns).ok())
+            .and_then(|program| attach_ebpf_filter(socket, &program).ok());
+        if attached.is_some() {
+            return Ok(AttachMechanism::Ebpf);
+        }
+    }
+    BPFFProg::new(filters).attach_filter(socket)?;
+    Ok(AttachMechanism::Classic)
+}
+
+/// filter the frames a TAP device hands to userspace (TUNATTACHFILTER)
+///
+/// `tap` is the /dev/net/tun fd the device was created on, with IFF_TAP:
+/// the kernel refuses filters on TUN devices with EINVAL. the frames
+/// `filters` drops never reach the fd, the others are not truncated.
+pub fn attach_tap_filter<T>(tap: &T, filters: &[BPFFilter]) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let program = BPFFProg::new(filters);
+    match unsafe { libc::ioctl(tap.as_raw_fd(), libc::TUNATTACHFILTER, &program) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// remove the filter of a TAP device (TUNDETACHFILTER)
+pub fn detach_tap_filter<T>(tap: &T) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    // the kernel ignores the program
+    let program: libc::sock_fprog = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(tap.as_raw_fd(), libc::TUNDETACHFILTER, &program) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// the number of instructions of the filter last attached to a TAP device
+/// (TUNGETFILTER)
+///
+/// the kernel only keeps the length of the program it was given, not a copy
+/// of the instructions it hands back. the length stays after
+/// `detach_tap_filter`, 0 means no filter was ever attached.
+pub fn tap_filter_len<T>(tap: &T) -> io::Result<usize>
+where
+    T: AsRawFd,
+{
+    let mut program: libc::sock_fprog = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(tap.as_raw_fd(), libc::TUNGETFILTER, &mut program) } {
+        0 => Ok(program.len as usize),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+// _IOW('t', 71, struct sock_fprog) and _IOW('t', 70, struct sock_fprog)
+const PPPIOCSPASS: libc::Ioctl = libc::_IOW::<libc::sock_fprog>(b't' as u32, 71);
+const PPPIOCSACTIVE: libc::Ioctl = libc::_IOW::<libc::sock_fprog>(b't' as u32, 70);
+
+fn set_ppp_filter<T>(ppp: &T, request: libc::Ioctl, filters: &[BPFFilter]) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    // BPFFilter has the layout of sock_filter, an empty program is never read
+    let program = libc::sock_fprog {
+        len: filters.len() as u16,
+        filter: filters.as_ptr() as *mut libc::sock_filter,
+    };
+    match unsafe { libc::ioctl(ppp.as_raw_fd(), request, &program) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// pick the packets a PPP unit lets through (PPPIOCSPASS)
+///
+/// `ppp` is the /dev/ppp fd of the unit. the programs see each packet behind
+/// a 4-byte header: 1 in the first byte for the packets sent, 0 for those
+/// received, then the PPP protocol at offset 2, as in the pppd captures. the
+/// packets the program drops are discarded, an empty program removes the
+/// filter. the kernel must be built with CONFIG_PPP_FILTER.
+pub fn set_ppp_pass_filter<T>(ppp: &T, filters: &[BPFFilter]) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    set_ppp_filter(ppp, PPPIOCSPASS, filters)
+}
+
+/// pick the packets that keep a PPP link active (PPPIOCSACTIVE)
+///
+/// only the packets the program accepts reset the idle time pppd reads to
+/// hang up an idle link or dial on demand, the others still go through.
+/// the packets are seen as by `set_ppp_pass_filter`.
+pub fn set_ppp_active_filter<T>(ppp: &T, filters: &[BPFFilter]) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    set_ppp_filter(ppp, PPPIOCSACTIVE, filters)
+}
+
+/// ask the kernel to stamp the frames received on `socket`
+///
+/// the stamps are reported by `recv_batch` through `FrameBuf::timestamp`.
+/// hardware stamps are always nanosecond precise, and fall back to software
+/// stamps for packets the NIC did not stamp.
+pub fn set_timestamping<T>(socket: &T, source: TsSource, precision: TsPrecision) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let (option, value) = match (source, precision) {
+        (TsSource::Kernel, TsPrecision::Micro) => (libc::SO_TIMESTAMP, 1),
+        (TsSource::Kernel, TsPrecision::Nano) => (libc::SO_TIMESTAMPNS, 1),
+        (TsSource::Hardware, _) => (
+            libc::SO_TIMESTAMPING,
+            (libc::SOF_TIMESTAMPING_RX_HARDWARE
+                | libc::SOF_TIMESTAMPING_RAW_HARDWARE
+                | libc::SOF_TIMESTAMPING_RX_SOFTWARE
+                | libc::SOF_TIMESTAMPING_SOFTWARE) as libc::c_int,
+        ),
+    };
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            &value as *const _ as *const libc::c_void,
+            size_of::<libc::c_int>() as u32,
         )
     } {
         0 => Ok(()),
-        errno => Err(errno),
+        _ => Err(io::Error::last_os_error()),
     }
 }
-// test
+
+/// extract the receive timestamp from the control messages of `msg`
+fn parse_timestamp(msg: &libc::msghdr) -> Option<Duration> {
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+    while !cmsg.is_null() {
+        let (level, kind, data) =
+            unsafe { ((*cmsg).cmsg_level, (*cmsg).cmsg_type, libc::CMSG_DATA(cmsg)) };
+        if level == libc::SOL_SOCKET {
+            match kind {
+                libc::SCM_TIMESTAMP => {
+                    let tv = unsafe { std::ptr::read_unaligned(data as *const libc::timeval) };
+                    return Some(Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000));
+                }
+                libc::SCM_TIMESTAMPNS => {
+                    let ts = unsafe { std::ptr::read_unaligned(data as *const libc::timespec) };
+                    return Some(Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32));
+                }
+                libc::SCM_TIMESTAMPING => {
+                    // [0] is the software stamp, [2] the raw hardware one
+                    let ts =
+                        unsafe { std::ptr::read_unaligned(data as *const [libc::timespec; 3]) };
+                    let best = if ts[2].tv_sec != 0 || ts[2].tv_nsec != 0 {
+                        ts[2]
+                    } else {
+                        ts[0]
+                    };
+                    return Some(Duration::new(best.tv_sec as u64, best.tv_nsec as u32));
+                }
+                _ => {}
+            }
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+    }
+    None
+}
+
+/// receive up to `frames.len()` packets from a (filtered) socket with a single `recvmmsg(2)`
+///
+/// blocks until at least one packet is queued, then fills the remaining slots
+/// with whatever is already waiting. `timeout` is passed to `recvmmsg(2)` as is,
+/// so it is only checked after each received packet.
+///
+/// returns the number of slots filled, starting from `frames[0]`
+pub fn recv_batch<T>(
+    socket: &T,
+    frames: &mut [FrameBuf],
+    timeout: Option<Duration>,
+) -> io::Result<usize>
+where
+    T: AsRawFd,
+{
+    let mut iovecs: Vec<libc::iovec> = frames
+        .iter_mut()
+        .map(|frame| {
+            let buf = frame.buf_mut();
+            libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            }
+        })
+        .collect();
+    // room for the largest timestamp control message, SCM_TIMESTAMPING
+    let mut controls = vec![[0u64; 16]; frames.len()];
+    let mut msgs: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .zip(controls.iter_mut())
+        .map(|(iov, control)| {
+            let mut msg: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg.msg_hdr.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_hdr.msg_controllen = size_of::<[u64; 16]>() as _;
+            msg
+        })
+        .collect();
+    let n = recvmmsg(socket, &mut msgs, timeout)?;
+    for (frame, msg) in frames.iter_mut().zip(&msgs).take(n) {
+        let len = msg.msg_len as usize;
+        frame.set_received(len, len > frame.capacity(), parse_timestamp(&msg.msg_hdr));
+    }
+    Ok(n)
+}
+
+/// receive the packets queued on a (filtered) socket into `buf` with a
+/// single `recvmmsg(2)`, without allocating
+///
+/// `buf` is cut into slots of `snaplen` bytes rounded up to `BUF_ALIGN`, up
+/// to `READ_BATCH` of them, in which the packets are cut at `snaplen`. it
+/// blocks and times out as `recv_batch`, which also reports the stamps.
+///
+/// fails with `EINVAL` when `buf` cannot hold a slot.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut buf = AlignedBuf::new(1 << 16);
+/// for frame in read_into(&socket, &mut buf, 1500, None).unwrap() {
+///     println!("{} of {} bytes", frame.data.len(), frame.len);
+/// }
+/// ```
+pub fn read_into<'a, T>(
+    socket: &T,
+    buf: &'a mut AlignedBuf,
+    snaplen: usize,
+    timeout: Option<Duration>,
+) -> io::Result<FrameIter<'a>>
+where
+    T: AsRawFd,
+{
+    let stride = snaplen.checked_next_multiple_of(BUF_ALIGN).unwrap_or(0);
+    let slots = buf.len().checked_div(stride).unwrap_or(0).min(READ_BATCH);
+    if slots == 0 {
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    let base = buf.as_mut_ptr();
+    let mut iovecs: [libc::iovec; READ_BATCH] = unsafe { std::mem::zeroed() };
+    let mut msgs: [libc::mmsghdr; READ_BATCH] = unsafe { std::mem::zeroed() };
+    let slices = iovecs[..slots].iter_mut().zip(msgs[..slots].iter_mut());
+    for (i, (iov, msg)) in slices.enumerate() {
+        iov.iov_base = unsafe { base.add(i * stride) } as *mut libc::c_void;
+        iov.iov_len = snaplen;
+        msg.msg_hdr.msg_iov = iov;
+        msg.msg_hdr.msg_iovlen = 1;
+    }
+    let n = recvmmsg(socket, &mut msgs[..slots], timeout)?;
+    let mut lens = [0; READ_BATCH];
+    for (len, msg) in lens.iter_mut().zip(&msgs[..n]) {
+        *len = msg.msg_len as usize;
+    }
+    Ok(FrameIter::new(buf, stride, snaplen, lens, n))
+}
+
+/// `recvmmsg(2)` into `msgs`, blocking until the first packet
+fn recvmmsg<T>(
+    socket: &T,
+    msgs: &mut [libc::mmsghdr],
+    timeout: Option<Duration>,
+) -> io::Result<usize>
+where
+    T: AsRawFd,
+{
+    let mut ts = timeout.map(|t| libc::timespec {
+        tv_sec: t.as_secs() as libc::time_t,
+        tv_nsec: t.subsec_nanos() as _,
+    });
+    let ts_ptr = ts
+        .as_mut()
+        .map_or(std::ptr::null_mut(), |ts| ts as *mut libc::timespec);
+
+    match unsafe {
+        libc::recvmmsg(
+            socket.as_raw_fd(),
+            msgs.as_mut_ptr(),
+            msgs.len() as libc::c_uint,
+            (libc::MSG_WAITFORONE | libc::MSG_TRUNC) as _,
+            ts_ptr,
+        )
+    } {
+        -1 => Err(io::Error::last_os_error()),
+        n => Ok(n as usize),
+    }
+}
+
+/// the packets a packet socket dropped since the previous call (PACKET_STATISTICS)
+///
+/// the kernel resets its counters on each read, feed the result to a
+/// `DropMonitor`.
+pub fn packet_drops<T>(socket: &T) -> io::Result<u64>
+where
+    T: AsRawFd,
+{
+    let mut stats: libc::tpacket_stats = unsafe { std::mem::zeroed() };
+    let mut len = size_of::<libc::tpacket_stats>() as libc::socklen_t;
+    match unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_PACKET,
+            libc::PACKET_STATISTICS,
+            &mut stats as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    } {
+        0 => Ok(stats.tp_drops as u64),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// how long `BPFProgram::self_test` waits for a probe it then deems dropped
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+impl BPFProgram {
+    /// check how the kernel itself runs the program on `probe`, on a socket
+    /// of the kind of `socket`
+    ///
+    /// `socket` is a datagram socket, UDP or AF_UNIX, and is left untouched:
+    /// the program is attached to a private socket of the same family, over
+    /// the loopback or a socket pair, and `probe` is sent to it. the program
+    /// sees it as it sees the traffic of `socket`, after the 8 bytes of the
+    /// UDP header or from offset 0 on AF_UNIX. the kernel verifier and
+    /// interpreter or JIT are the ones of the production sockets, which
+    /// catches byte-order and offset mistakes before a program is trusted.
+    /// other sockets fail with EOPNOTSUPP.
+    ///
+    /// returns whether the probe was delivered as `accept` expects, whole or
+    /// cut to the length the program returns, or dropped when `accept` is
+    /// false. the length is predicted with the UDP checksum taken as 0, the
+    /// loopback leaves it unfinished.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// // the payload of the UDP datagrams starting with 1
+    /// let program = BPFProgram::from(
+    ///     ProgramBuilder::new()
+    ///         .ld_abs_b(8)
+    ///         .jmp(bpf::JEQ, bpf::K, 1, 0, 1)
+    ///         .ret_k(u32::MAX)
+    ///         .ret_k(0)
+    ///         .build()
+    ///         .unwrap(),
+    /// );
+    /// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// assert!(program.self_test(&socket, &[1, 2, 3], true).unwrap());
+    /// assert!(program.self_test(&socket, &[2, 2, 3], false).unwrap());
+    /// ```
+    pub fn self_test<T>(&self, socket: &T, probe: &[u8], accept: bool) -> io::Result<bool>
+    where
+        T: AsRawFd,
+    {
+        let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        let ptr = &mut addr as *mut _ as *mut libc::sockaddr;
+        if unsafe { libc::getsockname(socket.as_raw_fd(), ptr, &mut len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut kind: libc::c_int = 0;
+        let mut kind_len = size_of::<libc::c_int>() as libc::socklen_t;
+        if unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                &mut kind as *mut _ as *mut libc::c_void,
+                &mut kind_len,
+            )
+        } != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        if kind != libc::SOCK_DGRAM {
+            return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP));
+        }
+
+        let mut buf = vec![0u8; probe.len() + 1];
+        // the packet as the program sees it, and the header it is cut after
+        let (packet, header, received) = match addr.ss_family as libc::c_int {
+            family @ (libc::AF_INET | libc::AF_INET6) => {
+                let loopback: std::net::IpAddr = match family {
+                    libc::AF_INET => std::net::Ipv4Addr::LOCALHOST.into(),
+                    _ => std::net::Ipv6Addr::LOCALHOST.into(),
+                };
+                let rx = std::net::UdpSocket::bind((loopback, 0))?;
+                let tx = std::net::UdpSocket::bind((loopback, 0))?;
+                // nothing but the probe reaches a connected socket
+                rx.connect(tx.local_addr()?)?;
+                rx.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                attach_filter_clean(&rx, &self.as_prog())?;
+                tx.send_to(probe, rx.local_addr()?)?;
+
+                let mut packet = Vec::with_capacity(8 + probe.len());
+                packet.extend(tx.local_addr()?.port().to_be_bytes());
+                packet.extend(rx.local_addr()?.port().to_be_bytes());
+                packet.extend(((8 + probe.len()) as u16).to_be_bytes());
+                packet.extend([0, 0]);
+                packet.extend(probe);
+                (packet, 8, rx.recv(&mut buf))
+            }
+            libc::AF_UNIX => {
+                let (tx, rx) = std::os::unix::net::UnixDatagram::pair()?;
+                rx.set_read_timeout(Some(PROBE_TIMEOUT))?;
+                attach_filter_clean(&rx, &self.as_prog())?;
+                tx.send(probe)?;
+                (probe.to_vec(), 0, rx.recv(&mut buf))
+            }
+            _ => return Err(io::Error::from_raw_os_error(libc::EOPNOTSUPP)),
+        };
+
+        // the kernel keeps at least the header of what it does not drop
+        let expected = match crate::interpreter::run(self, &packet, packet.len() as u32) {
+            0 => None,
+            ret => Some(probe.len().min((ret as usize).max(header) - header)),
+        };
+        let delivered = match received {
+            Ok(n) => {
+                (n == probe.len() || Some(n) == expected) && buf[..n] == probe[..n.min(probe.len())]
+            }
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                false
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(delivered == accept)
+    }
+}
+
+/// the details of the AF_PACKET address entry of an interface
+pub(crate) fn link_details(ifa: &libc::ifaddrs) -> Option<LinkDetails> {
+    let addr = unsafe { (ifa.ifa_addr as *const libc::sockaddr_ll).as_ref()? };
+    if addr.sll_family != libc::AF_PACKET as u16 {
+        return None;
+    }
+    let link_type = match addr.sll_hatype {
+        libc::ARPHRD_ETHER | libc::ARPHRD_LOOPBACK => Some(DLT_EN10MB),
+        libc::ARPHRD_NONE => Some(DLT_RAW),
+        libc::ARPHRD_IEEE80211 => Some(DLT_IEEE802_11),
+        libc::ARPHRD_IEEE80211_RADIOTAP => Some(DLT_IEEE802_11_RADIO),
+        // captured in cooked mode
+        _ => Some(DLT_LINUX_SLL),
+    };
+    let len = (addr.sll_halen as usize).min(addr.sll_addr.len());
+    Some(LinkDetails {
+        mtu: mtu(ifa.ifa_name),
+        link_type,
+        hwaddr: addr.sll_addr[..len].to_vec(),
+    })
+}
+
+/// the MTU of the interface named `name`, with SIOCGIFMTU
+fn mtu(name: *const libc::c_char) -> Option<u32> {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return None;
+    }
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    let name = unsafe { std::ffi::CStr::from_ptr(name) }.to_bytes();
+    for (dst, src) in req.ifr_name.iter_mut().zip(name).take(libc::IFNAMSIZ - 1) {
+        *dst = *src as libc::c_char;
+    }
+    match unsafe { libc::ioctl(socket.as_raw_fd(), libc::SIOCGIFMTU, &mut req) } {
+        0 => Some(unsafe { req.ifr_ifru.ifru_mtu } as u32),
+        _ => None,
+    }
+}
+
+/// the changes to a file, seen through inotify on its directory
+///
+/// editors often replace the file rather than writing it in place.
+#[derive(Debug)]
+pub(crate) struct Watch {
+    inotify: OwnedFd,
+    name: Vec<u8>,
+}
+
+impl Watch {
+    pub(crate) fn new(path: &Path) -> io::Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let invalid = || io::Error::from_raw_os_error(libc::EINVAL);
+        let name = path.file_name().ok_or_else(invalid)?.as_bytes().to_vec();
+        let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).map_err(|_| invalid())?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+        if unsafe { libc::inotify_add_watch(inotify.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { inotify, name })
+    }
+
+    /// wait for the file to change, `Ok(false)` once `timeout` expired
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let deadline = timeout.map(|t| std::time::Instant::now() + t);
+        loop {
+            let left = deadline.map_or(-1, |d| {
+                d.saturating_duration_since(std::time::Instant::now())
+                    .as_millis() as libc::c_int
+            });
+            let mut pfd = libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut pfd, 1, left) } {
+                -1 => match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e),
+                },
+                0 => return Ok(false),
+                _ => {}
+            }
+            let mut buf = [0u64; 512];
+            let n = unsafe {
+                libc::read(
+                    self.inotify.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    size_of::<[u64; 512]>(),
+                )
+            };
+            if n < 0 {
+                match io::Error::last_os_error() {
+                    e if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                    ) =>
+                    {
+                        continue
+                    }
+                    e => return Err(e),
+                }
+            }
+            let bytes =
+                unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, n as usize) };
+            let header = size_of::<libc::inotify_event>();
+            let mut at = 0;
+            let mut changed = false;
+            while at + header <= bytes.len() {
+                let event = unsafe { &*(bytes[at..].as_ptr() as *const libc::inotify_event) };
+                let name = &bytes[at + header..at + header + event.len as usize];
+                // the name is padded with NULs
+                let name = name.split(|b| *b == 0).next().unwrap_or_default();
+                changed |= name == self.name.as_slice();
+                at += header + event.len as usize;
+            }
+            if changed {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_NET_RAW: u32 = 13;
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+/// whether the Linux capability `cap` is effective for the process
+fn has_capability(cap: u32) -> Result<bool, PrivilegeError> {
+    let mut header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapUserData::default(); 2];
+    if unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) } != 0 {
+        return Err(PrivilegeError::Probe(errno()));
+    }
+    Ok(data[(cap / 32) as usize].effective & (1 << (cap % 32)) != 0)
+}
+
+/// check that the process may open packet sockets, which needs CAP_NET_RAW
+///
+/// capturing, filtering and injecting through the sockets need nothing
+/// more, see `check_admin_privileges` for changing the interfaces.
+pub fn check_capture_privileges() -> Result<(), PrivilegeError> {
+    match has_capability(CAP_NET_RAW)? {
+        true => Ok(()),
+        false => Err(PrivilegeError::MissingCapability("CAP_NET_RAW")),
+    }
+}
+
+/// check that the process may configure interfaces, which needs CAP_NET_ADMIN
+///
+/// setting interface flags such as IFF_PROMISC, enabling hardware stamps
+/// with SIOCSHWTSTAMP or attaching the filters of TUN/TAP and PPP devices
+/// need it, on top of `check_capture_privileges`.
+pub fn check_admin_privileges() -> Result<(), PrivilegeError> {
+    match has_capability(CAP_NET_ADMIN)? {
+        true => Ok(()),
+        false => Err(PrivilegeError::MissingCapability("CAP_NET_ADMIN")),
+    }
+}
+
+/// a new AF_PACKET socket, neither bound nor receiving anything yet
+///
+/// a packet socket created with protocol 0 receives nothing until it is
+/// bound, which leaves the time to attach its filter.
+pub(crate) fn packet_socket() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// bind a packet socket to the interface `index`, receiving the ethertype `protocol`
+pub(crate) fn bind_packet_socket<T>(socket: &T, index: u32, protocol: u16) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    addr.sll_family = libc::AF_PACKET as u16;
+    addr.sll_protocol = protocol.to_be();
+    addr.sll_ifindex = index as libc::c_int;
+    match unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &addr as *const _ as *const libc::sockaddr,
+            size_of::<libc::sockaddr_ll>() as u32,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// discard the packets queued on a datagram socket, without waiting
+fn drain_socket<T>(socket: &T) -> io::Result<usize>
+where
+    T: AsRawFd,
+{
+    let mut count = 0;
+    loop {
+        // MSG_TRUNC dequeues the whole packet into the empty buffer
+        let flags = libc::MSG_DONTWAIT | libc::MSG_TRUNC;
+        if unsafe { libc::recv(socket.as_raw_fd(), std::ptr::null_mut(), 0, flags) } >= 0 {
+            count += 1;
+            continue;
+        }
+        match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::WouldBlock => return Ok(count),
+            e if e.kind() == io::ErrorKind::Interrupted => continue,
+            e => return Err(e),
+        }
+    }
+}
+
+/// replace the program of `socket` with `prog`, discarding the packets
+/// queued before
+///
+/// the packets the old program accepted wait in the receive queue after the
+/// new one is attached. `socket` rejects everything while its queue is
+/// drained, then `prog` is attached: every packet received afterwards went
+/// through it. `socket` must receive datagrams, the data of a stream would
+/// be lost.
+pub fn attach_filter_clean<T>(socket: &T, prog: &BPFFProg) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    BPFFProg::new(crate::filters::drop_all()).attach_filter(socket)?;
+    drain_socket(socket)?;
+    BPFFProg::new(prog.filters()).attach_filter(socket)
+}
+
+/// open a packet socket capturing the ethertype `protocol` on `iface`,
+/// filtered with `filters` from its first packet
+///
+/// the socket drops everything while it is bound, then the packets
+/// queued in between are drained before `filters` is attached: no packet
+/// the program would reject is ever received. `protocol` is
+/// `libc::ETH_P_ALL` for every ethertype.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let socket = open_packet_socket("eth0", libc::ETH_P_ALL as u16, presets::arp_only())?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn open_packet_socket<I>(iface: I, protocol: u16, filters: &[BPFFilter]) -> io::Result<OwnedFd>
+where
+    I: Into<Interface>,
+{
+    let index = iface.into().index()?;
+    let socket = packet_socket()?;
+    BPFFProg::new(crate::filters::drop_all()).attach_filter(&socket)?;
+    bind_packet_socket(&socket, index, protocol)?;
+    drain_socket(&socket)?;
+    BPFFProg::new(filters).attach_filter(&socket)?;
+    Ok(socket)
+}
+
+/// open a packet socket capturing on `iface` inside the network namespace `netns`
+///
+/// `netns` is a namespace file such as `/proc/<pid>/ns/net` or
+/// `/var/run/netns/<name>`, `iface` is resolved inside it. the namespace is
+/// entered from a scoped thread, the socket stays in it and is usable from
+/// the calling thread. `filters` is attached before the socket is bound, so
+/// no unfiltered packet is queued.
+/// `protocol` is the ethertype to capture, `libc::ETH_P_ALL` for every one.
+///
+/// entering a namespace needs CAP_SYS_ADMIN.
+pub fn open_packet_socket_in_netns<P, I>(
+    netns: P,
+    iface: I,
+    protocol: u16,
+    filters: &[BPFFilter],
+) -> io::Result<OwnedFd>
+where
+    P: AsRef<Path>,
+    I: Into<Interface>,
+{
+    let netns = File::open(netns)?;
+    let iface = iface.into();
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                if unsafe { libc::setns(netns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let index = iface.index()?;
+                let socket = packet_socket()?;
+                BPFFProg::new(filters).attach_filter(&socket)?;
+                bind_packet_socket(&socket, index, protocol)?;
+                Ok(socket)
+            })
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+#[test]
+fn test_recv_batch() {
+    let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+    set_timestamping(&rx, TsSource::Kernel, TsPrecision::Nano).unwrap();
+    tx.send_to(b"abc", rx.local_addr().unwrap()).unwrap();
+    tx.send_to(b"0123456789", rx.local_addr().unwrap()).unwrap();
+
+    let mut frames: Vec<FrameBuf> = (0..4).map(|_| FrameBuf::new(8)).collect();
+    std::thread::sleep(Duration::from_millis(50));
+    let n = recv_batch(&rx, &mut frames, Some(Duration::from_secs(1))).unwrap();
+    assert_eq!(n, 2);
+    assert_eq!(frames[0].data(), b"abc");
+    assert!(!frames[0].is_truncated());
+    assert!(frames[0].timestamp().is_some());
+    assert_eq!(frames[1].len(), 10);
+    assert_eq!(frames[1].data(), b"01234567");
+    assert!(frames[1].is_truncated());
+}
+
+#[test]
+fn test_read_into() {
+    let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    tx.send_to(b"abc", rx.local_addr().unwrap()).unwrap();
+    tx.send_to(b"0123456789", rx.local_addr().unwrap()).unwrap();
+
+    let mut buf = AlignedBuf::new(64);
+    assert_eq!(
+        read_into(&rx, &mut buf, 0, None)
+            .unwrap_err()
+            .raw_os_error(),
+        Some(libc::EINVAL)
+    );
+    std::thread::sleep(Duration::from_millis(50));
+    let frames: Vec<_> = read_into(&rx, &mut buf, 8, Some(Duration::from_secs(1)))
+        .unwrap()
+        .collect();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].data, b"abc");
+    assert!(!frames[0].is_truncated());
+    assert_eq!((frames[1].data, frames[1].len), (&b"01234567"[..], 10));
+    assert!(frames[1].is_truncated());
+    assert_eq!(frames[1].data.as_ptr() as usize % BUF_ALIGN, 0);
+}
+
+#[test]
+fn test_check_capture_privileges() {
+    let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) };
+    if fd >= 0 {
+        unsafe { libc::close(fd) };
+    }
+    match check_capture_privileges() {
+        Ok(()) => assert!(fd >= 0),
+        Err(PrivilegeError::MissingCapability("CAP_NET_RAW")) => assert!(fd < 0),
+        Err(e) => panic!("{}", e),
+    }
+    match check_admin_privileges() {
+        Ok(()) | Err(PrivilegeError::MissingCapability("CAP_NET_ADMIN")) => {}
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn test_open_packet_socket_in_netns() {
+    let filters = [BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0)];
+    match open_packet_socket_in_netns("/proc/self/ns/net", "lo", libc::ETH_P_ALL as u16, &filters) {
+        Ok(socket) => assert!(socket.as_raw_fd() >= 0),
+        Err(e) => assert_eq!(e.raw_os_error(), Some(libc::EPERM)),
+    }
+}
+
+#[test]
+fn test_open_packet_socket() {
+    let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = rx.local_addr().unwrap().port();
+    let filters = crate::compile(&format!("udp dst port {}", port), Dlt::En10mb).unwrap();
+    let socket = match open_packet_socket("lo", libc::ETH_P_ALL as u16, &filters) {
+        Ok(socket) => socket,
+        Err(e) => return assert_eq!(e.raw_os_error(), Some(libc::EPERM)),
+    };
+    assert_eq!(get_filter(&socket).unwrap(), &filters[..]);
+
+    let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    tx.send_to(b"clean", rx.local_addr().unwrap()).unwrap();
+    let mut frame = [0u8; 128];
+    let n = unsafe {
+        libc::recv(
+            socket.as_raw_fd(),
+            frame.as_mut_ptr() as *mut libc::c_void,
+            frame.len(),
+            0,
+        )
+    };
+    assert!(n > 0);
+    assert!(frame[..n as usize].ends_with(b"clean"));
+}
+
+#[test]
+fn test_attach_filter_clean() {
+    let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    tx.send_to(b"old", rx.local_addr().unwrap()).unwrap();
+    tx.send_to(b"old", rx.local_addr().unwrap()).unwrap();
+
+    let accept_all = crate::filters::accept_all();
+    attach_filter_clean(&rx, &BPFFProg::new(accept_all)).unwrap();
+    assert_eq!(get_filter(&rx).unwrap(), accept_all);
+    tx.send_to(b"new", rx.local_addr().unwrap()).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(rx.recv(&mut buf).unwrap(), 3);
+    assert_eq!(&buf[..3], b"new");
+}
+
+#[test]
+fn test_self_test() {
+    let probe = [0x45u8; 64];
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let truncated = crate::ProgramBuilder::new()
+        .accept_truncated(20)
+        .build()
+        .unwrap();
+    let truncated = BPFProgram::from(truncated);
+    assert!(truncated.self_test(&socket, &probe, true).unwrap());
+    assert!(!truncated.self_test(&socket, &probe, false).unwrap());
+    let drop_all = BPFProgram::from(crate::filters::drop_all());
+    assert!(drop_all.self_test(&socket, &probe, false).unwrap());
+    assert!(!drop_all.self_test(&socket, &probe, true).unwrap());
+
+    if let Ok(v6) = std::net::UdpSocket::bind("[::]:0") {
+        assert!(truncated.self_test(&v6, &probe, true).unwrap());
+    }
+    let (a, _) = std::os::unix::net::UnixDatagram::pair().unwrap();
+    assert!(truncated.self_test(&a, &probe, true).unwrap());
+    // dropping the probe is a failure when it is expected
+    let first_byte = BPFProgram::from(
+        crate::ProgramBuilder::new()
+            .ld_abs_b(0)
+            .jmp(bpf::JEQ, bpf::K, 0x45, 0, 1)
+            .ret_k(4)
+            .ret_k(0)
+            .build()
+            .unwrap(),
+    );
+    assert!(first_byte.self_test(&a, &probe, true).unwrap());
+    assert!(!first_byte.self_test(&a, &[0; 8], true).unwrap());
+
+    // the socket is left as it was, with its queued traffic
+    socket
+        .send_to(b"queued", socket.local_addr().unwrap())
+        .unwrap();
+    assert!(drop_all.self_test(&socket, &probe, false).unwrap());
+    let mut buf = [0; 8];
+    assert_eq!(socket.recv(&mut buf).unwrap(), 6);
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let error = truncated.self_test(&listener, &probe, true).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EOPNOTSUPP));
+}
+
+#[test]
+fn test_lock_filter() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    BPFFProg::new(crate::filters::drop_all())
+        .attach_filter(&socket)
+        .unwrap();
+    lock_filter(&socket).unwrap();
+    let error = detach_filter(&socket).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+    let accept_all = crate::filters::accept_all();
+    let error = BPFFProg::new(accept_all)
+        .attach_filter(&socket)
+        .unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+}
+
+#[test]
+fn test_attach_reuseport_filter() {
+    let filters = crate::filters::steer_by_cpu();
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let error = attach_reuseport_filter(&socket, &filters).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EINVAL));
+
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0) };
+    assert!(fd >= 0);
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+    let reuse: libc::c_int = 1;
+    assert_eq!(
+        unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_REUSEPORT,
+                &reuse as *const _ as *const libc::c_void,
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        },
+        0
+    );
+    attach_reuseport_filter(&socket, &filters).unwrap();
+}
+
+#[test]
+fn test_get_filter() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    assert_eq!(get_filter(&socket).unwrap(), vec![]);
+    let arp = crate::presets::arp_only();
+    BPFFProg::new(arp).attach_filter(&socket).unwrap();
+    assert_eq!(get_filter(&socket).unwrap(), arp);
+}
+
+#[test]
+fn test_attach_best_filter() {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let filters = crate::filters::drop_all();
+    let mechanism = attach_best_filter(&socket, filters).unwrap();
+    #[cfg(not(feature = "ebpf"))]
+    assert_eq!(mechanism, AttachMechanism::Classic);
+    if mechanism == AttachMechanism::Classic {
+        assert_eq!(get_filter(&socket).unwrap(), filters);
+    }
+    detach_filter(&socket).unwrap();
+
+    // the hardware type has no eBPF translation
+    let filters = [
+        BPFFilter::bpf_stmt(
+            bpf::LD | bpf::W | bpf::ABS,
+            crate::ancillary::SKF_AD_OFF + crate::ancillary::SKF_AD_HATYPE,
+        ),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0),
+    ];
+    assert_eq!(
+        attach_best_filter(&socket, &filters).unwrap(),
+        AttachMechanism::Classic
+    );
+}
+
+#[test]
+fn test_tap_filter() {
+    let open = |flags: libc::c_int| -> io::Result<File> {
+        let tun = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/net/tun")?;
+        let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+        req.ifr_ifru.ifru_flags = (flags | libc::IFF_NO_PI) as libc::c_short;
+        match unsafe { libc::ioctl(tun.as_raw_fd(), libc::TUNSETIFF, &req) } {
+            0 => Ok(tun),
+            _ => Err(io::Error::last_os_error()),
+        }
+    };
+    let tap = match open(libc::IFF_TAP) {
+        Ok(tap) => tap,
+        // without CAP_NET_ADMIN or the tun module
+        Err(e) => return assert!(e.raw_os_error() != Some(libc::EINVAL), "{}", e),
+    };
+    assert_eq!(tap_filter_len(&tap).unwrap(), 0);
+    attach_tap_filter(&tap, crate::presets::arp_only()).unwrap();
+    assert_eq!(
+        tap_filter_len(&tap).unwrap(),
+        crate::presets::arp_only().len()
+    );
+    detach_tap_filter(&tap).unwrap();
+
+    let tun = open(libc::IFF_TUN).unwrap();
+    let error = attach_tap_filter(&tun, crate::presets::arp_only()).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::EINVAL));
+}
+
+#[test]
+fn test_set_ppp_filter() {
+    assert_eq!(PPPIOCSPASS & 0xffff, 0x7447);
+    // only PPP units know the requests
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let filters = crate::filters::accept_all();
+    let error = set_ppp_pass_filter(&socket, filters).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::ENOTTY));
+    let error = set_ppp_active_filter(&socket, &[]).unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(libc::ENOTTY));
+}
@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+/// packets lost since the previous report of a [`DropMonitor`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropEvent {
+    /// the packets lost since the previous report
+    pub dropped: u64,
+    /// the packets lost since the monitor was created
+    pub total: u64,
+    /// the ring blocks handed over full since the previous report, the
+    /// consumer falling behind before the kernel drops anything
+    pub full_blocks: u64,
+}
+
+/// calls back when packets are lost or ring blocks come back full, at most
+/// once per interval
+///
+/// the capture loop feeds it with `RxRing::report_drops` on Linux and
+/// `BpfDevice::report_drops` on the BSDs, or with the drop counts it reads
+/// itself. drops seen within `interval` of a report are accumulated into
+/// the next one, so an overloaded consumer is warned without being slowed
+/// down further by the warnings.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use std::time::Duration;
+///
+/// let mut reports = Vec::new();
+/// let mut monitor = DropMonitor::new(Duration::from_secs(60), |event| reports.push(event));
+/// monitor.add(0);
+/// monitor.add(3);
+/// monitor.add(2);
+/// assert_eq!(monitor.total(), 5);
+/// assert_eq!(reports, [DropEvent { dropped: 3, total: 3, full_blocks: 0 }]);
+/// ```
+pub struct DropMonitor<F>
+where
+    F: FnMut(DropEvent),
+{
+    callback: F,
+    interval: Duration,
+    total: u64,
+    reported: u64,
+    full_blocks: u64,
+    /// the last value of a counter that only grows, see `update`
+    counter: u64,
+    last_report: Option<Instant>,
+}
+
+impl<F> DropMonitor<F>
+where
+    F: FnMut(DropEvent),
+{
+    pub fn new(interval: Duration, callback: F) -> Self {
+        Self {
+            callback,
+            interval,
+            total: 0,
+            reported: 0,
+            full_blocks: 0,
+            counter: 0,
+            last_report: None,
+        }
+    }
+
+    /// account for `drops` more lost packets, calling back if it is time to
+    ///
+    /// `add(0)` reports the drops held back by the rate limit.
+    pub fn add(&mut self, drops: u64) {
+        self.record(drops, 0);
+    }
+
+    /// account for the drop counter `counter`, which only grows, such as the
+    /// one of `BpfDevice::stats`
+    pub fn update(&mut self, counter: u64) {
+        let drops = counter.saturating_sub(self.counter);
+        self.counter = counter;
+        self.record(drops, 0);
+    }
+
+    /// account for `blocks` more ring blocks handed over full
+    pub fn add_full_blocks(&mut self, blocks: u64) {
+        self.record(0, blocks);
+    }
+
+    pub(crate) fn record(&mut self, drops: u64, full_blocks: u64) {
+        self.total += drops;
+        self.full_blocks += full_blocks;
+        if self.total == self.reported && self.full_blocks == 0 {
+            return;
+        }
+        if self
+            .last_report
+            .is_some_and(|last| last.elapsed() < self.interval)
+        {
+            return;
+        }
+        (self.callback)(DropEvent {
+            dropped: self.total - self.reported,
+            total: self.total,
+            full_blocks: self.full_blocks,
+        });
+        self.reported = self.total;
+        self.full_blocks = 0;
+        self.last_report = Some(Instant::now());
+    }
+
+    /// the packets lost since the monitor was created
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+impl<F> std::fmt::Debug for DropMonitor<F>
+where
+    F: FnMut(DropEvent),
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DropMonitor")
+            .field("interval", &self.interval)
+            .field("total", &self.total)
+            .field("reported", &self.reported)
+            .field("full_blocks", &self.full_blocks)
+            .finish()
+    }
+}
+
+#[test]
+fn test_drop_monitor() {
+    let mut reports = Vec::new();
+    let mut monitor = DropMonitor::new(Duration::from_millis(20), |event| reports.push(event));
+    monitor.add(1);
+    monitor.add(1);
+    monitor.add_full_blocks(2);
+    std::thread::sleep(Duration::from_millis(30));
+    monitor.add(0);
+    monitor.add(0);
+    std::thread::sleep(Duration::from_millis(30));
+    monitor.update(4);
+    monitor.update(4);
+    assert_eq!(monitor.total(), 6);
+    assert_eq!(
+        reports,
+        [
+            DropEvent {
+                dropped: 1,
+                total: 1,
+                full_blocks: 0,
+            },
+            DropEvent {
+                dropped: 1,
+                total: 2,
+                full_blocks: 2,
+            },
+            DropEvent {
+                dropped: 4,
+                total: 6,
+                full_blocks: 0,
+            },
+        ]
+    );
+}
@@ -0,0 +1,116 @@
+//! netlink message filters
+//!
+//! a program attached to a netlink socket sees each message from its
+//! `nlmsghdr`, and drops the multicast notifications the process does not
+//! care about before they wake it up. the header fields are in the byte order
+//! of the host, the programs here compare them as such.
+
+use crate::bpf_base::BPFFilter;
+use crate::builder::{ByteOrder, ProgramBuilder};
+use crate::filters::build;
+
+/// the offset of `nlmsg_len`, the length of the message with its header
+pub const NLMSG_LEN: u32 = 0;
+/// the offset of `nlmsg_type`, such as `RTM_NEWLINK`
+pub const NLMSG_TYPE: u32 = 4;
+/// the offset of `nlmsg_flags`
+pub const NLMSG_FLAGS: u32 = 6;
+/// the offset of `nlmsg_seq`
+pub const NLMSG_SEQ: u32 = 8;
+/// the offset of `nlmsg_pid`, the port of the sender, 0 for the kernel
+pub const NLMSG_PID: u32 = 12;
+/// the length of `nlmsghdr`, where the payload starts
+pub const NLMSG_HDRLEN: u32 = 16;
+
+/// the offset of `ifi_family` in the `ifinfomsg` of the link messages
+pub const IFI_FAMILY: u32 = NLMSG_HDRLEN;
+/// the offset of `ifi_type`, the ARPHRD_* type of the interface
+pub const IFI_TYPE: u32 = NLMSG_HDRLEN + 2;
+/// the offset of `ifi_index`, the index of the interface
+pub const IFI_INDEX: u32 = NLMSG_HDRLEN + 4;
+/// the offset of `ifi_flags`, the IFF_* flags of the interface
+pub const IFI_FLAGS: u32 = NLMSG_HDRLEN + 8;
+/// the offset of `ifi_change`, the flags that changed
+pub const IFI_CHANGE: u32 = NLMSG_HDRLEN + 12;
+
+/// the link messages of rtnetlink
+pub const RTM_NEWLINK: u16 = 16;
+/// the notification of a removed interface
+pub const RTM_DELLINK: u16 = 17;
+
+/// keep the messages whose `nlmsg_type` is one of `types`
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::netlink;
+///
+/// // the addresses added and removed
+/// let filters = netlink::message_types(&[20, 21]);
+/// assert_eq!(filters.len(), 5);
+/// ```
+pub fn message_types(types: &[u16]) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (keep, drop) = (builder.label(), builder.label());
+    let values: Vec<u32> = types.iter().map(|t| host_u16(*t)).collect();
+    builder.ld_abs_h(NLMSG_TYPE).jeq_any(&values, keep, drop);
+    builder.bind(keep).ret_k(u32::MAX);
+    builder.bind(drop).ret_k(0);
+    build(&builder)
+}
+
+/// keep the `RTM_NEWLINK` and `RTM_DELLINK` messages about the interface `index`
+///
+/// a link monitor watching one interface no longer wakes up for the changes
+/// of the others.
+pub fn link_changes(index: u32) -> Vec<BPFFilter> {
+    let mut builder = ProgramBuilder::new();
+    let (link, drop) = (builder.label(), builder.label());
+    builder.ld_abs_h(NLMSG_TYPE).jeq_any(
+        &[host_u16(RTM_NEWLINK), host_u16(RTM_DELLINK)],
+        link,
+        drop,
+    );
+    builder
+        .bind(link)
+        .byte_order(ByteOrder::Host)
+        .jeq_field_u32(IFI_INDEX, index, 0, 1)
+        .ret_k(u32::MAX);
+    builder.bind(drop).ret_k(0);
+    build(&builder)
+}
+
+/// a host-order half-word, as an H load reads it
+fn host_u16(value: u16) -> u32 {
+    u16::from_be_bytes(value.to_ne_bytes()) as u32
+}
+
+#[test]
+fn test_netlink() {
+    use crate::interpreter::run;
+
+    let message = |kind: u16, index: u32| {
+        let mut message = vec![0u8; 32];
+        message[..4].copy_from_slice(&32u32.to_ne_bytes());
+        message[4..6].copy_from_slice(&kind.to_ne_bytes());
+        message[20..24].copy_from_slice(&index.to_ne_bytes());
+        message
+    };
+    let filters = message_types(&[20, 21, 0x1234]);
+    for (kind, kept) in [
+        (20, true),
+        (21, true),
+        (22, false),
+        (0x1234, true),
+        (0x3412, false),
+    ] {
+        let message = message(kind, 0);
+        assert_eq!(run(&filters, &message, 32) != 0, kept, "type {:#x}", kind);
+    }
+
+    let filters = link_changes(3);
+    assert_eq!(run(&filters, &message(RTM_NEWLINK, 3), 32), u32::MAX);
+    assert_eq!(run(&filters, &message(RTM_DELLINK, 3), 32), u32::MAX);
+    assert_eq!(run(&filters, &message(RTM_NEWLINK, 4), 32), 0);
+    assert_eq!(run(&filters, &message(20, 3), 32), 0);
+}
@@ -0,0 +1,281 @@
+//! named protocol fields, for [`ProgramBuilder::ld_field`](crate::ProgramBuilder::ld_field)
+//!
+//! each field is an offset into its own header and a load size. the link
+//! type of the capture places the headers in the frame.
+//!
+//! the link headers ([`Eth`], [`Sll`], [`Null`]) start the frame, their
+//! `NETWORK` is where the network header starts. the offsets of the other
+//! headers are added to it:
+//!
+//! ```
+//! use classic_bpf::offsets::*;
+//! use classic_bpf::*;
+//!
+//! let mut builder = ProgramBuilder::new();
+//! builder
+//!     .ld_abs_h(Sll::Protocol.offset())
+//!     .jmp(bpf::JEQ, bpf::K, 0x86dd, 0, 3)
+//!     .ld_abs_b(Sll::NETWORK + Ipv6::NextHeader.offset())
+//!     .jmp(bpf::JEQ, bpf::K, 58, 0, 1)
+//!     .ret_k(u32::MAX)
+//!     .ret_k(0);
+//! ```
+
+use crate::bpf_base::bpf::{self, BPFSize};
+
+/// the header a field belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// at the start of the frame
+    Link,
+    /// at the network offset of the link type
+    Network,
+    /// behind the network header, whose length is in X
+    Transport,
+}
+
+/// a field of a protocol header
+pub trait Field {
+    /// the header holding the field
+    fn layer(&self) -> Layer;
+    /// the offset of the field in its header
+    fn offset(&self) -> u32;
+    /// the size of the load reading the field
+    fn size(&self) -> BPFSize;
+}
+
+macro_rules! fields {
+    ($(#[$doc:meta])* $name:ident, $layer:ident { $($(#[$fdoc:meta])* $field:ident => ($offset:expr, $size:ident),)* }) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$fdoc])* $field,)*
+        }
+
+        impl Field for $name {
+            fn layer(&self) -> Layer {
+                Layer::$layer
+            }
+
+            fn offset(&self) -> u32 {
+                match self {
+                    $($name::$field => $offset,)*
+                }
+            }
+
+            fn size(&self) -> BPFSize {
+                match self {
+                    $($name::$field => bpf::$size,)*
+                }
+            }
+        }
+    };
+}
+
+fields!(
+    /// Ethernet header fields, MAC addresses split in a word and a half-word
+    Eth, Link {
+        DstHigh => (0, W),
+        DstLow => (4, H),
+        SrcHigh => (6, W),
+        SrcLow => (10, H),
+        EtherType => (12, H),
+    }
+);
+
+impl Eth {
+    /// the network header, without VLAN tags
+    pub const NETWORK: u32 = 14;
+}
+
+fields!(
+    /// Linux cooked capture header fields, `DLT_LINUX_SLL`
+    Sll, Link {
+        /// the `PACKET_*` type of the packet, such as `PACKET_OUTGOING`
+        PktType => (0, H),
+        /// the `ARPHRD_*` type of the interface
+        HaType => (2, H),
+        AddrLen => (4, H),
+        /// the link-layer address of the sender, split in two words
+        Addr0 => (6, W),
+        Addr1 => (10, W),
+        Protocol => (14, H),
+    }
+);
+
+impl Sll {
+    pub const NETWORK: u32 = 16;
+}
+
+fields!(
+    /// BSD loopback header fields, `DLT_NULL` and `DLT_LOOP`
+    Null, Link {
+        /// the address family, in host order for `DLT_NULL`, network order for `DLT_LOOP`
+        Family => (0, W),
+    }
+);
+
+impl Null {
+    pub const NETWORK: u32 = 4;
+}
+
+fields!(
+    /// IPv4 header fields
+    Ipv4, Network {
+        /// the version and the header length in words
+        VersionIhl => (0, B),
+        Tos => (1, B),
+        TotalLength => (2, H),
+        Id => (4, H),
+        /// the flags and the fragment offset
+        FlagsFragment => (6, H),
+        Ttl => (8, B),
+        Protocol => (9, B),
+        Checksum => (10, H),
+        Src => (12, W),
+        Dst => (16, W),
+    }
+);
+
+impl Ipv4 {
+    /// the length of a header without options, where the payload starts at least
+    pub const MIN_LEN: u32 = 20;
+}
+
+fields!(
+    /// IPv6 header fields, addresses split in four words
+    Ipv6, Network {
+        /// the version, traffic class and flow label
+        VersionClassFlow => (0, W),
+        PayloadLength => (4, H),
+        NextHeader => (6, B),
+        HopLimit => (7, B),
+        Src0 => (8, W),
+        Src1 => (12, W),
+        Src2 => (16, W),
+        Src3 => (20, W),
+        Dst0 => (24, W),
+        Dst1 => (28, W),
+        Dst2 => (32, W),
+        Dst3 => (36, W),
+    }
+);
+
+impl Ipv6 {
+    /// the length of the header, where the payload starts without extension headers
+    pub const LEN: u32 = 40;
+}
+
+fields!(
+    /// ARP header fields, for Ethernet and IPv4 addresses
+    Arp, Network {
+        /// the `ARPHRD_*` hardware type, 1 for Ethernet
+        HType => (0, H),
+        /// the ethertype of the protocol addresses, 0x0800 for IPv4
+        PType => (2, H),
+        HLen => (4, B),
+        PLen => (5, B),
+        /// the operation, 1 for a request and 2 for a reply
+        Oper => (6, H),
+        ShaHigh => (8, W),
+        ShaLow => (12, H),
+        Spa => (14, W),
+        ThaHigh => (18, W),
+        ThaLow => (22, H),
+        Tpa => (24, W),
+    }
+);
+
+fields!(
+    /// TCP header fields
+    Tcp, Transport {
+        SrcPort => (0, H),
+        DstPort => (2, H),
+        Seq => (4, W),
+        Ack => (8, W),
+        /// the header length in words, in the upper 4 bits
+        DataOffset => (12, B),
+        Flags => (13, B),
+        Window => (14, H),
+        Checksum => (16, H),
+        Urgent => (18, H),
+    }
+);
+
+fields!(
+    /// UDP header fields
+    Udp, Transport {
+        SrcPort => (0, H),
+        DstPort => (2, H),
+        Length => (4, H),
+        Checksum => (6, H),
+    }
+);
+
+fields!(
+    /// ICMP and ICMPv6 header fields
+    Icmp, Transport {
+        Type => (0, B),
+        Code => (1, B),
+        Checksum => (2, H),
+    }
+);
+
+/// the offset of the network header of the link type `dlt`, the `NETWORK`
+/// constant of its header
+pub fn network(dlt: crate::dlt::Dlt) -> Option<u32> {
+    crate::dlt::network_offset(dlt.value())
+}
+
+#[test]
+fn test_ld_field() {
+    use crate::builder::BuildError;
+    use crate::builder::ProgramBuilder;
+    use crate::dlt::Dlt;
+    use crate::BPFFilter;
+
+    let mut builder = ProgramBuilder::new();
+    builder
+        .ld_field(Dlt::En10mb, Eth::EtherType)
+        .ld_field(Dlt::En10mb, Ipv4::Protocol)
+        .ld_field(Dlt::Raw, Ipv6::NextHeader)
+        .ldx_msh(14)
+        .ld_field(Dlt::En10mb, Tcp::DstPort)
+        .ret_a();
+    assert_eq!(
+        builder.build().unwrap()[..5],
+        [
+            BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
+            BPFFilter::bpf_stmt(bpf::LDX | bpf::B | bpf::MSH, 14),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::IND, 16),
+        ]
+    );
+
+    let mut builder = ProgramBuilder::new();
+    builder
+        .ld_field(Dlt::Ieee802_11Radio, Eth::DstHigh)
+        .ld_field(Dlt::Ieee802_11Radio, Ipv4::Protocol)
+        .ret_a();
+    assert_eq!(
+        builder.build(),
+        Err(BuildError::NoNetworkOffset {
+            dlt: Dlt::Ieee802_11Radio,
+            index: 1
+        })
+    );
+}
+
+#[test]
+fn test_link_offsets() {
+    use crate::dlt::Dlt;
+    assert_eq!(network(Dlt::En10mb), Some(Eth::NETWORK));
+    assert_eq!(network(Dlt::LinuxSll), Some(Sll::NETWORK));
+    assert_eq!(network(Dlt::Null), Some(Null::NETWORK));
+    assert_eq!(network(Dlt::Loop), Some(Null::NETWORK));
+    assert_eq!(network(Dlt::Raw), Some(0));
+    assert_eq!(network(Dlt::Ieee802_11Radio), None);
+    assert_eq!(Sll::Protocol.offset() + 2, Sll::NETWORK);
+    assert_eq!(Arp::Tpa.offset() + 4, 28);
+}
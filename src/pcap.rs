@@ -0,0 +1,117 @@
+use crate::buffer::FrameBuf;
+use crate::dlt::DLT_RAW;
+use crate::timestamp::TsPrecision;
+use std::io::{self, Write};
+use std::time::Duration;
+
+const MAGIC_MICRO: u32 = 0xa1b2_c3d4;
+const MAGIC_NANO: u32 = 0xa1b2_3c4d;
+/// the LINKTYPE_RAW of the files, DLT_RAW varies between systems
+const LINKTYPE_RAW: u32 = 101;
+
+/// writes captured frames to a classic pcap file
+///
+/// the file header is written by `new`, each frame adds a record.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+/// use std::time::Duration;
+///
+/// let mut writer = PcapWriter::new(Vec::new(), dlt::DLT_EN10MB, 65535, TsPrecision::Nano).unwrap();
+/// writer.write_packet(Duration::from_nanos(1), &[0u8; 60], 60).unwrap();
+/// assert_eq!(writer.into_inner().len(), 24 + 16 + 60);
+/// ```
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    precision: TsPrecision,
+    snaplen: u32,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// write the header of a file of `dlt` frames of up to `snaplen` bytes
+    ///
+    /// nanosecond precision uses the newer magic number, read by libpcap 1.5
+    /// and later.
+    pub fn new(mut writer: W, dlt: u32, snaplen: u32, precision: TsPrecision) -> io::Result<Self> {
+        let magic = match precision {
+            TsPrecision::Micro => MAGIC_MICRO,
+            TsPrecision::Nano => MAGIC_NANO,
+        };
+        let linktype = if dlt == DLT_RAW { LINKTYPE_RAW } else { dlt };
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&magic.to_ne_bytes());
+        header.extend_from_slice(&2u16.to_ne_bytes());
+        header.extend_from_slice(&4u16.to_ne_bytes());
+        // thiszone and sigfigs
+        header.extend_from_slice(&[0; 8]);
+        header.extend_from_slice(&snaplen.to_ne_bytes());
+        header.extend_from_slice(&linktype.to_ne_bytes());
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            precision,
+            snaplen,
+        })
+    }
+
+    /// add a record for `data`, the first bytes of a frame of `orig_len` bytes
+    ///
+    /// `data` is cut to the snaplen of the file.
+    pub fn write_packet(
+        &mut self,
+        timestamp: Duration,
+        data: &[u8],
+        orig_len: usize,
+    ) -> io::Result<()> {
+        let data = &data[..data.len().min(self.snaplen as usize)];
+        let fraction = match self.precision {
+            TsPrecision::Micro => timestamp.subsec_micros(),
+            TsPrecision::Nano => timestamp.subsec_nanos(),
+        };
+        let mut record = Vec::with_capacity(16 + data.len());
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_ne_bytes());
+        record.extend_from_slice(&fraction.to_ne_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_ne_bytes());
+        record.extend_from_slice(&(orig_len.max(data.len()) as u32).to_ne_bytes());
+        record.extend_from_slice(data);
+        self.writer.write_all(&record)
+    }
+
+    /// add a record for the frame received in `frame`
+    ///
+    /// frames without timestamp are recorded at the UNIX epoch.
+    pub fn write_frame(&mut self, frame: &FrameBuf) -> io::Result<()> {
+        self.write_packet(
+            frame.timestamp().unwrap_or_default(),
+            frame.data(),
+            frame.len(),
+        )
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[test]
+fn test_pcap_writer() {
+    let mut writer = PcapWriter::new(Vec::new(), DLT_RAW, 4, TsPrecision::Micro).unwrap();
+    writer
+        .write_packet(Duration::new(7, 1_500), &[1, 2, 3, 4, 5, 6], 6)
+        .unwrap();
+    let file = writer.into_inner();
+    let word = |at: usize| u32::from_ne_bytes([file[at], file[at + 1], file[at + 2], file[at + 3]]);
+    assert_eq!(word(0), MAGIC_MICRO);
+    assert_eq!(word(16), 4);
+    assert_eq!(word(20), LINKTYPE_RAW);
+    assert_eq!([word(24), word(28), word(32), word(36)], [7, 1, 4, 6]);
+    assert_eq!(&file[40..], &[1, 2, 3, 4]);
+}
@@ -0,0 +1,176 @@
+//! canonical ready-made programs for Ethernet captures
+//!
+//! the programs are assembled and checked at compile time, the accepted
+//! packets are returned whole.
+
+use crate::bpf_base::bpf::*;
+use crate::bpf_base::BPFFilter;
+use crate::const_builder::ConstBuilder;
+use crate::fragments;
+
+const ACCEPT: u32 = u32::MAX;
+
+const ACCEPT_ALL: [BPFFilter; 1] = ConstBuilder::new().ret_k(ACCEPT).build();
+
+const DROP_ALL: [BPFFilter; 1] = ConstBuilder::new().ret_k(0).build();
+
+const ICMPV6_ONLY: [BPFFilter; 6] = ConstBuilder::new()
+    .ld(H, ABS, 12)
+    .jmp(JEQ, K, 0x86dd, 0, 3)
+    .ld(B, ABS, 20)
+    .jmp(JEQ, K, 58, 0, 1)
+    .ret_k(ACCEPT)
+    .ret_k(0)
+    .build();
+
+const ARP_ONLY: [BPFFilter; 4] = ConstBuilder::new()
+    .ld(H, ABS, 12)
+    .jmp(JEQ, K, 0x0806, 0, 1)
+    .ret_k(ACCEPT)
+    .ret_k(0)
+    .build();
+
+const DHCP_CLIENT: [BPFFilter; 11] = {
+    let builder = ConstBuilder::new()
+        .ld(H, ABS, 12)
+        .jmp(JEQ, K, 0x0800, 0, 8)
+        .ld(B, ABS, 23)
+        .jmp(JEQ, K, 17, 0, 6)
+        // only the first fragment holds the UDP header
+        .ld(H, ABS, 20)
+        .jmp(JSET, K, 0x1fff, 4, 0);
+    fragments::const_ipv4_transport_base(builder, 14)
+        .ld(H, IND, 16)
+        .jmp(JEQ, K, 68, 0, 1)
+        .ret_k(ACCEPT)
+        .ret_k(0)
+        .build()
+};
+
+const LLDP_ONLY: [BPFFilter; 4] = ConstBuilder::new()
+    .ld(H, ABS, 12)
+    .jmp(JEQ, K, 0x88cc, 0, 1)
+    .ret_k(ACCEPT)
+    .ret_k(0)
+    .build();
+
+const NDP_ONLY: [BPFFilter; 9] = ConstBuilder::new()
+    .ld(H, ABS, 12)
+    .jmp(JEQ, K, 0x86dd, 0, 6)
+    .ld(B, ABS, 20)
+    .jmp(JEQ, K, 58, 0, 4)
+    // router solicitation (133) to redirect (137)
+    .ld(B, ABS, 54)
+    .jmp(JGE, K, 133, 0, 2)
+    .jmp(JGT, K, 137, 1, 0)
+    .ret_k(ACCEPT)
+    .ret_k(0)
+    .build();
+
+/// accept every packet, whole
+///
+/// the value a program returns is the capture length,
+/// [`ProgramBuilder::accept_truncated`](crate::ProgramBuilder::accept_truncated)
+/// keeps only the first bytes instead.
+pub fn accept_all() -> &'static [BPFFilter] {
+    &ACCEPT_ALL
+}
+
+/// drop every packet
+///
+/// attached to a fresh socket, it keeps packets from being queued until the
+/// real filter is attached.
+pub fn drop_all() -> &'static [BPFFilter] {
+    &DROP_ALL
+}
+
+/// accept the ICMPv6 packets not using extension headers
+pub fn icmpv6_only() -> &'static [BPFFilter] {
+    &ICMPV6_ONLY
+}
+
+/// accept the ARP packets
+pub fn arp_only() -> &'static [BPFFilter] {
+    &ARP_ONLY
+}
+
+/// accept the IPv4 UDP packets sent to the DHCP client port (68)
+pub fn dhcp_client() -> &'static [BPFFilter] {
+    &DHCP_CLIENT
+}
+
+/// accept the LLDP frames
+pub fn lldp_only() -> &'static [BPFFilter] {
+    &LLDP_ONLY
+}
+
+/// accept the neighbor discovery messages, ICMPv6 types 133 to 137
+pub fn ndp_only() -> &'static [BPFFilter] {
+    &NDP_ONLY
+}
+
+/// the preset called `name`, such as `"arp_only"`
+pub fn by_name(name: &str) -> Option<&'static [BPFFilter]> {
+    match name {
+        "accept_all" => Some(accept_all()),
+        "drop_all" => Some(drop_all()),
+        "icmpv6_only" => Some(icmpv6_only()),
+        "arp_only" => Some(arp_only()),
+        "dhcp_client" => Some(dhcp_client()),
+        "lldp_only" => Some(lldp_only()),
+        "ndp_only" => Some(ndp_only()),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_presets() {
+    use crate::interpreter::run;
+
+    let frame = |ethertype: u16, payload: &[(usize, u8)]| {
+        let mut frame = vec![0u8; 64];
+        frame[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        for (at, byte) in payload {
+            frame[*at] = *byte;
+        }
+        frame
+    };
+
+    let arp = frame(0x0806, &[]);
+    let lldp = frame(0x88cc, &[]);
+    // neighbor solicitation and echo request
+    let ns = frame(0x86dd, &[(20, 58), (54, 135)]);
+    let ping6 = frame(0x86dd, &[(20, 58), (54, 128)]);
+    // UDP 67 -> 68, then a non-first fragment of it
+    let dhcp = frame(0x0800, &[(14, 0x45), (23, 17), (35, 67), (37, 68)]);
+    let fragment = frame(0x0800, &[(14, 0x45), (20, 0x01), (23, 17), (37, 68)]);
+
+    let all = [&arp, &lldp, &ns, &ping6, &dhcp, &fragment];
+    let accepted = |filters: &[BPFFilter]| {
+        all.iter()
+            .map(|packet| run(filters, packet, packet.len() as u32) == ACCEPT)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(accepted(accept_all()), [true; 6]);
+    assert_eq!(accepted(drop_all()), [false; 6]);
+    assert_eq!(
+        accepted(arp_only()),
+        [true, false, false, false, false, false]
+    );
+    assert_eq!(
+        accepted(lldp_only()),
+        [false, true, false, false, false, false]
+    );
+    assert_eq!(
+        accepted(icmpv6_only()),
+        [false, false, true, true, false, false]
+    );
+    assert_eq!(
+        accepted(ndp_only()),
+        [false, false, true, false, false, false]
+    );
+    assert_eq!(
+        accepted(dhcp_client()),
+        [false, false, false, false, true, false]
+    );
+}
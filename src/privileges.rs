@@ -0,0 +1,38 @@
+use std::fmt;
+
+/// a privilege needed to capture that the process lacks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrivilegeError {
+    /// the Linux capability, such as `CAP_NET_RAW`, is not effective
+    MissingCapability(&'static str),
+    /// the BPF device cannot be opened for reading and writing
+    DeviceAccess { path: String, errno: i32 },
+    /// the privileges could not be probed, with the errno
+    Probe(i32),
+}
+
+impl fmt::Display for PrivilegeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrivilegeError::MissingCapability(cap) => write!(
+                f,
+                "missing {}; run as root or grant it with `setcap {}+ep <program>`",
+                cap,
+                cap.to_lowercase()
+            ),
+            PrivilegeError::DeviceAccess { path, errno } => write!(
+                f,
+                "cannot open {} for reading and writing ({}); run as root or give the user access to the BPF devices, e.g. through the group owning them",
+                path,
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            PrivilegeError::Probe(errno) => write!(
+                f,
+                "cannot probe the capture privileges: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PrivilegeError {}
@@ -0,0 +1,433 @@
+//! subset and disjointness checks between programs
+//!
+//! programs are compared path by path: two paths can be taken by the same
+//! packet unless their conditions contradict each other. a contradiction
+//! proves the property, a packet satisfying both paths refutes it.
+//!
+//! a load past the end of the packet stops a program and drops the packet,
+//! as [`run`](crate::run) does, so every load adds a condition on the packet
+//! length to its path. the packets are taken whole, their length on the wire
+//! being the one captured.
+
+use crate::analysis::{bounded_paths, AluOp, AnalysisError, Cmp, Condition, Path, Value};
+use crate::ancillary::SKF_LL_OFF;
+use crate::bpf_base::{BPFFilter, BPFProgram};
+use crate::interpreter::run;
+
+/// the answer of a proof attempt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// the property holds for every packet
+    Proven,
+    /// the property does not hold, as shown by this packet
+    Refuted(Vec<u8>),
+    /// the conditions are beyond what the prover can decide
+    Unknown,
+}
+
+/// longest packet the prover builds as a counterexample
+const MAX_PACKET: usize = 0x10000;
+
+/// how many candidate values are tried for a single field
+const MAX_CANDIDATES: u32 = 256;
+
+enum Solution {
+    Unsat,
+    Witness(Vec<u8>),
+    Unknown,
+}
+
+/// the constraints on one symbolic value
+#[derive(Clone)]
+struct Domain {
+    lo: u32,
+    hi: u32,
+    eq: Option<u32>,
+    ne: Vec<u32>,
+    /// bits that must be clear
+    zero: u32,
+    /// masks of which at least one bit must be set
+    any: Vec<u32>,
+    conflict: bool,
+}
+
+impl Domain {
+    fn new(value: &Value) -> Self {
+        let (hi, zero) = match value {
+            Value::Packet { size, .. } if *size < 4 => {
+                let hi = (1u32 << (8 * *size as u32)) - 1;
+                (hi, !hi)
+            }
+            Value::HeaderLen(_) => (60, !0x3c),
+            Value::Alu(AluOp::And, _, mask) => match **mask {
+                Value::Const(m) => (m, !m),
+                _ => (u32::MAX, 0),
+            },
+            _ => (u32::MAX, 0),
+        };
+        Self {
+            lo: 0,
+            hi,
+            eq: None,
+            ne: Vec::new(),
+            zero,
+            any: Vec::new(),
+            conflict: false,
+        }
+    }
+
+    fn add(&mut self, cmp: Cmp, holds: bool, k: u32) {
+        match (cmp, holds) {
+            (Cmp::Eq, true) => match self.eq {
+                Some(eq) if eq != k => self.conflict = true,
+                _ => self.eq = Some(k),
+            },
+            (Cmp::Eq, false) => self.ne.push(k),
+            (Cmp::Gt, true) => match k.checked_add(1) {
+                Some(lo) => self.lo = self.lo.max(lo),
+                None => self.conflict = true,
+            },
+            (Cmp::Gt, false) => self.hi = self.hi.min(k),
+            (Cmp::Ge, true) => self.lo = self.lo.max(k),
+            (Cmp::Ge, false) => match k.checked_sub(1) {
+                Some(hi) => self.hi = self.hi.min(hi),
+                None => self.conflict = true,
+            },
+            (Cmp::Set, true) => self.any.push(k),
+            (Cmp::Set, false) => self.zero |= k,
+        }
+    }
+
+    fn accepts(&self, v: u32) -> bool {
+        v >= self.lo
+            && v <= self.hi
+            && v & self.zero == 0
+            && !self.ne.contains(&v)
+            && self.any.iter().all(|m| v & m != 0)
+            && self.eq.iter().all(|&eq| eq == v)
+    }
+
+    fn contradicts(&self) -> bool {
+        self.conflict
+            || self.lo > self.hi
+            || self.any.iter().any(|m| m & !self.zero == 0)
+            || self.eq.is_some_and(|eq| !self.accepts(eq))
+    }
+
+    /// find a value satisfying the domain
+    fn pick(&self) -> Option<u32> {
+        if let Some(eq) = self.eq {
+            return Some(eq).filter(|v| self.accepts(*v));
+        }
+        let set: u32 = self
+            .any
+            .iter()
+            .map(|m| 1 << (m & !self.zero).trailing_zeros())
+            .fold(0, |a, b| a | b);
+        (0..MAX_CANDIDATES)
+            .filter_map(|i| self.lo.checked_add(i))
+            .flat_map(|base| [base, (base & !self.zero) | set])
+            .find(|v| self.accepts(*v))
+    }
+}
+
+/// write `v` into the packet so that `value` evaluates to it
+fn assign(value: &Value, v: u32, packet: &mut Vec<u8>) -> bool {
+    match value {
+        Value::Const(k) => *k == v,
+        Value::Packet {
+            index,
+            offset,
+            size,
+        } => {
+            let base = match index {
+                Some(x) => match x.eval(packet).and_then(|x| x.checked_add(*offset)) {
+                    Some(base) => base as usize,
+                    None => return false,
+                },
+                None => *offset as usize,
+            };
+            let size = *size as usize;
+            if base + size > MAX_PACKET || (size < 4 && v >> (8 * size) != 0) {
+                return false;
+            }
+            if packet.len() < base + size {
+                packet.resize(base + size, 0);
+            }
+            for i in 0..size {
+                packet[base + i] = (v >> (8 * (size - 1 - i))) as u8;
+            }
+            true
+        }
+        Value::HeaderLen(offset) => {
+            let offset = *offset as usize;
+            if v & 3 != 0 || v > 60 || offset >= MAX_PACKET {
+                return false;
+            }
+            if packet.len() <= offset {
+                packet.resize(offset + 1, 0);
+            }
+            packet[offset] = (packet[offset] & 0xf0) | (v / 4) as u8;
+            true
+        }
+        Value::Alu(op, inner, rhs) => {
+            let k = match **rhs {
+                Value::Const(k) => k,
+                _ => return false,
+            };
+            let current = inner.eval(packet).unwrap_or(0);
+            let target = match op {
+                AluOp::And if v & !k == 0 => (current & !k) | v,
+                AluOp::Add => v.wrapping_sub(k),
+                AluOp::Sub => v.wrapping_add(k),
+                AluOp::Xor => v ^ k,
+                AluOp::Rsh if k < 32 && (v << k) >> k == v => {
+                    (current & ((1u32 << k) - 1)) | v << k
+                }
+                AluOp::Lsh if k < 32 && v.trailing_zeros() >= k => v >> k,
+                _ => return false,
+            };
+            assign(inner, target, packet)
+        }
+        Value::Neg(inner) => assign(inner, v.wrapping_neg(), packet),
+        Value::Len => false,
+    }
+}
+
+/// decide whether a single packet can satisfy all `conditions`
+fn solve(conditions: &[Condition]) -> Solution {
+    // a condition and its negation
+    if conditions.iter().enumerate().any(|(i, a)| {
+        conditions[i + 1..]
+            .iter()
+            .any(|b| a.lhs == b.lhs && a.cmp == b.cmp && a.rhs == b.rhs && a.holds != b.holds)
+    }) {
+        return Solution::Unsat;
+    }
+    // group the comparisons against constants by the value compared
+    let mut domains: Vec<(&Value, Domain)> = Vec::new();
+    for c in conditions {
+        if let Value::Const(k) = c.rhs {
+            let pos = match domains.iter().position(|(v, _)| *v == &c.lhs) {
+                Some(pos) => pos,
+                None => {
+                    domains.push((&c.lhs, Domain::new(&c.lhs)));
+                    domains.len() - 1
+                }
+            };
+            domains[pos].1.add(c.cmp, c.holds, k);
+        }
+    }
+    if domains.iter().any(|(_, d)| d.contradicts()) {
+        return Solution::Unsat;
+    }
+
+    let mut packet = Vec::new();
+    let mut len = None;
+    for (value, domain) in &domains {
+        if let Value::Len = value {
+            len = Some(domain);
+            continue;
+        }
+        match domain.pick() {
+            Some(v) if assign(value, v, &mut packet) => {}
+            _ => return Solution::Unknown,
+        }
+    }
+    let needed = packet.len();
+    match len {
+        Some(domain) => {
+            let mut domain = domain.clone();
+            domain.lo = domain.lo.max(needed as u32);
+            domain.hi = domain.hi.min(MAX_PACKET as u32);
+            match domain.pick() {
+                Some(v) => packet.resize(v as usize, 0),
+                None => return Solution::Unknown,
+            }
+        }
+        None => packet.resize(needed, 0),
+    }
+
+    if conditions.iter().all(|c| c.eval(&packet) == Some(true)) {
+        Solution::Witness(packet)
+    } else {
+        Solution::Unknown
+    }
+}
+
+/// whether a program uses the Linux negative offsets, whose loads the prover
+/// cannot follow
+fn uses_extensions(filters: &[BPFFilter]) -> bool {
+    filters
+        .iter()
+        .any(|f| f.code & 0x07 == 0x00 && f.code & 0xe0 == 0x20 && f.k >= SKF_LL_OFF)
+}
+
+/// the conditions of a path plus the one making its return value an accept or a drop
+fn with_verdict(path: &Path, accept: bool) -> Option<Vec<Condition>> {
+    let mut conditions = path.conditions.clone();
+    match path.ret {
+        Value::Const(k) => {
+            if (k != 0) != accept {
+                return None;
+            }
+        }
+        ref ret => conditions.push(Condition {
+            lhs: ret.clone(),
+            cmp: Cmp::Eq,
+            rhs: Value::Const(0),
+            holds: !accept,
+        }),
+    }
+    Some(conditions)
+}
+
+/// check that no packet is both accepted by `a` as told by `a_accept` and by
+/// `b` as told by `b_accept`
+///
+/// the packets found are run through both programs before refuting anything.
+fn exclusive(
+    a: &[BPFFilter],
+    a_accept: bool,
+    b: &[BPFFilter],
+    b_accept: bool,
+) -> Result<Verdict, AnalysisError> {
+    let first = split(a, a_accept)?;
+    let second = split(b, b_accept)?;
+    if uses_extensions(a) || uses_extensions(b) {
+        return Ok(Verdict::Unknown);
+    }
+    let mut verdict = Verdict::Proven;
+    for x in &first {
+        for y in &second {
+            let both: Vec<Condition> = x.iter().chain(y).cloned().collect();
+            match solve(&both) {
+                Solution::Unsat => {}
+                Solution::Witness(packet) => {
+                    let len = packet.len() as u32;
+                    if (run(a, &packet, len) != 0) == a_accept
+                        && (run(b, &packet, len) != 0) == b_accept
+                    {
+                        return Ok(Verdict::Refuted(packet));
+                    }
+                    verdict = Verdict::Unknown;
+                }
+                Solution::Unknown => verdict = Verdict::Unknown,
+            }
+        }
+    }
+    Ok(verdict)
+}
+
+fn split(filters: &[BPFFilter], accept: bool) -> Result<Vec<Vec<Condition>>, AnalysisError> {
+    Ok(bounded_paths(filters)?
+        .iter()
+        .filter_map(|p| with_verdict(p, accept))
+        .collect())
+}
+
+impl BPFProgram {
+    /// check that every packet accepted by the program is also accepted by `other`
+    ///
+    /// a refutation carries a packet accepted by the program and dropped by `other`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let icmpv6 = BPFProgram::from(vec![
+    ///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 6),
+    ///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 58, 0, 1),
+    ///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+    ///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    /// ]);
+    /// let accept_all = BPFProgram::from(presets::accept_all());
+    ///
+    /// assert_eq!(icmpv6.implies(&accept_all).unwrap(), Verdict::Proven);
+    /// assert!(matches!(accept_all.implies(&icmpv6).unwrap(), Verdict::Refuted(_)));
+    /// ```
+    pub fn implies(&self, other: &[BPFFilter]) -> Result<Verdict, AnalysisError> {
+        implies(self, other)
+    }
+
+    /// check that no packet is accepted by both the program and `other`
+    ///
+    /// a refutation carries a packet accepted by both.
+    pub fn is_disjoint_with(&self, other: &[BPFFilter]) -> Result<Verdict, AnalysisError> {
+        exclusive(self, true, other, true)
+    }
+
+    /// check that the program and `other` accept exactly the same packets
+    ///
+    /// only accepting or dropping matters, not the snapshot length returned.
+    /// a refutation carries a packet accepted by one program and dropped by the other.
+    pub fn equivalent(&self, other: &[BPFFilter]) -> Result<Verdict, AnalysisError> {
+        equivalent(self, other)
+    }
+}
+
+fn implies(a: &[BPFFilter], b: &[BPFFilter]) -> Result<Verdict, AnalysisError> {
+    exclusive(a, true, b, false)
+}
+
+/// see [`BPFProgram::equivalent`]
+pub(crate) fn equivalent(a: &[BPFFilter], b: &[BPFFilter]) -> Result<Verdict, AnalysisError> {
+    Ok(match implies(a, b)? {
+        Verdict::Proven => implies(b, a)?,
+        Verdict::Unknown => match implies(b, a)? {
+            Verdict::Proven => Verdict::Unknown,
+            other => other,
+        },
+        refuted => refuted,
+    })
+}
+
+#[test]
+fn test_disjoint_protocols() {
+    use crate::bpf_base::bpf;
+    let proto = |p: u32| {
+        [
+            BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 3),
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JGE | bpf::K, p, 0, 1),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+            BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+        ]
+    };
+    // proto >= 17 implies proto >= 6, but both accept proto 17
+    assert_eq!(implies(&proto(17), &proto(6)).unwrap(), Verdict::Proven);
+    match BPFProgram::from(&proto(17)[..])
+        .is_disjoint_with(&proto(6))
+        .unwrap()
+    {
+        Verdict::Refuted(packet) => {
+            assert_eq!(&packet[12..14], &[0x08, 0x00]);
+            assert!(packet[23] >= 17);
+        }
+        v => panic!("unexpected verdict {:?}", v),
+    }
+}
+
+#[test]
+fn test_short_packets() {
+    use crate::bpf_base::bpf;
+    let accept_all = [BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX)];
+    let far_load = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 1000),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+    ];
+    // the load drops the packets shorter than 1001 bytes
+    assert_eq!(implies(&far_load, &accept_all).unwrap(), Verdict::Proven);
+    match implies(&accept_all, &far_load).unwrap() {
+        Verdict::Refuted(packet) => assert_eq!(run(&far_load, &packet, packet.len() as u32), 0),
+        v => panic!("unexpected verdict {:?}", v),
+    }
+    assert_eq!(
+        BPFProgram::from(&far_load[..])
+            .is_disjoint_with(&far_load)
+            .unwrap(),
+        Verdict::Refuted(vec![0; 1001])
+    );
+}
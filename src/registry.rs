@@ -0,0 +1,283 @@
+use crate::bpf_base::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+/// a stable 64-bit FNV-1a hash of the instructions of a program
+pub fn fingerprint(filters: &[BPFFilter]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for filter in filters {
+        let mut bytes = [0u8; 8];
+        bytes[..2].copy_from_slice(&filter.code.to_le_bytes());
+        bytes[2] = filter.jt;
+        bytes[3] = filter.jf;
+        bytes[4..].copy_from_slice(&filter.k.to_le_bytes());
+        for byte in bytes {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// an operation refused by a [`FilterRegistry`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RegistryError {
+    /// the tenant would use `needed` instructions, more than its `budget`
+    OverBudget {
+        tenant: String,
+        needed: usize,
+        budget: usize,
+    },
+    /// no program of the registry is attached to the descriptor
+    NotAttached(RawFd),
+    /// the kernel refused the program, with the errno
+    Attach(i32),
+    /// the program could not be removed, with the errno
+    Detach(i32),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::OverBudget {
+                tenant,
+                needed,
+                budget,
+            } => write!(
+                f,
+                "tenant {} would use {} instructions, more than its budget of {}",
+                tenant, needed, budget
+            ),
+            RegistryError::NotAttached(fd) => write!(f, "no program is registered for fd {}", fd),
+            RegistryError::Attach(errno) => write!(
+                f,
+                "cannot attach the program: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+            RegistryError::Detach(errno) => write!(
+                f,
+                "cannot detach the program: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RegistryError {}
+
+/// a program attached through a [`FilterRegistry`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InventoryEntry {
+    pub fd: RawFd,
+    pub tenant: String,
+    /// the [`fingerprint`] of the program
+    pub fingerprint: u64,
+    /// the number of instructions of the program
+    pub len: usize,
+}
+
+#[derive(Debug)]
+struct Attachment {
+    tenant: String,
+    program: Vec<BPFFilter>,
+}
+
+/// keeps track of the programs attached to many sockets or devices
+///
+/// every program is attached on behalf of a tenant, whose instructions
+/// across all its attachments may be capped with `set_budget`. a program the
+/// kernel refuses leaves the previous one in place.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let mut registry = FilterRegistry::new();
+/// registry.set_budget("dns", 4);
+/// registry.attach("dns", &socket, presets::drop_all().to_vec()).unwrap();
+/// assert_eq!(registry.usage("dns"), 1);
+/// assert!(registry.attach("dns", &socket, presets::ndp_only().to_vec()).is_err());
+/// registry.detach(&socket).unwrap();
+/// assert!(registry.inventory().is_empty());
+/// ```
+#[derive(Debug, Default)]
+pub struct FilterRegistry {
+    budgets: HashMap<String, usize>,
+    attachments: HashMap<RawFd, Attachment>,
+}
+
+impl FilterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// cap the instructions attached for `tenant` to `budget`
+    ///
+    /// the programs already attached are kept, even over the budget.
+    pub fn set_budget(&mut self, tenant: &str, budget: usize) {
+        self.budgets.insert(tenant.to_string(), budget);
+    }
+
+    /// the instructions currently attached for `tenant`
+    pub fn usage(&self, tenant: &str) -> usize {
+        self.attachments
+            .values()
+            .filter(|a| a.tenant == tenant)
+            .map(|a| a.program.len())
+            .sum()
+    }
+
+    /// attach `program` to `target` for `tenant`, replacing its current one
+    ///
+    /// returns the fingerprint of the program. on failure, the program the
+    /// registry attached before is attached again, a descriptor the registry
+    /// did not attach anything to is left untouched.
+    pub fn attach<T>(
+        &mut self,
+        tenant: &str,
+        target: &T,
+        program: Vec<BPFFilter>,
+    ) -> Result<u64, RegistryError>
+    where
+        T: AsRawFd,
+    {
+        let fd = target.as_raw_fd();
+        if let Some(&budget) = self.budgets.get(tenant) {
+            let replaced = match self.attachments.get(&fd) {
+                Some(a) if a.tenant == tenant => a.program.len(),
+                _ => 0,
+            };
+            let needed = self.usage(tenant) - replaced + program.len();
+            if needed > budget {
+                return Err(RegistryError::OverBudget {
+                    tenant: tenant.to_string(),
+                    needed,
+                    budget,
+                });
+            }
+        }
+        if let Err(e) = BPFFProg::new(&program).attach_filter(&fd) {
+            let errno = e.raw_os_error().unwrap_or(libc::EIO);
+            // the kernel may have dropped the previous program on the way
+            if let Some(a) = self.attachments.get(&fd) {
+                let _ = BPFFProg::new(&a.program).attach_filter(&fd);
+            }
+            return Err(RegistryError::Attach(errno));
+        }
+        let fingerprint = fingerprint(&program);
+        self.attachments.insert(
+            fd,
+            Attachment {
+                tenant: tenant.to_string(),
+                program,
+            },
+        );
+        Ok(fingerprint)
+    }
+
+    /// replace the program already attached to `target`, for the same tenant
+    pub fn replace<T>(&mut self, target: &T, program: Vec<BPFFilter>) -> Result<u64, RegistryError>
+    where
+        T: AsRawFd,
+    {
+        let fd = target.as_raw_fd();
+        let tenant = match self.attachments.get(&fd) {
+            Some(a) => a.tenant.clone(),
+            None => return Err(RegistryError::NotAttached(fd)),
+        };
+        self.attach(&tenant, target, program)
+    }
+
+    /// remove the program attached to `target`, which then gets every packet
+    pub fn detach<T>(&mut self, target: &T) -> Result<(), RegistryError>
+    where
+        T: AsRawFd,
+    {
+        let fd = target.as_raw_fd();
+        if !self.attachments.contains_key(&fd) {
+            return Err(RegistryError::NotAttached(fd));
+        }
+        crate::scoped::reset_filter(&fd)
+            .map_err(|e| RegistryError::Detach(e.raw_os_error().unwrap_or(libc::EIO)))?;
+        self.attachments.remove(&fd);
+        Ok(())
+    }
+
+    /// forget `target` once it is closed, without touching it
+    pub fn forget<T>(&mut self, target: &T)
+    where
+        T: AsRawFd,
+    {
+        self.attachments.remove(&target.as_raw_fd());
+    }
+
+    /// a snapshot of the attachments, sorted by descriptor
+    pub fn inventory(&self) -> Vec<InventoryEntry> {
+        let mut entries: Vec<InventoryEntry> = self
+            .attachments
+            .iter()
+            .map(|(fd, a)| InventoryEntry {
+                fd: *fd,
+                tenant: a.tenant.clone(),
+                fingerprint: fingerprint(&a.program),
+                len: a.program.len(),
+            })
+            .collect();
+        entries.sort_by_key(|e| e.fd);
+        entries
+    }
+}
+
+#[test]
+fn test_filter_registry() {
+    use std::net::UdpSocket;
+
+    let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut registry = FilterRegistry::new();
+    registry.set_budget("t", 5);
+    let arp = crate::presets::arp_only().to_vec();
+    let fp = registry.attach("t", &a, arp.clone()).unwrap();
+    assert_eq!(fp, fingerprint(&arp));
+    assert_eq!(
+        registry.attach("t", &b, arp.clone()),
+        Err(RegistryError::OverBudget {
+            tenant: "t".to_string(),
+            needed: 8,
+            budget: 5
+        })
+    );
+    // replacing counts the new program instead of the old one
+    registry
+        .replace(&a, crate::presets::accept_all().to_vec())
+        .unwrap();
+    registry.attach("t", &b, arp).unwrap();
+
+    // a jump past the end is refused by the kernel
+    let broken = vec![BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0, 0, 1)];
+    assert!(matches!(
+        registry.replace(&b, broken.clone()),
+        Err(RegistryError::Attach(_))
+    ));
+    // a program attached outside of the registry stays in place
+    let c = UdpSocket::bind("127.0.0.1:0").unwrap();
+    c.set_read_timeout(Some(std::time::Duration::from_millis(100)))
+        .unwrap();
+    BPFFProg::new(crate::presets::drop_all())
+        .attach_filter(&c)
+        .unwrap();
+    assert!(registry.attach("u", &c, broken).is_err());
+    a.send_to(b"dropped", c.local_addr().unwrap()).unwrap();
+    assert!(c.recv(&mut [0u8; 8]).is_err());
+
+    let inventory = registry.inventory();
+    assert_eq!(inventory.len(), 2);
+    assert_eq!(inventory.iter().map(|e| e.len).sum::<usize>(), 5);
+    assert_eq!(
+        registry.replace(&0, vec![]),
+        Err(RegistryError::NotAttached(0))
+    );
+}
@@ -0,0 +1,207 @@
+use crate::bpf_base::*;
+use crate::config::{self, ConfigError};
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(any(target_os = "freebsd", target_os = "macos"))]
+use crate::bsd::Watch;
+#[cfg(target_os = "linux")]
+use crate::linux::Watch;
+
+/// why a target of a [`HotReloader`] kept its previous program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadFailure {
+    /// the filter of the target is no longer in the configuration
+    Missing,
+    /// the filter of the target is invalid
+    Config(ConfigError),
+    /// the program was refused, with the errno
+    Attach(i32),
+}
+
+impl fmt::Display for ReloadFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadFailure::Missing => write!(f, "the filter is no longer configured"),
+            ReloadFailure::Config(e) => write!(f, "{}", e),
+            ReloadFailure::Attach(errno) => write!(
+                f,
+                "cannot attach the program: {}",
+                std::io::Error::from_raw_os_error(*errno)
+            ),
+        }
+    }
+}
+
+/// the outcome of a reload for one target whose program changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// the configured filter of the target
+    pub filter: String,
+    pub fd: RawFd,
+    pub result: Result<(), ReloadFailure>,
+}
+
+struct Target {
+    filter: String,
+    fd: RawFd,
+    attached: Option<Vec<BPFFilter>>,
+}
+
+/// keeps sockets and devices in sync with a configuration file
+///
+/// targets are registered with the name of their filter in the file. each
+/// `reload` attaches the programs that changed since they were last attached,
+/// replacing the previous ones without a gap on Linux sockets. targets whose
+/// new program cannot be built or attached keep the previous one.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// # fn example(socket: std::net::UdpSocket) -> std::io::Result<()> {
+/// let mut reloader = HotReloader::new("/etc/capture/filters.toml")?;
+/// reloader.register("dns", &socket);
+/// loop {
+///     let compile = |_: &str, _: u32| Err("expressions are not supported");
+///     match reloader.reload(compile) {
+///         Ok(reports) => reports.iter().for_each(|r| println!("{:?}", r)),
+///         Err(e) => eprintln!("{}", e),
+///     }
+///     reloader.wait(None)?;
+/// }
+/// # }
+/// ```
+pub struct HotReloader {
+    path: PathBuf,
+    watch: Watch,
+    targets: Vec<Target>,
+}
+
+impl HotReloader {
+    /// watch the configuration file at `path`, which may not exist yet
+    pub fn new<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        Ok(Self {
+            watch: Watch::new(&path)?,
+            path,
+            targets: Vec::new(),
+        })
+    }
+
+    /// keep `target` attached to the configured filter `filter`
+    ///
+    /// the target must stay open as long as it is registered, the program
+    /// is attached by the next `reload`.
+    pub fn register<T>(&mut self, filter: &str, target: &T)
+    where
+        T: AsRawFd,
+    {
+        self.targets.push(Target {
+            filter: filter.to_string(),
+            fd: target.as_raw_fd(),
+            attached: None,
+        });
+    }
+
+    /// stop tracking `target`, its program stays attached
+    pub fn unregister<T>(&mut self, target: &T)
+    where
+        T: AsRawFd,
+    {
+        self.targets.retain(|t| t.fd != target.as_raw_fd());
+    }
+
+    /// block until the configuration file changes, `Ok(false)` on timeout
+    pub fn wait(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        self.watch.wait(timeout)
+    }
+
+    /// read the configuration again and attach the programs that changed
+    ///
+    /// expressions are passed to `compile` along with their link type. an
+    /// unreadable or malformed file changes nothing and is returned as an error.
+    pub fn reload<F, E>(&mut self, mut compile: F) -> Result<Vec<ReloadReport>, ConfigError>
+    where
+        F: FnMut(&str, u32) -> Result<Vec<BPFFilter>, E>,
+        E: fmt::Display,
+    {
+        let text = std::fs::read_to_string(&self.path).map_err(|e| ConfigError {
+            line: 0,
+            column: 0,
+            message: format!("{}: {}", self.path.display(), e),
+        })?;
+        let filters = config::parse(&text)?;
+        let mut programs = Vec::new();
+        for filter in &filters {
+            let program = filter
+                .program(|expression, dlt| compile(expression, dlt))
+                .map_err(ReloadFailure::Config);
+            programs.push((filter.name.as_str(), program));
+        }
+
+        let mut reports = Vec::new();
+        for target in &mut self.targets {
+            let program = match programs.iter().find(|(name, _)| *name == target.filter) {
+                Some((_, program)) => program.clone(),
+                None => Err(ReloadFailure::Missing),
+            };
+            let result = match program {
+                Ok(program) if target.attached.as_ref() == Some(&program) => continue,
+                Ok(program) => match BPFFProg::new(&program).attach_filter(&target.fd) {
+                    Ok(()) => {
+                        target.attached = Some(program);
+                        Ok(())
+                    }
+                    Err(e) => Err(ReloadFailure::Attach(e.raw_os_error().unwrap_or(libc::EIO))),
+                },
+                Err(failure) => Err(failure),
+            };
+            reports.push(ReloadReport {
+                filter: target.filter.clone(),
+                fd: target.fd,
+                result,
+            });
+        }
+        Ok(reports)
+    }
+}
+
+impl fmt::Debug for HotReloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HotReloader")
+            .field("path", &self.path)
+            .field("targets", &self.targets.len())
+            .finish()
+    }
+}
+
+#[test]
+fn test_hot_reload() {
+    use std::net::UdpSocket;
+
+    let dir = std::env::temp_dir().join(format!("classic_bpf-reload-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("filters.toml");
+    std::fs::write(&path, "[filters.a]\npreset = \"arp_only\"\n").unwrap();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut reloader = HotReloader::new(&path).unwrap();
+    reloader.register("a", &socket);
+    let compile = |_: &str, _: u32| Err::<Vec<BPFFilter>, _>("unused");
+    let reports = reloader.reload(compile).unwrap();
+    assert_eq!(reports[0].result, Ok(()));
+    // nothing changed
+    assert!(reloader.reload(compile).unwrap().is_empty());
+
+    std::fs::write(&path, "[filters.b]\npreset = \"drop_all\"\n").unwrap();
+    assert!(reloader.wait(Some(Duration::from_secs(5))).unwrap());
+    let reports = reloader.reload(compile).unwrap();
+    assert_eq!(reports[0].result, Err(ReloadFailure::Missing));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
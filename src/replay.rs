@@ -0,0 +1,134 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// pacing of the frames sent by [`replay`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Timing {
+    /// keep the gaps between the capture timestamps
+    Original,
+    /// send the frames back to back
+    AsFastAsPossible,
+    /// divide the gaps by the factor, 2.0 replays twice as fast
+    ///
+    /// the factor must be finite and positive.
+    Scaled(f64),
+}
+
+/// something frames can be sent through
+///
+/// it is implemented with `write(2)` for file descriptors, such as a BPF device
+/// or an AF_PACKET socket bound to an interface.
+pub trait Injector {
+    /// send one whole frame, link-layer header included
+    ///
+    /// a frame only partly written fails with EMSGSIZE.
+    fn inject(&self, frame: &[u8]) -> io::Result<()>;
+}
+
+impl<T> Injector for T
+where
+    T: AsRawFd,
+{
+    fn inject(&self, frame: &[u8]) -> io::Result<()> {
+        match unsafe {
+            libc::write(
+                self.as_raw_fd(),
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+            )
+        } {
+            -1 => Err(io::Error::last_os_error()),
+            n if (n as usize) < frame.len() => Err(io::Error::from_raw_os_error(libc::EMSGSIZE)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// send captured frames again through `injector`
+///
+/// `frames` yields each frame with its capture timestamp, the gaps are
+/// measured from the first one. frames stamped before their predecessor are
+/// sent at once.
+///
+/// returns the number of frames sent, stopping at the first failure. a
+/// `Timing::Scaled` factor that is not finite and positive fails with EINVAL
+/// before anything is sent.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+///
+/// # fn example(device: std::fs::File, frames: &[FrameBuf]) -> std::io::Result<()> {
+/// let frames = frames
+///     .iter()
+///     .map(|frame| (frame.timestamp().unwrap_or_default(), frame.data()));
+/// replay(frames, &device, Timing::Scaled(10.0))?;
+/// # Ok(())
+/// # }
+/// ```
+pub fn replay<'a, I, J>(frames: I, injector: &J, timing: Timing) -> io::Result<usize>
+where
+    I: IntoIterator<Item = (Duration, &'a [u8])>,
+    J: Injector + ?Sized,
+{
+    if let Timing::Scaled(factor) = timing {
+        if !(factor.is_finite() && factor > 0.0) {
+            return Err(io::Error::from_raw_os_error(libc::EINVAL));
+        }
+    }
+    let start = Instant::now();
+    let mut first = None;
+    let mut sent = 0;
+    for (timestamp, frame) in frames {
+        let origin = *first.get_or_insert(timestamp);
+        let gap = timestamp.saturating_sub(origin);
+        let offset = match timing {
+            Timing::Original => Some(gap),
+            Timing::AsFastAsPossible => None,
+            Timing::Scaled(factor) => Some(gap.div_f64(factor)),
+        };
+        if let Some(offset) = offset {
+            let elapsed = start.elapsed();
+            if offset > elapsed {
+                std::thread::sleep(offset - elapsed);
+            }
+        }
+        injector.inject(frame)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+#[test]
+fn test_replay() {
+    use std::net::UdpSocket;
+
+    let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    tx.connect(rx.local_addr().unwrap()).unwrap();
+
+    let frames: [(Duration, &[u8]); 3] = [
+        (Duration::from_secs(100), b"one"),
+        (Duration::from_millis(100_050), b"two"),
+        (Duration::from_secs(99), b"three"),
+    ];
+    let start = Instant::now();
+    assert_eq!(
+        replay(frames.iter().copied(), &tx, Timing::Original).unwrap(),
+        3
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let mut buf = [0u8; 16];
+    for (_, frame) in &frames {
+        let n = rx.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], *frame);
+    }
+
+    for factor in [0.0, -1.0, f64::NAN, f64::INFINITY].iter() {
+        let error = replay(frames.iter().copied(), &tx, Timing::Scaled(*factor)).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EINVAL));
+    }
+}
@@ -0,0 +1,424 @@
+use crate::linux::packet_drops;
+use crate::monitor::{DropEvent, DropMonitor};
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// the layout of the frames in a receive ring
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RingVersion {
+    /// one frame per fixed-size slot, handed over one at a time
+    V2,
+    /// frames packed in blocks, handed over a block at a time
+    V3,
+}
+
+/// the geometry of a receive ring
+///
+/// the ring is `block_count` blocks of `block_size` bytes, a multiple of the
+/// page size. with `RingVersion::V2` each block holds slots of `frame_size`
+/// bytes, a multiple of 16 capping the length captured; with
+/// `RingVersion::V3` the frames take the room they need and `frame_size`
+/// only bounds them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingConfig {
+    pub block_size: u32,
+    pub block_count: u32,
+    pub frame_size: u32,
+    /// how long the kernel fills a V3 block before handing it over anyway
+    pub block_timeout: Duration,
+}
+
+impl Default for RingConfig {
+    /// 64 blocks of 1 MiB, frames of up to 2 KiB, blocks retired after 100 ms
+    fn default() -> Self {
+        Self {
+            block_size: 1 << 20,
+            block_count: 64,
+            frame_size: 2048,
+            block_timeout: Duration::from_millis(100),
+        }
+    }
+}
+
+/// a memory-mapped receive ring of a packet socket (PACKET_RX_RING)
+///
+/// the kernel runs the filter attached to the socket then copies the frames
+/// it accepts straight into the ring, truncated to the length the filter
+/// returned, with no system call per packet. [`next_block`](Self::next_block)
+/// hands over the frames, the kernel gets their room back when the
+/// [`RingBlock`] is dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// use classic_bpf::*;
+/// use std::time::Duration;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let socket = open_packet_socket_in_netns("/proc/self/ns/net", "eth0", 0x0003, presets::arp_only())?;
+/// let mut ring = RxRing::new(socket, RingVersion::V3, RingConfig::default())?;
+/// while let Some(block) = ring.next_block(Some(Duration::from_secs(1)))? {
+///     for frame in block {
+///         println!("{} bytes at {:?}", frame.len(), frame.timestamp());
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct RxRing {
+    socket: OwnedFd,
+    version: RingVersion,
+    map: *mut u8,
+    len: usize,
+    /// the size of the blocks handed over, frames for V2
+    slot_size: usize,
+    slots_per_block: usize,
+    block_size: usize,
+    slots: usize,
+    next: usize,
+    /// the V3 blocks handed over full since the last `report_drops`
+    full_blocks: u64,
+}
+
+impl RxRing {
+    /// set up and map a ring of `config` on the packet socket `socket`
+    ///
+    /// the frames queued on the socket before are still read with recv(2).
+    pub fn new(socket: OwnedFd, version: RingVersion, config: RingConfig) -> io::Result<Self> {
+        let value: libc::c_int = match version {
+            RingVersion::V2 => libc::tpacket_versions::TPACKET_V2 as libc::c_int,
+            RingVersion::V3 => libc::tpacket_versions::TPACKET_V3 as libc::c_int,
+        };
+        setsockopt(&socket, libc::PACKET_VERSION, &value)?;
+
+        let frames_per_block = config
+            .block_size
+            .checked_div(config.frame_size)
+            .unwrap_or(0);
+        let req = libc::tpacket_req3 {
+            tp_block_size: config.block_size,
+            tp_block_nr: config.block_count,
+            tp_frame_size: config.frame_size,
+            tp_frame_nr: frames_per_block * config.block_count,
+            tp_retire_blk_tov: match version {
+                RingVersion::V2 => 0,
+                RingVersion::V3 => config.block_timeout.as_millis() as libc::c_uint,
+            },
+            tp_sizeof_priv: 0,
+            tp_feature_req_word: 0,
+        };
+        match version {
+            // V2 takes the leading tpacket_req of tpacket_req3
+            RingVersion::V2 => setsockopt(&socket, libc::PACKET_RX_RING, unsafe {
+                &*(&req as *const libc::tpacket_req3 as *const libc::tpacket_req)
+            })?,
+            RingVersion::V3 => setsockopt(&socket, libc::PACKET_RX_RING, &req)?,
+        }
+
+        let len = config.block_size as usize * config.block_count as usize;
+        let map = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                socket.as_raw_fd(),
+                0,
+            )
+        };
+        if map == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        let (slot_size, slots_per_block) = match version {
+            RingVersion::V2 => (config.frame_size as usize, frames_per_block as usize),
+            RingVersion::V3 => (config.block_size as usize, 1),
+        };
+        Ok(Self {
+            socket,
+            version,
+            map: map as *mut u8,
+            len,
+            slot_size,
+            slots_per_block,
+            block_size: config.block_size as usize,
+            slots: slots_per_block * config.block_count as usize,
+            next: 0,
+            full_blocks: 0,
+        })
+    }
+
+    /// feed `monitor` the packets the socket dropped and the blocks handed
+    /// over full since the previous call
+    ///
+    /// full blocks warn of a consumer falling behind before the kernel
+    /// drops anything, a V2 ring has none.
+    pub fn report_drops<F>(&mut self, monitor: &mut DropMonitor<F>) -> io::Result<()>
+    where
+        F: FnMut(DropEvent),
+    {
+        let drops = packet_drops(&self.socket)?;
+        monitor.record(drops, std::mem::take(&mut self.full_blocks));
+        Ok(())
+    }
+
+    /// the socket of the ring
+    pub fn socket(&self) -> &OwnedFd {
+        &self.socket
+    }
+
+    /// the version the ring was set up with
+    pub fn version(&self) -> RingVersion {
+        self.version
+    }
+
+    /// the next block of frames, waiting up to `timeout` for the kernel to
+    /// fill it, forever with `None`
+    ///
+    /// returns `Ok(None)` once `timeout` expired. a V2 ring hands over its
+    /// frames as blocks of one frame.
+    pub fn next_block(&mut self, timeout: Option<Duration>) -> io::Result<Option<RingBlock<'_>>> {
+        let deadline = timeout.map(|t| Instant::now() + t);
+        let offset = (self.next / self.slots_per_block) * self.block_size
+            + (self.next % self.slots_per_block) * self.slot_size;
+        let slot = unsafe { self.map.add(offset) };
+        let status = unsafe {
+            &*(slot.add(match self.version {
+                RingVersion::V2 => 0,
+                // tpacket_block_desc.hdr.bh1.block_status
+                RingVersion::V3 => 8,
+            }) as *const AtomicU32)
+        };
+        while status.load(Ordering::Acquire) & libc::TP_STATUS_USER == 0 {
+            let left = deadline.map_or(-1, |d| {
+                d.saturating_duration_since(Instant::now()).as_millis() as libc::c_int
+            });
+            if left == 0 {
+                return Ok(None);
+            }
+            let mut pfd = libc::pollfd {
+                fd: self.socket.as_raw_fd(),
+                events: libc::POLLIN | libc::POLLERR,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut pfd, 1, left) } < 0 {
+                match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => continue,
+                    e => return Err(e),
+                }
+            }
+        }
+        // a V3 block the timeout did not retire was filled up
+        let full = self.version == RingVersion::V3
+            && status.load(Ordering::Acquire) & libc::TP_STATUS_BLK_TMO == 0;
+        self.full_blocks += full as u64;
+        self.next = (self.next + 1) % self.slots;
+        let data = unsafe { std::slice::from_raw_parts(slot as *const u8, self.slot_size) };
+        let (cursor, remaining) = match self.version {
+            RingVersion::V2 => (0, 1),
+            RingVersion::V3 => {
+                let desc = unsafe { &*(slot as *const libc::tpacket_block_desc) };
+                let bh1 = unsafe { &desc.hdr.bh1 };
+                (bh1.offset_to_first_pkt as usize, bh1.num_pkts)
+            }
+        };
+        Ok(Some(RingBlock {
+            version: self.version,
+            status,
+            data,
+            cursor,
+            remaining,
+        }))
+    }
+}
+
+impl Drop for RxRing {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.map as *mut libc::c_void, self.len) };
+    }
+}
+
+impl AsRawFd for RxRing {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl AsFd for RxRing {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+fn setsockopt<T>(socket: &OwnedFd, name: libc::c_int, value: &T) -> io::Result<()> {
+    match unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_PACKET,
+            name,
+            value as *const T as *const libc::c_void,
+            size_of::<T>() as libc::socklen_t,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// frames handed over by the kernel, iterated as [`RingFrame`]s
+///
+/// dropping the block gives its room back to the kernel.
+#[derive(Debug)]
+pub struct RingBlock<'a> {
+    version: RingVersion,
+    status: &'a AtomicU32,
+    data: &'a [u8],
+    cursor: usize,
+    remaining: u32,
+}
+
+impl<'a> Iterator for RingBlock<'a> {
+    type Item = RingFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let at = self.data.get(self.cursor..)?.as_ptr();
+        let (status, sec, nsec, snaplen, len, mac, vlan_tci, next) = match self.version {
+            RingVersion::V2 => {
+                let hdr = unsafe { &*(at as *const libc::tpacket2_hdr) };
+                let tci = hdr.tp_vlan_tci as u32;
+                let (sec, nsec) = (hdr.tp_sec, hdr.tp_nsec);
+                (
+                    hdr.tp_status,
+                    sec,
+                    nsec,
+                    hdr.tp_snaplen,
+                    hdr.tp_len,
+                    hdr.tp_mac,
+                    tci,
+                    0,
+                )
+            }
+            RingVersion::V3 => {
+                let hdr = unsafe { &*(at as *const libc::tpacket3_hdr) };
+                let (tci, next) = (hdr.hv1.tp_vlan_tci, hdr.tp_next_offset);
+                let (sec, nsec) = (hdr.tp_sec, hdr.tp_nsec);
+                (
+                    hdr.tp_status,
+                    sec,
+                    nsec,
+                    hdr.tp_snaplen,
+                    hdr.tp_len,
+                    hdr.tp_mac,
+                    tci,
+                    next,
+                )
+            }
+        };
+        let start = self.cursor + mac as usize;
+        let data = self.data.get(start..start + snaplen as usize)?;
+        self.cursor += next as usize;
+        Some(RingFrame {
+            data,
+            len,
+            timestamp: Duration::new(sec as u64, nsec),
+            vlan_tci: match status & libc::TP_STATUS_VLAN_VALID {
+                0 => None,
+                _ => Some(vlan_tci as u16),
+            },
+        })
+    }
+}
+
+impl Drop for RingBlock<'_> {
+    fn drop(&mut self) {
+        self.status.store(libc::TP_STATUS_KERNEL, Ordering::Release);
+    }
+}
+
+/// a frame of a [`RingBlock`]
+#[derive(Debug, Clone, Copy)]
+pub struct RingFrame<'a> {
+    data: &'a [u8],
+    len: u32,
+    timestamp: Duration,
+    vlan_tci: Option<u16>,
+}
+
+impl<'a> RingFrame<'a> {
+    /// the captured bytes, from the link-layer header
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// the length of the frame on the wire
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// whether the frame was empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// whether fewer bytes were captured than the frame had
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.len as usize
+    }
+
+    /// when the kernel received the frame, since the UNIX epoch
+    pub fn timestamp(&self) -> Duration {
+        self.timestamp
+    }
+
+    /// the TCI of the VLAN tag the NIC stripped from the frame
+    pub fn vlan_tci(&self) -> Option<u16> {
+        self.vlan_tci
+    }
+}
+
+#[test]
+fn test_rx_ring() {
+    use crate::bpf_base::*;
+    use crate::linux::{bind_packet_socket, packet_socket};
+
+    let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    let port = rx.local_addr().unwrap().port();
+    let filters = crate::compile(&format!("udp dst port {}", port), crate::Dlt::En10mb).unwrap();
+    let config = RingConfig {
+        block_size: 1 << 16,
+        block_count: 4,
+        frame_size: 1 << 11,
+        block_timeout: Duration::from_millis(10),
+    };
+    for version in [RingVersion::V2, RingVersion::V3] {
+        let socket = match packet_socket() {
+            Ok(socket) => socket,
+            Err(e) => return assert_eq!(e.raw_os_error(), Some(libc::EPERM)),
+        };
+        BPFFProg::new(&filters).attach_filter(&socket).unwrap();
+        let mut ring = RxRing::new(socket, version, config).unwrap();
+        let lo = crate::Interface::from("lo").index().unwrap();
+        bind_packet_socket(ring.socket(), lo, libc::ETH_P_ALL as u16).unwrap();
+
+        let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        tx.send_to(b"ring", ("127.0.0.1", port)).unwrap();
+        let block = ring.next_block(Some(Duration::from_secs(2))).unwrap();
+        let frames: Vec<_> = block.unwrap().map(|frame| frame.data().to_vec()).collect();
+        // the loopback shows the packet as sent then as received
+        assert!(!frames.is_empty(), "{:?}", version);
+        assert!(frames.iter().all(|frame| frame.ends_with(b"ring")));
+
+        // the timeout handed the block over, not the lack of room
+        let mut reports = Vec::new();
+        let mut monitor = DropMonitor::new(Duration::ZERO, |event| reports.push(event));
+        ring.report_drops(&mut monitor).unwrap();
+        assert!(reports.iter().all(|event| event.full_blocks == 0));
+    }
+}
@@ -0,0 +1,105 @@
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// remove the program attached to `socket`, which then gets every packet
+///
+/// Linux detaches it with SO_DETACH_FILTER, the BSDs have no such request
+/// and get a program accepting everything instead.
+pub(crate) fn reset_filter<T>(socket: &T) -> io::Result<()>
+where
+    T: AsRawFd,
+{
+    #[cfg(target_os = "linux")]
+    return crate::linux::detach_filter(socket);
+    #[cfg(not(target_os = "linux"))]
+    {
+        use crate::bpf_base::*;
+        BPFFProg::new(&[BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX)]).attach_filter(socket)
+    }
+}
+
+/// a program attached to a socket until the guard is dropped
+///
+/// returned by [`BPFOperations::attach_scoped`](crate::BPFOperations::attach_scoped),
+/// it detaches the program even when the capture returns early or panics.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// {
+///     let filter = BPFFProg::new(presets::drop_all()).attach_scoped(&socket).unwrap();
+///     assert_eq!(filter.socket().local_addr().unwrap(), socket.local_addr().unwrap());
+/// }
+/// // the program is gone
+/// ```
+#[derive(Debug)]
+#[must_use = "the program is detached as soon as the guard is dropped"]
+pub struct AttachedFilter<'a, T>
+where
+    T: AsRawFd,
+{
+    socket: &'a T,
+}
+
+impl<'a, T> AttachedFilter<'a, T>
+where
+    T: AsRawFd,
+{
+    pub(crate) fn new(socket: &'a T) -> Self {
+        Self { socket }
+    }
+
+    /// the socket the program is attached to
+    pub fn socket(&self) -> &'a T {
+        self.socket
+    }
+
+    /// detach the program now, reporting the failure the drop would ignore
+    pub fn detach(self) -> io::Result<()> {
+        let socket = self.socket;
+        std::mem::forget(self);
+        reset_filter(socket)
+    }
+}
+
+impl<T> Drop for AttachedFilter<'_, T>
+where
+    T: AsRawFd,
+{
+    fn drop(&mut self) {
+        let _ = reset_filter(self.socket);
+    }
+}
+
+#[test]
+fn test_attached_filter() {
+    use crate::bpf_base::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    let rx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rx.set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let tx = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut buf = [0u8; 8];
+
+    let filter = BPFFProg::new(crate::filters::drop_all())
+        .attach_scoped(&rx)
+        .unwrap();
+    tx.send_to(b"dropped", rx.local_addr().unwrap()).unwrap();
+    assert!(rx.recv(&mut buf).is_err());
+    drop(filter);
+    tx.send_to(b"kept", rx.local_addr().unwrap()).unwrap();
+    assert_eq!(rx.recv(&mut buf).unwrap(), 4);
+
+    let filter = BPFFProg::new(crate::filters::drop_all())
+        .attach_scoped(&rx)
+        .unwrap();
+    filter.detach().unwrap();
+    // nothing is left to detach
+    #[cfg(target_os = "linux")]
+    assert!(crate::linux::detach_filter(&rx).is_err());
+}
@@ -0,0 +1,898 @@
+//! seccomp filters
+//!
+//! a seccomp program runs over the `struct seccomp_data` of each system call
+//! rather than over a packet, and returns one of the `SECCOMP_RET_*` actions.
+//! its loads are 32-bit words of `seccomp_data`, in the byte order of the
+//! host.
+
+use crate::bpf_base::*;
+use crate::builder::{BuildError, ProgramBuilder};
+use crate::interpreter;
+use crate::validate::validate;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem::size_of;
+
+/// kill the whole process, as if by an uncatchable SIGSYS
+pub const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+/// kill the calling thread
+pub const SECCOMP_RET_KILL_THREAD: u32 = 0x0000_0000;
+/// the older name of `SECCOMP_RET_KILL_THREAD`
+pub const SECCOMP_RET_KILL: u32 = SECCOMP_RET_KILL_THREAD;
+/// send SIGSYS to the thread, the data ends up in `si_errno`
+pub const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+/// fail the system call with the errno in the data
+pub const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+/// hand the system call to the process listening on the notification fd
+pub const SECCOMP_RET_USER_NOTIF: u32 = 0x7fc0_0000;
+/// stop the thread for its ptrace tracer, with the data as the event message
+pub const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+/// allow the system call and log it
+pub const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+/// allow the system call
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// the part of a return value selecting the action
+pub const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+/// the part of a return value carried along with the action
+pub const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+/// what the kernel does with a system call
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    KillProcess,
+    KillThread,
+    /// SIGSYS with this value in `si_errno`
+    Trap(u16),
+    /// fail with this errno
+    Errno(u16),
+    UserNotif,
+    /// notify the tracer with this event message
+    Trace(u16),
+    Log,
+    Allow,
+}
+
+impl Action {
+    /// the value a program returns for this action
+    pub fn value(self) -> u32 {
+        match self {
+            Action::KillProcess => SECCOMP_RET_KILL_PROCESS,
+            Action::KillThread => SECCOMP_RET_KILL_THREAD,
+            Action::Trap(data) => SECCOMP_RET_TRAP | data as u32,
+            Action::Errno(errno) => SECCOMP_RET_ERRNO | errno as u32,
+            Action::UserNotif => SECCOMP_RET_USER_NOTIF,
+            Action::Trace(data) => SECCOMP_RET_TRACE | data as u32,
+            Action::Log => SECCOMP_RET_LOG,
+            Action::Allow => SECCOMP_RET_ALLOW,
+        }
+    }
+
+    /// the action of a value a program returned, None for an unknown one
+    ///
+    /// the kernel takes the unknown actions as `KillProcess`.
+    pub fn from_value(value: u32) -> Option<Self> {
+        let data = (value & SECCOMP_RET_DATA) as u16;
+        Some(match value & SECCOMP_RET_ACTION_FULL {
+            SECCOMP_RET_KILL_PROCESS => Action::KillProcess,
+            SECCOMP_RET_KILL_THREAD => Action::KillThread,
+            SECCOMP_RET_TRAP => Action::Trap(data),
+            SECCOMP_RET_ERRNO => Action::Errno(data),
+            SECCOMP_RET_USER_NOTIF => Action::UserNotif,
+            SECCOMP_RET_TRACE => Action::Trace(data),
+            SECCOMP_RET_LOG => Action::Log,
+            SECCOMP_RET_ALLOW => Action::Allow,
+            _ => return None,
+        })
+    }
+}
+
+/// the input of a seccomp program, laid out as the Linux `struct seccomp_data`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct SeccompData {
+    /// the system call number
+    pub nr: i32,
+    /// the `AUDIT_ARCH_*` value of the calling convention
+    pub arch: u32,
+    /// the address of the system call instruction
+    pub instruction_pointer: u64,
+    /// the arguments, zero-extended to 64 bits
+    pub args: [u64; 6],
+}
+
+impl SeccompData {
+    /// the 32-bit word at `offset`, as a load of a seccomp program reads it
+    ///
+    /// None past the end of the structure and for unaligned offsets, which
+    /// the kernel refuses.
+    pub fn word(&self, offset: u32) -> Option<u32> {
+        let offset = offset as usize;
+        if offset & 3 != 0 || offset >= size_of::<SeccompData>() {
+            return None;
+        }
+        let mut bytes = Vec::with_capacity(size_of::<SeccompData>());
+        bytes.extend_from_slice(&self.nr.to_ne_bytes());
+        bytes.extend_from_slice(&self.arch.to_ne_bytes());
+        bytes.extend_from_slice(&self.instruction_pointer.to_ne_bytes());
+        for arg in self.args.iter() {
+            bytes.extend_from_slice(&arg.to_ne_bytes());
+        }
+        let mut word = [0u8; 4];
+        word.copy_from_slice(&bytes[offset..offset + 4]);
+        Some(u32::from_ne_bytes(word))
+    }
+}
+
+/// half of a 64-bit field of `seccomp_data`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Half {
+    /// bits 0 to 31
+    Low,
+    /// bits 32 to 63
+    High,
+}
+
+/// a 32-bit word of `seccomp_data`, what a load of a seccomp program reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Word {
+    /// the system call number
+    Nr,
+    /// the `AUDIT_ARCH_*` value of the calling convention
+    Arch,
+    /// half of the address of the system call instruction
+    InstructionPointer(Half),
+    /// half of the argument 0 to 5
+    Arg(u8, Half),
+}
+
+impl Word {
+    /// the offset of the word in `seccomp_data`, in the byte order of the host
+    ///
+    /// # Panics
+    ///
+    /// panics for the arguments past 5
+    pub fn offset(self) -> u32 {
+        let wide = |offset: u32, half: Half| match (half, cfg!(target_endian = "little")) {
+            (Half::Low, true) | (Half::High, false) => offset,
+            _ => offset + 4,
+        };
+        match self {
+            Word::Nr => 0,
+            Word::Arch => 4,
+            Word::InstructionPointer(half) => wide(8, half),
+            Word::Arg(n, half) => {
+                assert!(n < 6, "system calls have 6 arguments, not {}", n + 1);
+                wide(16 + 8 * n as u32, half)
+            }
+        }
+    }
+}
+
+/// A = `word`
+pub fn load(word: Word) -> BPFFilter {
+    BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, word.offset())
+}
+
+/// A = the system call number
+pub fn load_nr() -> BPFFilter {
+    load(Word::Nr)
+}
+
+/// A = the `AUDIT_ARCH_*` value of the calling convention
+pub fn load_arch() -> BPFFilter {
+    load(Word::Arch)
+}
+
+/// the `arch` of the x86_64 system calls, x32 ones included
+pub const AUDIT_ARCH_X86_64: u32 = 0xc000_003e;
+/// the `arch` of the i386 system calls, those of 32-bit processes on x86_64 too
+pub const AUDIT_ARCH_I386: u32 = 0x4000_0003;
+/// the `arch` of the AArch64 system calls
+pub const AUDIT_ARCH_AARCH64: u32 = 0xc000_00b7;
+/// the `arch` of the 32-bit ARM system calls, those of 32-bit processes on AArch64 too
+pub const AUDIT_ARCH_ARM: u32 = 0x4000_0028;
+/// the `arch` of the 64-bit RISC-V system calls
+pub const AUDIT_ARCH_RISCV64: u32 = 0xc000_00f3;
+/// the `arch` of the little-endian 64-bit PowerPC system calls
+pub const AUDIT_ARCH_PPC64LE: u32 = 0xc000_0015;
+/// the `arch` of the big-endian 64-bit PowerPC system calls
+pub const AUDIT_ARCH_PPC64: u32 = 0x8000_0015;
+/// the `arch` of the s390x system calls
+pub const AUDIT_ARCH_S390X: u32 = 0x8000_0016;
+
+/// the `arch` of the system calls of this process, for the targets above
+pub const AUDIT_ARCH_NATIVE: Option<u32> =
+    if cfg!(all(target_arch = "x86_64", target_pointer_width = "64")) {
+        Some(AUDIT_ARCH_X86_64)
+    } else if cfg!(target_arch = "x86") {
+        Some(AUDIT_ARCH_I386)
+    } else if cfg!(target_arch = "aarch64") {
+        Some(AUDIT_ARCH_AARCH64)
+    } else if cfg!(target_arch = "arm") {
+        Some(AUDIT_ARCH_ARM)
+    } else if cfg!(target_arch = "riscv64") {
+        Some(AUDIT_ARCH_RISCV64)
+    } else if cfg!(all(target_arch = "powerpc64", target_endian = "little")) {
+        Some(AUDIT_ARCH_PPC64LE)
+    } else if cfg!(target_arch = "powerpc64") {
+        Some(AUDIT_ARCH_PPC64)
+    } else if cfg!(target_arch = "s390x") {
+        Some(AUDIT_ARCH_S390X)
+    } else {
+        None
+    };
+
+/// the bit of the x32 system call numbers, which share `AUDIT_ARCH_X86_64`
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// take `mismatch` for the system calls of another calling convention than
+/// `arch`, then A = the system call number
+///
+/// a system call number only means something for a given `arch`: a 64-bit
+/// process can still make 32-bit system calls, through `int 0x80` on x86_64,
+/// with the numbers of another table. every filter must check it first. for
+/// `AUDIT_ARCH_X86_64` the x32 system calls, whose numbers have bit 30 set,
+/// take `mismatch` too.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::seccomp::{self, Action, SeccompData};
+/// use classic_bpf::*;
+///
+/// let mut builder = ProgramBuilder::new();
+/// seccomp::check_arch(&mut builder, seccomp::AUDIT_ARCH_X86_64, Action::KillProcess)
+///     .push(seccomp::ret(Action::Allow));
+/// let filters = builder.build().unwrap();
+///
+/// let mut data = SeccompData { arch: seccomp::AUDIT_ARCH_X86_64, nr: 1, ..Default::default() };
+/// assert_eq!(seccomp::run(&filters, &data), Action::Allow.value());
+/// data.nr = 0x4000_0001;
+/// assert_eq!(seccomp::run(&filters, &data), Action::KillProcess.value());
+/// data.arch = seccomp::AUDIT_ARCH_I386;
+/// data.nr = 1;
+/// assert_eq!(seccomp::run(&filters, &data), Action::KillProcess.value());
+/// ```
+pub fn check_arch(
+    builder: &mut ProgramBuilder,
+    arch: u32,
+    mismatch: Action,
+) -> &mut ProgramBuilder {
+    builder
+        .push(load_arch())
+        .jmp(bpf::JEQ, bpf::K, arch, 1, 0)
+        .push(ret(mismatch))
+        .push(load_nr());
+    if arch == AUDIT_ARCH_X86_64 {
+        builder
+            .jmp(bpf::JGE, bpf::K, X32_SYSCALL_BIT, 0, 1)
+            .push(ret(mismatch));
+    }
+    builder
+}
+
+/// return `action`
+pub fn ret(action: Action) -> BPFFilter {
+    BPFFilter::bpf_stmt(bpf::RET | bpf::K, action.value())
+}
+
+/// run a seccomp program over `data`, as the kernel does on a system call
+///
+/// the loads are the aligned words of `data`, anything the kernel would
+/// refuse to attach kills the process.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::seccomp::{self, Action, SeccompData};
+/// use classic_bpf::*;
+///
+/// // fail getpid() with EPERM, allow the rest
+/// let filters = [
+///     seccomp::load_nr(),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 39, 0, 1),
+///     seccomp::ret(Action::Errno(1)),
+///     seccomp::ret(Action::Allow),
+/// ];
+///
+/// let mut data = SeccompData::default();
+/// assert_eq!(seccomp::run(&filters, &data), Action::Allow.value());
+/// data.nr = 39;
+/// assert_eq!(seccomp::run(&filters, &data), Action::Errno(1).value());
+/// ```
+pub fn run(filters: &[BPFFilter], data: &SeccompData) -> u32 {
+    // the only packet loads seccomp knows are the aligned words of
+    // seccomp_data, its length is that of the structure
+    let loads_words = filters.iter().all(|insn| match insn.code() {
+        0x20 => data.word(insn.k()).is_some(),
+        0x28 | 0x30 | 0x40 | 0x48 | 0x50 | 0xb1 => false,
+        _ => true,
+    });
+    if !loads_words || validate(filters).is_err() {
+        return SECCOMP_RET_KILL_PROCESS;
+    }
+    // the interpreter loads big-endian words, store each word for it to read
+    // as the host does
+    let mut packet = Vec::with_capacity(size_of::<SeccompData>());
+    for offset in (0..size_of::<SeccompData>() as u32).step_by(4) {
+        let word = data.word(offset).unwrap_or_default();
+        packet.extend_from_slice(&word.to_be_bytes());
+    }
+    interpreter::run(filters, &packet, packet.len() as u32)
+}
+
+/// synchronize all the threads of the process to the new filter
+#[cfg(target_os = "linux")]
+pub const SECCOMP_FILTER_FLAG_TSYNC: u32 = libc::SECCOMP_FILTER_FLAG_TSYNC as u32;
+/// log all the actions but `SECCOMP_RET_ALLOW`
+#[cfg(target_os = "linux")]
+pub const SECCOMP_FILTER_FLAG_LOG: u32 = libc::SECCOMP_FILTER_FLAG_LOG as u32;
+/// keep the speculative store bypass mitigation off
+#[cfg(target_os = "linux")]
+pub const SECCOMP_FILTER_FLAG_SPEC_ALLOW: u32 = libc::SECCOMP_FILTER_FLAG_SPEC_ALLOW as u32;
+
+/// install `filters` on the calling thread, with the `SECCOMP_FILTER_FLAG_*` `flags`
+///
+/// sets PR_SET_NO_NEW_PRIVS first, so that no privilege is needed, then
+/// calls seccomp(2), or prctl(2) on the kernels without it when there is no
+/// flag. the filter is inherited by the children and cannot be removed.
+/// with `SECCOMP_FILTER_FLAG_TSYNC`, a thread that cannot be synchronized
+/// fails the whole install with EAGAIN.
+#[cfg(target_os = "linux")]
+pub fn install(filters: &[BPFFilter], flags: u32) -> std::io::Result<()> {
+    use std::io;
+
+    if flags & libc::SECCOMP_FILTER_FLAG_NEW_LISTENER as u32 != 0 {
+        // the notification fd it returns would leak
+        return Err(io::Error::from_raw_os_error(libc::EINVAL));
+    }
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let program = BPFFProg::new(filters);
+    match unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            flags,
+            &program as *const BPFFProg,
+        )
+    } {
+        0 => Ok(()),
+        // the id of the thread that could not be synchronized
+        tid if tid > 0 => Err(io::Error::from_raw_os_error(libc::EAGAIN)),
+        _ => match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::ENOSYS) && flags == 0 => {
+                match unsafe {
+                    libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        libc::SECCOMP_MODE_FILTER,
+                        &program as *const BPFFProg,
+                    )
+                } {
+                    0 => Ok(()),
+                    _ => Err(io::Error::last_os_error()),
+                }
+            }
+            e => Err(e),
+        },
+    }
+}
+
+/// build a filter acting on system calls by number
+///
+/// the filter first checks the architecture with [`check_arch`], killing the
+/// process on any other calling convention. the system calls sharing an
+/// action are then tested as in [`ProgramBuilder::jeq_any`], the others get
+/// the default action.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::seccomp::{self, Action, SeccompData, SyscallFilter};
+///
+/// // x86_64: read, write and exit_group, getpid fails with EPERM
+/// let filters = SyscallFilter::new(seccomp::AUDIT_ARCH_X86_64, Action::KillProcess)
+///     .allow(&[0, 1, 231])
+///     .deny(&[39], Action::Errno(1))
+///     .build()
+///     .unwrap();
+///
+/// let mut data = SeccompData { arch: seccomp::AUDIT_ARCH_X86_64, ..Default::default() };
+/// assert_eq!(seccomp::run(&filters, &data), Action::Allow.value());
+/// data.nr = 39;
+/// assert_eq!(seccomp::run(&filters, &data), Action::Errno(1).value());
+/// data.nr = 59;
+/// assert_eq!(seccomp::run(&filters, &data), Action::KillProcess.value());
+/// ```
+#[derive(Debug, Clone)]
+pub struct SyscallFilter {
+    arch: u32,
+    default: Action,
+    rules: BTreeMap<u32, Action>,
+}
+
+impl SyscallFilter {
+    /// a filter for the `AUDIT_ARCH_*` `arch`, taking `default` for the
+    /// system calls without a rule
+    pub fn new(arch: u32, default: Action) -> Self {
+        Self {
+            arch,
+            default,
+            rules: BTreeMap::new(),
+        }
+    }
+
+    /// a filter for the system calls of this process, see [`AUDIT_ARCH_NATIVE`]
+    pub fn native(default: Action) -> Option<Self> {
+        Some(Self::new(AUDIT_ARCH_NATIVE?, default))
+    }
+
+    /// take `action` for the system call `nr`, replacing its previous rule
+    pub fn rule(&mut self, nr: u32, action: Action) -> &mut Self {
+        self.rules.insert(nr, action);
+        self
+    }
+
+    /// allow the system calls `nrs`
+    pub fn allow(&mut self, nrs: &[u32]) -> &mut Self {
+        self.deny(nrs, Action::Allow)
+    }
+
+    /// take `action` for the system calls `nrs`
+    pub fn deny(&mut self, nrs: &[u32], action: Action) -> &mut Self {
+        for nr in nrs {
+            self.rule(*nr, action);
+        }
+        self
+    }
+
+    /// allow the system calls `names`, see [`syscall_number`]
+    ///
+    /// the names map to the numbers of the target, a filter for another
+    /// architecture fails with `NameError::ForeignArch`.
+    #[cfg(target_os = "linux")]
+    pub fn allow_names(&mut self, names: &[&str]) -> Result<&mut Self, NameError> {
+        if AUDIT_ARCH_NATIVE != Some(self.arch) {
+            return Err(NameError::ForeignArch(self.arch));
+        }
+        for name in names {
+            let nr = syscall_number(name).ok_or_else(|| NameError::Unknown(name.to_string()))?;
+            self.rule(nr, Action::Allow);
+        }
+        Ok(self)
+    }
+
+    /// the program
+    pub fn build(&self) -> Result<Vec<BPFFilter>, BuildError> {
+        let mut builder = ProgramBuilder::new();
+        check_arch(&mut builder, self.arch, Action::KillProcess);
+
+        let mut groups: Vec<(Action, Vec<u32>)> = Vec::new();
+        for (nr, action) in &self.rules {
+            match groups.iter_mut().find(|(a, _)| a == action) {
+                Some((_, nrs)) => nrs.push(*nr),
+                None if *action == self.default => {}
+                None => groups.push((*action, vec![*nr])),
+            }
+        }
+        // each group jumps to its own return, a bounded number of values
+        // keeps the jumps short
+        for (action, nrs) in groups {
+            for chunk in nrs.chunks(128) {
+                let (hit, miss) = (builder.label(), builder.label());
+                builder.jeq_any(chunk, hit, miss);
+                builder.bind(hit).push(ret(action));
+                builder.bind(miss);
+            }
+        }
+        builder.push(ret(self.default)).build()
+    }
+}
+
+/// why [`SyscallFilter::allow_names`] failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NameError {
+    /// a system call name [`syscall_number`] does not know
+    Unknown(String),
+    /// the filter is for the `arch` of another target, numbered differently
+    ForeignArch(u32),
+}
+
+impl fmt::Display for NameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NameError::Unknown(name) => write!(f, "unknown system call {}", name),
+            NameError::ForeignArch(arch) => {
+                write!(f, "system call names of arch {:#x} are not known", arch)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NameError {}
+
+/// the number of the system call `name` on the target
+///
+/// only the system calls every Linux architecture has are known, the others
+/// such as `open` or `mmap` are in `libc::SYS_*`.
+#[cfg(target_os = "linux")]
+pub fn syscall_number(name: &str) -> Option<u32> {
+    let nr = match name {
+        "accept4" => libc::SYS_accept4,
+        "acct" => libc::SYS_acct,
+        "add_key" => libc::SYS_add_key,
+        "bind" => libc::SYS_bind,
+        "bpf" => libc::SYS_bpf,
+        "brk" => libc::SYS_brk,
+        "capget" => libc::SYS_capget,
+        "capset" => libc::SYS_capset,
+        "chdir" => libc::SYS_chdir,
+        "chroot" => libc::SYS_chroot,
+        "clone" => libc::SYS_clone,
+        "clone3" => libc::SYS_clone3,
+        "close" => libc::SYS_close,
+        "close_range" => libc::SYS_close_range,
+        "connect" => libc::SYS_connect,
+        "copy_file_range" => libc::SYS_copy_file_range,
+        "delete_module" => libc::SYS_delete_module,
+        "dup" => libc::SYS_dup,
+        "dup3" => libc::SYS_dup3,
+        "epoll_create1" => libc::SYS_epoll_create1,
+        "epoll_ctl" => libc::SYS_epoll_ctl,
+        "epoll_pwait" => libc::SYS_epoll_pwait,
+        "epoll_pwait2" => libc::SYS_epoll_pwait2,
+        "eventfd2" => libc::SYS_eventfd2,
+        "execve" => libc::SYS_execve,
+        "execveat" => libc::SYS_execveat,
+        "exit" => libc::SYS_exit,
+        "exit_group" => libc::SYS_exit_group,
+        "faccessat" => libc::SYS_faccessat,
+        "faccessat2" => libc::SYS_faccessat2,
+        "fallocate" => libc::SYS_fallocate,
+        "fanotify_init" => libc::SYS_fanotify_init,
+        "fanotify_mark" => libc::SYS_fanotify_mark,
+        "fchdir" => libc::SYS_fchdir,
+        "fchmod" => libc::SYS_fchmod,
+        "fchmodat" => libc::SYS_fchmodat,
+        "fchown" => libc::SYS_fchown,
+        "fchownat" => libc::SYS_fchownat,
+        "fcntl" => libc::SYS_fcntl,
+        "fdatasync" => libc::SYS_fdatasync,
+        "fgetxattr" => libc::SYS_fgetxattr,
+        "finit_module" => libc::SYS_finit_module,
+        "flistxattr" => libc::SYS_flistxattr,
+        "flock" => libc::SYS_flock,
+        "fremovexattr" => libc::SYS_fremovexattr,
+        "fsconfig" => libc::SYS_fsconfig,
+        "fsetxattr" => libc::SYS_fsetxattr,
+        "fsmount" => libc::SYS_fsmount,
+        "fsopen" => libc::SYS_fsopen,
+        "fspick" => libc::SYS_fspick,
+        "fstatfs" => libc::SYS_fstatfs,
+        "fsync" => libc::SYS_fsync,
+        "ftruncate" => libc::SYS_ftruncate,
+        "get_mempolicy" => libc::SYS_get_mempolicy,
+        "get_robust_list" => libc::SYS_get_robust_list,
+        "getcpu" => libc::SYS_getcpu,
+        "getcwd" => libc::SYS_getcwd,
+        "getdents64" => libc::SYS_getdents64,
+        "getegid" => libc::SYS_getegid,
+        "geteuid" => libc::SYS_geteuid,
+        "getgid" => libc::SYS_getgid,
+        "getgroups" => libc::SYS_getgroups,
+        "getitimer" => libc::SYS_getitimer,
+        "getpeername" => libc::SYS_getpeername,
+        "getpgid" => libc::SYS_getpgid,
+        "getpid" => libc::SYS_getpid,
+        "getppid" => libc::SYS_getppid,
+        "getpriority" => libc::SYS_getpriority,
+        "getrandom" => libc::SYS_getrandom,
+        "getrusage" => libc::SYS_getrusage,
+        "getsid" => libc::SYS_getsid,
+        "getsockname" => libc::SYS_getsockname,
+        "getsockopt" => libc::SYS_getsockopt,
+        "gettid" => libc::SYS_gettid,
+        "getuid" => libc::SYS_getuid,
+        "getxattr" => libc::SYS_getxattr,
+        "init_module" => libc::SYS_init_module,
+        "inotify_add_watch" => libc::SYS_inotify_add_watch,
+        "inotify_init1" => libc::SYS_inotify_init1,
+        "inotify_rm_watch" => libc::SYS_inotify_rm_watch,
+        "io_cancel" => libc::SYS_io_cancel,
+        "io_destroy" => libc::SYS_io_destroy,
+        "io_setup" => libc::SYS_io_setup,
+        "io_submit" => libc::SYS_io_submit,
+        "io_uring_enter" => libc::SYS_io_uring_enter,
+        "io_uring_register" => libc::SYS_io_uring_register,
+        "io_uring_setup" => libc::SYS_io_uring_setup,
+        "ioctl" => libc::SYS_ioctl,
+        "ioprio_get" => libc::SYS_ioprio_get,
+        "ioprio_set" => libc::SYS_ioprio_set,
+        "kcmp" => libc::SYS_kcmp,
+        "kexec_load" => libc::SYS_kexec_load,
+        "keyctl" => libc::SYS_keyctl,
+        "kill" => libc::SYS_kill,
+        "lgetxattr" => libc::SYS_lgetxattr,
+        "linkat" => libc::SYS_linkat,
+        "listen" => libc::SYS_listen,
+        "listxattr" => libc::SYS_listxattr,
+        "llistxattr" => libc::SYS_llistxattr,
+        "lookup_dcookie" => libc::SYS_lookup_dcookie,
+        "lremovexattr" => libc::SYS_lremovexattr,
+        "lseek" => libc::SYS_lseek,
+        "lsetxattr" => libc::SYS_lsetxattr,
+        "madvise" => libc::SYS_madvise,
+        "mbind" => libc::SYS_mbind,
+        "membarrier" => libc::SYS_membarrier,
+        "memfd_create" => libc::SYS_memfd_create,
+        "mincore" => libc::SYS_mincore,
+        "mkdirat" => libc::SYS_mkdirat,
+        "mknodat" => libc::SYS_mknodat,
+        "mlock" => libc::SYS_mlock,
+        "mlock2" => libc::SYS_mlock2,
+        "mlockall" => libc::SYS_mlockall,
+        "mount" => libc::SYS_mount,
+        "mount_setattr" => libc::SYS_mount_setattr,
+        "move_mount" => libc::SYS_move_mount,
+        "move_pages" => libc::SYS_move_pages,
+        "mprotect" => libc::SYS_mprotect,
+        "mq_getsetattr" => libc::SYS_mq_getsetattr,
+        "mq_notify" => libc::SYS_mq_notify,
+        "mq_open" => libc::SYS_mq_open,
+        "mq_unlink" => libc::SYS_mq_unlink,
+        "mremap" => libc::SYS_mremap,
+        "msync" => libc::SYS_msync,
+        "munlock" => libc::SYS_munlock,
+        "munlockall" => libc::SYS_munlockall,
+        "munmap" => libc::SYS_munmap,
+        "name_to_handle_at" => libc::SYS_name_to_handle_at,
+        "open_by_handle_at" => libc::SYS_open_by_handle_at,
+        "open_tree" => libc::SYS_open_tree,
+        "openat" => libc::SYS_openat,
+        "openat2" => libc::SYS_openat2,
+        "perf_event_open" => libc::SYS_perf_event_open,
+        "personality" => libc::SYS_personality,
+        "pidfd_getfd" => libc::SYS_pidfd_getfd,
+        "pidfd_open" => libc::SYS_pidfd_open,
+        "pidfd_send_signal" => libc::SYS_pidfd_send_signal,
+        "pipe2" => libc::SYS_pipe2,
+        "pivot_root" => libc::SYS_pivot_root,
+        "prctl" => libc::SYS_prctl,
+        "preadv" => libc::SYS_preadv,
+        "preadv2" => libc::SYS_preadv2,
+        "prlimit64" => libc::SYS_prlimit64,
+        "process_madvise" => libc::SYS_process_madvise,
+        "process_vm_readv" => libc::SYS_process_vm_readv,
+        "process_vm_writev" => libc::SYS_process_vm_writev,
+        "ptrace" => libc::SYS_ptrace,
+        "pwritev" => libc::SYS_pwritev,
+        "pwritev2" => libc::SYS_pwritev2,
+        "quotactl" => libc::SYS_quotactl,
+        "read" => libc::SYS_read,
+        "readahead" => libc::SYS_readahead,
+        "readlinkat" => libc::SYS_readlinkat,
+        "readv" => libc::SYS_readv,
+        "reboot" => libc::SYS_reboot,
+        "recvfrom" => libc::SYS_recvfrom,
+        "recvmsg" => libc::SYS_recvmsg,
+        "remap_file_pages" => libc::SYS_remap_file_pages,
+        "removexattr" => libc::SYS_removexattr,
+        "renameat2" => libc::SYS_renameat2,
+        "request_key" => libc::SYS_request_key,
+        "restart_syscall" => libc::SYS_restart_syscall,
+        "rt_sigaction" => libc::SYS_rt_sigaction,
+        "rt_sigpending" => libc::SYS_rt_sigpending,
+        "rt_sigprocmask" => libc::SYS_rt_sigprocmask,
+        "rt_sigqueueinfo" => libc::SYS_rt_sigqueueinfo,
+        "rt_sigreturn" => libc::SYS_rt_sigreturn,
+        "rt_sigsuspend" => libc::SYS_rt_sigsuspend,
+        "rt_tgsigqueueinfo" => libc::SYS_rt_tgsigqueueinfo,
+        "sched_get_priority_max" => libc::SYS_sched_get_priority_max,
+        "sched_get_priority_min" => libc::SYS_sched_get_priority_min,
+        "sched_getaffinity" => libc::SYS_sched_getaffinity,
+        "sched_getattr" => libc::SYS_sched_getattr,
+        "sched_getparam" => libc::SYS_sched_getparam,
+        "sched_getscheduler" => libc::SYS_sched_getscheduler,
+        "sched_setaffinity" => libc::SYS_sched_setaffinity,
+        "sched_setattr" => libc::SYS_sched_setattr,
+        "sched_setparam" => libc::SYS_sched_setparam,
+        "sched_setscheduler" => libc::SYS_sched_setscheduler,
+        "sched_yield" => libc::SYS_sched_yield,
+        "seccomp" => libc::SYS_seccomp,
+        "sendfile" => libc::SYS_sendfile,
+        "sendmmsg" => libc::SYS_sendmmsg,
+        "sendmsg" => libc::SYS_sendmsg,
+        "sendto" => libc::SYS_sendto,
+        "set_mempolicy" => libc::SYS_set_mempolicy,
+        "set_robust_list" => libc::SYS_set_robust_list,
+        "set_tid_address" => libc::SYS_set_tid_address,
+        "setdomainname" => libc::SYS_setdomainname,
+        "setfsgid" => libc::SYS_setfsgid,
+        "setfsuid" => libc::SYS_setfsuid,
+        "setgid" => libc::SYS_setgid,
+        "setgroups" => libc::SYS_setgroups,
+        "sethostname" => libc::SYS_sethostname,
+        "setitimer" => libc::SYS_setitimer,
+        "setns" => libc::SYS_setns,
+        "setpgid" => libc::SYS_setpgid,
+        "setpriority" => libc::SYS_setpriority,
+        "setregid" => libc::SYS_setregid,
+        "setreuid" => libc::SYS_setreuid,
+        "setsid" => libc::SYS_setsid,
+        "setsockopt" => libc::SYS_setsockopt,
+        "setuid" => libc::SYS_setuid,
+        "setxattr" => libc::SYS_setxattr,
+        "shutdown" => libc::SYS_shutdown,
+        "sigaltstack" => libc::SYS_sigaltstack,
+        "signalfd4" => libc::SYS_signalfd4,
+        "socket" => libc::SYS_socket,
+        "socketpair" => libc::SYS_socketpair,
+        "splice" => libc::SYS_splice,
+        "statfs" => libc::SYS_statfs,
+        "statx" => libc::SYS_statx,
+        "swapoff" => libc::SYS_swapoff,
+        "swapon" => libc::SYS_swapon,
+        "symlinkat" => libc::SYS_symlinkat,
+        "sync" => libc::SYS_sync,
+        "syncfs" => libc::SYS_syncfs,
+        "sysinfo" => libc::SYS_sysinfo,
+        "syslog" => libc::SYS_syslog,
+        "tee" => libc::SYS_tee,
+        "tgkill" => libc::SYS_tgkill,
+        "timer_create" => libc::SYS_timer_create,
+        "timer_delete" => libc::SYS_timer_delete,
+        "timer_getoverrun" => libc::SYS_timer_getoverrun,
+        "timerfd_create" => libc::SYS_timerfd_create,
+        "times" => libc::SYS_times,
+        "tkill" => libc::SYS_tkill,
+        "truncate" => libc::SYS_truncate,
+        "umask" => libc::SYS_umask,
+        "unlinkat" => libc::SYS_unlinkat,
+        "unshare" => libc::SYS_unshare,
+        "userfaultfd" => libc::SYS_userfaultfd,
+        "vhangup" => libc::SYS_vhangup,
+        "vmsplice" => libc::SYS_vmsplice,
+        "waitid" => libc::SYS_waitid,
+        "write" => libc::SYS_write,
+        "writev" => libc::SYS_writev,
+        _ => return None,
+    };
+    Some(nr as u32)
+}
+
+#[test]
+fn test_seccomp() {
+    let data = SeccompData {
+        nr: 59,
+        arch: 0xc000_003e,
+        instruction_pointer: 0x1234_5678_9abc_def0,
+        args: [1, 2, 3, 4, 5, u64::MAX],
+    };
+    assert_eq!(data.word(Word::Arch.offset()), Some(0xc000_003e));
+    let ip = |half| data.word(Word::InstructionPointer(half).offset());
+    assert_eq!(ip(Half::Low), Some(0x9abc_def0));
+    assert_eq!(ip(Half::High), Some(0x1234_5678));
+    assert_eq!(data.word(Word::Arg(5, Half::High).offset()), Some(u32::MAX));
+    assert_eq!(data.word(Word::Arg(2, Half::Low).offset()), Some(3));
+    assert_eq!(data.word(2), None);
+    assert_eq!(data.word(64), None);
+
+    for action in [
+        Action::KillProcess,
+        Action::KillThread,
+        Action::Trap(3),
+        Action::Errno(13),
+        Action::UserNotif,
+        Action::Trace(7),
+        Action::Log,
+        Action::Allow,
+    ] {
+        assert_eq!(Action::from_value(action.value()), Some(action));
+    }
+    assert_eq!(Action::from_value(0x0001_0000), None);
+
+    let filters = [load_arch(), BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0)];
+    assert_eq!(run(&filters, &data), 0xc000_003e);
+    // the byte loads and the loads past seccomp_data kill
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 0),
+        ret(Action::Allow),
+    ];
+    assert_eq!(run(&filters, &data), SECCOMP_RET_KILL_PROCESS);
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, 64),
+        ret(Action::Allow),
+    ];
+    assert_eq!(run(&filters, &data), SECCOMP_RET_KILL_PROCESS);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_install() {
+    // the filter stays with the thread installing it
+    std::thread::spawn(|| {
+        let error = install(&[], 0).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EINVAL));
+
+        let filters = [
+            load_nr(),
+            BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, libc::SYS_getppid as u32, 0, 1),
+            ret(Action::Errno(libc::EPERM as u16)),
+            ret(Action::Allow),
+        ];
+        assert!(unsafe { libc::syscall(libc::SYS_getppid) } > 0);
+        install(&filters, 0).unwrap();
+        assert_eq!(unsafe { libc::syscall(libc::SYS_getppid) }, -1);
+        assert_eq!(
+            std::io::Error::last_os_error().raw_os_error(),
+            Some(libc::EPERM)
+        );
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_syscall_filter() {
+    let arch = AUDIT_ARCH_X86_64;
+    let filters = SyscallFilter::new(arch, Action::Errno(38))
+        .allow(&[0, 1, 2, 3, 60, 231])
+        .deny(&[59, 322], Action::KillProcess)
+        .rule(60, Action::Log)
+        .rule(100, Action::Errno(38))
+        .build()
+        .unwrap();
+    // 0 to 3 are tested as a range, 100 has the default action
+    assert_eq!(filters.len(), 6 + 4 + 3 + 2 + 1);
+
+    let mut data = SeccompData {
+        arch,
+        ..Default::default()
+    };
+    for (nr, action) in [
+        (0, Action::Allow),
+        (1, Action::Allow),
+        (2, Action::Allow),
+        (3, Action::Allow),
+        (4, Action::Errno(38)),
+        (59, Action::KillProcess),
+        (60, Action::Log),
+        (100, Action::Errno(38)),
+        (231, Action::Allow),
+        (322, Action::KillProcess),
+    ] {
+        data.nr = nr;
+        assert_eq!(run(&filters, &data), action.value(), "system call {}", nr);
+    }
+    data.arch = AUDIT_ARCH_I386;
+    data.nr = 0;
+    assert_eq!(run(&filters, &data), SECCOMP_RET_KILL_PROCESS);
+
+    // more system calls than a single chain of jumps can reach
+    let nrs: Vec<u32> = (0..400).map(|nr| nr * 2).collect();
+    let filters = SyscallFilter::new(arch, Action::KillProcess)
+        .allow(&nrs)
+        .build()
+        .unwrap();
+    data.arch = arch;
+    data.nr = 798;
+    assert_eq!(run(&filters, &data), SECCOMP_RET_ALLOW);
+    data.nr = 799;
+    assert_eq!(run(&filters, &data), SECCOMP_RET_KILL_PROCESS);
+
+    #[cfg(target_os = "linux")]
+    {
+        assert_eq!(syscall_number("getpid"), Some(libc::SYS_getpid as u32));
+        if let Some(mut filter) = SyscallFilter::native(Action::Allow) {
+            let error = filter.allow_names(&["read", "frobnicate"]).unwrap_err();
+            assert_eq!(error, NameError::Unknown("frobnicate".to_string()));
+        }
+        // the numbers of the target would be wrong for s390x
+        let error = SyscallFilter::new(AUDIT_ARCH_S390X, Action::Allow)
+            .allow_names(&["read"])
+            .unwrap_err();
+        assert_eq!(error, NameError::ForeignArch(AUDIT_ARCH_S390X));
+    }
+}
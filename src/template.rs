@@ -0,0 +1,232 @@
+use crate::bpf_base::BPFFilter;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// a placeholder a [`Template`] cannot fill as asked
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// the template has no placeholder of this name
+    UnknownPlaceholder(String),
+    /// no value was given for this placeholder
+    MissingValue(String),
+    /// the instruction at `index` is out of the program or has no constant operand
+    NotAConstant { index: usize },
+    /// no instruction of the program has the marker `k`
+    MarkerNotFound(u32),
+    /// `value` is not a valid operand of the instruction at `index`,
+    /// such as a constant divisor of 0
+    InvalidValue { index: usize, value: u32 },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::UnknownPlaceholder(name) => write!(f, "no placeholder {:?}", name),
+            TemplateError::MissingValue(name) => write!(f, "no value for {:?}", name),
+            TemplateError::NotAConstant { index } => {
+                write!(f, "instruction {} has no constant operand", index)
+            }
+            TemplateError::MarkerNotFound(k) => write!(f, "no instruction has k = {:#x}", k),
+            TemplateError::InvalidValue { index, value } => {
+                write!(f, "instruction {} cannot take {:#x}", index, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// whether the `k` of `code` is a constant operand
+fn has_constant(code: u16) -> bool {
+    match code & 0x07 {
+        // ld #k, ld [k], ld [x + k]
+        0x00 => matches!(code & 0xe0, 0x00 | 0x20 | 0x40),
+        // ldx #k, ldxb 4*([k]&0xf)
+        0x01 => matches!(code & 0xe0, 0x00 | 0xa0),
+        // not neg, nor the X operands
+        0x04 => code & 0x08 == 0 && code & 0xf0 != 0x80,
+        // not ja, whose k is an offset
+        0x05 => code & 0x08 == 0 && code & 0xf0 != 0x00,
+        0x06 => code & 0x18 == 0x00,
+        _ => false,
+    }
+}
+
+/// a program whose constants are named placeholders, instantiated with
+/// their values at runtime
+///
+/// the shape of the program is built and checked once, each instantiation
+/// then only patches the `k` of the marked instructions.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // udp to a port given per connection, 0xdead marks it
+/// let filters = compile("udp dst port 0xdead", Dlt::En10mb).unwrap();
+/// let mut template = Template::new(filters.into_filters());
+/// template.mark_value("port", 0xdead).unwrap();
+///
+/// let dns = template.instantiate(&[("port", 53)]).unwrap();
+/// let ntp = template.instantiate(&[("port", 123)]).unwrap();
+/// assert_eq!(dns.len(), ntp.len());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    filters: Vec<BPFFilter>,
+    placeholders: BTreeMap<String, Vec<usize>>,
+}
+
+impl Template {
+    /// a template of `filters`, without placeholders yet
+    pub fn new(filters: Vec<BPFFilter>) -> Self {
+        Self {
+            filters,
+            placeholders: BTreeMap::new(),
+        }
+    }
+
+    /// make the `k` of the instruction at `index` part of the placeholder `name`
+    pub fn mark(&mut self, name: &str, index: usize) -> Result<&mut Self, TemplateError> {
+        match self.filters.get(index) {
+            Some(insn) if has_constant(insn.code) => {}
+            _ => return Err(TemplateError::NotAConstant { index }),
+        }
+        let indices = self.placeholders.entry(name.to_owned()).or_default();
+        if !indices.contains(&index) {
+            indices.push(index);
+        }
+        Ok(self)
+    }
+
+    /// make every constant operand equal to `marker` part of the placeholder `name`
+    ///
+    /// the program is built with an unlikely value in place of the
+    /// parameter, the marker, then the template finds it back.
+    pub fn mark_value(&mut self, name: &str, marker: u32) -> Result<&mut Self, TemplateError> {
+        let indices: Vec<usize> = (0..self.filters.len())
+            .filter(|i| self.filters[*i].k == marker && has_constant(self.filters[*i].code))
+            .collect();
+        if indices.is_empty() {
+            return Err(TemplateError::MarkerNotFound(marker));
+        }
+        for index in indices {
+            self.mark(name, index)?;
+        }
+        Ok(self)
+    }
+
+    /// the names of the placeholders
+    pub fn placeholders(&self) -> impl Iterator<Item = &str> {
+        self.placeholders.keys().map(String::as_str)
+    }
+
+    /// the program with every placeholder replaced by its value in `values`
+    pub fn instantiate(&self, values: &[(&str, u32)]) -> Result<Vec<BPFFilter>, TemplateError> {
+        if let Some((name, _)) = values
+            .iter()
+            .find(|(name, _)| !self.placeholders.contains_key(*name))
+        {
+            return Err(TemplateError::UnknownPlaceholder((*name).to_owned()));
+        }
+        let mut filters = self.filters.clone();
+        for name in self.placeholders.keys() {
+            match values.iter().rev().find(|(n, _)| n == name) {
+                Some((name, value)) => self.set(&mut filters, name, *value)?,
+                None => return Err(TemplateError::MissingValue(name.clone())),
+            }
+        }
+        Ok(filters)
+    }
+
+    /// replace the placeholder `name` by `value` in `filters`, an
+    /// instantiation of the template
+    pub fn set(
+        &self,
+        filters: &mut [BPFFilter],
+        name: &str,
+        value: u32,
+    ) -> Result<(), TemplateError> {
+        let indices = self
+            .placeholders
+            .get(name)
+            .ok_or_else(|| TemplateError::UnknownPlaceholder(name.to_owned()))?;
+        for index in indices {
+            let code = self.filters[*index].code;
+            let invalid = match code & 0xf7 {
+                // div and mod
+                0x34 | 0x94 => value == 0,
+                // lsh and rsh
+                0x64 | 0x74 => value >= 32,
+                _ => false,
+            };
+            if invalid {
+                return Err(TemplateError::InvalidValue {
+                    index: *index,
+                    value,
+                });
+            }
+        }
+        for index in indices {
+            filters
+                .get_mut(*index)
+                .ok_or(TemplateError::NotAConstant { index: *index })?
+                .k = value;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_template() {
+    use crate::bpf_base::bpf;
+    use crate::interpreter::run;
+    let filters = vec![
+        BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 0),
+        BPFFilter::bpf_stmt(bpf::ALU | bpf::DIV | bpf::K, 1),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x4242, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0x4242),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    ];
+    let mut template = Template::new(filters);
+    template
+        .mark_value("value", 0x4242)
+        .unwrap()
+        .mark("divisor", 1)
+        .unwrap();
+    assert_eq!(
+        template.placeholders().collect::<Vec<_>>(),
+        ["divisor", "value"]
+    );
+
+    let mut filters = template
+        .instantiate(&[("value", 3), ("divisor", 2)])
+        .unwrap();
+    assert_eq!(run(&filters, &[6], 1), 3);
+    assert_eq!(run(&filters, &[7], 1), 3);
+    assert_eq!(run(&filters, &[8], 1), 0);
+    template.set(&mut filters, "divisor", 3).unwrap();
+    assert_eq!(run(&filters, &[9], 1), 3);
+
+    assert_eq!(
+        template.instantiate(&[("value", 3)]),
+        Err(TemplateError::MissingValue("divisor".into()))
+    );
+    assert_eq!(
+        template.instantiate(&[("value", 3), ("divisor", 0)]),
+        Err(TemplateError::InvalidValue { index: 1, value: 0 })
+    );
+    assert_eq!(
+        template.instantiate(&[("port", 3)]),
+        Err(TemplateError::UnknownPlaceholder("port".into()))
+    );
+    assert_eq!(
+        template.mark("x", 5).unwrap_err(),
+        TemplateError::NotAConstant { index: 5 }
+    );
+    assert_eq!(
+        template.mark_value("x", 7).unwrap_err(),
+        TemplateError::MarkerNotFound(7)
+    );
+}
@@ -0,0 +1,15 @@
+/// clock used to stamp captured frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsSource {
+    /// stamped by the kernel when the packet is received
+    Kernel,
+    /// stamped by the NIC, which must have hardware stamping enabled (SIOCSHWTSTAMP)
+    Hardware,
+}
+
+/// resolution of the frame timestamps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TsPrecision {
+    Micro,
+    Nano,
+}
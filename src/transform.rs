@@ -0,0 +1,747 @@
+//! rewriting of programs
+//!
+//! programs are decoded into instructions with absolute jump targets,
+//! edited, then assembled back with the relative offsets recomputed.
+
+use crate::analysis::AnalysisError;
+use crate::bpf_base::{bpf, BPFFilter, BPFProgram};
+use crate::validate::{validate, ValidationError};
+use std::convert::TryFrom;
+
+/// an instruction whose jump targets are absolute indices
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Node {
+    pub(crate) insn: BPFFilter,
+    /// (taken, not taken) for conditional jumps, (target, target) for JA
+    pub(crate) jump: Option<(usize, usize)>,
+}
+
+impl Node {
+    pub(crate) fn new(insn: BPFFilter) -> Self {
+        Self { insn, jump: None }
+    }
+
+    fn is_ja(&self) -> bool {
+        self.insn.code == 0x05
+    }
+
+    fn is_ret(&self) -> bool {
+        self.insn.code & 0x07 == 0x06
+    }
+
+    /// the indices execution can continue at
+    pub(crate) fn successors(&self, index: usize) -> Vec<usize> {
+        match self.jump {
+            Some((jt, jf)) if jt == jf => vec![jt],
+            Some((jt, jf)) => vec![jt, jf],
+            None if self.is_ret() => vec![],
+            None => vec![index + 1],
+        }
+    }
+}
+
+/// resolve the relative jumps of `filters`, checking they stay in the program
+pub(crate) fn decode(filters: &[BPFFilter]) -> Result<Vec<Node>, AnalysisError> {
+    let len = filters.len();
+    filters
+        .iter()
+        .enumerate()
+        .map(|(i, insn)| {
+            let mut node = Node::new(*insn);
+            if insn.code & 0x07 == 0x05 {
+                let (jt, jf) = if node.is_ja() {
+                    let target = i + 1 + insn.k as usize;
+                    (target, target)
+                } else {
+                    (i + 1 + insn.jt as usize, i + 1 + insn.jf as usize)
+                };
+                if jt >= len || jf >= len {
+                    return Err(AnalysisError::OutOfBounds(i));
+                }
+                node.jump = Some((jt, jf));
+            } else if !node.is_ret() && i + 1 >= len {
+                return Err(AnalysisError::OutOfBounds(i));
+            }
+            Ok(node)
+        })
+        .collect()
+}
+
+/// import a program produced elsewhere, checking it is well formed
+///
+/// `raw` holds the instructions of another library, such as the
+/// `libc::sock_filter` of a seccomp or socket filter on Linux, or raw
+/// `(code, jt, jf, k)` tuples. the program is checked with [`validate`],
+/// as the kernel would before attaching it.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // the output of `tcpdump -dd arp`
+/// let raw: [(u16, u8, u8, u32); 4] = [
+///     (0x28, 0, 0, 0x0000000c),
+///     (0x15, 0, 1, 0x00000806),
+///     (0x6, 0, 0, 0x00040000),
+///     (0x6, 0, 0, 0x00000000),
+/// ];
+/// let program = import_program(&raw).unwrap();
+/// assert_eq!(program[0].to_string(), "ldh [12]");
+/// ```
+pub fn import_program<T>(raw: &[T]) -> Result<BPFProgram, ValidationError>
+where
+    T: Copy + Into<BPFFilter>,
+{
+    let program: BPFProgram = raw.iter().map(|insn| (*insn).into()).collect();
+    validate(&program)?;
+    Ok(program)
+}
+
+impl TryFrom<&[(u16, u8, u8, u32)]> for BPFProgram {
+    type Error = ValidationError;
+
+    /// the program of raw `(code, jt, jf, k)` tuples, see [`import_program`]
+    fn try_from(raw: &[(u16, u8, u8, u32)]) -> Result<Self, ValidationError> {
+        import_program(raw)
+    }
+}
+
+/// turn the absolute jump targets of `nodes` back into relative offsets
+pub(crate) fn encode(nodes: &[Node]) -> Result<Vec<BPFFilter>, AnalysisError> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let mut insn = node.insn;
+            if let Some((jt, jf)) = node.jump {
+                if jt <= i || jf <= i || jt >= nodes.len() || jf >= nodes.len() {
+                    return Err(AnalysisError::OutOfBounds(i));
+                }
+                if node.is_ja() {
+                    insn.k = (jt - i - 1) as u32;
+                } else {
+                    if jt - i - 1 > 0xff || jf - i - 1 > 0xff {
+                        return Err(AnalysisError::JumpTooFar(i));
+                    }
+                    insn.jt = (jt - i - 1) as u8;
+                    insn.jf = (jf - i - 1) as u8;
+                }
+            }
+            Ok(insn)
+        })
+        .collect()
+}
+
+impl BPFProgram {
+    /// extract the instructions of the program lying on a path to `ret #value`
+    ///
+    /// the result returns `value` for exactly the packets the original program
+    /// returns `value` for, and 0 for every other packet. only `ret #k`
+    /// instructions count as returning `value`, not `ret a`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// // classify IPv4 as 1 and IPv6 as 2
+    /// let mut builder = ProgramBuilder::new();
+    /// builder
+    ///     .ld_abs_h(12)
+    ///     .jmp(bpf::JEQ, bpf::K, 0x0800, 0, 1)
+    ///     .ret_k(1)
+    ///     .jmp(bpf::JEQ, bpf::K, 0x86dd, 0, 1)
+    ///     .ret_k(2)
+    ///     .ret_k(0);
+    /// let filters = builder.build().unwrap();
+    ///
+    /// let ipv6 = BPFProgram::from(filters).slice_to_ret(2).unwrap();
+    /// assert_eq!(ipv6.len(), 5);
+    /// ```
+    pub fn slice_to_ret(&self, value: u32) -> Result<BPFProgram, AnalysisError> {
+        let nodes = decode(self)?;
+        let len = nodes.len();
+
+        let mut reachable = vec![false; len];
+        if len > 0 {
+            reachable[0] = true;
+        }
+        for i in 0..len {
+            if reachable[i] {
+                for next in nodes[i].successors(i) {
+                    reachable[next] = true;
+                }
+            }
+        }
+
+        // jumps only go forward, so one backward sweep finds every instruction
+        // with a path to the wanted return
+        let mut reaches = vec![false; len];
+        for i in (0..len).rev() {
+            reaches[i] = if nodes[i].is_ret() {
+                nodes[i].insn.code == 0x06 && nodes[i].insn.k == value
+            } else {
+                nodes[i].successors(i).iter().any(|next| reaches[*next])
+            };
+        }
+
+        let kept: Vec<usize> = (0..len).filter(|i| reachable[*i] && reaches[*i]).collect();
+        let fallback = kept.len();
+        let mut renumbered = vec![fallback; len];
+        for (new, old) in kept.iter().enumerate() {
+            renumbered[*old] = new;
+        }
+
+        let mut sliced: Vec<Node> = kept
+            .iter()
+            .map(|old| {
+                let mut node = nodes[*old];
+                node.jump = node.jump.map(|(jt, jf)| (renumbered[jt], renumbered[jf]));
+                node
+            })
+            .collect();
+        if sliced.is_empty()
+            || sliced.iter().any(|n| {
+                n.jump
+                    .is_some_and(|(jt, jf)| jt == fallback || jf == fallback)
+            })
+        {
+            sliced.push(Node::new(BPFFilter {
+                code: 0x06,
+                jt: 0,
+                jf: 0,
+                k: 0,
+            }));
+        }
+        encode(&sliced).map(BPFProgram::from)
+    }
+}
+
+fn stmt(code: u16, k: u32) -> BPFFilter {
+    BPFFilter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+/// point a jump to the next filter at `target`
+fn patch(node: &mut Node, target: usize) {
+    node.jump = match node.jump {
+        // the taken branch of the `ret a` test
+        Some((_, jf)) if !node.is_ja() => Some((target, jf)),
+        _ => Some((target, target)),
+    };
+}
+
+/// chain several filters into one program returning a distinct code for each
+///
+/// the filters are tried in order: the first one accepting a packet decides,
+/// as the merged program returns its code. packets no filter accepts are
+/// dropped. each filter after the first starts with A and X cleared, as it
+/// would when attached on its own. codes should not be 0, which means "no match".
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let mut ipv4 = ProgramBuilder::new();
+/// ipv4.ld_abs_h(12).jmp(bpf::JEQ, bpf::K, 0x0800, 0, 1).ret_k(u32::MAX).ret_k(0);
+/// let mut ipv6 = ProgramBuilder::new();
+/// ipv6.ld_abs_h(12).jmp(bpf::JEQ, bpf::K, 0x86dd, 0, 1).ret_k(u32::MAX).ret_k(0);
+///
+/// let merged = merge_classifier(&[
+///     (&ipv4.build().unwrap(), 1),
+///     (&ipv6.build().unwrap(), 2),
+/// ])
+/// .unwrap();
+/// ```
+pub fn merge_classifier(entries: &[(&[BPFFilter], u32)]) -> Result<Vec<BPFFilter>, AnalysisError> {
+    let mut merged: Vec<Node> = Vec::new();
+    // the jumps to the start of the next filter, patched once it is known
+    let mut pending: Vec<usize> = Vec::new();
+    for (n, (filters, code)) in entries.iter().enumerate() {
+        let nodes = decode(filters)?;
+        let start = merged.len();
+        for i in pending.drain(..) {
+            patch(&mut merged[i], start);
+        }
+        let prologue = if n == 0 { 0 } else { 2 };
+        // the index of each old instruction in the merged program,
+        // `ret a` expanding into a test and a return
+        let mut at = Vec::with_capacity(nodes.len());
+        let mut next = start + prologue;
+        for node in &nodes {
+            at.push(next);
+            next += if node.insn.code == 0x16 { 2 } else { 1 };
+        }
+        if n > 0 {
+            merged.push(Node::new(stmt(0x00, 0))); // ld #0
+            merged.push(Node::new(stmt(0x07, 0))); // tax
+        }
+        for (i, node) in nodes.iter().enumerate() {
+            match node.insn.code {
+                // ret #0, on to the next filter
+                0x06 if node.insn.k == 0 => {
+                    pending.push(merged.len());
+                    merged.push(Node::new(stmt(0x05, 0)));
+                }
+                0x06 => merged.push(Node::new(stmt(0x06, *code))),
+                // ret a: jeq #0, next filter, ret #code
+                0x16 => {
+                    pending.push(merged.len());
+                    merged.push(Node {
+                        insn: stmt(0x15, 0),
+                        jump: Some((0, merged.len() + 1)),
+                    });
+                    merged.push(Node::new(stmt(0x06, *code)));
+                }
+                c if c & 0x07 == 0x06 => return Err(AnalysisError::InvalidInstruction(i)),
+                _ => merged.push(Node {
+                    insn: node.insn,
+                    jump: node.jump.map(|(jt, jf)| (at[jt], at[jf])),
+                }),
+            }
+        }
+    }
+    let end = merged.len();
+    for i in pending {
+        patch(&mut merged[i], end);
+    }
+    merged.push(Node::new(stmt(0x06, 0)));
+    encode(&merged)
+}
+
+/// append fragments of programs into one, fixing up their jumps
+///
+/// a jump to the end of a fragment, or falling past it, goes on with the
+/// next fragment. the last fragment holds the exits shared by the others: a
+/// jump `n` instructions past the end of an earlier fragment lands on the
+/// instruction `n - 1` of the last one. the offsets come out relative to
+/// the concatenation, `AnalysisError::JumpTooFar` reports a conditional jump
+/// no longer fitting in 8 bits.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let ipv4 = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     // on to the next fragment, or to the second exit
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 2),
+/// ];
+/// let udp = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 23),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 17, 1, 2),
+/// ];
+/// let exits = [
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+/// let filters = concat(&[&ipv4, &udp, &exits]).unwrap();
+/// assert_eq!(filters.len(), 6);
+///
+/// let mut packet = [0u8; 34];
+/// packet[12..14].copy_from_slice(&[0x08, 0x00]);
+/// packet[23] = 17;
+/// assert_eq!(run(&filters, &packet, 34), u32::MAX);
+/// ```
+pub fn concat(fragments: &[&[BPFFilter]]) -> Result<Vec<BPFFilter>, AnalysisError> {
+    let total: usize = fragments.iter().map(|f| f.len()).sum();
+    if total == 0 {
+        return Err(AnalysisError::Empty);
+    }
+    if total > bpf::MAXINSNS {
+        return Err(AnalysisError::TooLong(total));
+    }
+    let exits = total - fragments.last().map_or(0, |last| last.len());
+    let mut nodes = Vec::with_capacity(total);
+    let mut start = 0;
+    for (n, fragment) in fragments.iter().enumerate() {
+        let end = start + fragment.len();
+        let last = n + 1 == fragments.len();
+        // the index of the instruction `offset` past `at`
+        let target = |at: usize, offset: usize| {
+            let target = at + 1 + offset;
+            match target.checked_sub(end) {
+                Some(past) if past > 0 && !last => exits + past - 1,
+                _ => target,
+            }
+        };
+        for (i, insn) in fragment.iter().enumerate() {
+            let at = start + i;
+            let mut node = Node::new(*insn);
+            if insn.code & 0x07 == 0x05 {
+                node.jump = Some(if node.is_ja() {
+                    let target = target(at, insn.k as usize);
+                    (target, target)
+                } else {
+                    (target(at, insn.jt as usize), target(at, insn.jf as usize))
+                });
+            } else if !node.is_ret() && at + 1 >= total {
+                return Err(AnalysisError::OutOfBounds(at));
+            }
+            nodes.push(node);
+        }
+        start = end;
+    }
+    encode(&nodes)
+}
+
+/// the returns of a program handing the packet over to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handover {
+    Accepted,
+    Rejected,
+    Never,
+}
+
+/// append `filters` to `merged`, its returns matching `handover` becoming
+/// jumps to the next program, recorded in `pending`
+fn append(
+    merged: &mut Vec<Node>,
+    pending: &mut Vec<usize>,
+    filters: &[BPFFilter],
+    handover: Handover,
+) -> Result<(), AnalysisError> {
+    let nodes = decode(filters)?;
+    let start = merged.len();
+    for i in pending.drain(..) {
+        patch(&mut merged[i], start);
+    }
+    let prologue = if start == 0 { 0 } else { 2 };
+    // `ret a` expands into a test and a return when it may hand over
+    let mut at = Vec::with_capacity(nodes.len());
+    let mut next = start + prologue;
+    for node in &nodes {
+        at.push(next);
+        next += if node.insn.code == 0x16 && handover != Handover::Never {
+            2
+        } else {
+            1
+        };
+    }
+    if start > 0 {
+        merged.push(Node::new(stmt(0x00, 0))); // ld #0
+        merged.push(Node::new(stmt(0x07, 0))); // tax
+    }
+    for (i, node) in nodes.iter().enumerate() {
+        match (node.insn.code, handover) {
+            (0x06, Handover::Accepted) if node.insn.k != 0 => {
+                pending.push(merged.len());
+                merged.push(Node::new(stmt(0x05, 0)));
+            }
+            (0x06, Handover::Rejected) if node.insn.k == 0 => {
+                pending.push(merged.len());
+                merged.push(Node::new(stmt(0x05, 0)));
+            }
+            (0x06, _) | (0x16, Handover::Never) => merged.push(Node::new(node.insn)),
+            // jgt #0, next program, ret a
+            (0x16, Handover::Accepted) => {
+                pending.push(merged.len());
+                merged.push(Node {
+                    insn: stmt(0x25, 0),
+                    jump: Some((0, merged.len() + 1)),
+                });
+                merged.push(Node::new(node.insn));
+            }
+            // jeq #0, next program, ret a
+            (0x16, Handover::Rejected) => {
+                pending.push(merged.len());
+                merged.push(Node {
+                    insn: stmt(0x15, 0),
+                    jump: Some((0, merged.len() + 1)),
+                });
+                merged.push(Node::new(node.insn));
+            }
+            (c, _) if c & 0x07 == 0x06 => return Err(AnalysisError::InvalidInstruction(i)),
+            _ => merged.push(Node {
+                insn: node.insn,
+                jump: node.jump.map(|(jt, jf)| (at[jt], at[jf])),
+            }),
+        }
+    }
+    Ok(())
+}
+
+/// run `first`, handing the packets it accepts or rejects over to `second`
+fn chain(
+    first: &[BPFFilter],
+    second: &[BPFFilter],
+    handover: Handover,
+) -> Result<BPFProgram, AnalysisError> {
+    let mut merged = Vec::new();
+    let mut pending = Vec::new();
+    append(&mut merged, &mut pending, first, handover)?;
+    append(&mut merged, &mut pending, second, Handover::Never)?;
+    if merged.len() > bpf::MAXINSNS {
+        return Err(AnalysisError::TooLong(merged.len()));
+    }
+    encode(&merged).map(BPFProgram::from)
+}
+
+impl BPFProgram {
+    /// accept the packets both programs accept
+    ///
+    /// `other` runs on the packets `self` accepts, starting with A and X
+    /// cleared, and returns the length to keep. the others are dropped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use classic_bpf::*;
+    ///
+    /// let ipv4 = compile("ip", Dlt::En10mb).unwrap();
+    /// let udp = compile("ip proto 17", Dlt::En10mb).unwrap();
+    /// let both = ipv4.and(&udp).unwrap();
+    ///
+    /// let mut packet = [0u8; 34];
+    /// packet[12..14].copy_from_slice(&[0x08, 0x00]);
+    /// packet[23] = 17;
+    /// assert_ne!(run(&both, &packet, 34), 0);
+    /// packet[23] = 6;
+    /// assert_eq!(run(&both, &packet, 34), 0);
+    /// ```
+    pub fn and(&self, other: &[BPFFilter]) -> Result<BPFProgram, AnalysisError> {
+        chain(self, other, Handover::Accepted)
+    }
+
+    /// accept the packets one of the programs accepts
+    ///
+    /// `other` runs on the packets `self` rejects, starting with A and X
+    /// cleared. the length kept is the one of the program accepting.
+    ///
+    /// a load past the end of the packet stops the whole program: the
+    /// packets `self` drops that way are dropped without running `other`.
+    pub fn or(&self, other: &[BPFFilter]) -> Result<BPFProgram, AnalysisError> {
+        chain(self, other, Handover::Rejected)
+    }
+
+    /// accept the packets the program rejects, whole, and reject the others
+    ///
+    /// a load past the end of the packet stops the whole program: the
+    /// packets `self` drops that way are dropped by the inverse as well.
+    pub fn not(&self) -> Result<BPFProgram, AnalysisError> {
+        let nodes = decode(self)?;
+        let mut at = Vec::with_capacity(nodes.len());
+        let mut next = 0;
+        for node in &nodes {
+            at.push(next);
+            next += if node.insn.code == 0x16 { 3 } else { 1 };
+        }
+        let mut inverted = Vec::with_capacity(next);
+        for (i, node) in nodes.iter().enumerate() {
+            match node.insn.code {
+                0x06 if node.insn.k == 0 => inverted.push(Node::new(stmt(0x06, u32::MAX))),
+                0x06 => inverted.push(Node::new(stmt(0x06, 0))),
+                // jeq #0, ret #-1, ret #0
+                0x16 => {
+                    let here = inverted.len();
+                    inverted.push(Node {
+                        insn: stmt(0x15, 0),
+                        jump: Some((here + 1, here + 2)),
+                    });
+                    inverted.push(Node::new(stmt(0x06, u32::MAX)));
+                    inverted.push(Node::new(stmt(0x06, 0)));
+                }
+                c if c & 0x07 == 0x06 => return Err(AnalysisError::InvalidInstruction(i)),
+                _ => inverted.push(Node {
+                    insn: node.insn,
+                    jump: node.jump.map(|(jt, jf)| (at[jt], at[jf])),
+                }),
+            }
+        }
+        if inverted.len() > bpf::MAXINSNS {
+            return Err(AnalysisError::TooLong(inverted.len()));
+        }
+        encode(&inverted).map(BPFProgram::from)
+    }
+}
+
+#[test]
+fn test_slice_to_ret() {
+    use crate::analysis::{outcomes, Value};
+    use crate::bpf_base::bpf;
+    let filters = [
+        BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x0800, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 1),
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 1),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 2),
+        BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+    ];
+    let sliced = BPFProgram::from(&filters[..]).slice_to_ret(1).unwrap();
+    // ldh, jeq, ret #1, ret #0
+    assert_eq!(sliced.len(), 4);
+    let outcomes = outcomes(&sliced).unwrap();
+    assert_eq!(outcomes[0].ret, Value::Const(1));
+    assert_eq!(outcomes[0].paths.len(), 1);
+    assert_eq!(outcomes[1].ret, Value::Const(0));
+}
+
+#[test]
+fn test_merge_classifier() {
+    use crate::analysis::{outcomes, Value};
+    use crate::bpf_base::bpf::*;
+    use crate::builder::ProgramBuilder;
+    let mut ipv4 = ProgramBuilder::new();
+    ipv4.ld_abs_h(12)
+        .jmp(JEQ, K, 0x0800, 0, 1)
+        .ret_k(u32::MAX)
+        .ret_k(0);
+    // returns the ethertype itself when it is IPv6
+    let mut ipv6 = ProgramBuilder::new();
+    ipv6.ld_abs_h(12)
+        .jmp(JEQ, K, 0x86dd, 1, 0)
+        .ld_imm(0)
+        .ret_a();
+    let merged =
+        merge_classifier(&[(&ipv4.build().unwrap(), 1), (&ipv6.build().unwrap(), 2)]).unwrap();
+    let mut returns: Vec<u32> = outcomes(&merged)
+        .unwrap()
+        .iter()
+        .map(|o| match o.ret {
+            Value::Const(k) => k,
+            _ => panic!("symbolic return"),
+        })
+        .collect();
+    returns.sort_unstable();
+    assert_eq!(returns, [0, 1, 2]);
+}
+
+#[test]
+fn test_import_program() {
+    let raw: [(u16, u8, u8, u32); 2] = [(0x15, 0, 1, 6), (0x06, 0, 0, 0)];
+    assert_eq!(
+        import_program(&raw),
+        Err(ValidationError::JumpOutOfRange { index: 0 })
+    );
+    let raw: [(u16, u8, u8, u32); 2] = [(0x02, 0, 0, 16), (0x06, 0, 0, 0)];
+    assert_eq!(
+        import_program(&raw),
+        Err(ValidationError::InvalidSlot { index: 0, slot: 16 })
+    );
+    let raw: [(u16, u8, u8, u32); 2] = [(0x38, 0, 0, 0), (0x06, 0, 0, 0)];
+    assert_eq!(
+        import_program(&raw),
+        Err(ValidationError::InvalidOpcode {
+            index: 0,
+            code: 0x38
+        })
+    );
+    let raw: [(u16, u8, u8, u32); 0] = [];
+    assert_eq!(import_program(&raw), Err(ValidationError::Empty));
+    let raw = vec![(0x06u16, 0u8, 0u8, 0u32); bpf::MAXINSNS + 1];
+    assert_eq!(import_program(&raw), Err(ValidationError::TooLong(4097)));
+
+    let raw: [(u16, u8, u8, u32); 2] = [(0x28, 0, 0, 12), (0x16, 0, 0, 0)];
+    let program = BPFProgram::try_from(&raw[..]).unwrap();
+    assert_eq!(program[1], BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0));
+}
+
+#[test]
+fn test_program_combinators() {
+    use crate::builder::ProgramBuilder;
+    use crate::interpreter::run;
+    let mut ipv4 = ProgramBuilder::new();
+    ipv4.ld_abs_h(12)
+        .jmp(bpf::JEQ, bpf::K, 0x0800, 0, 1)
+        .ret_k(96)
+        .ret_k(0);
+    let ipv4 = BPFProgram::from(ipv4.build().unwrap());
+    // returns the protocol, 0 unless it is UDP
+    let mut udp = ProgramBuilder::new();
+    udp.ld_abs_b(23)
+        .jmp(bpf::JEQ, bpf::K, 17, 1, 0)
+        .ld_imm(0)
+        .ret_a();
+    let udp = BPFProgram::from(udp.build().unwrap());
+
+    let packet = |ethertype: u16, protocol: u8| {
+        let mut packet = [0u8; 34];
+        packet[12..14].copy_from_slice(&ethertype.to_be_bytes());
+        packet[23] = protocol;
+        packet
+    };
+    let cases = [
+        (packet(0x0800, 17), [17, 96, 0, 0]),
+        (packet(0x0800, 6), [0, 96, 0, u32::MAX]),
+        (packet(0x86dd, 17), [0, 17, u32::MAX, 0]),
+        (packet(0x86dd, 6), [0, 0, u32::MAX, u32::MAX]),
+    ];
+    let combined = [
+        ipv4.and(&udp).unwrap(),
+        ipv4.or(&udp).unwrap(),
+        ipv4.not().unwrap(),
+        udp.not().unwrap(),
+    ];
+    for (packet, expected) in cases {
+        for (program, expected) in combined.iter().zip(expected) {
+            assert_eq!(
+                run(program, &packet, 34),
+                expected,
+                "{:?}",
+                program.filters()
+            );
+        }
+    }
+    // the packets too short for a load are dropped by the inverse too
+    let mut far = ProgramBuilder::new();
+    far.ld_abs_b(100)
+        .jmp(bpf::JEQ, bpf::K, 1, 0, 1)
+        .ret_k(u32::MAX)
+        .ret_k(0);
+    let far = BPFProgram::from(far.build().unwrap());
+    let short = packet(0x0800, 17);
+    assert_eq!(run(&far, &short, 34), 0);
+    assert_eq!(run(&far.not().unwrap(), &short, 34), 0);
+    assert_eq!(run(&far.or(&[stmt(0x06, 7)]).unwrap(), &short, 34), 0);
+    assert_eq!(run(&far.not().unwrap(), &[0; 101], 101), u32::MAX);
+
+    assert_eq!(
+        BPFProgram::from(vec![stmt(0x0e, 0)]).not(),
+        Err(AnalysisError::InvalidInstruction(0))
+    );
+}
+
+#[test]
+fn test_concat() {
+    use crate::interpreter::run;
+    let jeq = |k, jt, jf| BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, k, jt, jf);
+    let fragment = |k: u32| {
+        vec![
+            BPFFilter::bpf_stmt(bpf::LD | bpf::B | bpf::ABS, 0),
+            jeq(k, 0, 2),
+        ]
+    };
+    let exits = [stmt(0x06, 1), stmt(0x06, 0)];
+    // the first byte must be each of the values, which none is
+    let mut fragments: Vec<Vec<BPFFilter>> = (0..3).map(fragment).collect();
+    fragments.push(exits.to_vec());
+    let slices: Vec<&[BPFFilter]> = fragments.iter().map(|f| &f[..]).collect();
+    let filters = concat(&slices).unwrap();
+    assert_eq!(filters.len(), 8);
+    assert_eq!(filters[1].jf, 5);
+    assert_eq!(run(&filters, &[0], 1), 0);
+
+    // one fragment falling through to the shared exits
+    let filters = concat(&[&[jeq(0, 0, 2)], &exits]).unwrap();
+    assert_eq!(run(&filters, &[], 0), 1);
+
+    let mut fragments: Vec<Vec<BPFFilter>> = (0..130).map(fragment).collect();
+    fragments.push(exits.to_vec());
+    let slices: Vec<&[BPFFilter]> = fragments.iter().map(|f| &f[..]).collect();
+    assert_eq!(concat(&slices), Err(AnalysisError::JumpTooFar(1)));
+    assert_eq!(
+        concat(&[&[jeq(7, 0, 4)], &exits]),
+        Err(AnalysisError::OutOfBounds(0))
+    );
+    assert_eq!(
+        concat(&[&[stmt(0x00, 0)]]),
+        Err(AnalysisError::OutOfBounds(0))
+    );
+}
@@ -0,0 +1,143 @@
+use crate::bpf_base::*;
+use crate::interpreter::run;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// the packets seen by a [`TwoStageFilter`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TwoStageStats {
+    /// the packets the kernel program let through
+    pub delivered: u64,
+    /// the delivered packets the second stage accepted as well
+    pub matched: u64,
+}
+
+/// a coarse kernel program refined by a second program run in userspace
+///
+/// the program attached in the kernel keeps most of the unwanted traffic
+/// away, the second stage then decides on each delivered packet with the
+/// interpreter, free of the limits the kernel puts on the first.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// // ARP in the kernel, then requests only
+/// let requests = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 20),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 1, 0, 1),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, 0),
+/// ];
+/// let mut filter = TwoStageFilter::with_second_stage(presets::arp_only().to_vec(), &requests);
+/// let mut request = [0u8; 42];
+/// request[21] = 1;
+/// assert!(filter.matches(&request));
+/// assert!(!filter.matches(&[0u8; 42]));
+/// assert_eq!(filter.stats(), TwoStageStats { delivered: 2, matched: 1 });
+/// ```
+#[derive(Debug, Clone)]
+pub struct TwoStageFilter {
+    kernel: Vec<BPFFilter>,
+    second: Option<Vec<BPFFilter>>,
+    stats: TwoStageStats,
+}
+
+impl TwoStageFilter {
+    /// a filter whose kernel program decides alone
+    pub fn new(kernel: Vec<BPFFilter>) -> Self {
+        Self {
+            kernel,
+            second: None,
+            stats: TwoStageStats::default(),
+        }
+    }
+
+    /// a filter whose delivered packets must also be accepted by `second`
+    pub fn with_second_stage(kernel: Vec<BPFFilter>, second: &[BPFFilter]) -> Self {
+        Self {
+            kernel,
+            second: Some(second.to_vec()),
+            stats: TwoStageStats::default(),
+        }
+    }
+
+    /// the program run in the kernel
+    pub fn kernel_program(&self) -> &[BPFFilter] {
+        &self.kernel
+    }
+
+    /// the program run in userspace, if any
+    pub fn second_stage(&self) -> Option<&[BPFFilter]> {
+        self.second.as_deref()
+    }
+
+    /// attach the kernel program to `socket`
+    pub fn attach<T>(&self, socket: &T) -> io::Result<()>
+    where
+        T: AsRawFd,
+    {
+        BPFFProg::new(&self.kernel).attach_filter(socket)
+    }
+
+    /// run the second stage on a packet delivered by the kernel
+    ///
+    /// the packet matches when the second stage returns anything but 0.
+    pub fn matches(&mut self, packet: &[u8]) -> bool {
+        self.stats.delivered += 1;
+        let matched = match &self.second {
+            Some(second) => run(second, packet, packet.len() as u32) != 0,
+            None => true,
+        };
+        if matched {
+            self.stats.matched += 1;
+        }
+        matched
+    }
+
+    pub fn stats(&self) -> TwoStageStats {
+        self.stats
+    }
+}
+
+#[test]
+fn test_two_stage_filter() {
+    use crate::builder::ProgramBuilder;
+
+    // UDP in the kernel, then a payload starting with 0xab
+    let udp = ProgramBuilder::new()
+        .ld_abs_b(23)
+        .jmp(bpf::JEQ, bpf::K, 17, 0, 1)
+        .ret_k(u32::MAX)
+        .ret_k(0)
+        .build()
+        .unwrap();
+    let magic = ProgramBuilder::new()
+        .ld_abs_b(42)
+        .jmp(bpf::JEQ, bpf::K, 0xab, 0, 1)
+        .ret_k(u32::MAX)
+        .ret_k(0)
+        .build()
+        .unwrap();
+
+    let mut packet = [0u8; 43];
+    packet[23] = 17;
+    let mut filter = TwoStageFilter::new(udp.clone());
+    assert!(filter.matches(&packet));
+    assert_eq!(filter.second_stage(), None);
+
+    let mut filter = TwoStageFilter::with_second_stage(udp, &magic);
+    assert!(!filter.matches(&packet));
+    packet[42] = 0xab;
+    assert!(filter.matches(&packet));
+    // the second stage drops the packets too short for its loads
+    assert!(!filter.matches(&packet[..42]));
+    assert_eq!(
+        filter.stats(),
+        TwoStageStats {
+            delivered: 3,
+            matched: 1
+        }
+    );
+}
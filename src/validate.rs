@@ -0,0 +1,275 @@
+use crate::ancillary::{SKF_AD_MAX, SKF_AD_OFF};
+use crate::bpf_base::*;
+use std::fmt;
+
+/// the reason the kernel would refuse a program, from [`validate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// the program has no instruction
+    Empty,
+    /// the program has this many instructions, more than `bpf::MAXINSNS`
+    TooLong(usize),
+    /// the instruction at `index` has an opcode classic BPF does not know
+    InvalidOpcode { index: usize, code: u16 },
+    /// the instruction at `index` divides by a constant 0
+    DivisionByZero { index: usize },
+    /// the instruction at `index` shifts by a constant of 32 or more
+    InvalidShift { index: usize, shift: u32 },
+    /// the load at `index` reads `SKF_AD_OFF + offset`, no ancillary field
+    UnknownAncillary { index: usize, offset: u32 },
+    /// the instruction at `index` uses M[slot], beyond M[15]
+    InvalidSlot { index: usize, slot: u32 },
+    /// the jump at `index` goes past the end of the program
+    JumpOutOfRange { index: usize },
+    /// the instruction at `index` may read M[slot] before anything is stored there
+    UninitializedSlot { index: usize, slot: u32 },
+    /// the last instruction, at `index`, is not a RET
+    MissingRet { index: usize },
+}
+
+impl ValidationError {
+    /// the index of the offending instruction, if there is one
+    pub fn index(&self) -> Option<usize> {
+        match self {
+            ValidationError::Empty | ValidationError::TooLong(_) => None,
+            ValidationError::InvalidOpcode { index, .. }
+            | ValidationError::DivisionByZero { index }
+            | ValidationError::InvalidShift { index, .. }
+            | ValidationError::UnknownAncillary { index, .. }
+            | ValidationError::InvalidSlot { index, .. }
+            | ValidationError::JumpOutOfRange { index }
+            | ValidationError::UninitializedSlot { index, .. }
+            | ValidationError::MissingRet { index } => Some(*index),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Empty => write!(f, "the program is empty"),
+            ValidationError::TooLong(len) => write!(
+                f,
+                "the program has {} instructions, more than {}",
+                len,
+                bpf::MAXINSNS
+            ),
+            ValidationError::InvalidOpcode { index, code } => {
+                write!(f, "instruction {}: unknown opcode {:#x}", index, code)
+            }
+            ValidationError::DivisionByZero { index } => {
+                write!(f, "instruction {}: division by a constant 0", index)
+            }
+            ValidationError::InvalidShift { index, shift } => {
+                write!(f, "instruction {}: shift by {}, beyond 31", index, shift)
+            }
+            ValidationError::UnknownAncillary { index, offset } => write!(
+                f,
+                "instruction {}: SKF_AD_OFF + {} is no ancillary field",
+                index, offset
+            ),
+            ValidationError::InvalidSlot { index, slot } => write!(
+                f,
+                "instruction {}: scratch slot M[{}] is out of range, the last one is M[15]",
+                index, slot
+            ),
+            ValidationError::JumpOutOfRange { index } => write!(
+                f,
+                "instruction {}: the jump goes past the end of the program",
+                index
+            ),
+            ValidationError::UninitializedSlot { index, slot } => write!(
+                f,
+                "instruction {}: M[{}] may be read before anything is stored there",
+                index, slot
+            ),
+            ValidationError::MissingRet { index } => {
+                write!(f, "instruction {}: the program must end with a RET", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// check a program as the Linux `bpf_check_classic()` does before attaching it
+///
+/// the kernel refuses invalid programs with a bare EINVAL, this tells which
+/// instruction is wrong and why.
+///
+/// # Example
+///
+/// ```
+/// use classic_bpf::*;
+///
+/// let filters = [
+///     BPFFilter::bpf_stmt(bpf::LD | bpf::H | bpf::ABS, 12),
+///     BPFFilter::bpf_jump(bpf::JMP | bpf::JEQ | bpf::K, 0x86dd, 0, 2),
+///     BPFFilter::bpf_stmt(bpf::RET | bpf::K, u32::MAX),
+/// ];
+///
+/// let error = validate(&filters).unwrap_err();
+/// assert_eq!(error, ValidationError::JumpOutOfRange { index: 1 });
+/// assert_eq!(error.to_string(), "instruction 1: the jump goes past the end of the program");
+/// ```
+pub fn validate(filters: &[BPFFilter]) -> Result<(), ValidationError> {
+    let len = filters.len();
+    if len == 0 {
+        return Err(ValidationError::Empty);
+    }
+    if len > bpf::MAXINSNS {
+        return Err(ValidationError::TooLong(len));
+    }
+
+    for (index, insn) in filters.iter().enumerate() {
+        let code = insn.code;
+        if !is_known_opcode(code) {
+            return Err(ValidationError::InvalidOpcode { index, code });
+        }
+        let class = code & 0x07;
+        let mem = match class {
+            0x00 | 0x01 => code & 0xe0 == 0x60,
+            0x02 | 0x03 => true,
+            _ => false,
+        };
+        if mem && insn.k >= 16 {
+            return Err(ValidationError::InvalidSlot {
+                index,
+                slot: insn.k,
+            });
+        }
+        // DIV and MOD by K
+        if matches!(code, 0x34 | 0x94) && insn.k == 0 {
+            return Err(ValidationError::DivisionByZero { index });
+        }
+        // LSH and RSH by K
+        if matches!(code, 0x64 | 0x74) && insn.k >= 32 {
+            return Err(ValidationError::InvalidShift {
+                index,
+                shift: insn.k,
+            });
+        }
+        // LD ABS beyond SKF_AD_OFF, where only the SKF_AD_* fields are known
+        if matches!(code, 0x20 | 0x28 | 0x30) && insn.k >= SKF_AD_OFF {
+            let offset = insn.k - SKF_AD_OFF;
+            if offset >= SKF_AD_MAX || offset & 3 != 0 {
+                return Err(ValidationError::UnknownAncillary { index, offset });
+            }
+        }
+        if class == 0x05 {
+            let remaining = len - index - 1;
+            let in_range = if code == 0x05 {
+                (insn.k as usize) < remaining
+            } else {
+                (insn.jt as usize) < remaining && (insn.jf as usize) < remaining
+            };
+            if !in_range {
+                return Err(ValidationError::JumpOutOfRange { index });
+            }
+        }
+    }
+    if filters[len - 1].code & 0x07 != 0x06 {
+        return Err(ValidationError::MissingRet { index: len - 1 });
+    }
+
+    // the slots stored on every path to each instruction, as the kernel
+    // check_load_and_stores() follows them
+    let mut masks = vec![u16::MAX; len];
+    let mut valid: u16 = 0;
+    for (index, insn) in filters.iter().enumerate() {
+        valid &= masks[index];
+        match insn.code {
+            0x02 | 0x03 => valid |= 1 << insn.k,
+            0x60 | 0x61 if valid & (1 << insn.k) == 0 => {
+                return Err(ValidationError::UninitializedSlot {
+                    index,
+                    slot: insn.k,
+                })
+            }
+            0x05 => {
+                masks[index + 1 + insn.k as usize] &= valid;
+                valid = u16::MAX;
+            }
+            code if code & 0x07 == 0x05 => {
+                masks[index + 1 + insn.jt as usize] &= valid;
+                masks[index + 1 + insn.jf as usize] &= valid;
+                valid = u16::MAX;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_validate() {
+    let ret = BPFFilter::bpf_stmt(bpf::RET | bpf::A, 0);
+    assert_eq!(validate(&[]), Err(ValidationError::Empty));
+    assert_eq!(
+        validate(&vec![ret; bpf::MAXINSNS + 1]),
+        Err(ValidationError::TooLong(bpf::MAXINSNS + 1))
+    );
+    assert_eq!(
+        validate(&[BPFFilter::from((0xff, 0, 0, 0)), ret]),
+        Err(ValidationError::InvalidOpcode {
+            index: 0,
+            code: 0xff
+        })
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ALU | bpf::DIV | bpf::K, 0), ret]),
+        Err(ValidationError::DivisionByZero { index: 0 })
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ALU | bpf::MOD | bpf::K, 0), ret]),
+        Err(ValidationError::DivisionByZero { index: 0 })
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ALU | bpf::XOR | bpf::K, 0), ret]),
+        Ok(())
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ALU | bpf::LSH | bpf::K, 32), ret]),
+        Err(ValidationError::InvalidShift {
+            index: 0,
+            shift: 32
+        })
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ALU | bpf::RSH | bpf::K, 31), ret]),
+        Ok(())
+    );
+    let ld_ad = |offset| BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::ABS, SKF_AD_OFF + offset);
+    assert_eq!(
+        validate(&[ld_ad(900), ret]),
+        Err(ValidationError::UnknownAncillary {
+            index: 0,
+            offset: 900
+        })
+    );
+    assert_eq!(
+        validate(&[ld_ad(crate::ancillary::SKF_AD_VLAN_TPID), ret]),
+        Ok(())
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::ST, 16), ret]),
+        Err(ValidationError::InvalidSlot { index: 0, slot: 16 })
+    );
+    assert_eq!(
+        validate(&[BPFFilter::bpf_stmt(bpf::LD | bpf::W | bpf::LEN, 0)]),
+        Err(ValidationError::MissingRet { index: 0 })
+    );
+
+    // M[2] is only stored when the jump is not taken
+    let filters = [
+        BPFFilter::bpf_jump(bpf::JMP | bpf::JGT | bpf::K, 0, 1, 0),
+        BPFFilter::bpf_stmt(bpf::ST, 2),
+        BPFFilter::bpf_stmt(bpf::LD | bpf::MEM, 2),
+        ret,
+    ];
+    assert_eq!(
+        validate(&filters),
+        Err(ValidationError::UninitializedSlot { index: 2, slot: 2 })
+    );
+    assert_eq!(validate(&filters[1..]), Ok(()));
+}